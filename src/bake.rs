@@ -1,4 +1,4 @@
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use owo_colors::OwoColorize;
 use std::fs::{self, File};
 use std::io::{Read, Write};
@@ -6,86 +6,347 @@ use std::path::{Path, PathBuf};
 
 const MAGIC: &[u8; 8] = b"DEKBAKE\0";
 const FOOTER_SIZE: usize = 8 + 32 + 8 + 64 + 64; // magic + hash + size + timestamp + user_host
+/// The timestamp field's tail: dek version of the base binary (31 bytes),
+/// then the archive format (0 = gzip). Older bakes have nulls there, so they
+/// read as "unknown version" and gzip.
+const VERSION_OFFSET: usize = 80;
+const FORMAT_OFFSET: usize = 111;
+const FORMAT_ZSTD: u8 = 1;
+/// Format bit: the archive is encrypted (salt + nonce + ChaCha20-Poly1305 ciphertext)
+const FLAG_ENCRYPTED: u8 = 0x80;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+/// zstd level for baked archives; configs are small, so favour size
+const ZSTD_LEVEL: i32 = 19;
+
+/// How the embedded config archive is compressed (`bake --compression`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    /// gzip level 0-9
+    Gzip(u32),
+    Zstd,
+}
 
-/// Check if current binary has embedded data, extract if needed, return config path
-pub fn check_embedded() -> Option<PathBuf> {
-    let exe = std::env::current_exe().ok()?;
-    let mut file = File::open(&exe).ok()?;
+impl Default for Compression {
+    fn default() -> Self {
+        Self::Gzip(flate2::Compression::default().level())
+    }
+}
 
-    // Read footer
-    let file_size = file.metadata().ok()?.len() as usize;
-    if file_size < FOOTER_SIZE {
-        return None;
+impl std::str::FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "zstd" => Ok(Self::Zstd),
+            _ => match s.parse::<u32>() {
+                Ok(level) if level <= 9 => Ok(Self::Gzip(level)),
+                _ => Err(format!("expected a gzip level 0-9 or 'zstd', got '{}'", s)),
+            },
+        }
     }
+}
 
-    use std::io::Seek;
-    file.seek(std::io::SeekFrom::End(-(FOOTER_SIZE as i64))).ok()?;
+impl Compression {
+    fn format_byte(self) -> u8 {
+        match self {
+            Self::Gzip(_) => 0,
+            Self::Zstd => FORMAT_ZSTD,
+        }
+    }
+}
 
-    let mut footer = [0u8; FOOTER_SIZE];
-    file.read_exact(&mut footer).ok()?;
+/// Parsed footer of a baked binary
+pub struct Footer {
+    /// md5 of the embedded archive
+    pub hash: String,
+    pub size: u64,
+    pub timestamp: String,
+    /// dek version of the base binary, empty for older bakes
+    pub version: String,
+    pub user_host: String,
+    format: u8,
+}
+
+impl Footer {
+    /// Read the footer of `binary`; None if it has no embedded config
+    pub fn read(binary: &Path) -> Option<Self> {
+        use std::io::Seek;
 
-    // Check magic
-    if &footer[0..8] != MAGIC {
-        return None;
+        let mut file = File::open(binary).ok()?;
+        let file_size = file.metadata().ok()?.len() as usize;
+        if file_size < FOOTER_SIZE {
+            return None;
+        }
+        file.seek(std::io::SeekFrom::End(-(FOOTER_SIZE as i64))).ok()?;
+
+        let mut footer = [0u8; FOOTER_SIZE];
+        file.read_exact(&mut footer).ok()?;
+
+        // Check magic
+        if &footer[0..8] != MAGIC {
+            return None;
+        }
+
+        let text = |range: std::ops::Range<usize>| {
+            std::str::from_utf8(&footer[range]).ok().map(|s| s.trim_end_matches('\0').to_string())
+        };
+        Some(Self {
+            hash: text(8..40)?,
+            size: u64::from_le_bytes(footer[40..48].try_into().ok()?),
+            timestamp: text(48..VERSION_OFFSET)?,
+            version: text(VERSION_OFFSET..FORMAT_OFFSET)?,
+            user_host: text(112..176)?,
+            format: footer[FORMAT_OFFSET],
+        })
+    }
+
+    pub fn encrypted(&self) -> bool {
+        self.format & FLAG_ENCRYPTED != 0
+    }
+
+    pub fn compression(&self) -> &'static str {
+        match self.format & !FLAG_ENCRYPTED {
+            FORMAT_ZSTD => "zstd",
+            _ => "gzip",
+        }
     }
+}
 
-    // Parse footer
-    let hash = std::str::from_utf8(&footer[8..40]).ok()?.trim_end_matches('\0');
-    let tar_size = u64::from_le_bytes(footer[40..48].try_into().ok()?);
+/// Check if current binary has embedded data, extract if needed, return config path.
+/// Runs once per process, so an encrypted config asks for its passphrase once.
+pub fn check_embedded() -> Option<PathBuf> {
+    static EMBEDDED: std::sync::OnceLock<Option<PathBuf>> = std::sync::OnceLock::new();
+    EMBEDDED
+        .get_or_init(|| extract_embedded(&std::env::current_exe().ok()?))
+        .clone()
+}
+
+/// Directories configs were extracted to, removed when the process exits
+static EXTRACTED: std::sync::Mutex<Vec<PathBuf>> = std::sync::Mutex::new(Vec::new());
 
-    // Cache path
-    let cache_dir = PathBuf::from(format!("/tmp/dek-{}", hash));
+extern "C" fn remove_extracted() {
+    if let Ok(dirs) = EXTRACTED.lock() {
+        for dir in dirs.iter() {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+}
 
-    // Already extracted?
-    if cache_dir.exists() {
-        return Some(cache_dir);
+/// A fresh, owner-only directory to extract into, under $XDG_RUNTIME_DIR
+/// when that's private, else the temp dir. Removed when the process exits.
+fn extract_dir() -> Result<PathBuf> {
+    let parent = std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .filter(|dir| crate::util::check_private(dir).is_ok())
+        .unwrap_or_else(std::env::temp_dir);
+    let mut builder = tempfile::Builder::new();
+    builder.prefix("dek-baked-");
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        builder.permissions(fs::Permissions::from_mode(0o700));
+    }
+    let dir = builder.tempdir_in(&parent)?.keep();
+    let mut extracted = EXTRACTED.lock().unwrap();
+    if extracted.is_empty() {
+        unsafe { libc::atexit(remove_extracted) };
     }
+    extracted.push(dir.clone());
+    crate::util::check_private(&dir)?;
+    Ok(dir)
+}
+
+/// Extract the config embedded in `binary` to a fresh private directory,
+/// return its path
+fn extract_embedded(binary: &Path) -> Option<PathBuf> {
+    use std::io::Seek;
+
+    let footer = Footer::read(binary)?;
+    let encrypted = footer.encrypted();
 
     // Extract
-    file.seek(std::io::SeekFrom::End(-((FOOTER_SIZE + tar_size as usize) as i64))).ok()?;
-    let mut tar_data = vec![0u8; tar_size as usize];
+    let mut file = File::open(binary).ok()?;
+    file.seek(std::io::SeekFrom::End(-((FOOTER_SIZE + footer.size as usize) as i64))).ok()?;
+    let mut tar_data = vec![0u8; footer.size as usize];
     file.read_exact(&mut tar_data).ok()?;
 
+    if encrypted {
+        tar_data = match passphrase(false).and_then(|pass| decrypt(&tar_data, &pass)) {
+            Ok(data) => data,
+            Err(e) => {
+                eprintln!("{} {}", c!("✗", red), e);
+                std::process::exit(1);
+            }
+        };
+    }
+
     // Decompress and untar
-    let decoder = flate2::read::GzDecoder::new(&tar_data[..]);
+    let decoder: Box<dyn Read> = match footer.compression() {
+        "zstd" => Box::new(zstd::stream::read::Decoder::new(&tar_data[..]).ok()?),
+        _ => Box::new(flate2::read::GzDecoder::new(&tar_data[..])),
+    };
     let mut archive = tar::Archive::new(decoder);
-    fs::create_dir_all(&cache_dir).ok()?;
-    archive.unpack(&cache_dir).ok()?;
+    let dir = match extract_dir() {
+        Ok(dir) => dir,
+        Err(e) => {
+            eprintln!("{} Failed to extract embedded config: {}", c!("✗", red), e);
+            return None;
+        }
+    };
+    archive.unpack(&dir).ok()?;
 
-    Some(cache_dir)
+    Some(dir)
+}
+
+/// Passphrase for encrypted bakes: $DEK_BAKE_PASS, else prompted on the terminal
+fn passphrase(confirm: bool) -> Result<String> {
+    if let Ok(pass) = std::env::var("DEK_BAKE_PASS") {
+        if pass.is_empty() {
+            bail!("DEK_BAKE_PASS is empty");
+        }
+        return Ok(pass);
+    }
+    let mut prompt = dialoguer::Password::new().with_prompt("Bake passphrase");
+    if confirm {
+        prompt = prompt.with_confirmation("Confirm passphrase", "Passphrases don't match");
+    }
+    prompt.interact().context("Failed to read passphrase (set DEK_BAKE_PASS when not on a terminal)")
+}
+
+fn derive_key(pass: &str, salt: &[u8]) -> Result<chacha20poly1305::Key> {
+    let mut key = chacha20poly1305::Key::default();
+    argon2::Argon2::default()
+        .hash_password_into(pass.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow::anyhow!("Failed to derive key: {}", e))?;
+    Ok(key)
+}
+
+/// salt + nonce + ChaCha20-Poly1305(data), keyed by argon2id(pass, salt)
+fn encrypt(data: &[u8], pass: &str) -> Result<Vec<u8>> {
+    use chacha20poly1305::aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng};
+
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let cipher = chacha20poly1305::ChaCha20Poly1305::new(&derive_key(pass, &salt)?);
+    let nonce = chacha20poly1305::ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, data)
+        .map_err(|_| anyhow::anyhow!("Failed to encrypt config"))?;
+
+    let mut out = Vec::with_capacity(SALT_LEN + NONCE_LEN + ciphertext.len());
+    out.extend_from_slice(&salt);
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&ciphertext);
+    Ok(out)
+}
+
+fn decrypt(data: &[u8], pass: &str) -> Result<Vec<u8>> {
+    use chacha20poly1305::aead::{Aead, KeyInit};
+
+    if data.len() < SALT_LEN + NONCE_LEN {
+        bail!("Embedded config is corrupted");
+    }
+    let (salt, rest) = data.split_at(SALT_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    let cipher = chacha20poly1305::ChaCha20Poly1305::new(&derive_key(pass, salt)?);
+    cipher
+        .decrypt(chacha20poly1305::Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| anyhow::anyhow!("Wrong passphrase for the embedded config"))
 }
 
 /// Get bake info from embedded footer
 pub fn get_bake_info() -> Option<String> {
-    let exe = std::env::current_exe().ok()?;
-    let mut file = File::open(&exe).ok()?;
+    let footer = Footer::read(&std::env::current_exe().ok()?)?;
+    let encrypted = if footer.encrypted() { " (encrypted)" } else { "" };
+
+    Some(format!("Baked on {} by {}{}", footer.timestamp, footer.user_host, encrypted))
+}
+
+/// `dek info`: what a baked binary carries — bake metadata, dek version and
+/// the embedded config's configs, run commands and state probes
+pub fn info(binary: Option<PathBuf>) -> Result<()> {
+    let binary = match binary {
+        Some(path) => path,
+        None => std::env::current_exe()?,
+    };
+    let Some(footer) = Footer::read(&binary) else {
+        bail!("{} has no embedded config (not a baked binary)", binary.display());
+    };
 
-    let file_size = file.metadata().ok()?.len() as usize;
-    if file_size < FOOTER_SIZE {
-        return None;
+    println!("{}", c!(binary.display(), bold));
+    println!();
+    println!("  {} Baked:   {} by {}", c!("•", blue), footer.timestamp, footer.user_host);
+    let version = if footer.version.is_empty() { "unknown" } else { &footer.version };
+    println!("  {} dek:     {}", c!("•", blue), version);
+    let mut archive = format!("{}, {}", format_size(footer.size), footer.compression());
+    if footer.encrypted() {
+        archive.push_str(", encrypted");
     }
+    println!("  {} Config:  {} {}", c!("•", blue), footer.hash, c!(format!("({})", archive), dimmed));
+    println!();
 
-    use std::io::Seek;
-    file.seek(std::io::SeekFrom::End(-(FOOTER_SIZE as i64))).ok()?;
+    let path = extract_embedded(&binary).ok_or_else(|| anyhow::anyhow!("Failed to extract embedded config"))?;
+    let meta = crate::config::load_meta(&path)?;
+    let cfg = crate::config::load_all(&path)?;
+    let configs = crate::config::list_configs(&path, meta.as_ref())?;
 
-    let mut footer = [0u8; FOOTER_SIZE];
-    file.read_exact(&mut footer).ok()?;
+    if let Some(ref m) = meta {
+        if let Some(ref name) = m.name {
+            let version = m.version.as_ref().map(|v| format!(" v{}", v)).unwrap_or_default();
+            println!("  {}", c!(format!("{}{}", name, version), bold));
+            println!();
+        }
+    }
 
-    if &footer[0..8] != MAGIC {
-        return None;
+    if !configs.is_empty() {
+        println!("  {}", c!("CONFIGS", dimmed));
+        for info in &configs {
+            let marker = if info.optional { c!("○", dimmed).to_string() } else { c!("•", green).to_string() };
+            print!("    {} {}", marker, info.key);
+            for l in &info.labels {
+                print!(" {}", c!(format!("@{}", l), cyan));
+            }
+            if let Some(ref d) = info.description {
+                print!("  {}", c!(d, dimmed));
+            }
+            println!();
+        }
+        println!();
     }
 
-    let timestamp = std::str::from_utf8(&footer[48..112]).ok()?.trim_end_matches('\0');
-    let user_host = std::str::from_utf8(&footer[112..176]).ok()?.trim_end_matches('\0');
+    if let Some(run) = cfg.run.as_ref().filter(|r| !r.is_empty()) {
+        println!("  {}", c!("RUN", dimmed));
+        let mut names: Vec<_> = run.keys().collect();
+        names.sort();
+        for name in names {
+            println!("    {}", c!(name, yellow));
+        }
+        println!();
+    }
 
-    Some(format!("Baked on {} by {}", timestamp, user_host))
+    if !cfg.state.is_empty() {
+        println!("  {}", c!("STATE", dimmed));
+        for state in &cfg.state {
+            println!("    {}", state.name);
+        }
+        println!();
+    }
+
+    Ok(())
 }
 
 /// Bake a prepared config directory into a standalone binary.
 /// base_binary: the dek executable to use as the base (release build or current_exe)
 /// output: where to write the baked binary
-pub fn create_baked_binary(prepared_path: &Path, base_binary: &Path, output: &Path) -> Result<()> {
-    let tar_data = create_tarball(prepared_path)?;
+/// passphrase: encrypt the embedded config with it
+pub fn create_baked_binary(
+    prepared_path: &Path, base_binary: &Path, output: &Path, compression: Compression, passphrase: Option<&str>,
+) -> Result<()> {
+    let mut tar_data = create_tarball(prepared_path, compression)?;
+    if let Some(pass) = passphrase {
+        tar_data = encrypt(&tar_data, pass)?;
+    }
 
     // Hash for cache key
     let hash = format!("{:x}", md5::compute(&tar_data));
@@ -109,10 +370,14 @@ pub fn create_baked_binary(prepared_path: &Path, base_binary: &Path, output: &Pa
     // Tar size (8 bytes)
     footer[40..48].copy_from_slice(&(tar_data.len() as u64).to_le_bytes());
 
-    // Timestamp (64 bytes, null-padded)
+    // Timestamp (32 bytes) + dek version (31 bytes), null-padded, + archive format (1 byte)
     let timestamp = chrono::Local::now().format("%Y-%m-%d %H:%M").to_string();
     let ts_bytes = timestamp.as_bytes();
-    footer[48..48 + ts_bytes.len().min(64)].copy_from_slice(&ts_bytes[..ts_bytes.len().min(64)]);
+    footer[48..48 + ts_bytes.len().min(32)].copy_from_slice(&ts_bytes[..ts_bytes.len().min(32)]);
+    let version = binary_version(base_binary);
+    let v_bytes = version.as_bytes();
+    footer[VERSION_OFFSET..VERSION_OFFSET + v_bytes.len().min(31)].copy_from_slice(&v_bytes[..v_bytes.len().min(31)]);
+    footer[FORMAT_OFFSET] = compression.format_byte() | if passphrase.is_some() { FLAG_ENCRYPTED } else { 0 };
 
     // User@host (64 bytes, null-padded)
     let user = std::env::var("USER").unwrap_or_else(|_| "unknown".to_string());
@@ -138,17 +403,27 @@ pub fn create_baked_binary(prepared_path: &Path, base_binary: &Path, output: &Pa
 }
 
 /// Bake a config path into a standalone binary
-pub fn run(config_path: Option<PathBuf>, output: PathBuf) -> Result<()> {
+pub fn run(
+    config_path: Option<PathBuf>, output: PathBuf, compression: Compression, encrypt: bool, target: Option<String>,
+) -> Result<()> {
     let config_path = config_path
-        .or_else(|| crate::config::find_default_config())
+        .or_else(crate::config::find_default_config)
         .ok_or_else(|| anyhow::anyhow!("No config found"))?;
 
     println!("{}", c!("Baking", bold));
     println!();
     println!("  {} Config: {}", c!("•", blue), config_path.display());
     println!("  {} Output: {}", c!("•", blue), output.display());
+    if let Some(ref t) = target {
+        println!("  {} Target: {}", c!("•", blue), t);
+    }
     println!();
 
+    let base_binary = match target {
+        Some(ref t) => target_binary(t)?,
+        None => std::env::current_exe()?,
+    };
+
     // Handle tar.gz input - extract first, then re-tarball
     let actual_path = if crate::util::is_tar_gz(&config_path) {
         println!("  {} Extracting archive...", c!("→", yellow));
@@ -161,11 +436,15 @@ pub fn run(config_path: Option<PathBuf>, output: PathBuf) -> Result<()> {
     let dek_config = crate::config::load_all(&actual_path)?;
     let prepared_path = crate::prepare_config(&actual_path, &dek_config)?;
 
+    let pass = if encrypt { Some(passphrase(true)?) } else { None };
+
     println!("  {} Creating archive...", c!("→", yellow));
+    if pass.is_some() {
+        println!("  {} Encrypting...", c!("→", yellow));
+    }
     println!("  {} Writing binary...", c!("→", yellow));
 
-    let exe = std::env::current_exe()?;
-    create_baked_binary(&prepared_path, &exe, &output)?;
+    create_baked_binary(&prepared_path, &base_binary, &output, compression, pass.as_deref())?;
 
     let size = fs::metadata(&output)?.len();
     println!("  {} Created {} ({})", c!("✓", green), output.display(), format_size(size));
@@ -173,28 +452,115 @@ pub fn run(config_path: Option<PathBuf>, output: PathBuf) -> Result<()> {
     Ok(())
 }
 
-fn create_tarball(path: &Path) -> Result<Vec<u8>> {
-    let mut tar_data = Vec::new();
-    {
-        let encoder = flate2::write::GzEncoder::new(&mut tar_data, flate2::Compression::default());
-        let mut tar = tar::Builder::new(encoder);
-
-        if path.is_file() {
-            // Single file - add it with just the filename
-            let name = path.file_name().unwrap_or_default();
-            tar.append_path_with_name(path, name)?;
-        } else if path.is_dir() {
-            // Directory - add all contents
-            tar.append_dir_all(".", path)?;
-        } else {
-            bail!("Config path does not exist: {}", path.display());
+/// Pack a config path into a tar.gz, for hosts that already have dek:
+/// `dek -C config.tar.gz apply`
+pub fn pack(config_path: Option<PathBuf>, output: PathBuf) -> Result<()> {
+    if !crate::util::is_tar_gz(&output) {
+        bail!("Output must end in .tar.gz or .tgz, so -C takes it as an archive");
+    }
+    let config_path = config_path
+        .or_else(crate::config::find_default_config)
+        .ok_or_else(|| anyhow::anyhow!("No config found"))?;
+
+    println!("{}", c!("Packing", bold));
+    println!();
+    println!("  {} Config: {}", c!("•", blue), config_path.display());
+    println!("  {} Output: {}", c!("•", blue), output.display());
+    println!();
+
+    // Artifacts, includes and outside imports are resolved here, so the
+    // archive applies as-is
+    let actual_path = crate::config::resolve_path(&config_path)?;
+    let dek_config = crate::config::load_all(&actual_path)?;
+    let prepared_path = crate::prepare_config(&actual_path, &dek_config)?;
+
+    println!("  {} Creating archive...", c!("→", yellow));
+    let data = crate::util::create_tar_gz(&prepared_path)?;
+    fs::write(&output, &data).with_context(|| format!("Failed to write {}", output.display()))?;
+    println!("  {} Created {} ({})", c!("✓", green), output.display(), format_size(data.len() as u64));
+
+    Ok(())
+}
+
+/// dek version of a base binary: ours when baking the running dek, otherwise
+/// asked from the binary (empty if it can't run here, e.g. another arch)
+fn binary_version(base_binary: &Path) -> String {
+    let is_self = std::env::current_exe()
+        .and_then(|exe| exe.canonicalize())
+        .ok()
+        .zip(base_binary.canonicalize().ok())
+        .is_some_and(|(exe, base)| exe == base);
+    if is_self {
+        return env!("CARGO_PKG_VERSION").to_string();
+    }
+    std::process::Command::new(base_binary)
+        .arg("--version")
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .and_then(|o| String::from_utf8_lossy(&o.stdout).split_whitespace().last().map(str::to_string))
+        .unwrap_or_default()
+}
+
+/// Base binary for `bake --target`: a path to a pre-built dek binary, or a
+/// target triple resolved to `target/<triple>/release/dek` (built with cargo
+/// when baking from the dek source tree)
+fn target_binary(target: &str) -> Result<PathBuf> {
+    let path = Path::new(target);
+    if path.is_file() {
+        return Ok(path.to_path_buf());
+    }
+    if target.contains('/') {
+        bail!("dek binary not found: {}", target);
+    }
+
+    let cwd = std::env::current_dir()?;
+    let binary = cwd.join(format!("target/{}/release/dek", target));
+    if !binary.is_file() && cwd.join("Cargo.toml").exists() {
+        println!("  {} Building dek ({})...", c!("→", yellow), target);
+        let status = std::process::Command::new("cargo")
+            .args(["build", "--release", "--quiet", "--target", target])
+            .status()?;
+        if !status.success() {
+            bail!("cargo build failed (is the target installed? rustup target add {})", target);
         }
+    }
+    if !binary.is_file() {
+        bail!("No dek binary for {} (expected {}); pass a binary path instead", target, binary.display());
+    }
+    Ok(binary)
+}
 
-        tar.into_inner()?.finish()?;
+fn create_tarball(path: &Path, compression: Compression) -> Result<Vec<u8>> {
+    let mut tar_data = Vec::new();
+    match compression {
+        Compression::Gzip(level) => {
+            let encoder = flate2::write::GzEncoder::new(&mut tar_data, flate2::Compression::new(level));
+            append_config(tar::Builder::new(encoder), path)?.finish()?;
+        }
+        Compression::Zstd => {
+            let encoder = zstd::stream::write::Encoder::new(&mut tar_data, ZSTD_LEVEL)?;
+            append_config(tar::Builder::new(encoder), path)?.finish()?;
+        }
     }
     Ok(tar_data)
 }
 
+/// Add the config file or directory to the archive, returning the encoder
+fn append_config<W: Write>(mut tar: tar::Builder<W>, path: &Path) -> Result<W> {
+    if path.is_file() {
+        // Single file - add it with just the filename
+        let name = path.file_name().unwrap_or_default();
+        tar.append_path_with_name(path, name)?;
+    } else if path.is_dir() {
+        // Directory - add all contents
+        tar.append_dir_all(".", path)?;
+    } else {
+        bail!("Config path does not exist: {}", path.display());
+    }
+    Ok(tar.into_inner()?)
+}
+
 fn format_size(bytes: u64) -> String {
     if bytes < 1024 {
         format!("{} B", bytes)
@@ -1,5 +1,7 @@
+use anyhow::Result;
+use owo_colors::OwoColorize;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 fn base_dir() -> PathBuf {
@@ -13,6 +15,18 @@ fn base_dir() -> PathBuf {
         .join("dek")
 }
 
+static FORCE: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+/// Ignore cached data for this run (--force). Entries are still written, so
+/// the next normal run starts from fresh values.
+pub fn set_force(force: bool) {
+    FORCE.store(force, std::sync::atomic::Ordering::Relaxed);
+}
+
+pub fn force() -> bool {
+    FORCE.load(std::sync::atomic::Ordering::Relaxed)
+}
+
 fn cache_dir() -> PathBuf {
     base_dir().join("url")
 }
@@ -23,6 +37,9 @@ fn cache_path(url: &str) -> PathBuf {
 }
 
 pub fn get(url: &str, max_age: Option<Duration>) -> Option<Vec<u8>> {
+    if force() {
+        return None;
+    }
     let path = cache_path(url);
     if !path.exists() {
         return None;
@@ -56,6 +73,9 @@ fn state_path(item_id: &str) -> PathBuf {
 }
 
 pub fn get_state(item_id: &str) -> Option<String> {
+    if force() {
+        return None;
+    }
     fs::read_to_string(state_path(item_id)).ok()
 }
 
@@ -64,3 +84,237 @@ pub fn set_state(item_id: &str, value: &str) {
     let _ = fs::create_dir_all(path.parent().unwrap());
     let _ = fs::write(&path, value);
 }
+
+// =============================================================================
+// Applied cache — hash of each item as last seen in its desired state, so
+// apply can skip re-checking it
+// =============================================================================
+
+fn applied_dir() -> PathBuf {
+    base_dir().join("applied")
+}
+
+fn applied_path(item_id: &str) -> PathBuf {
+    let hash = format!("{:x}", md5::compute(item_id));
+    applied_dir().join(hash)
+}
+
+pub fn get_applied(item_id: &str) -> Option<String> {
+    if force() {
+        return None;
+    }
+    fs::read_to_string(applied_path(item_id)).ok()
+}
+
+pub fn set_applied(item_id: &str, item_hash: &str) {
+    let path = applied_path(item_id);
+    let _ = fs::create_dir_all(path.parent().unwrap());
+    let _ = fs::write(&path, item_hash);
+}
+
+pub fn clear_applied(item_id: &str) {
+    let _ = fs::remove_file(applied_path(item_id));
+}
+
+// =============================================================================
+// Managed files — destinations dek has copied or linked, so `apply --prune`
+// can find the ones no longer in config
+// =============================================================================
+
+fn managed_dir() -> PathBuf {
+    base_dir().join("managed")
+}
+
+fn managed_path(dest: &Path) -> PathBuf {
+    let hash = format!("{:x}", md5::compute(dest.to_string_lossy().as_bytes()));
+    managed_dir().join(hash)
+}
+
+/// Remember that a `kind` item ("file.copy", "file.symlink") put something at `dest`
+pub fn add_managed(kind: &str, dest: &Path) {
+    let path = managed_path(dest);
+    let _ = fs::create_dir_all(path.parent().unwrap());
+    let _ = fs::write(&path, format!("{}\n{}", kind, dest.display()));
+}
+
+/// Every remembered destination, as (kind, dest)
+pub fn managed() -> Vec<(String, PathBuf)> {
+    dir_entries(&managed_dir(), |_| true)
+        .iter()
+        .filter_map(|p| {
+            let content = fs::read_to_string(p).ok()?;
+            let (kind, dest) = content.split_once('\n')?;
+            Some((kind.to_string(), PathBuf::from(dest)))
+        })
+        .collect()
+}
+
+pub fn remove_managed(dest: &Path) {
+    let _ = fs::remove_file(managed_path(dest));
+}
+
+// =============================================================================
+// Written templates — hash of each template file as apply last left it, so
+// check can vouch for files holding secrets it doesn't fetch
+// =============================================================================
+
+fn written_dir() -> PathBuf {
+    base_dir().join("written")
+}
+
+fn written_path(dest: &Path) -> PathBuf {
+    let hash = format!("{:x}", md5::compute(dest.to_string_lossy().as_bytes()));
+    written_dir().join(hash)
+}
+
+pub fn get_written(dest: &Path) -> Option<String> {
+    if force() {
+        return None;
+    }
+    fs::read_to_string(written_path(dest)).ok()
+}
+
+pub fn set_written(dest: &Path, content_hash: &str) {
+    let path = written_path(dest);
+    let _ = fs::create_dir_all(path.parent().unwrap());
+    let _ = fs::write(&path, content_hash);
+}
+
+// =============================================================================
+// Deploy cache — hash of the config last rsynced to each remote host
+// =============================================================================
+
+/// Where `-C <url>` configs are fetched to, one dir per source
+pub fn remote_config_dir() -> PathBuf {
+    base_dir().join("remote-config")
+}
+
+fn deploy_dir() -> PathBuf {
+    base_dir().join("deploy")
+}
+
+fn deploy_path(host: &str) -> PathBuf {
+    let hash = format!("{:x}", md5::compute(host));
+    deploy_dir().join(hash)
+}
+
+pub fn get_deployed(host: &str) -> Option<String> {
+    if force() {
+        return None;
+    }
+    fs::read_to_string(deploy_path(host)).ok()
+}
+
+pub fn set_deployed(host: &str, config_hash: &str) {
+    let path = deploy_path(host);
+    let _ = fs::create_dir_all(path.parent().unwrap());
+    let _ = fs::write(&path, config_hash);
+}
+
+// =============================================================================
+// dek cache — list and clear everything dek caches locally
+// =============================================================================
+
+/// One kind of cached data and the paths holding it
+struct CacheGroup {
+    label: &'static str,
+    paths: Vec<PathBuf>,
+}
+
+impl CacheGroup {
+    fn size(&self) -> u64 {
+        self.paths.iter().map(|p| path_size(p)).sum()
+    }
+}
+
+fn path_size(path: &Path) -> u64 {
+    if path.is_dir() {
+        crate::dir_size(path)
+    } else {
+        fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+    }
+}
+
+/// Entries directly under `dir` whose file name matches
+fn dir_entries(dir: &Path, matches: impl Fn(&str) -> bool) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+        .map(|rd| {
+            rd.flatten()
+                .filter(|e| matches(&e.file_name().to_string_lossy()))
+                .map(|e| e.path())
+                .collect()
+        })
+        .unwrap_or_default();
+    paths.sort();
+    paths
+}
+
+fn groups() -> Vec<CacheGroup> {
+    let tmp = Path::new("/tmp");
+    vec![
+        CacheGroup { label: "downloads", paths: dir_entries(&cache_dir(), |_| true) },
+        CacheGroup { label: "state", paths: dir_entries(&state_dir(), |_| true) },
+        CacheGroup { label: "applied", paths: dir_entries(&applied_dir(), |_| true) },
+        CacheGroup { label: "managed files", paths: dir_entries(&managed_dir(), |_| true) },
+        CacheGroup { label: "written", paths: dir_entries(&written_dir(), |_| true) },
+        CacheGroup { label: "deployed", paths: dir_entries(&deploy_dir(), |_| true) },
+        CacheGroup {
+            label: "artifact watch",
+            paths: dir_entries(tmp, |n| n.starts_with("dek-watch-") && n.ends_with(".hash")),
+        },
+        CacheGroup {
+            label: "extracted",
+            // /tmp/dek-<md5> from tarball configs
+            paths: dir_entries(tmp, |n| {
+                n.strip_prefix("dek-")
+                    .is_some_and(|h| h.len() == 32 && h.chars().all(|c| c.is_ascii_hexdigit()))
+            }),
+        },
+        CacheGroup {
+            label: "remote configs",
+            paths: dir_entries(&remote_config_dir(), |_| true),
+        },
+    ]
+}
+
+/// Show cached entries with sizes, and remove them all with `clear`
+pub fn run(list: bool, clear: bool) -> Result<()> {
+    let groups = groups();
+
+    if list || !clear {
+        for g in &groups {
+            println!(
+                "  {:<16} {:>8}  {}",
+                c!(g.label, cyan),
+                crate::output::format_bytes(g.size()),
+                c!(format!("{} entries", g.paths.len()), dimmed)
+            );
+            if list {
+                for p in &g.paths {
+                    println!("    {}", c!(p.display(), dimmed));
+                }
+            }
+        }
+    }
+
+    if clear {
+        let mut freed = 0;
+        let mut failed = 0;
+        for p in groups.iter().flat_map(|g| &g.paths) {
+            let size = path_size(p);
+            let res = if p.is_dir() { fs::remove_dir_all(p) } else { fs::remove_file(p) };
+            match res {
+                Ok(()) => freed += size,
+                Err(e) => {
+                    eprintln!("  {} {}: {}", c!("✗", red), p.display(), e);
+                    failed += 1;
+                }
+            }
+        }
+        println!("{} Cleared {}", c!("✓", green), crate::output::format_bytes(freed));
+        if failed > 0 {
+            anyhow::bail!("{} cache entries could not be removed", failed);
+        }
+    }
+    Ok(())
+}
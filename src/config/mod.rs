@@ -2,7 +2,8 @@ mod types;
 
 pub use types::*;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
 
@@ -21,7 +22,11 @@ pub fn load_all<P: AsRef<Path>>(path: P) -> Result<Config> {
     }
 
     if path.is_dir() {
-        load_all_from_dir(path)
+        let local = load_all_from_dir(path)?;
+        match user_config_base(path)? {
+            Some(base) => Ok(overlay(rebased(load_all_from_dir(&base)?, &base)?, local)),
+            None => Ok(local),
+        }
     } else {
         load_file(path)
     }
@@ -31,15 +36,6 @@ pub fn load_all<P: AsRef<Path>>(path: P) -> Result<Config> {
 pub fn load_for_apply<P: AsRef<Path>>(path: P, selectors: &[String], meta: Option<&Meta>) -> Result<Config> {
     let path = path.as_ref();
 
-    // No selectors and no defaults → main dir only (backward compat)
-    let defaults = meta.map(|m| &m.defaults[..]).unwrap_or(&[]);
-    if selectors.is_empty() && defaults.is_empty() {
-        return load(path);
-    }
-
-    // Determine effective selectors
-    let effective: &[String] = if selectors.is_empty() { defaults } else { selectors };
-
     let dir = if crate::util::is_tar_gz(path) {
         crate::util::extract_tar_gz(path)?
     } else if path.is_dir() {
@@ -48,21 +44,164 @@ pub fn load_for_apply<P: AsRef<Path>>(path: P, selectors: &[String], meta: Optio
         return load_file(path);
     };
 
+    // Selectors go to both roots; each falls back to its own defaults
+    let local = load_dir_for_apply(&dir, selectors, meta)?;
+    match user_config_base(&dir)? {
+        Some(base) => {
+            let base_meta = load_meta(&base)?;
+            Ok(overlay(rebased(load_dir_for_apply(&base, selectors, base_meta.as_ref())?, &base)?, local))
+        }
+        None => Ok(local),
+    }
+}
+
+static USER_CONFIG_MERGE: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(true);
+
+/// Turn `merge_user_config` off for this run. Remote (`--prepared`) and
+/// baked runs use it, so a deployed config doesn't pick up whatever is in
+/// the target host's ~/.config/dek.
+pub fn disable_user_config_merge() {
+    USER_CONFIG_MERGE.store(false, std::sync::atomic::Ordering::Relaxed);
+}
+
+/// `merge_user_config`: the user config dir to load underneath `dir`, if
+/// there is one and it isn't `dir` itself
+fn user_config_base(dir: &Path) -> Result<Option<PathBuf>> {
+    if !USER_CONFIG_MERGE.load(std::sync::atomic::Ordering::Relaxed) {
+        return Ok(None);
+    }
+    if !load_meta(dir)?.is_some_and(|m| m.merge_user_config) {
+        return Ok(None);
+    }
+    let Some(base) = user_config_dir() else {
+        return Ok(None);
+    };
+    let same = fs::canonicalize(&base).ok() == fs::canonicalize(dir).ok();
+    Ok((!same).then_some(base))
+}
+
+/// The user config loaded from `dir`, its sources made absolute, since
+/// items get resolved against the local config dir
+fn rebased(mut config: Config, dir: &Path) -> Result<Config> {
+    rebase_sources(&mut config, dir)?;
+    Ok(config)
+}
+
+/// `local` merged over `base`, so local wins
+fn overlay(mut base: Config, local: Config) -> Config {
+    merge_config(&mut base, local);
+    base
+}
+
+fn load_dir_for_apply(dir: &Path, selectors: &[String], meta: Option<&Meta>) -> Result<Config> {
+    let (entries, order) = select_for_apply(dir, selectors, meta)?;
+    load_entries(&entries, &order)
+}
+
+/// Keys of the configs in `dir` that apply with `selectors` loads, in order
+pub fn apply_keys(dir: &Path, selectors: &[String], meta: Option<&Meta>) -> Result<Vec<String>> {
+    Ok(select_for_apply(dir, selectors, meta)?.1)
+}
+
+fn select_for_apply(dir: &Path, selectors: &[String], meta: Option<&Meta>) -> Result<(Vec<ConfigEntry>, Vec<String>)> {
+    // No selectors and no defaults → main dir only (backward compat), plus
+    // whatever those configs require
+    let defaults = meta.map(|m| &m.defaults[..]).unwrap_or(&[]);
+    if selectors.is_empty() && defaults.is_empty() {
+        let mut entries = Vec::new();
+        scan_entries_from_dir(dir, &mut entries)?;
+        let keys: Vec<String> = entries.iter().map(|e| e.key.clone()).collect();
+        // Only scan optional/ when something reaches into it
+        if entries.iter().any(|e| e.requires.iter().any(|r| !keys.contains(r))) {
+            entries = scan_config_entries(dir)?;
+        }
+        let order = order_by_requires(keys, &entries)?;
+        return Ok((entries, order));
+    }
+
+    // Determine effective selectors
+    let effective: &[String] = if selectors.is_empty() { defaults } else { selectors };
+
     // Scan all entries (main + optional/)
-    let entries = scan_config_entries(&dir)?;
+    let entries = scan_config_entries(dir)?;
 
-    // Resolve selectors to keys
+    // Resolve selectors to keys, pull in requirements, dependencies first
     let resolved_keys = resolve_selectors(effective, &entries);
-
-    // Load only resolved keys from all dirs
-    let keys: Vec<String> = resolved_keys.into_iter().collect();
-    load_directory(&dir, Some(&keys))
+    let order = order_by_requires(resolved_keys, &entries)?;
+    Ok((entries, order))
 }
 
 /// Internal entry representing a scanned config file
 struct ConfigEntry {
     key: String,
     labels: Vec<String>,
+    requires: Vec<String>,
+    path: PathBuf,
+}
+
+/// Expand `keys` with everything they (transitively) require and order the
+/// result so each config's requirements come right before it. Otherwise
+/// keeps file order.
+fn order_by_requires(keys: Vec<String>, entries: &[ConfigEntry]) -> Result<Vec<String>> {
+    fn visit(key: &str, entries: &[ConfigEntry], stack: &mut Vec<String>, order: &mut Vec<String>) -> Result<()> {
+        if order.iter().any(|k| k == key) {
+            return Ok(());
+        }
+        if let Some(pos) = stack.iter().position(|k| k == key) {
+            bail!("Cycle detected in config requires: {} -> {}", stack[pos..].join(" -> "), key);
+        }
+        stack.push(key.to_string());
+        for entry in entries.iter().filter(|e| e.key == key) {
+            for req in &entry.requires {
+                if !entries.iter().any(|e| &e.key == req) {
+                    bail!("Config '{}' requires unknown config '{}'", key, req);
+                }
+                visit(req, entries, stack, order)?;
+            }
+        }
+        stack.pop();
+        order.push(key.to_string());
+        Ok(())
+    }
+
+    // Walk selected configs in file order; unknown selectors keep their place at the end
+    let mut walk: Vec<&str> = Vec::new();
+    for e in entries {
+        if keys.contains(&e.key) && !walk.contains(&e.key.as_str()) {
+            walk.push(&e.key);
+        }
+    }
+    for key in &keys {
+        if !walk.contains(&key.as_str()) {
+            walk.push(key);
+        }
+    }
+
+    let mut order = Vec::new();
+    for key in walk {
+        visit(key, entries, &mut Vec::new(), &mut order)?;
+    }
+    Ok(order)
+}
+
+/// Load and merge configs in the given key order, skipping failed run_if
+fn load_entries(entries: &[ConfigEntry], order: &[String]) -> Result<Config> {
+    let mut merged = Config::default();
+    for key in order {
+        for entry in entries.iter().filter(|e| &e.key == key) {
+            let config = load_file(&entry.path)?;
+            if !when_matches(&config.meta.as_ref().and_then(|m| m.when.clone())) {
+                continue;
+            }
+            if let Some(ref run_if) = config.meta.as_ref().and_then(|m| m.run_if.clone()) {
+                if !eval_run_if(run_if) {
+                    continue;
+                }
+            }
+            merge_config(&mut merged, config);
+        }
+    }
+    Ok(merged)
 }
 
 /// Scan main + optional/ dirs for config entries with their labels
@@ -83,10 +222,8 @@ fn scan_entries_from_dir(dir: &Path, entries: &mut Vec<ConfigEntry>) -> Result<(
             continue;
         }
         let config = load_file(&entry.path())?;
-        let labels = config.meta.as_ref()
-            .map(|m| m.labels.clone())
-            .unwrap_or_default();
-        entries.push(ConfigEntry { key, labels });
+        let cm = config.meta.unwrap_or_default();
+        entries.push(ConfigEntry { key, labels: cm.labels, requires: cm.requires, path: entry.path() });
     }
     Ok(())
 }
@@ -141,6 +278,32 @@ fn load_filtered<P: AsRef<Path>>(path: P, filter_keys: Option<&[String]>) -> Res
     }
 }
 
+/// Every config file `load_all` would read (main + optional/, no meta.toml)
+pub fn config_files<P: AsRef<Path>>(path: P) -> Result<Vec<PathBuf>> {
+    let path = path.as_ref();
+
+    let dir = if crate::util::is_tar_gz(path) {
+        crate::util::extract_tar_gz(path)?
+    } else if path.is_dir() {
+        path.to_path_buf()
+    } else {
+        return Ok(vec![path.to_path_buf()]);
+    };
+
+    let mut files = Vec::new();
+    for d in [dir.clone(), dir.join("optional")] {
+        if !d.is_dir() {
+            continue;
+        }
+        for entry in get_config_entries(&d)? {
+            if file_key(&entry.path()) != "meta" {
+                files.push(entry.path());
+            }
+        }
+    }
+    Ok(files)
+}
+
 fn load_all_from_dir(dir: &Path) -> Result<Config> {
     let mut merged = Config::default();
     load_from_dir_skip_run_if(dir, &mut merged)?;
@@ -172,6 +335,16 @@ pub fn list_configs<P: AsRef<Path>>(path: P, meta: Option<&Meta>) -> Result<Vec<
         list_configs_from_dir(&optional_dir, true, meta, &mut configs)?;
     }
 
+    // User config entries the local ones don't shadow
+    if let Some(base) = user_config_base(path)? {
+        let base_meta = load_meta(&base)?;
+        for info in list_configs(&base, base_meta.as_ref())? {
+            if !configs.iter().any(|c| c.key == info.key) {
+                configs.push(info);
+            }
+        }
+    }
+
     Ok(configs)
 }
 
@@ -187,20 +360,405 @@ fn list_configs_from_dir(dir: &Path, optional: bool, meta: Option<&Meta>, config
             .and_then(|m| m.name.clone())
             .unwrap_or_else(|| key.clone());
         let description = cm.and_then(|m| m.description.clone());
-        let run_if = cm.and_then(|m| m.run_if.clone());
         let labels = cm.map(|m| m.labels.clone()).unwrap_or_default();
+        let requires = cm.map(|m| m.requires.clone()).unwrap_or_default();
         let is_default = compute_is_default(&key, &labels, optional, meta);
-        configs.push(ConfigInfo { key, name, description, labels, optional, is_default, run_if });
+        configs.push(ConfigInfo { key, name, description, labels, optional, is_default, requires });
     }
     Ok(())
 }
 
 fn load_file(path: &Path) -> Result<Config> {
+    load_file_with_imports(path, &mut Vec::new())
+}
+
+/// Parse a config file and merge its imports underneath it, so the file's
+/// own values win. `stack` holds the files currently being imported.
+fn load_file_with_imports(path: &Path, stack: &mut Vec<PathBuf>) -> Result<Config> {
+    let mut config = parse_file(path)?;
+    if config.import.is_empty() {
+        return Ok(config);
+    }
+
+    let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    if let Some(pos) = stack.iter().position(|p| p == &canonical) {
+        let chain: Vec<String> = stack[pos..].iter().chain([&canonical])
+            .map(|p| p.display().to_string())
+            .collect();
+        bail!("Import cycle: {}", chain.join(" -> "));
+    }
+    stack.push(canonical);
+
+    let mut merged = Config::default();
+    for import in std::mem::take(&mut config.import) {
+        let import_path = resolve_import(path, &import);
+        let mut imported = load_file_with_imports(&import_path, stack)
+            .with_context(|| format!("Failed to import '{}' from {}", import, path.display()))?;
+        // Its sources are relative to it, not to the config dir items resolve against
+        rebase_sources(&mut imported, import_path.parent().unwrap_or(Path::new(".")))?;
+        merge_config(&mut merged, imported);
+    }
+    stack.pop();
+
+    // Imported files only contribute items; this file's meta stays its own
+    let meta = config.meta.take();
+    merge_config(&mut merged, config);
+    merged.meta = meta;
+    Ok(merged)
+}
+
+/// A config file that parses but sets a value dek can't use. `dek validate`
+/// reports these against the file itself.
+#[derive(Debug)]
+pub struct InvalidConfig(pub PathBuf);
+
+impl std::fmt::Display for InvalidConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed to parse config file: {}", self.0.display())
+    }
+}
+
+fn parse_file(path: &Path) -> Result<Config> {
+    let config = parse_file_vars(path)?;
+    check_durations(&config)
+        .and_then(|_| check_file_lines(&config))
+        .context(InvalidConfig(path.to_path_buf()))?;
+    Ok(config)
+}
+
+fn parse_file_vars(path: &Path) -> Result<Config> {
     let content = fs::read_to_string(path)
         .with_context(|| format!("Failed to read config file: {}", path.display()))?;
     let config: Config = toml::from_str(&content)
         .with_context(|| format!("Failed to parse config file: {}", path.display()))?;
-    Ok(config)
+    let Some(ref vars) = config.vars else {
+        return Ok(config);
+    };
+
+    // Substitute the file's [vars] into every other string (and table key),
+    // then read it again
+    let vars = resolve_config_vars(vars).with_context(|| format!("Invalid [vars] in {}", path.display()))?;
+    let mut table: toml::Table = toml::from_str(&content)?;
+    table.remove("vars");
+    let mut value = toml::Value::Table(table);
+    interpolate_vars(&mut value, &vars);
+    value
+        .try_into()
+        .with_context(|| format!("Failed to parse config file: {}", path.display()))
+}
+
+/// Every duration the config sets, as (item, field, value)
+pub fn durations(config: &Config) -> Vec<(String, &'static str, &str)> {
+    let mut durations: Vec<(String, &'static str, &str)> = config
+        .command
+        .iter()
+        .filter_map(|c| Some((format!("command '{}'", c.name), "timeout", c.timeout.as_deref()?)))
+        .collect();
+    if let Some(ref pkg) = config.package {
+        let lists = [
+            ("os", pkg.os.as_ref().and_then(|l| l.timeout.as_deref())),
+            ("apt", pkg.apt.as_ref().and_then(|l| l.timeout.as_deref())),
+            ("pacman", pkg.pacman.as_ref().and_then(|l| l.timeout.as_deref())),
+            ("brew", pkg.brew.as_ref().and_then(|l| l.timeout.as_deref())),
+            ("cargo", pkg.cargo.as_ref().and_then(|l| l.timeout.as_deref())),
+            ("go", pkg.go.as_ref().and_then(|l| l.timeout.as_deref())),
+            ("npm", pkg.npm.as_ref().and_then(|l| l.timeout.as_deref())),
+            ("pip", pkg.pip.as_ref().and_then(|l| l.timeout.as_deref())),
+            ("pipx", pkg.pipx.as_ref().and_then(|l| l.timeout.as_deref())),
+            ("webi", pkg.webi.as_ref().and_then(|l| l.timeout.as_deref())),
+        ];
+        for (pm, timeout) in lists {
+            if let Some(timeout) = timeout {
+                durations.push((format!("package.{}", pm), "timeout", timeout));
+            }
+        }
+    }
+    for cmd in &config.command {
+        durations.extend(cmd.check_ttl.as_deref().map(|t| (format!("command '{}'", cmd.name), "check_ttl", t)));
+    }
+    for assertion in &config.assert {
+        let label = assertion.name.as_deref().or(assertion.url.as_deref()).unwrap_or_default();
+        durations.extend(assertion.check_ttl.as_deref().map(|t| (format!("assert '{}'", label), "check_ttl", t)));
+    }
+    for (url, target) in config.file.iter().flat_map(|f| f.fetch.iter().flatten()) {
+        durations.extend(target.check_ttl().map(|t| (format!("file.fetch '{}'", url), "check_ttl", t)));
+    }
+    durations
+}
+
+/// A duration that doesn't parse is an error, not a missing limit
+fn check_durations(config: &Config) -> Result<()> {
+    for (item, field, value) in durations(config) {
+        if let Err(e) = crate::util::parse_duration(value) {
+            bail!("{}: invalid {} '{}': {}", item, field, value, e);
+        }
+    }
+    Ok(())
+}
+
+/// A file.line entry that places a line has to say which. An empty one
+/// would match every file and never be written.
+fn check_file_lines(config: &Config) -> Result<()> {
+    let files = config.file.iter().map(|f| ("file".to_string(), f));
+    let run_files = config
+        .run
+        .iter()
+        .flatten()
+        .filter_map(|(name, run)| Some((format!("run.{}.file", name), run.file.as_ref()?)));
+    for (prefix, file) in files.chain(run_files) {
+        for entry in &file.line {
+            if !matches!(entry.mode, FileLineMode::Delete) && entry.line.is_empty() {
+                bail!("{}.line for '{}': missing line", prefix, entry.path);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `[vars]` values with references to each other expanded, in any order
+fn resolve_config_vars(table: &toml::Table) -> Result<HashMap<String, String>> {
+    let mut raw = HashMap::new();
+    for (name, value) in table {
+        let value = match value {
+            toml::Value::String(s) => s.clone(),
+            toml::Value::Integer(_) | toml::Value::Float(_) | toml::Value::Boolean(_) => value.to_string(),
+            _ => bail!("vars.{}: must be a string, number or boolean", name),
+        };
+        raw.insert(name.clone(), value);
+    }
+    let mut resolved = HashMap::new();
+    for name in raw.keys() {
+        resolve_config_var(name, &raw, &mut resolved, &mut Vec::new())?;
+    }
+    Ok(resolved)
+}
+
+fn resolve_config_var(
+    name: &str,
+    raw: &HashMap<String, String>,
+    resolved: &mut HashMap<String, String>,
+    stack: &mut Vec<String>,
+) -> Result<String> {
+    if let Some(value) = resolved.get(name) {
+        return Ok(value.clone());
+    }
+    if stack.iter().any(|n| n == name) {
+        bail!("vars cycle: {} -> {}", stack.join(" -> "), name);
+    }
+    stack.push(name.to_string());
+    let mut error = None;
+    let value = crate::util::expand_with(&raw[name], |n| {
+        if !raw.contains_key(n) || error.is_some() {
+            return None;
+        }
+        resolve_config_var(n, raw, resolved, stack).map_err(|e| error = Some(e)).ok()
+    });
+    stack.pop();
+    if let Some(e) = error {
+        return Err(e);
+    }
+    resolved.insert(name.to_string(), value.clone());
+    Ok(value)
+}
+
+fn interpolate_vars(value: &mut toml::Value, vars: &HashMap<String, String>) {
+    let expand = |s: &str| crate::util::expand_with(s, |n| vars.get(n).cloned());
+    match value {
+        toml::Value::String(s) => *s = expand(s),
+        toml::Value::Array(items) => items.iter_mut().for_each(|v| interpolate_vars(v, vars)),
+        toml::Value::Table(table) => {
+            *table = std::mem::take(table)
+                .into_iter()
+                .map(|(k, mut v)| {
+                    interpolate_vars(&mut v, vars);
+                    (expand(&k), v)
+                })
+                .collect();
+        }
+        _ => {}
+    }
+}
+
+/// Resolve an `import` entry against the directory of the file declaring it
+pub fn resolve_import(file: &Path, import: &str) -> PathBuf {
+    let dir = file.parent().unwrap_or(Path::new("."));
+    dir.join(crate::util::expand_path(import))
+}
+
+/// Sources that aren't absolute, `~` or `$VAR` paths are relative to the
+/// config they're in
+fn is_relative_source(src: &str) -> bool {
+    !src.starts_with(['/', '~', '$'])
+}
+
+/// Apply `f` to each relative source path in `config`: copy and symlink
+/// sources, templates and their vars files, scripts, pip requirements and
+/// cargo `--path`s
+fn map_sources(config: &mut Config, f: &mut dyn FnMut(&str) -> Result<String>) -> Result<()> {
+    let mut map = |src: &mut String| -> Result<()> {
+        if is_relative_source(src) {
+            *src = f(src)?;
+        }
+        Ok(())
+    };
+    if let Some(ref mut file) = config.file {
+        for paths in [&mut file.copy, &mut file.symlink].into_iter().flatten() {
+            // Sorted, so vendored file names come out the same every time
+            let mut entries: Vec<_> = std::mem::take(paths).into_iter().collect();
+            entries.sort();
+            for (mut src, dst) in entries {
+                map(&mut src)?;
+                paths.insert(src, dst);
+            }
+        }
+        for tmpl in &mut file.template {
+            map(&mut tmpl.src)?;
+            tmpl.vars.iter_mut().try_for_each(&mut map)?;
+        }
+        file.vars.iter_mut().try_for_each(&mut map)?;
+    }
+    if let Some(ref mut scripts) = config.script {
+        scripts.values_mut().try_for_each(&mut map)?;
+    }
+    if let Some(ref mut package) = config.package {
+        if let Some(ref mut pip) = package.pip {
+            pip.requirements.iter_mut().try_for_each(&mut map)?;
+        }
+        if let Some(ref mut cargo) = package.cargo {
+            for spec in cargo.items.iter_mut().filter(|s| s.split_whitespace().any(|w| w == "--path")) {
+                let mut words: Vec<String> = spec.split_whitespace().map(str::to_string).collect();
+                for i in 1..words.len() {
+                    if words[i - 1] == "--path" {
+                        map(&mut words[i])?;
+                    }
+                }
+                *spec = words.join(" ");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Make the relative sources of a config loaded from `dir` absolute, for
+/// when its items get resolved against some other config dir
+fn rebase_sources(config: &mut Config, dir: &Path) -> Result<()> {
+    let dir = fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+    map_sources(config, &mut |src| Ok(dir.join(src.trim_start_matches("./")).to_string_lossy().to_string()))
+}
+
+/// Directory a config path is rooted at (the dir itself, or a file's parent)
+fn config_root(path: &Path) -> PathBuf {
+    let dir = if path.is_dir() { path } else { path.parent().unwrap_or(Path::new(".")) };
+    fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf())
+}
+
+/// Whether any config file imports a file from outside the config dir
+pub fn has_external_imports(path: &Path) -> Result<bool> {
+    let root = config_root(path);
+    for file in config_files(path)? {
+        for import in parse_file(&file)?.import {
+            let target = resolve_import(&file, &import);
+            if !fs::canonicalize(&target).unwrap_or(target).starts_with(&root) {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Copy imports from outside the config dir into `dest/.imports/` and point
+/// the copied config files at them, so a prepared config is self-contained
+pub fn vendor_imports(path: &Path, dest: &Path) -> Result<()> {
+    let root = config_root(path);
+    let mut vendored = HashMap::new();
+    for file in config_files(path)? {
+        let file = fs::canonicalize(&file)?;
+        let imports = parse_file(&file)?.import;
+        let rel_dir = file.parent().and_then(|d| d.strip_prefix(&root).ok()).unwrap_or(Path::new(""));
+        let up = "../".repeat(rel_dir.components().count());
+
+        let mut changed = false;
+        let mut rewritten = Vec::new();
+        for import in imports {
+            let target = resolve_import(&file, &import);
+            let target = fs::canonicalize(&target)
+                .with_context(|| format!("Failed to import '{}' from {}", import, file.display()))?;
+            if target.starts_with(&root) {
+                rewritten.push(import);
+            } else {
+                let name = vendor_import(&target, dest, &mut vendored)?;
+                rewritten.push(format!("{}.imports/{}", up, name));
+                changed = true;
+            }
+        }
+        if changed {
+            let rel = file.strip_prefix(&root).unwrap_or(&file);
+            write_with_imports(&file, &dest.join(rel), &rewritten)?;
+        }
+    }
+    Ok(())
+}
+
+/// Copy one imported file (and everything it imports) into `dest/.imports/`,
+/// along with the files it reads from, which go in `<name>.files/`
+fn vendor_import(src: &Path, dest: &Path, vendored: &mut HashMap<PathBuf, String>) -> Result<String> {
+    if let Some(name) = vendored.get(src) {
+        return Ok(name.clone());
+    }
+    let file_name = src.file_name().unwrap_or_default().to_string_lossy();
+    let name = format!("{}-{}", vendored.len(), file_name);
+    vendored.insert(src.to_path_buf(), name.clone());
+
+    let mut config = parse_file(src)?;
+    let mut rewritten = Vec::new();
+    for import in std::mem::take(&mut config.import) {
+        let target = resolve_import(src, &import);
+        let target = fs::canonicalize(&target)
+            .with_context(|| format!("Failed to import '{}' from {}", import, src.display()))?;
+        rewritten.push(vendor_import(&target, dest, vendored)?);
+    }
+    config.import = rewritten;
+
+    let dir = dest.join(".imports");
+    let src_dir = src.parent().unwrap_or(Path::new("."));
+    let files = format!("{}.files", name);
+    let mut copied: HashMap<String, String> = HashMap::new();
+    map_sources(&mut config, &mut |source| {
+        if let Some(to) = copied.get(source) {
+            return Ok(to.clone());
+        }
+        let from = src_dir.join(source);
+        let base = from.file_name().unwrap_or_default().to_string_lossy();
+        let to = format!("{}/{}-{}", files, copied.len(), base);
+        if from.is_dir() {
+            crate::copy_dir_recursive(&from, &dir.join(&to))?;
+        } else {
+            fs::create_dir_all(dir.join(&files))?;
+            fs::copy(&from, dir.join(&to))
+                .with_context(|| format!("Failed to vendor '{}' from {}", source, src.display()))?;
+        }
+        copied.insert(source.to_string(), to.clone());
+        Ok(to)
+    })?;
+
+    let mut value = serde_json::to_value(&config)?;
+    crate::drop_unset(&mut value);
+    fs::create_dir_all(&dir)?;
+    fs::write(dir.join(&name), toml::to_string(&toml::Value::try_from(&value)?)?)?;
+    Ok(name)
+}
+
+/// Write `src` to `dst` with its `import` list replaced
+fn write_with_imports(src: &Path, dst: &Path, imports: &[String]) -> Result<()> {
+    if imports.is_empty() {
+        fs::copy(src, dst)?;
+        return Ok(());
+    }
+    let mut table: toml::Table = fs::read_to_string(src)?.parse()?;
+    let list = imports.iter().map(|i| toml::Value::String(i.clone())).collect();
+    table.insert("import".into(), toml::Value::Array(list));
+    fs::write(dst, toml::to_string(&table)?)?;
+    Ok(())
 }
 
 fn load_directory(dir: &Path, filter_keys: Option<&[String]>) -> Result<Config> {
@@ -243,8 +801,11 @@ fn load_from_dir_inner(dir: &Path, filter_keys: Option<&[String]>, merged: &mut
 
         let config = load_file(&entry.path())?;
 
-        // Skip config if run_if condition fails
+        // Skip config if when/run_if condition fails
         if eval_conditions {
+            if !when_matches(&config.meta.as_ref().and_then(|m| m.when.clone())) {
+                continue;
+            }
             if let Some(ref run_if) = config.meta.as_ref().and_then(|m| m.run_if.clone()) {
                 if !eval_run_if(run_if) {
                     continue;
@@ -282,7 +843,7 @@ fn get_config_entries(dir: &Path) -> Result<Vec<fs::DirEntry>> {
 }
 
 /// Extract key from filename: "10-tools.toml" -> "tools", "tools.dek.toml" -> "tools"
-fn file_key(path: &Path) -> String {
+pub fn file_key(path: &Path) -> String {
     let stem = path.file_stem().unwrap_or_default().to_string_lossy();
     // Strip .dek suffix (for *.dek.toml files used with nvim-dek)
     let stem = stem.strip_suffix(".dek").unwrap_or(&stem);
@@ -296,6 +857,22 @@ fn file_key(path: &Path) -> String {
 }
 
 fn merge_config(base: &mut Config, other: Config) {
+    // The AUR helper and cargo strategy are run-wide: later config wins
+    if let Some(helper) = other.meta.as_ref().and_then(|m| m.aur_helper) {
+        base.meta.get_or_insert_with(ConfigMeta::default).aur_helper = Some(helper);
+    }
+    if let Some(prefer) = other.meta.as_ref().and_then(|m| m.cargo_prefer) {
+        base.meta.get_or_insert_with(ConfigMeta::default).cargo_prefer = Some(prefer);
+    }
+    // Script pins add up; requiring them anywhere requires them for the run
+    if let Some(ref meta) = other.meta {
+        if !meta.install_script_sha256.is_empty() || meta.pinned_install_scripts {
+            let base_meta = base.meta.get_or_insert_with(ConfigMeta::default);
+            base_meta.install_script_sha256.extend(meta.install_script_sha256.clone());
+            base_meta.pinned_install_scripts |= meta.pinned_install_scripts;
+        }
+    }
+
     // Merge proxy (later config wins for each field)
     if let Some(proxy) = other.proxy {
         let base_proxy = base.proxy.get_or_insert_with(ProxyConfig::default);
@@ -313,17 +890,35 @@ fn merge_config(base: &mut Config, other: Config) {
         }
     }
 
-    // Merge packages
+    // Merge packages (lists from several files become one, so `when` is
+    // settled here rather than at collect time)
     if let Some(pkg) = other.package {
         let base_pkg = base.package.get_or_insert_with(PackageConfig::default);
         merge_package_list(&mut base_pkg.os, pkg.os);
         merge_package_list(&mut base_pkg.apt, pkg.apt);
         merge_package_list(&mut base_pkg.pacman, pkg.pacman);
+        if let Some(brew) = pkg.brew.filter(|b| when_matches(&b.when)) {
+            let base_brew = base_pkg.brew.get_or_insert_with(BrewPackageList::default);
+            base_brew.timeout = brew.timeout.or(base_brew.timeout.take());
+            base_brew.items.extend(brew.items);
+            base_brew.cask.extend(brew.cask);
+        }
         merge_package_list(&mut base_pkg.cargo, pkg.cargo);
         merge_package_list(&mut base_pkg.go, pkg.go);
         merge_package_list(&mut base_pkg.npm, pkg.npm);
-        merge_package_list(&mut base_pkg.pip, pkg.pip);
-        merge_package_list(&mut base_pkg.pipx, pkg.pipx);
+        if let Some(pip) = pkg.pip.filter(|p| when_matches(&p.when)) {
+            let base_pip = base_pkg.pip.get_or_insert_with(PipPackageList::default);
+            base_pip.timeout = pip.timeout.or(base_pip.timeout.take());
+            base_pip.items.extend(pip.items);
+            base_pip.requirements.extend(pip.requirements);
+        }
+        if let Some(pipx) = pkg.pipx.filter(|p| when_matches(&p.when)) {
+            let base_pipx = base_pkg.pipx.get_or_insert_with(PipxPackageList::default);
+            base_pipx.timeout = pipx.timeout.or(base_pipx.timeout.take());
+            base_pipx.items.extend(pipx.items);
+            base_pipx.include_deps |= pipx.include_deps;
+            base_pipx.inject.extend(pipx.inject);
+        }
         merge_package_list(&mut base_pkg.webi, pkg.webi);
     }
 
@@ -342,6 +937,9 @@ fn merge_config(base: &mut Config, other: Config) {
         if let Some(ensure_line) = file.ensure_line {
             base_file.ensure_line.get_or_insert_with(Default::default).extend(ensure_line);
         }
+        if let Some(ensure_absent) = file.ensure_absent {
+            base_file.ensure_absent.get_or_insert_with(Default::default).extend(ensure_absent);
+        }
         base_file.line.extend(file.line);
         base_file.template.extend(file.template);
         base_file.vars.extend(file.vars);
@@ -357,6 +955,26 @@ fn merge_config(base: &mut Config, other: Config) {
         base.env.get_or_insert_with(Default::default).extend(env);
     }
 
+    // Merge users
+    base.user.extend(other.user);
+    base.apt_repo.extend(other.apt_repo);
+    base.pacman_repo.extend(other.pacman_repo);
+
+    // Merge cron jobs
+    base.cron.extend(other.cron);
+
+    // Merge sysctl (later config wins per key)
+    if let Some(sysctl) = other.sysctl {
+        merge_table(base.sysctl.get_or_insert_with(Default::default), sysctl);
+    }
+
+    // Merge PATH entries
+    if let Some(path) = other.path {
+        let base_path = base.path.get_or_insert_with(PathConfig::default);
+        base_path.prepend.extend(path.prepend);
+        base_path.append.extend(path.append);
+    }
+
     // Override scalars
     if other.timezone.is_some() {
         base.timezone = other.timezone;
@@ -393,14 +1011,30 @@ fn merge_config(base: &mut Config, other: Config) {
     base.state.extend(other.state);
 }
 
+/// Merge `other` into `base` key by key, into nested tables too: dotted keys
+/// (`vm.swappiness`) nest, and a later `vm.*` mustn't drop the earlier ones
+fn merge_table(base: &mut toml::Table, other: toml::Table) {
+    for (key, value) in other {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(other)) => merge_table(base, other),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
 fn merge_package_list(base: &mut Option<PackageList>, other: Option<PackageList>) {
-    if let Some(other_list) = other {
-        base.get_or_insert_with(|| PackageList {
+    if let Some(other_list) = other.filter(|l| when_matches(&l.when)) {
+        let base_list = base.get_or_insert_with(|| PackageList {
             items: vec![],
             run_if: None,
-        })
-        .items
-        .extend(other_list.items);
+            when: None,
+            timeout: None,
+        });
+        // The list's timeout covers every install in it; a later file's wins
+        base_list.timeout = other_list.timeout.or(base_list.timeout.take());
+        base_list.items.extend(other_list.items);
     }
 }
 
@@ -431,8 +1065,7 @@ pub fn apply_vars(vars: &toml::Value, selectors: &[String]) {
 
     // Collect labels from selectors (strip @ prefix) and config keys
     let active_labels: Vec<&str> = selectors.iter()
-        .filter(|s| s.starts_with('@'))
-        .map(|s| &s[1..])
+        .filter_map(|s| s.strip_prefix('@'))
         .collect();
     let active_keys: Vec<&str> = selectors.iter()
         .filter(|s| !s.starts_with('@'))
@@ -452,8 +1085,8 @@ pub fn apply_vars(vars: &toml::Value, selectors: &[String]) {
         if !val.is_table() {
             continue;
         }
-        let matches = if key.starts_with('@') {
-            active_labels.contains(&&key[1..])
+        let matches = if let Some(label) = key.strip_prefix('@') {
+            active_labels.contains(&label)
         } else {
             active_keys.contains(&key.as_str())
         };
@@ -492,35 +1125,93 @@ pub fn find_default_config() -> Option<std::path::PathBuf> {
         return Some(dir.to_path_buf());
     }
 
-    // User config: $XDG_CONFIG_HOME/dek or ~/.config/dek
+    if let Some(found) = find_in_parents() {
+        return Some(found);
+    }
+
+    user_config_dir()
+}
+
+/// User config: $XDG_CONFIG_HOME/dek or ~/.config/dek, when it exists
+pub fn user_config_dir() -> Option<PathBuf> {
     let config_home = std::env::var("XDG_CONFIG_HOME")
         .ok()
         .filter(|s| !s.is_empty())
         .map(PathBuf::from)
-        .or_else(|| std::env::var("HOME").ok().map(|h| PathBuf::from(h).join(".config")));
+        .or_else(|| std::env::var("HOME").ok().map(|h| PathBuf::from(h).join(".config")))?;
+    let global = config_home.join("dek");
+    global.is_dir().then_some(global)
+}
 
-    if let Some(config_dir) = config_home {
-        let global = config_dir.join("dek");
-        if global.is_dir() {
-            return Some(global);
+/// Nearest parent of the working directory with a dek.toml or a dek/ config
+/// dir, like git looks for .git. Only searched under $HOME, up to $HOME
+/// itself, and only configs owned by the current user count: a parent like
+/// /tmp or a shared project dir could hold someone else's.
+fn find_in_parents() -> Option<PathBuf> {
+    let cwd = std::env::current_dir().ok()?;
+    let home = std::env::var("HOME").ok().filter(|h| !h.is_empty()).map(PathBuf::from).filter(|h| cwd.starts_with(h))?;
+    for dir in cwd.ancestors().skip(1) {
+        if !dir.starts_with(&home) {
+            break;
+        }
+        let file = dir.join("dek.toml");
+        if file.is_file() && crate::util::owned_by_current_user(&file) {
+            return Some(file);
+        }
+        let config_dir = dir.join("dek");
+        if looks_like_config_dir(&config_dir) && crate::util::owned_by_current_user(&config_dir) {
+            return Some(config_dir);
         }
     }
-
     None
 }
 
-/// Load meta.toml from config path (file's parent dir or directory itself)
-pub fn load_meta<P: AsRef<Path>>(config_path: P) -> Option<Meta> {
+/// A `dek/` found in a parent has to hold config files, and not be a
+/// checkout of some project that happens to be named dek
+fn looks_like_config_dir(dir: &Path) -> bool {
+    if !dir.is_dir() || dir.join("Cargo.toml").exists() {
+        return false;
+    }
+    get_config_entries(dir).is_ok_and(|entries| !entries.is_empty())
+}
+
+/// A config file inside a dek directory (`dek/30-servers.toml`,
+/// `dek/optional/gpu.toml`): the directory and the file's key, so the file can
+/// be applied as a selector with the directory's meta.toml, vars and requires.
+/// None for standalone files.
+pub fn config_file_in_dir<P: AsRef<Path>>(path: P) -> Option<(PathBuf, String)> {
+    let path = fs::canonicalize(path.as_ref()).ok()?;
+    if !path.is_file() || path.extension()? != "toml" {
+        return None;
+    }
+    let key = file_key(&path);
+    if key == "meta" {
+        return None;
+    }
+    let parent = path.parent()?;
+    if parent.file_name()? == "optional" {
+        return Some((parent.parent()?.to_path_buf(), key));
+    }
+    parent.join("meta.toml").is_file().then(|| (parent.to_path_buf(), key))
+}
+
+/// Load meta.toml from config path (file's parent dir or directory itself).
+/// None without one; a meta.toml that doesn't parse is an error.
+pub fn load_meta<P: AsRef<Path>>(config_path: P) -> Result<Option<Meta>> {
     let path = config_path.as_ref();
-    let dir = if path.is_dir() { path } else { path.parent()? };
+    let Some(dir) = (if path.is_dir() { Some(path) } else { path.parent() }) else {
+        return Ok(None);
+    };
     let meta_path = dir.join("meta.toml");
 
     if !meta_path.exists() {
-        return None;
+        return Ok(None);
     }
 
-    let content = fs::read_to_string(&meta_path).ok()?;
-    let mut meta: Meta = toml::from_str(&content).ok()?;
+    let content = fs::read_to_string(&meta_path)
+        .with_context(|| format!("Failed to read {}", meta_path.display()))?;
+    let mut meta: Meta = toml::from_str(&content)
+        .with_context(|| format!("Failed to parse {}", meta_path.display()))?;
 
     // Load banner from banner.txt if present
     let banner_path = dir.join("banner.txt");
@@ -530,17 +1221,19 @@ pub fn load_meta<P: AsRef<Path>>(config_path: P) -> Option<Meta> {
         }
     }
 
-    Some(meta)
+    Ok(Some(meta))
 }
 
 /// Load inventory from config path
 /// Checks meta.toml for custom inventory path, falls back to inventory.ini in config dir
-pub fn load_inventory<P: AsRef<Path>>(config_path: P) -> Option<Inventory> {
+pub fn load_inventory<P: AsRef<Path>>(config_path: P) -> Result<Option<Inventory>> {
     let path = config_path.as_ref();
-    let dir = if path.is_dir() { path } else { path.parent()? };
+    let Some(dir) = (if path.is_dir() { Some(path) } else { path.parent() }) else {
+        return Ok(None);
+    };
 
     // Check meta.toml for custom inventory path
-    let inventory_path = if let Some(meta) = load_meta(path) {
+    let inventory_path = if let Some(meta) = load_meta(path)? {
         if let Some(ref custom) = meta.inventory {
             let p = Path::new(custom);
             if p.is_absolute() {
@@ -556,22 +1249,33 @@ pub fn load_inventory<P: AsRef<Path>>(config_path: P) -> Option<Inventory> {
     };
 
     if !inventory_path.exists() {
-        return None;
+        return Ok(None);
     }
 
-    let content = fs::read_to_string(&inventory_path).ok()?;
-    Some(parse_inventory_ini(&content))
+    let content = fs::read_to_string(&inventory_path)
+        .with_context(|| format!("Failed to read inventory: {}", inventory_path.display()))?;
+    Ok(Some(parse_inventory_ini(&content)))
 }
 
 /// Parse ansible-style inventory.ini
-/// Ignores [group] headers, comments (;/#), and blank lines
+/// Hosts under a [group] header are also recorded in that group.
+/// Ignores comments (;/#) and blank lines.
 fn parse_inventory_ini(content: &str) -> Inventory {
-    let hosts: Vec<String> = content
-        .lines()
-        .map(|l| l.trim())
-        .filter(|l| !l.is_empty())
-        .filter(|l| !l.starts_with('[') && !l.starts_with(';') && !l.starts_with('#'))
-        .map(|l| l.to_string())
-        .collect();
-    Inventory { hosts }
+    let mut inventory = Inventory::default();
+    let mut group: Option<String> = None;
+    for line in content.lines().map(|l| l.trim()) {
+        if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            inventory.groups.entry(name.to_string()).or_default();
+            group = Some(name.to_string());
+            continue;
+        }
+        inventory.hosts.push(line.to_string());
+        if let Some(ref g) = group {
+            inventory.groups.entry(g.clone()).or_default().push(line.to_string());
+        }
+    }
+    inventory
 }
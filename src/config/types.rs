@@ -1,8 +1,8 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 /// Metadata for baked binaries (loaded from meta.toml)
-#[derive(Debug, Deserialize, Default, Clone)]
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
 #[serde(default)]
 pub struct Meta {
     /// Name shown in help/banner (defaults to binary name)
@@ -28,6 +28,9 @@ pub struct Meta {
     pub remote_install: bool,
     /// Custom binary name for remote_install symlink (defaults to "dek")
     pub bin_name: Option<String>,
+    /// Remote deploy settings
+    #[serde(default)]
+    pub remote: Option<RemoteConfig>,
     /// Sections to hide from the welcome screen: "usage", "commands", "options", "configs", "run", "powered", "powered_url"
     #[serde(default)]
     pub hide: Vec<String>,
@@ -39,15 +42,35 @@ pub struct Meta {
     /// selector (@label or config key).
     #[serde(default)]
     pub vars: Option<toml::Value>,
+    /// Where `secret.<name>` in file templates comes from
+    pub secrets: Option<SecretsConfig>,
+    /// Load the user config ($XDG_CONFIG_HOME/dek) underneath this one, so
+    /// this one overrides it
+    #[serde(default)]
+    pub merge_user_config: bool,
+}
+
+/// `[secrets]` — resolved on first use, never written to disk by dek
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
+#[serde(default)]
+pub struct SecretsConfig {
+    /// Preset command: "pass", "1password" (`op read`), or "vault"
+    pub backend: Option<String>,
+    /// Shell command printing the secret; `{{key}}` becomes the quoted key
+    pub command: Option<String>,
+    /// KEY=value file checked before the command (relative to the config dir)
+    pub env_file: Option<String>,
+    /// Backend key per secret name; unlisted names are looked up as-is
+    pub keys: HashMap<String, String>,
 }
 
-#[derive(Debug, Deserialize, Clone, Default)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
 pub struct WelcomeEntry {
     pub name: String,
     pub description: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Default, Clone)]
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
 #[serde(default)]
 pub struct TestConfig {
     pub image: Option<String>,
@@ -55,13 +78,55 @@ pub struct TestConfig {
     /// Volume mounts for test container (e.g. "/host/path:/container/path")
     #[serde(default)]
     pub mount: Vec<String>,
+    /// Container CLI for test/exec ("docker" or "podman"; default: auto-detect)
+    pub engine: Option<String>,
+    /// Images for `dek test --all` (one container each)
+    #[serde(default)]
+    pub images: Vec<String>,
+    /// Environment variables for the test container and its `dek apply`
+    #[serde(default)]
+    pub env: HashMap<String, String>,
 }
 
-#[derive(Debug, Deserialize, Default, Clone)]
+/// Remote deploy settings ([remote] in meta.toml)
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
+#[serde(default)]
+pub struct RemoteConfig {
+    /// Patterns excluded from the config rsync (passed as --exclude)
+    pub rsync_exclude: Vec<String>,
+    /// Jump/bastion host for all ssh connections (ssh -J)
+    pub bastion: Option<String>,
+    /// Unknown host keys: "accept-new" (default), "off" or "strict"
+    pub host_key_checking: crate::ssh::HostKeyChecking,
+    /// dek binaries for hosts of another platform, by `<os>-<arch>` (a bare
+    /// arch is Linux), relative to the config dir
+    pub binaries: std::collections::BTreeMap<String, String>,
+    /// Per-group defaults for remote runs ([remote.groups.<name>])
+    pub groups: std::collections::BTreeMap<String, RemoteGroup>,
+}
+
+/// Hosts of a group: those under `[<name>]` in inventory.ini, plus any
+/// matching `hosts`
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
+#[serde(default)]
+pub struct RemoteGroup {
+    /// Config selectors for the group's hosts when none are given on the command line
+    pub configs: Vec<String>,
+    /// Host globs (`logger*`) that also belong to the group
+    pub hosts: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
 #[serde(default)]
 pub struct Config {
     /// Per-file metadata (name, description)
     pub meta: Option<ConfigMeta>,
+    /// Values substituted for `$name` / `${name}` in this file's strings
+    /// (not put in the environment). Resolved while the file loads.
+    pub vars: Option<toml::Table>,
+    /// Other config files merged in before this one (relative to this file)
+    #[serde(default)]
+    pub import: Vec<String>,
     /// Proxy settings (applied to current process for all commands)
     pub proxy: Option<ProxyConfig>,
     pub package: Option<PackageConfig>,
@@ -70,9 +135,25 @@ pub struct Config {
     pub file: Option<FileConfig>,
     #[serde(rename = "alias")]
     pub aliases: Option<HashMap<String, String>>,
-    pub env: Option<HashMap<String, String>>,
+    pub env: Option<HashMap<String, EnvValue>>,
+    /// Directories added to PATH persistently (via the env file)
+    pub path: Option<PathConfig>,
     pub timezone: Option<String>,
     pub hostname: Option<String>,
+    /// Users to create (and keep in their groups)
+    #[serde(default)]
+    pub user: Vec<UserConfig>,
+    /// Third-party apt repositories (sources file + signing key)
+    #[serde(default)]
+    pub apt_repo: Vec<AptRepoConfig>,
+    /// Extra pacman repositories (sections in /etc/pacman.conf)
+    #[serde(default)]
+    pub pacman_repo: Vec<PacmanRepoConfig>,
+    /// Scheduled jobs (crontab, or systemd user timers)
+    #[serde(default)]
+    pub cron: Vec<CronConfig>,
+    /// Kernel parameters (`"vm.swappiness" = 10`; dotted keys nest, which works too)
+    pub sysctl: Option<toml::Table>,
     /// Custom commands with check/apply
     #[serde(default)]
     pub command: Vec<CommandConfig>,
@@ -93,8 +174,89 @@ pub struct Config {
     pub state: Vec<StateConfig>,
 }
 
+/// `[[apt_repo]]` — a vendor apt repository
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AptRepoConfig {
+    /// Base name of the sources file and keyring
+    pub name: String,
+    pub uri: String,
+    /// Distribution codename (`noble`), or a path ending in `/` for flat repos
+    pub suite: String,
+    #[serde(default)]
+    pub components: Vec<String>,
+    /// Signing key, armored or binary; the repo is `Signed-By` it
+    pub key_url: Option<String>,
+    #[serde(default)]
+    pub run_if: Option<String>,
+    #[serde(default)]
+    pub when: Option<When>,
+}
+
+/// `[[pacman_repo]]` — a `[name]` section in /etc/pacman.conf
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct PacmanRepoConfig {
+    pub name: String,
+    /// `Server =` lines, tried in order
+    #[serde(default)]
+    pub server: Vec<String>,
+    /// `Include =` a mirrorlist file instead of (or besides) servers
+    pub include: Option<String>,
+    /// `SigLevel =` (default: pacman.conf's global SigLevel)
+    pub sig_level: Option<String>,
+    #[serde(default)]
+    pub run_if: Option<String>,
+    #[serde(default)]
+    pub when: Option<When>,
+}
+
+/// `[[cron]]` — a scheduled job
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CronConfig {
+    pub name: String,
+    /// Cron expression (`0 3 * * *`), or an OnCalendar spec with `timer = true`
+    pub schedule: String,
+    pub command: String,
+    /// Use a systemd user timer instead of the crontab
+    #[serde(default)]
+    pub timer: bool,
+    #[serde(default)]
+    pub run_if: Option<String>,
+    #[serde(default)]
+    pub when: Option<When>,
+}
+
+/// `[[user]]` — a local account
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct UserConfig {
+    pub name: String,
+    /// Supplementary groups (created if missing); existing memberships are kept
+    #[serde(default)]
+    pub groups: Vec<String>,
+    /// Login shell
+    pub shell: Option<String>,
+    /// Home directory (default: the system's, created for non-system users)
+    pub home: Option<String>,
+    /// System account (useradd --system, no home created)
+    #[serde(default)]
+    pub system: bool,
+    #[serde(default)]
+    pub run_if: Option<String>,
+    #[serde(default)]
+    pub when: Option<When>,
+}
+
+/// `[path]` — directories to put on PATH, in order
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
+#[serde(default)]
+pub struct PathConfig {
+    /// Ahead of the existing PATH (first entry ends up first)
+    pub prepend: Vec<String>,
+    /// After the existing PATH
+    pub append: Vec<String>,
+}
+
 /// Proxy configuration
-#[derive(Debug, Deserialize, Default, Clone)]
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
 #[serde(default)]
 pub struct ProxyConfig {
     /// HTTP proxy URL (sets http_proxy and HTTP_PROXY)
@@ -103,13 +265,13 @@ pub struct ProxyConfig {
     pub https: Option<String>,
     /// No-proxy list (comma-separated, sets no_proxy and NO_PROXY)
     pub no_proxy: Option<String>,
-    /// Persist to ~/.dek_env for future shell sessions (default: false)
+    /// Persist to the dek env file (~/.dek_env) for future shell sessions (default: false)
     #[serde(default)]
     pub persist: bool,
 }
 
 /// Per-file metadata
-#[derive(Debug, Deserialize, Default, Clone)]
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
 #[serde(default)]
 pub struct ConfigMeta {
     /// Display name for this config
@@ -118,33 +280,94 @@ pub struct ConfigMeta {
     pub description: Option<String>,
     /// Shell command — skip this config when it exits non-zero
     pub run_if: Option<String>,
+    /// Platform condition — skip this config when it doesn't match
+    pub when: Option<When>,
     /// Labels for grouping (selectable via @label)
     #[serde(default)]
     pub labels: Vec<String>,
+    /// Configs that must apply before this one (pulled in when not selected)
+    #[serde(default)]
+    pub requires: Vec<String>,
+    /// AUR helper for pacman packages not in the repos: paru, yay or pikaur
+    /// (default: whichever is installed, else yay)
+    pub aur_helper: Option<AurHelper>,
+    /// How cargo packages install: "binstall" (never compile) or "source"
+    /// (always compile). Default: binstall, compiling when that fails
+    pub cargo_prefer: Option<CargoPrefer>,
+    /// sha256 that an install script (rustup, cargo-binstall, webi) must
+    /// have before it runs, by URL
+    #[serde(default)]
+    pub install_script_sha256: HashMap<String, String>,
+    /// Refuse install scripts without a pinned sha256
+    #[serde(default)]
+    pub pinned_install_scripts: bool,
 }
 
-#[derive(Debug, Deserialize, Default, Clone)]
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
 #[serde(default)]
 pub struct PackageConfig {
     pub os: Option<PackageList>,
     pub apt: Option<PackageList>,
     pub pacman: Option<PackageList>,
+    pub brew: Option<BrewPackageList>,
     pub cargo: Option<PackageList>,
     pub go: Option<PackageList>,
     pub npm: Option<PackageList>,
-    pub pip: Option<PackageList>,
-    pub pipx: Option<PackageList>,
+    pub pip: Option<PipPackageList>,
+    pub pipx: Option<PipxPackageList>,
     pub webi: Option<PackageList>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct PackageList {
     pub items: Vec<String>,
     #[serde(default)]
     pub run_if: Option<String>,
+    #[serde(default)]
+    pub when: Option<When>,
+    /// Kill an install that runs longer than this ("10m")
+    #[serde(default)]
+    pub timeout: Option<String>,
+}
+
+/// `[package.brew]` — formulae in items, GUI apps in cask
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
+#[serde(default)]
+pub struct BrewPackageList {
+    pub items: Vec<String>,
+    pub cask: Vec<String>,
+    pub run_if: Option<String>,
+    pub when: Option<When>,
+    pub timeout: Option<String>,
+}
+
+/// `[package.pip]` — items take pip specs ("httpie[socks]", "black==24.1.0")
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
+#[serde(default)]
+pub struct PipPackageList {
+    pub items: Vec<String>,
+    pub run_if: Option<String>,
+    pub when: Option<When>,
+    pub timeout: Option<String>,
+    /// requirements.txt files installed with `pip install -r` (relative to config dir)
+    pub requirements: Vec<String>,
+}
+
+/// `[package.pipx]` — items take pip specs, like `[package.pip]`
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
+#[serde(default)]
+pub struct PipxPackageList {
+    pub items: Vec<String>,
+    pub run_if: Option<String>,
+    pub when: Option<When>,
+    pub timeout: Option<String>,
+    /// Also expose apps of the packages' dependencies (`pipx install --include-deps`)
+    pub include_deps: bool,
+    /// Extra packages to inject into an app's venv: { httpie = ["httpie-jwt-auth"] }
+    pub inject: HashMap<String, Vec<String>>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct ServiceConfig {
     pub name: String,
     #[serde(default = "default_service_state")]
@@ -157,6 +380,8 @@ pub struct ServiceConfig {
     #[serde(default)]
     pub run_if: Option<String>,
     #[serde(default)]
+    pub when: Option<When>,
+    #[serde(default)]
     pub cache_key: Option<String>,
     #[serde(default)]
     pub cache_key_cmd: Option<String>,
@@ -170,13 +395,15 @@ fn default_service_state() -> String {
     "active".to_string()
 }
 
-#[derive(Debug, Deserialize, Default, Clone)]
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
 #[serde(default)]
 pub struct FileConfig {
     pub copy: Option<HashMap<String, String>>,
     pub fetch: Option<HashMap<String, FetchTarget>>,
     pub symlink: Option<HashMap<String, String>>,
     pub ensure_line: Option<HashMap<String, Vec<String>>>,
+    /// Lines that must not be present (every matching line is removed)
+    pub ensure_absent: Option<HashMap<String, Vec<String>>>,
     /// Structured line entries with original pattern matching
     #[serde(default)]
     pub line: Vec<FileLineConfig>,
@@ -188,7 +415,7 @@ pub struct FileConfig {
     pub vars: Vec<String>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct FileTemplateConfig {
     pub src: String,
     pub dest: String,
@@ -197,14 +424,25 @@ pub struct FileTemplateConfig {
     /// Per-template vars files (merged on top of shared file.vars)
     #[serde(default)]
     pub vars: Vec<String>,
+    /// Render once per item, with `item` in the context (dest included)
+    pub foreach: Option<TemplateForeach>,
 }
 
-/// Fetch target: either a plain path string or { path, ttl }
-#[derive(Debug, Deserialize, Clone)]
+/// `foreach` on a template: a list, or an expression evaluated in the
+/// template context (`groups.web`, `site_vars.services`, `units.raw`)
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum TemplateForeach {
+    List(Vec<toml::Value>),
+    Expr(String),
+}
+
+/// Fetch target: either a plain path string or { path, ttl, check_ttl }
+#[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(untagged)]
 pub enum FetchTarget {
     Path(String),
-    WithOptions { path: String, ttl: Option<String> },
+    WithOptions { path: String, ttl: Option<String>, check_ttl: Option<String> },
 }
 
 impl FetchTarget {
@@ -221,47 +459,153 @@ impl FetchTarget {
             Self::WithOptions { ttl, .. } => ttl.as_deref(),
         }
     }
+
+    pub fn check_ttl(&self) -> Option<&str> {
+        match self {
+            Self::Path(_) => None,
+            Self::WithOptions { check_ttl, .. } => check_ttl.as_deref(),
+        }
+    }
+}
+
+/// `[env]` value: a plain string, or { cmd } whose output becomes the value
+/// when the item is checked/applied
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum EnvValue {
+    Value(String),
+    Cmd { cmd: String },
+}
+
+/// Platform condition, checked in-process instead of shelling out like `run_if`.
+/// Every key given must match; a list matches any of its values.
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
+#[serde(default)]
+pub struct When {
+    /// `std::env::consts::OS` ("linux", "macos", "windows")
+    pub os: Option<WhenValue>,
+    /// `ID` or `ID_LIKE` from /etc/os-release ("ubuntu", "debian", "arch")
+    pub distro: Option<WhenValue>,
+    /// `std::env::consts::ARCH` ("x86_64", "aarch64")
+    pub arch: Option<WhenValue>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum WhenValue {
+    One(String),
+    Any(Vec<String>),
+}
+
+impl WhenValue {
+    fn matches(&self, actual: &[String]) -> bool {
+        match self {
+            Self::One(v) => actual.contains(v),
+            Self::Any(vs) => vs.iter().any(|v| actual.contains(v)),
+        }
+    }
+}
+
+impl When {
+    pub fn matches(&self) -> bool {
+        let os = [std::env::consts::OS.to_string()];
+        let arch = [std::env::consts::ARCH.to_string()];
+        self.os.as_ref().is_none_or(|v| v.matches(&os))
+            && self.arch.as_ref().is_none_or(|v| v.matches(&arch))
+            && self.distro.as_ref().is_none_or(|v| v.matches(&crate::util::distro_ids()))
+    }
+}
+
+/// True when there's no `when`, or it matches this machine
+pub fn when_matches(when: &Option<When>) -> bool {
+    when.as_ref().is_none_or(When::matches)
 }
 
 /// Structured ensure_line with original pattern support
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct FileLineConfig {
     pub path: String,
+    /// Line to place (unused with mode = "delete")
+    #[serde(default)]
     pub line: String,
     /// Literal string to match an existing line
     pub original: Option<String>,
     /// Regex pattern to match an existing line
     pub original_regex: Option<String>,
-    /// "replace" (default) or "below"
+    /// "replace" (default), "below", or "delete" (remove every matching line)
     #[serde(default)]
     pub mode: FileLineMode,
     #[serde(default)]
     pub run_if: Option<String>,
     #[serde(default)]
+    pub when: Option<When>,
+    #[serde(default)]
     pub cache_key: Option<String>,
     #[serde(default)]
     pub cache_key_cmd: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Clone, Default)]
+/// `[meta] aur_helper`: AUR helpers dek knows how to drive (all take
+/// `-S --noconfirm`)
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AurHelper {
+    Paru,
+    Yay,
+    Pikaur,
+}
+
+impl AurHelper {
+    /// Auto-detect order when `aur_helper` is unset
+    pub const ALL: [AurHelper; 3] = [AurHelper::Paru, AurHelper::Yay, AurHelper::Pikaur];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AurHelper::Paru => "paru",
+            AurHelper::Yay => "yay",
+            AurHelper::Pikaur => "pikaur",
+        }
+    }
+}
+
+/// `[meta] cargo_prefer`: "binstall" never compiles, "source" never uses
+/// cargo-binstall. Unset tries binstall, then compiles.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CargoPrefer {
+    Binstall,
+    Source,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
 #[serde(rename_all = "lowercase")]
 pub enum FileLineMode {
     #[default]
     Replace,
     Below,
+    Delete,
 }
 
 /// Custom command with check/apply scripts
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct CommandConfig {
     pub name: String,
     /// Shell command that returns 0 if satisfied
-    pub check: String,
+    #[serde(default)]
+    pub check: Option<String>,
+    /// Satisfied once this path exists (checked before `check`)
+    #[serde(default)]
+    pub creates: Option<String>,
+    /// Satisfied once this path is gone (checked before `check`)
+    #[serde(default)]
+    pub removes: Option<String>,
     /// Shell command to apply the state (accepts "cmd" as alias)
     #[serde(alias = "cmd")]
     pub apply: String,
     #[serde(default)]
     pub run_if: Option<String>,
+    #[serde(default)]
+    pub when: Option<When>,
     /// Skip if this value (supports $VAR) hasn't changed since last apply
     #[serde(default)]
     pub cache_key: Option<String>,
@@ -271,10 +615,16 @@ pub struct CommandConfig {
     /// Prompt [y/N] before applying
     #[serde(default)]
     pub confirm: bool,
+    /// Kill the apply command if it runs longer than this ("30s", "10m")
+    #[serde(default)]
+    pub timeout: Option<String>,
+    /// Trust a passing check for this long before running it again ("5m")
+    #[serde(default)]
+    pub check_ttl: Option<String>,
 }
 
 /// Runnable command (dek run <name>)
-#[derive(Debug, Deserialize, Default, Clone)]
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
 #[serde(default)]
 pub struct RunConfig {
     /// Description for completions/help
@@ -297,6 +647,28 @@ pub struct RunConfig {
     /// Prompt before running
     #[serde(default)]
     pub confirm: bool,
+    /// Shell command; skip the run command unless it exits 0
+    pub run_if: Option<String>,
+    /// State probes ("name" or "name.variant") that must have these values
+    #[serde(default)]
+    pub requires_state: HashMap<String, String>,
+    /// Run commands to run first, in order (e.g. ["build", "test"])
+    #[serde(default)]
+    pub needs: Vec<String>,
+    /// Named arguments (`--name value`), rendered into cmd/script as `{{ name }}`
+    #[serde(default)]
+    pub args: Vec<RunArg>,
+}
+
+/// Declared argument of a run command
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RunArg {
+    pub name: String,
+    /// Error if not given and no default
+    #[serde(default)]
+    pub required: bool,
+    pub default: Option<String>,
+    pub description: Option<String>,
 }
 
 /// Info about a config file (for listing)
@@ -314,18 +686,20 @@ pub struct ConfigInfo {
     pub optional: bool,
     /// Whether this config runs by default (computed from meta.defaults or !optional)
     pub is_default: bool,
-    /// Shell command condition from [meta] run_if
-    pub run_if: Option<String>,
+    /// Configs this one requires, from [meta] requires
+    pub requires: Vec<String>,
 }
 
 /// Inventory of remote hosts (loaded from inventory.ini)
 #[derive(Debug, Default, Clone)]
 pub struct Inventory {
     pub hosts: Vec<String>,
+    /// Hosts listed under each [group] header
+    pub groups: std::collections::BTreeMap<String, Vec<String>>,
 }
 
 /// Build artifact (resolved before bake/deploy)
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct ArtifactConfig {
     /// Display label
     pub name: Option<String>,
@@ -346,7 +720,7 @@ pub struct ArtifactConfig {
 }
 
 /// State probe (dek state)
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct StateConfig {
     pub name: String,
     pub cmd: Option<String>,
@@ -359,13 +733,15 @@ pub struct StateConfig {
     #[serde(default)]
     pub templates: HashMap<String, String>,
     pub ttl: Option<String>,
+    /// Kill cmd if it runs longer than this (e.g. "5s"); default 30s
+    pub timeout: Option<String>,
     /// Treat raw output as JSON — parsed in templates, embedded in --json output
     #[serde(default)]
     pub json: bool,
 }
 
 /// Rewrite rule for state probes
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct RewriteRule {
     #[serde(rename = "match")]
     pub pattern: String,
@@ -373,7 +749,7 @@ pub struct RewriteRule {
 }
 
 /// Assertion to check before apply
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct AssertConfig {
     /// Display label
     pub name: Option<String>,
@@ -381,12 +757,26 @@ pub struct AssertConfig {
     pub check: Option<String>,
     /// Shell command whose stdout lines are findings (0 lines = pass)
     pub foreach: Option<String>,
+    /// URL to GET (passes on the expected status)
+    pub url: Option<String>,
+    /// Expected HTTP status for `url` (default: 200)
+    pub status: Option<u16>,
+    /// Text the `url` response body must contain
+    pub contains: Option<String>,
     /// Optional regex to match against stdout (check mode only)
     pub stdout: Option<String>,
     /// Optional regex to match against stderr (check mode only)
     pub stderr: Option<String>,
     /// Custom failure message
     pub message: Option<String>,
+    /// Check before anything is applied; if it fails, apply changes nothing and exits 3
+    #[serde(default)]
+    pub required: bool,
+    /// Trust a pass for this long before checking again ("5m")
+    #[serde(default)]
+    pub check_ttl: Option<String>,
     #[serde(default)]
     pub run_if: Option<String>,
+    #[serde(default)]
+    pub when: Option<When>,
 }
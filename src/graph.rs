@@ -0,0 +1,194 @@
+use crate::config;
+use crate::state;
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use owo_colors::OwoColorize;
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum GraphFormat {
+    /// Indented tree, each node above what it depends on
+    Tree,
+    /// Graphviz DOT (pipe to `dot -Tsvg`)
+    Dot,
+}
+
+/// A probe or config and the names it depends on
+struct Node {
+    name: String,
+    deps: Vec<String>,
+}
+
+/// One graph to draw: state probes (`deps`) or configs (`[meta] requires`)
+struct Graph {
+    id: &'static str,
+    title: &'static str,
+    nodes: Vec<Node>,
+}
+
+impl Graph {
+    fn get(&self, name: &str) -> Option<&Node> {
+        self.nodes.iter().find(|n| n.name == name)
+    }
+
+    /// Whether `to` is reachable from `from` by following deps
+    fn reaches(&self, from: &str, to: &str) -> bool {
+        let mut seen = HashSet::new();
+        let mut stack = vec![from];
+        while let Some(name) = stack.pop() {
+            if name == to {
+                return true;
+            }
+            if seen.insert(name) {
+                if let Some(node) = self.get(name) {
+                    stack.extend(node.deps.iter().map(String::as_str));
+                }
+            }
+        }
+        false
+    }
+}
+
+/// Print the state probe and config dependency graphs
+pub fn run(config_path: Option<PathBuf>, format: GraphFormat) -> Result<()> {
+    let path = crate::resolve_config(config_path)?;
+    let resolved = config::resolve_path(&path)?;
+    let meta = config::load_meta(&resolved)?;
+    let cfg = config::load_all(&resolved)?;
+
+    let mut graphs = Vec::new();
+    if !cfg.state.is_empty() {
+        let nodes = cfg.state.iter().map(|s| Node { name: s.name.clone(), deps: s.deps.clone() }).collect();
+        graphs.push(Graph { id: "state", title: "State probes", nodes });
+    }
+    // Config requires only say something when there are some
+    let configs = config::list_configs(&resolved, meta.as_ref())?;
+    if configs.iter().any(|c| !c.requires.is_empty()) {
+        let nodes = configs.into_iter().map(|c| Node { name: c.key, deps: c.requires }).collect();
+        graphs.push(Graph { id: "config", title: "Configs", nodes });
+    }
+    if graphs.is_empty() {
+        bail!("Nothing to graph: no state probes or config requires");
+    }
+
+    match format {
+        GraphFormat::Tree => {
+            for (i, graph) in graphs.iter().enumerate() {
+                if i > 0 {
+                    println!();
+                }
+                print_tree(graph);
+            }
+            if !cfg.state.is_empty() {
+                println!();
+                print_eval_order(&cfg.state);
+            }
+        }
+        GraphFormat::Dot => print_dot(&graphs),
+    }
+    Ok(())
+}
+
+fn print_tree(graph: &Graph) {
+    println!("{}", c!(graph.title, bold));
+    // Start from what nothing depends on; nodes only reachable through a
+    // cycle have no such root, so they start their own tree afterwards
+    let depended: HashSet<&str> = graph.nodes.iter().flat_map(|n| n.deps.iter().map(String::as_str)).collect();
+    let mut shown = HashSet::new();
+    for node in graph.nodes.iter().filter(|n| !depended.contains(n.name.as_str())) {
+        print_tree_node(graph, &node.name, "", None, &mut Vec::new(), &mut shown);
+    }
+    for node in &graph.nodes {
+        if !shown.contains(node.name.as_str()) {
+            print_tree_node(graph, &node.name, "", None, &mut Vec::new(), &mut shown);
+        }
+    }
+}
+
+/// One line of the tree, then its deps below it. `last` is None for roots.
+fn print_tree_node<'a>(
+    graph: &'a Graph,
+    name: &'a str,
+    prefix: &str,
+    last: Option<bool>,
+    stack: &mut Vec<&'a str>,
+    shown: &mut HashSet<&'a str>,
+) {
+    let branch = match last {
+        None => "",
+        Some(true) => "└── ",
+        Some(false) => "├── ",
+    };
+    let line = format!("{}{}{}", prefix, c!(branch, dimmed), name);
+
+    let Some(node) = graph.get(name) else {
+        println!("{} {}", line, c!("(unknown)", red));
+        return;
+    };
+    if stack.contains(&name) {
+        println!("{} {}", line, c!("(cycle)", red));
+        return;
+    }
+    if !shown.insert(name) && !node.deps.is_empty() {
+        println!("{} {}", line, c!("(see above)", dimmed));
+        return;
+    }
+    println!("{}", line);
+
+    let prefix = match last {
+        None => String::new(),
+        Some(true) => format!("{}    ", prefix),
+        Some(false) => format!("{}{}", prefix, c!("│   ", dimmed)),
+    };
+    stack.push(name);
+    for (i, dep) in node.deps.iter().enumerate() {
+        print_tree_node(graph, dep, &prefix, Some(i + 1 == node.deps.len()), stack, shown);
+    }
+    stack.pop();
+}
+
+/// The layers probes are evaluated in, or why there's no valid order
+fn print_eval_order(states: &[config::StateConfig]) {
+    println!("{}", c!("Evaluation order", bold));
+    match state::topo_sort(states) {
+        Ok(layers) => {
+            for (i, layer) in layers.iter().enumerate() {
+                let names: Vec<&str> = layer.iter().map(|&idx| states[idx].name.as_str()).collect();
+                println!("  {} {}", c!(format!("{}.", i + 1), dimmed), names.join(", "));
+            }
+        }
+        Err(e) => println!("  {}", c!(e.to_string(), red)),
+    }
+}
+
+/// Edges run from a dependency to what depends on it, i.e. in evaluation order
+fn print_dot(graphs: &[Graph]) {
+    println!("digraph dek {{");
+    println!("  rankdir=LR;");
+    println!("  node [shape=box];");
+    for graph in graphs {
+        let id = |name: &str| dot_quote(&format!("{}:{}", graph.id, name));
+        println!("  subgraph cluster_{} {{", graph.id);
+        println!("    label={};", dot_quote(graph.title));
+        for node in &graph.nodes {
+            println!("    {} [label={}];", id(&node.name), dot_quote(&node.name));
+        }
+        let mut unknown = HashSet::new();
+        for node in &graph.nodes {
+            for dep in &node.deps {
+                if graph.get(dep).is_none() && unknown.insert(dep.as_str()) {
+                    println!("    {} [label={}, style=dashed, color=red];", id(dep), dot_quote(dep));
+                }
+                let attrs = if graph.reaches(dep, &node.name) { " [color=red]" } else { "" };
+                println!("    {} -> {}{};", id(dep), id(&node.name), attrs);
+            }
+        }
+        println!("  }}");
+    }
+    println!("}}");
+}
+
+fn dot_quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
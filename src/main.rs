@@ -9,11 +9,15 @@ macro_rules! c {
 mod bake;
 mod cache;
 mod config;
+mod graph;
 mod output;
 mod providers;
 mod runner;
+mod secrets;
+mod ssh;
 mod state;
 mod util;
+mod validate;
 
 use anyhow::{bail, Result};
 use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
@@ -26,6 +30,12 @@ enum ColorMode {
     Always,
     Never,
 }
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum ConfigFormat {
+    Toml,
+    Json,
+}
 use std::io;
 use std::path::PathBuf;
 use std::process::{Command, Stdio};
@@ -38,11 +48,11 @@ struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
 
-    /// Config directory path (default: dek.toml or dek/)
+    /// Config path, archive or git/http URL (default: dek.toml or dek/)
     #[arg(short = 'C', long, global = true, value_name = "PATH")]
     config: Option<PathBuf>,
 
-    /// Remote target (user@host or ssh hostname)
+    /// Remote target (user@host or ssh hostname); for bake, a target triple or dek binary to bake into
     #[arg(short, long, global = true, value_name = "TARGET")]
     target: Option<String>,
 
@@ -50,23 +60,108 @@ struct Cli {
     #[arg(short = 'r', long, global = true, value_name = "PATTERN")]
     remotes: Option<String>,
 
+    /// Max hosts to run on concurrently with --remotes
+    #[arg(long, global = true, value_name = "N", default_value_t = 10)]
+    parallel: usize,
+
+    /// Only run on a subset of --remotes matches: first N, or host1,host2
+    #[arg(long, global = true, value_name = "N|HOSTS")]
+    limit: Option<String>,
+
+    /// Print each host's output live, prefixed with [host], instead of spinners
+    #[arg(long, global = true)]
+    stream: bool,
+
+    /// Write per-host deploy results as JSON to this path
+    #[arg(long, global = true, value_name = "PATH")]
+    report: Option<PathBuf>,
+
+    /// Open a fresh ssh connection for every remote step (no ControlMaster)
+    #[arg(long, global = true)]
+    no_control_master: bool,
+
+    /// Jump/bastion host for remote targets (ssh -J)
+    #[arg(long, global = true, value_name = "HOST")]
+    jump: Option<String>,
+
+    /// Seconds to wait for each ssh connection to a remote host (0: ssh's default)
+    #[arg(long, global = true, value_name = "SECS", default_value_t = 10)]
+    connect_timeout: u64,
+
+    /// Give up on a remote host after this long, e.g. 5m (marked failed)
+    #[arg(long, global = true, value_name = "DURATION")]
+    host_timeout: Option<String>,
+
+    /// Prompt for the sudo password and forward it to remote hosts
+    #[arg(long, global = true)]
+    ask_become_pass: bool,
+
+    /// Ignore caches and freshness checks (cache keys, artifact watch/check, probe TTLs)
+    #[arg(long, global = true)]
+    force: bool,
+
+    /// Extra attempts, with backoff, for network steps (downloads, installs, ssh/scp/rsync)
+    #[arg(long, global = true, value_name = "N", default_value_t = 2)]
+    retries: u32,
+
+    /// Run install scripts (rustup, cargo-binstall, webi) even when they don't match their pinned sha256
+    #[arg(long, global = true)]
+    insecure: bool,
+
+    /// No spinners: one static line per step (default when stdout isn't a TTY or CI is set)
+    #[arg(long, global = true)]
+    no_progress: bool,
+
     /// Suppress banner and extra output
     #[arg(short, long, global = true)]
     quiet: bool,
 
+    /// Append timestamped item results and summaries to this file
+    #[arg(long, global = true, value_name = "PATH")]
+    log: Option<PathBuf>,
+
+    /// Show the full output of failing commands (repeatable)
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
     /// Config is already prepared (skip prepare_config). Used by remote deploy.
     #[arg(long, hide = true, global = true)]
     prepared: bool,
 
+    /// Read the sudo password from the first line of stdin. Used by remote deploy.
+    #[arg(long, hide = true, global = true)]
+    become_pass_stdin: bool,
+
     /// Color output: auto (default), always, never
     #[arg(long, global = true, default_value = "auto")]
     color: ColorMode,
 
+    /// Print the merged config apply would use for the given selectors, then exit (toml or json)
+    #[arg(long, value_name = "FORMAT", num_args = 0..=1, require_equals = true, default_missing_value = "toml")]
+    print_config: Option<ConfigFormat>,
+
     /// Inline install: provider.package (e.g., cargo.bat apt.htop)
     #[arg(value_name = "SPEC", trailing_var_arg = true)]
     inline: Vec<String>,
 }
 
+/// Provider filters for apply/check
+#[derive(clap::Args)]
+struct FilterArgs {
+    /// Only items of these providers (e.g., package.cargo,file)
+    #[arg(long, value_name = "KINDS", value_delimiter = ',')]
+    only: Vec<String>,
+    /// Skip items of these providers (e.g., service)
+    #[arg(long, value_name = "KINDS", value_delimiter = ',')]
+    skip: Vec<String>,
+}
+
+impl From<FilterArgs> for runner::Filter {
+    fn from(args: FilterArgs) -> Self {
+        runner::Filter { only: args.only, skip: args.skip }
+    }
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// Apply configuration (all or specific configs)
@@ -75,13 +170,53 @@ enum Commands {
         /// Configs to apply (e.g., "tools", "config"). Applies all if omitted.
         #[arg(value_name = "CONFIGS")]
         configs: Vec<String>,
+        /// Print newline-delimited JSON (one object per item, then a summary)
+        #[arg(long)]
+        json: bool,
+        #[command(flatten)]
+        filter: FilterArgs,
+        /// Keep running and re-apply when config or source files change
+        #[arg(long)]
+        watch: bool,
+        /// Show what apply would change (install, write, run) without changing anything
+        #[arg(short = 'n', long, conflicts_with = "watch")]
+        dry_run: bool,
+        /// Apply independent items (package managers, files, aliases, env) N at a time
+        #[arg(short = 'j', long, value_name = "N", default_value_t = 1)]
+        jobs: usize,
+        /// Ask [y/N/a] before applying each item that needs a change (a = all remaining)
+        #[arg(long, conflicts_with_all = ["dry_run", "json", "jobs"])]
+        confirm_each: bool,
+        /// Skip checking items unchanged since they were last applied (drift goes unnoticed)
+        #[arg(long)]
+        incremental: bool,
+        /// Afterwards, remove alias/env/PATH lines and (asking first) copied or linked files no longer in config
+        #[arg(long, conflicts_with = "json")]
+        prune: bool,
+        /// Install missing toolchains (rustup, cargo-binstall, webi) by script without asking
+        #[arg(long)]
+        yes_requirements: bool,
+        /// Only configs whose files (or copy/template sources) changed since the last git commit
+        #[arg(long, conflicts_with_all = ["configs", "watch"])]
+        changed: bool,
     },
-    /// Check what would change (dry-run)
+    /// Check which items need changes
     #[command(alias = "c")]
     Check {
         /// Configs to check
         #[arg(value_name = "CONFIGS")]
         configs: Vec<String>,
+        /// Print newline-delimited JSON (one object per item, then a summary)
+        #[arg(long)]
+        json: bool,
+        /// Exit with status 2 when any item is missing (drift detection)
+        #[arg(long)]
+        exit_code: bool,
+        #[command(flatten)]
+        filter: FilterArgs,
+        /// Check one item (kind.key, e.g. cargo.ripgrep) and show the commands and reasoning behind the result
+        #[arg(long, value_name = "ITEM", conflicts_with = "json")]
+        explain: Option<String>,
     },
     /// List items from config (no state check)
     #[command(alias = "p")]
@@ -89,6 +224,18 @@ enum Commands {
         /// Configs to plan
         #[arg(value_name = "CONFIGS")]
         configs: Vec<String>,
+        /// Print newline-delimited JSON (one object per item, then a summary)
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show the file changes apply would make, as unified diffs
+    Diff {
+        /// Configs to diff
+        #[arg(value_name = "CONFIGS")]
+        configs: Vec<String>,
+        /// Print newline-delimited JSON (one object per changed item, then a summary)
+        #[arg(long)]
+        json: bool,
     },
     /// Run a command from config (no name = list commands)
     #[command(alias = "r")]
@@ -97,7 +244,7 @@ enum Commands {
         name: Option<String>,
 
         /// Arguments to pass to the command
-        #[arg(trailing_var_arg = true)]
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
         args: Vec<String>,
     },
     /// Spin up container, apply config, drop into shell
@@ -119,6 +266,26 @@ enum Commands {
         #[arg(short, long)]
         attach: bool,
 
+        /// Rebuild the musl dek binary even if sources are unchanged
+        #[arg(long)]
+        rebuild: bool,
+
+        /// Set an environment variable in the container (KEY=VAL or KEY, repeatable)
+        #[arg(short, long, value_name = "KEY=VAL")]
+        env: Vec<String>,
+
+        /// Read environment variables from a file (repeatable)
+        #[arg(long, value_name = "PATH")]
+        env_file: Vec<PathBuf>,
+
+        /// Apply without dropping into a shell; exit with the apply status
+        #[arg(long)]
+        no_shell: bool,
+
+        /// Apply in one container per meta.toml [test].images and report pass/fail
+        #[arg(long, alias = "matrix")]
+        all: bool,
+
         /// Configs/selectors to apply (e.g., "tools", "@core")
         #[arg(value_name = "SELECTORS")]
         selectors: Vec<String>,
@@ -126,6 +293,14 @@ enum Commands {
     /// Run a command in the test container
     #[command(alias = "dx")]
     Exec {
+        /// User to run as (name or uid, docker exec -u)
+        #[arg(short, long)]
+        user: Option<String>,
+
+        /// Working directory inside the container (docker exec -w)
+        #[arg(short, long)]
+        workdir: Option<String>,
+
         /// Command and arguments to run
         #[arg(trailing_var_arg = true, required = true)]
         cmd: Vec<String>,
@@ -139,19 +314,72 @@ enum Commands {
         /// Output binary path
         #[arg(short, long, default_value = "dek-baked")]
         output: PathBuf,
+
+        /// Archive compression: gzip level 0-9, or zstd (smaller)
+        #[arg(long, value_name = "0-9|zstd", default_value = "6")]
+        compression: bake::Compression,
+
+        /// Encrypt the embedded config (passphrase prompted, or $DEK_BAKE_PASS)
+        #[arg(long)]
+        encrypt: bool,
+    },
+    /// Pack config into a tar.gz for hosts that already have dek (dek -C <archive> apply)
+    Pack {
+        /// Config file or directory to pack
+        #[arg(value_name = "CONFIG")]
+        config: Option<PathBuf>,
+
+        /// Output archive path (.tar.gz or .tgz)
+        #[arg(short, long, default_value = "config.tar.gz")]
+        output: PathBuf,
+    },
+    /// Inspect a baked binary: bake metadata and embedded config
+    Info {
+        /// Baked binary to inspect (default: this binary)
+        #[arg(long, value_name = "PATH")]
+        binary: Option<PathBuf>,
     },
     /// Query system state probes
     #[command(alias = "s")]
     State {
         /// Probe name (omit to list all)
         name: Option<String>,
-        /// Output as JSON
-        #[arg(long)]
+        /// Output as JSON (same as --format json)
+        #[arg(long, conflicts_with = "format")]
         json: bool,
+        /// Output format: table, json, yaml, or env (NAME='value' lines to eval)
+        #[arg(long, value_enum, default_value = "table")]
+        format: state::StateFormat,
+        /// Re-render every probe on an interval until interrupted, like watch(1)
+        #[arg(long)]
+        watch: bool,
+        /// Refresh interval for --watch (e.g. 2s, 1m)
+        #[arg(long, default_value = "2s")]
+        interval: String,
+        /// Run every probe fresh, ignoring cached values (the cache is still updated)
+        #[arg(long)]
+        no_cache: bool,
         /// Extra args: "is <val>" or "isnot <val>"
         #[arg(trailing_var_arg = true)]
         args: Vec<String>,
     },
+    /// Show the dependency graph of state probes and config requires
+    Graph {
+        /// Output format
+        #[arg(long, value_enum, default_value = "tree")]
+        format: graph::GraphFormat,
+    },
+    /// Lint config files: bad deps, empty artifact paths, invalid regexes, duplicates, state cycles
+    Validate,
+    /// Show or clear local caches (downloads, state, artifact hashes, extracted configs)
+    Cache {
+        /// List every cached path
+        #[arg(long)]
+        list: bool,
+        /// Remove all cached data
+        #[arg(long)]
+        clear: bool,
+    },
     /// Generate shell completions (raw output)
     Completions {
         /// Shell to generate completions for
@@ -159,7 +387,11 @@ enum Commands {
         shell: Shell,
     },
     /// Install dek completions for your shell
-    Setup,
+    Setup {
+        /// Remove the completions and the rc line setup added
+        #[arg(long)]
+        uninstall: bool,
+    },
 }
 
 fn main() -> Result<()> {
@@ -184,23 +416,48 @@ fn main() -> Result<()> {
     // Ensure well-known user binary dirs are in PATH (non-interactive SSH won't have them)
     ensure_user_path();
 
+    cache::set_force(cli.force);
+    util::set_retries(cli.retries);
+    util::set_insecure(cli.insecure);
+    if cli.prepared {
+        config::disable_user_config_merge();
+    }
+    output::set_plain(cli.no_progress || is_ci() || !io::IsTerminal::is_terminal(&io::stdout()));
+    output::set_verbosity(cli.verbose);
+    if let Some(ref path) = cli.log {
+        output::set_log(path)?;
+    }
+
+    if cli.become_pass_stdin {
+        let mut pass = String::new();
+        io::stdin().read_line(&mut pass)?;
+        util::set_become_pass(pass.trim_end_matches(['\r', '\n']).to_string());
+    }
+
+    let remote_opts = RemoteOpts::from_cli(&cli)?;
+
+    // dek --print-config [selectors]: the trailing args are selectors here
+    if let Some(format) = cli.print_config {
+        return print_config(cli.config, cli.inline, format);
+    }
+
     // Handle inline mode: dek cargo.bat apt.htop
     // If first arg has no dot, treat as: dek run <name> [args...]
     if !cli.inline.is_empty() {
         // Dynamic completion for shell scripts
         if cli.inline[0] == "_complete" {
             let what = cli.inline.get(1).map(|s| s.as_str()).unwrap_or("");
-            return run_complete(cli.config, what);
+            return run_complete(cli.config, what, cli.inline.get(2).map(|s| s.as_str()));
         }
         if !cli.inline[0].contains('.') {
             let mut args = cli.inline;
             let name = args.remove(0);
             if cli.remotes.is_some() || cli.target.is_some() {
-                return run_command_remote(cli.config, Some(name), args, cli.target, cli.remotes);
+                return run_command_remote(cli.config, Some(name), args, cli.target, cli.remotes, &remote_opts);
             }
             return run_command(cli.config, Some(name), args);
         }
-        return run_inline(&cli.inline);
+        return run_inline(cli.config, &cli.inline);
     }
 
     let config = cli.config;
@@ -210,64 +467,138 @@ fn main() -> Result<()> {
     let prepared = cli.prepared;
 
     match cli.command {
-        Some(Commands::Apply { configs }) => {
+        Some(Commands::Apply { configs, json, filter, watch, dry_run, jobs, confirm_each, incremental, prune, yes_requirements, changed }) => {
+            let filter = runner::Filter::from(filter);
+            let configs = match changed.then(|| changed_configs(config.clone())).transpose()?.flatten() {
+                Some(keys) if keys.is_empty() => {
+                    println!("{} no config changes since the last commit", c!("✓", green));
+                    return Ok(());
+                }
+                Some(keys) => keys,
+                None => configs,
+            };
+            let jobs_arg = if jobs > 1 { format!(" --jobs {}", jobs) } else { String::new() };
+            let cmd = format!(
+                "apply{}{}{}{}{}{}",
+                if dry_run { " --dry-run" } else { "" },
+                if incremental { " --incremental" } else { "" },
+                if prune { " --prune" } else { "" },
+                if yes_requirements { " --yes-requirements" } else { "" },
+                jobs_arg,
+                filter.to_args()
+            );
+            if confirm_each && (remotes.is_some() || target.is_some()) {
+                bail!("--confirm-each only works for local apply");
+            }
+            let opts = RunOpts {
+                filter,
+                dry_run,
+                jobs,
+                confirm_each,
+                incremental,
+                prune,
+                yes_requirements,
+                ..RunOpts::new(output_format(json))
+            };
+            if watch {
+                if remotes.is_some() || target.is_some() {
+                    bail!("--watch only works for local apply");
+                }
+                run_watch(config, configs, quiet, opts)
+            } else if let Some(pattern) = remotes {
+                run_remotes(&pattern, &cmd, config, &configs, &remote_opts)
+            } else if let Some(t) = target {
+                run_remote(&t, &cmd, config.clone(), &configs, &remote_opts)
+            } else {
+                let result = run_mode(runner::Mode::Apply, config, configs, quiet, prepared, opts);
+                // Distinct exit status so CI can tell a failed gate from a failed apply
+                if let Err(ref e) = result {
+                    if e.is::<runner::AssertionsFailed>() {
+                        eprintln!("Error: {}", e);
+                        std::process::exit(3);
+                    }
+                }
+                result
+            }
+        }
+        Some(Commands::Check { configs, json, exit_code, filter, explain }) => {
+            let filter = runner::Filter::from(filter);
+            let cmd = format!(
+                "check{}{}{}",
+                if exit_code { " --exit-code" } else { "" },
+                filter.to_args(),
+                explain.as_ref().map(|e| format!(" --explain {}", e)).unwrap_or_default(),
+            );
             if let Some(pattern) = remotes {
-                run_remotes(&pattern, "apply", config, &configs)
+                run_remotes(&pattern, &cmd, config, &configs, &remote_opts)
             } else if let Some(t) = target {
-                run_remote(&t, "apply", config.clone(), &configs)
+                run_remote(&t, &cmd, config.clone(), &configs, &remote_opts)
             } else {
-                run_mode(runner::Mode::Apply, config, configs, quiet, prepared)
+                let opts = RunOpts { filter, exit_code, explain, ..RunOpts::new(output_format(json)) };
+                run_mode(runner::Mode::Check, config, configs, quiet, prepared, opts)
             }
         }
-        Some(Commands::Check { configs }) => {
+        Some(Commands::Plan { configs, json }) => {
             if let Some(pattern) = remotes {
-                run_remotes(&pattern, "check", config, &configs)
+                run_remotes(&pattern, "plan", config, &configs, &remote_opts)
             } else if let Some(t) = target {
-                run_remote(&t, "check", config.clone(), &configs)
+                run_remote(&t, "plan", config.clone(), &configs, &remote_opts)
             } else {
-                run_mode(runner::Mode::Check, config, configs, quiet, prepared)
+                run_mode(runner::Mode::Plan, config, configs, quiet, prepared, RunOpts::new(output_format(json)))
             }
         }
-        Some(Commands::Plan { configs }) => {
+        Some(Commands::Diff { configs, json }) => {
             if let Some(pattern) = remotes {
-                run_remotes(&pattern, "plan", config, &configs)
+                run_remotes(&pattern, "diff", config, &configs, &remote_opts)
             } else if let Some(t) = target {
-                run_remote(&t, "plan", config.clone(), &configs)
+                run_remote(&t, "diff", config.clone(), &configs, &remote_opts)
             } else {
-                run_mode(runner::Mode::Plan, config, configs, quiet, prepared)
+                run_mode(runner::Mode::Diff, config, configs, quiet, prepared, RunOpts::new(output_format(json)))
             }
         }
         Some(Commands::Run { name, args }) => {
             if remotes.is_some() || target.is_some() {
-                run_command_remote(config, name, args, target, remotes)
+                run_command_remote(config, name, args, target, remotes, &remote_opts)
             } else {
                 run_command(config, name, args)
             }
         }
-        Some(Commands::Test { image, rm, fresh, attach, selectors }) => run_test(config, image, rm, fresh, attach, selectors),
-        Some(Commands::Exec { cmd }) => run_exec(config, cmd),
-        Some(Commands::State { name, json, args }) => {
+        Some(Commands::Test { image, rm, fresh, attach, rebuild, env, env_file, no_shell, all, selectors }) => {
+            let opts = TestOpts { image, rm, fresh, attach, rebuild, env, env_file, no_shell, all, selectors };
+            run_test(config, opts)
+        },
+        Some(Commands::Exec { user, workdir, cmd }) => run_exec(config, user, workdir, cmd),
+        Some(Commands::State { name, json, format, watch, interval, no_cache, args }) => {
+            let format = if json { state::StateFormat::Json } else { format };
             if let Some(t) = target {
-                run_state_remote(&t, config, name, json, args)
+                if watch {
+                    bail!("--watch only works locally");
+                }
+                run_state_remote(&t, config, name, format, no_cache, args, &remote_opts)
             } else {
-                state::run(config, name, json, args)
+                state::run(config, name, format, watch, interval, no_cache, args)
             }
         }
-        Some(Commands::Bake { config: bake_config, output }) => {
-            bake::run(bake_config.or(config), output)
+        Some(Commands::Bake { config: bake_config, output, compression, encrypt }) => {
+            bake::run(bake_config.or(config), output, compression, encrypt, target)
         }
+        Some(Commands::Pack { config: pack_config, output }) => bake::pack(pack_config.or(config), output),
+        Some(Commands::Info { binary }) => bake::info(binary),
+        Some(Commands::Graph { format }) => graph::run(config, format),
+        Some(Commands::Validate) => validate::run(config),
+        Some(Commands::Cache { list, clear }) => cache::run(list, clear),
         Some(Commands::Completions { shell }) => {
             generate(shell, &mut Cli::command(), "dek", &mut io::stdout());
             Ok(())
         }
-        Some(Commands::Setup) => run_setup(),
+        Some(Commands::Setup { uninstall }) => run_setup(uninstall),
         None => {
             // No command - show rich help
             let config_path = config
                 .or_else(bake::check_embedded)
                 .or_else(config::find_default_config);
             if let Some(path) = config_path {
-                let meta = config::load_meta(&path);
+                let meta = config::load_meta(&path)?;
                 return print_rich_help(meta.as_ref(), &path);
             }
             // No config found - show basic clap help
@@ -277,6 +608,66 @@ fn main() -> Result<()> {
     }
 }
 
+/// CI env var set to anything but "", "0" or "false"
+fn is_ci() -> bool {
+    std::env::var("CI").is_ok_and(|v| !v.is_empty() && v != "0" && v != "false")
+}
+
+/// Remote execution options from the command line
+struct RemoteOpts {
+    /// Max hosts to run on concurrently
+    parallel: usize,
+    /// Subset of matched hosts to run on (--limit)
+    limit: Option<String>,
+    /// Print host output live instead of spinners (--stream)
+    stream: bool,
+    /// JSON deploy report path (--report)
+    report: Option<PathBuf>,
+    /// sudo password forwarded to remote dek (--ask-become-pass)
+    become_pass: Option<String>,
+    ssh: ssh::Ssh,
+}
+
+impl RemoteOpts {
+    fn from_cli(cli: &Cli) -> Result<Self> {
+        let become_pass = if cli.ask_become_pass {
+            Some(
+                dialoguer::Password::new()
+                    .with_prompt("BECOME password")
+                    .allow_empty_password(true)
+                    .interact()?,
+            )
+        } else {
+            None
+        };
+        Ok(Self {
+            parallel: cli.parallel,
+            limit: cli.limit.clone(),
+            stream: cli.stream,
+            report: cli.report.clone(),
+            become_pass,
+            ssh: ssh::Ssh {
+                control_master: !cli.no_control_master,
+                jump: cli.jump.clone(),
+                connect_timeout: Some(cli.connect_timeout).filter(|&s| s > 0),
+                host_timeout: cli.host_timeout.as_deref().map(util::parse_duration).transpose()?,
+                ..Default::default()
+            },
+        })
+    }
+
+    /// SSH settings with meta.toml [remote] defaults for anything not set on the CLI
+    fn ssh(&self, meta: Option<&config::Meta>) -> ssh::Ssh {
+        let remote = meta.and_then(|m| m.remote.as_ref());
+        let mut ssh = self.ssh.clone();
+        if ssh.jump.is_none() {
+            ssh.jump = remote.and_then(|r| r.bastion.clone());
+        }
+        ssh.host_key_checking = remote.map(|r| r.host_key_checking).unwrap_or_default();
+        ssh
+    }
+}
+
 /// Ensure well-known user binary directories are in PATH.
 /// Non-interactive SSH doesn't source .bashrc/.profile, so paths like
 /// ~/.cargo/bin, ~/.local/bin, ~/go/bin etc. are missing.
@@ -292,14 +683,12 @@ fn ensure_user_path() {
         format!("{}/go/bin", home),
         format!("{}/.npm-global/bin", home),
     ];
-    let current = std::env::var("PATH").unwrap_or_default();
-    let mut parts: Vec<&str> = current.split(':').collect();
     for dir in &extra {
-        if !parts.contains(&dir.as_str()) && std::path::Path::new(dir).is_dir() {
-            parts.push(dir);
+        let dir = std::path::Path::new(dir);
+        if dir.is_dir() {
+            util::add_to_path(dir, true);
         }
     }
-    std::env::set_var("PATH", parts.join(":"));
 }
 
 /// Compare semver strings (e.g. "0.1.28" > "0.1.27")
@@ -352,10 +741,14 @@ fn check_min_version(meta: Option<&config::Meta>) -> Result<()> {
 
 pub(crate) fn resolve_config(config: Option<PathBuf>) -> Result<PathBuf> {
     match config {
+        Some(path) if util::is_remote_config(&path.to_string_lossy()) => {
+            util::fetch_remote_config(&path.to_string_lossy())
+        }
         Some(path) => Ok(path),
         None => {
             // Check for embedded config first (baked binary)
             if let Some(path) = bake::check_embedded() {
+                config::disable_user_config_merge();
                 return Ok(path);
             }
             config::find_default_config()
@@ -364,20 +757,91 @@ pub(crate) fn resolve_config(config: Option<PathBuf>) -> Result<PathBuf> {
     }
 }
 
-fn run_mode(mode: runner::Mode, config_path: Option<PathBuf>, configs: Vec<String>, quiet: bool, prepared: bool) -> Result<()> {
+fn output_format(json: bool) -> output::Format {
+    if json {
+        output::Format::Json
+    } else {
+        output::Format::Human
+    }
+}
+
+/// Local apply/check/plan/diff options from the command line
+#[derive(Clone)]
+struct RunOpts {
+    format: output::Format,
+    filter: runner::Filter,
+    dry_run: bool,
+    /// check --exit-code
+    exit_code: bool,
+    jobs: usize,
+    confirm_each: bool,
+    incremental: bool,
+    prune: bool,
+    yes_requirements: bool,
+    /// check --explain
+    explain: Option<String>,
+}
+
+impl RunOpts {
+    fn new(format: output::Format) -> Self {
+        Self {
+            format,
+            filter: runner::Filter::default(),
+            dry_run: false,
+            exit_code: false,
+            jobs: 1,
+            confirm_each: false,
+            incremental: false,
+            prune: false,
+            yes_requirements: false,
+            explain: None,
+        }
+    }
+}
+
+fn run_mode(
+    mode: runner::Mode, config_path: Option<PathBuf>, configs: Vec<String>, quiet: bool, prepared: bool, opts: RunOpts,
+) -> Result<()> {
+    let RunOpts { format, filter, dry_run, exit_code, jobs, confirm_each, incremental, prune, yes_requirements, explain } = opts;
     let path = resolve_config(config_path)?;
+    // -C dek/30-servers.toml: apply just that file, as a selector of its directory
+    let (path, configs) = match config::config_file_in_dir(&path) {
+        Some((dir, key)) if configs.is_empty() => (dir, vec![key]),
+        _ => (path, configs),
+    };
     let resolved_path = config::resolve_path(&path)?;
     util::init_lib(&resolved_path);
-    let meta = config::load_meta(&resolved_path);
+    let meta = config::load_meta(&resolved_path)?;
     check_min_version(meta.as_ref())?;
 
     let verb = match mode {
+        runner::Mode::Apply if dry_run => "Dry run for",
         runner::Mode::Apply => "Applying",
         runner::Mode::Check => "Checking",
         runner::Mode::Plan => "Plan for",
+        runner::Mode::Diff => "Diff for",
     };
 
-    if !quiet {
+    let mode_name = match mode {
+        runner::Mode::Apply if dry_run => "apply --dry-run",
+        runner::Mode::Apply => "apply",
+        runner::Mode::Check => "check",
+        runner::Mode::Plan => "plan",
+        runner::Mode::Diff => "diff",
+    };
+    output::log(&format!(
+        "{} {}{} on {}",
+        mode_name,
+        path.display(),
+        if configs.is_empty() { String::new() } else { format!(" [{}]", configs.join(", ")) },
+        hostname::get().map(|h| h.to_string_lossy().to_string()).unwrap_or_default(),
+    ));
+
+    // Keep stdout pure NDJSON in json mode (plain mode would print live command output)
+    if format == output::Format::Json {
+        output::set_plain(false);
+    }
+    if !quiet && format == output::Format::Human {
         if let Some(banner) = meta.as_ref().and_then(|m| m.banner.as_ref()) {
             for line in banner.lines() {
                 println!("{}", c!(line, bold));
@@ -398,7 +862,7 @@ fn run_mode(mode: runner::Mode, config_path: Option<PathBuf>, configs: Vec<Strin
 
     // Apply runtime vars from meta.toml before anything else.
     // Use effective selectors (explicit or defaults) for scoped vars.
-    if let Some(ref vars) = meta.as_ref().and_then(|m| m.vars.as_ref()) {
+    if let Some(vars) = meta.as_ref().and_then(|m| m.vars.as_ref()) {
         let defaults = meta.as_ref().map(|m| &m.defaults[..]).unwrap_or(&[]);
         let effective: &[String] = if configs.is_empty() { defaults } else { &configs };
         config::apply_vars(vars, effective);
@@ -407,51 +871,318 @@ fn run_mode(mode: runner::Mode, config_path: Option<PathBuf>, configs: Vec<Strin
     let config = config::load_for_apply(&resolved_path, &configs, meta.as_ref())?;
 
     // Resolve artifacts (build outputs) before running.
-    // Skip when --prepared (rsync remote deploy) or tarball (bake).
-    let working_path = if prepared || util::is_tar_gz(&path) {
+    // Skip when --prepared (rsync remote deploy), tarball (packed or baked) or dry run.
+    let working_path = if prepared || dry_run || util::is_tar_gz(&path) {
         resolved_path.clone()
     } else {
         prepare_config(&resolved_path, &config)?
     };
 
-    let runner = runner::Runner::new(mode);
+    // Prune against every config, so applying a selection doesn't drop the rest
+    let all = if prune { Some(config::load_all(&resolved_path)?) } else { None };
+
+    let runner = runner::Runner::new(mode, format).with_policy(providers::Policy::from_meta(config.meta.as_ref())).with_filter(filter).with_dry_run(dry_run).with_exit_code(exit_code).with_jobs(jobs)
+        .with_confirm_each(confirm_each).with_incremental(incremental).with_prune(all).with_yes_requirements(yes_requirements)
+        .with_explain(explain);
     runner.run(&config, &working_path)
 }
 
+/// `apply --watch`: apply, then re-apply whenever a config file or a file the
+/// items read from changes. Failed runs are reported and watching goes on.
+fn run_watch(config_path: Option<PathBuf>, configs: Vec<String>, quiet: bool, opts: RunOpts) -> Result<()> {
+    let path = resolve_config(config_path)?;
+    if util::is_tar_gz(&path) {
+        bail!("--watch needs a config file or directory, not an archive");
+    }
+    let path = std::fs::canonicalize(&path)?;
+    let config_dir = if path.is_dir() {
+        path.clone()
+    } else {
+        path.parent().unwrap_or(std::path::Path::new("/")).to_path_buf()
+    };
+
+    loop {
+        if let Err(e) = run_mode(runner::Mode::Apply, Some(path.clone()), configs.clone(), quiet, false, opts.clone()) {
+            eprintln!("{} {:#}", c!("✗", red), e);
+        }
+
+        // run_mode above already reported a broken meta.toml
+        let meta = config::load_meta(&path).unwrap_or_default();
+        let sources: Vec<PathBuf> = config::load_for_apply(&path, &configs, meta.as_ref())
+            .map(|c| runner::source_files(&c, &config_dir))
+            .unwrap_or_default()
+            .into_iter()
+            .map(|p| std::fs::canonicalize(&p).unwrap_or(p))
+            .collect();
+
+        eprintln!();
+        eprintln!("{} watching for changes (Ctrl-C to stop)", c!("…", dimmed));
+        let changed = wait_for_change(&config_dir, &sources)?;
+        eprintln!("{} {} changed", c!("↻", cyan), changed.display());
+        eprintln!();
+    }
+}
+
+/// Block until a .toml under `config_dir` or one of `sources` changes, then
+/// wait for events to settle (300ms) so a burst of writes triggers one run
+fn wait_for_change(config_dir: &std::path::Path, sources: &[PathBuf]) -> Result<PathBuf> {
+    use notify::{RecursiveMode, Watcher};
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        if let Ok(event) = res {
+            if !matches!(event.kind, notify::EventKind::Access(_)) {
+                for p in event.paths {
+                    let _ = tx.send(p);
+                }
+            }
+        }
+    })?;
+    watcher.watch(config_dir, RecursiveMode::Recursive)?;
+
+    // Watch parent dirs rather than the files: editors often save by
+    // replacing the file, which would drop a watch on the file itself
+    let mut parents: Vec<&std::path::Path> = sources
+        .iter()
+        .filter_map(|s| s.parent())
+        .filter(|p| !p.starts_with(config_dir))
+        .collect();
+    parents.sort();
+    parents.dedup();
+    for dir in parents {
+        let _ = watcher.watch(dir, RecursiveMode::NonRecursive);
+    }
+
+    let relevant = |p: &std::path::Path| {
+        (p.starts_with(config_dir) && p.extension().is_some_and(|e| e == "toml")) || sources.iter().any(|s| s == p)
+    };
+    let changed = loop {
+        let p = rx.recv()?;
+        if relevant(&p) {
+            break p;
+        }
+    };
+    while rx.recv_timeout(std::time::Duration::from_millis(300)).is_ok() {}
+    Ok(changed)
+}
+
 /// Pre-built config dir and binary info for remote deployment
 struct RemotePayload {
     prepared_dir: PathBuf,
     bin_hash: String,
     dek_binary: PathBuf,
+    /// Binaries for other platforms ([remote] binaries): "os-arch" → (path, hash)
+    binaries: std::collections::BTreeMap<String, (PathBuf, String)>,
+    /// rsync --exclude patterns from meta.toml [remote] and .dekignore
+    excludes: Vec<String>,
+    /// Content hash of the prepared dir and excludes, to skip unchanged syncs
+    config_hash: String,
 }
 
 impl RemotePayload {
-    fn prepare(prepared_dir: &std::path::Path) -> Result<Self> {
+    fn prepare(prepared_dir: &std::path::Path, meta: Option<&config::Meta>) -> Result<Self> {
         let dek_binary = std::env::current_exe()?;
         let bin_data = std::fs::read(&dek_binary)?;
         let bin_hash = format!("{:x}", md5::compute(&bin_data));
 
-        Ok(Self { prepared_dir: prepared_dir.to_path_buf(), bin_hash, dek_binary })
+        let mut excludes: Vec<String> = meta
+            .and_then(|m| m.remote.as_ref())
+            .map(|r| r.rsync_exclude.clone())
+            .unwrap_or_default();
+        if let Ok(content) = std::fs::read_to_string(prepared_dir.join(".dekignore")) {
+            excludes.extend(
+                content.lines()
+                    .map(|l| l.trim())
+                    .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                    .map(|l| l.to_string()),
+            );
+        }
+
+        let config_hash = dir_hash(prepared_dir, &excludes.join("\n"));
+
+        let mut binaries = std::collections::BTreeMap::new();
+        for (platform, path) in meta.and_then(|m| m.remote.as_ref()).map(|r| &r.binaries).into_iter().flatten() {
+            let path = prepared_dir.join(util::expand_path(path));
+            let data = std::fs::read(&path)
+                .map_err(|e| anyhow::anyhow!("[remote] binaries: no {} binary at {}: {}", platform, path.display(), e))?;
+            // A bare arch (`aarch64`) is a Linux binary
+            let (os, arch) = platform.split_once('-').unwrap_or(("linux", platform));
+            let platform = format!("{}-{}", normalize_os(os), normalize_arch(arch));
+            binaries.insert(platform, (path, format!("{:x}", md5::compute(&data))));
+        }
+
+        Ok(Self { prepared_dir: prepared_dir.to_path_buf(), bin_hash, dek_binary, binaries, excludes, config_hash })
+    }
+
+    /// The binary (and its hash) to run on the probed host: this dek when its
+    /// OS and arch (`uname -s`, `uname -m`) match, else one from [remote]
+    /// binaries. Checked before uploading, since a wrong one only fails once
+    /// it's run.
+    fn binary_for(&self, target: &str, probe: &RemoteProbe) -> Result<(&std::path::Path, &str)> {
+        let (os, arch) = (normalize_os(&probe.os), normalize_arch(&probe.arch));
+        if os.is_empty() || arch.is_empty() || (os == std::env::consts::OS && arch == std::env::consts::ARCH) {
+            return Ok((&self.dek_binary, &self.bin_hash));
+        }
+        let platform = format!("{}-{}", os, arch);
+        match self.binaries.get(&platform) {
+            Some((path, hash)) => Ok((path, hash)),
+            None => bail!(
+                "{} is {}, this dek is {}-{}: add a dek binary for it under [remote] binaries ({} = \"path/to/dek\")",
+                target,
+                platform,
+                std::env::consts::OS,
+                std::env::consts::ARCH,
+                platform
+            ),
+        }
+    }
+
+    /// rsync arguments for syncing the prepared config to `remote_dest`
+    fn rsync_args(&self, remote_dest: &str) -> Vec<String> {
+        let mut args = vec!["-az".to_string(), "--delete".to_string()];
+        for pattern in &self.excludes {
+            args.push(format!("--exclude={}", pattern));
+        }
+        args.push(format!("{}/", self.prepared_dir.display()));
+        args.push(remote_dest.to_string());
+        args
+    }
+
+    /// Copy `binary` (from `binary_for`) to `target`
+    fn upload_binary(&self, ssh: &ssh::Ssh, target: &str, binary: &std::path::Path) -> Result<()> {
+        let mut scp = ssh.scp();
+        scp.args(["-q", &binary.to_string_lossy(), &format!("{}:{}", target, REMOTE_BIN)]);
+        let output = util::retry_output(|| Ok(ssh.output(&mut scp)?), ssh::is_transient)?;
+        if !output.status.success() {
+            let err = String::from_utf8_lossy(&output.stderr);
+            bail!("Failed to copy dek binary to {}: {}", target, err.trim());
+        }
+        Ok(())
+    }
+
+    /// Rsync the prepared config to `target`, unless it's the config last
+    /// synced there and the remote copy still carries its hash. Returns
+    /// whether anything was synced.
+    fn sync_config(&self, ssh: &ssh::Ssh, target: &str, remote: &RemoteProbe) -> Result<bool> {
+        if remote.config_hash == self.config_hash
+            && cache::get_deployed(target).as_deref() == Some(self.config_hash.as_str())
+        {
+            return Ok(false);
+        }
+
+        let remote_dest = format!("{}:{}", target, REMOTE_CONFIG);
+        let mut rsync = ssh.rsync();
+        rsync.args(self.rsync_args(&remote_dest));
+        let rsync = util::retry_output(|| Ok(ssh.output(&mut rsync)?), ssh::is_transient)?;
+        if !rsync.status.success() {
+            let err = String::from_utf8_lossy(&rsync.stderr);
+            bail!("Failed to rsync config to {}: {}", target, err.trim());
+        }
+
+        let mark = format!("echo {} > {}", self.config_hash, REMOTE_CONFIG_HASH);
+        if ssh.output(ssh.ssh(target).arg(&mark)).map(|o| o.status.success()).unwrap_or(false) {
+            cache::set_deployed(target, &self.config_hash);
+        }
+        Ok(true)
     }
 }
 
-fn run_remote(target: &str, cmd: &str, config_path: Option<PathBuf>, configs: &[String]) -> Result<()> {
-    let config_path = resolve_config(config_path)?;
+/// `uname -s` names as Rust spells them (`std::env::consts::OS`)
+fn normalize_os(os: &str) -> String {
+    match os.to_ascii_lowercase().as_str() {
+        "darwin" => "macos".to_string(),
+        os => os.to_string(),
+    }
+}
+
+/// `uname -m` names as Rust spells them (`std::env::consts::ARCH`)
+fn normalize_arch(arch: &str) -> &str {
+    match arch {
+        "amd64" => "x86_64",
+        "arm64" => "aarch64",
+        "i386" | "i486" | "i586" | "i686" => "x86",
+        a if a.starts_with("armv") => "arm",
+        a => a,
+    }
+}
+
+const REMOTE_DIR: &str = "~/.cache/dek/remote";
+const REMOTE_BIN: &str = "~/.cache/dek/remote/dek";
+const REMOTE_CONFIG: &str = "~/.cache/dek/remote/config/";
+/// Hash of the last config synced to the host (outside config/, which rsync --delete owns)
+const REMOTE_CONFIG_HASH: &str = "~/.cache/dek/remote/config.hash";
+
+/// What's already on a remote host: its dek binary and config hashes, and
+/// its OS and architecture (`uname -s`, `uname -m`)
+struct RemoteProbe {
+    bin_hash: String,
+    os: String,
+    arch: String,
+    config_hash: String,
+}
+
+impl RemoteProbe {
+    /// Create the remote dir and read both hashes in one round trip; fails
+    /// if the host can't be reached
+    fn run(ssh: &ssh::Ssh, target: &str) -> Result<Self> {
+        let check_cmd = format!(
+            "mkdir -p {dir} && if [ -f {bin} ]; then md5sum {bin} | cut -d' ' -f1; else echo; fi && \
+             (uname -sm || echo) && if [ -d {cfg} ] && [ -f {hash} ]; then cat {hash}; fi",
+            dir = REMOTE_DIR,
+            bin = REMOTE_BIN,
+            cfg = REMOTE_CONFIG,
+            hash = REMOTE_CONFIG_HASH,
+        );
+        let mut probe = ssh.ssh(target);
+        probe.arg(&check_cmd);
+        let output = util::retry_output(|| Ok(ssh.output(&mut probe)?), ssh::is_transient)?;
+        if !output.status.success() {
+            bail!("Failed to connect to {}", target);
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let mut lines = stdout.lines().map(|l| l.trim().to_string());
+        let bin_hash = lines.next().unwrap_or_default();
+        let platform = lines.next().unwrap_or_default();
+        let (os, arch) = platform.split_once(' ').unwrap_or_default();
+        Ok(Self {
+            bin_hash,
+            os: os.to_string(),
+            arch: arch.to_string(),
+            config_hash: lines.next().unwrap_or_default(),
+        })
+    }
+}
+
+fn run_remote(target: &str, cmd: &str, config_path: Option<PathBuf>, configs: &[String], opts: &RemoteOpts) -> Result<()> {
+    // A packed config deploys as its extracted dir (rsync needs a directory)
+    let config_path = config::resolve_path(resolve_config(config_path)?)?;
     let config_abs = std::fs::canonicalize(&config_path)?;
     util::init_lib(&config_abs);
-    let meta = config::load_meta(&config_path);
+    let meta = config::load_meta(&config_path)?;
     let remote_install = meta.as_ref().map(|m| m.remote_install).unwrap_or(false);
     let bin_name = meta.as_ref().and_then(|m| m.bin_name.as_deref()).unwrap_or("dek");
 
     output::print_header(&format!("{} on {}", cmd, target));
     println!();
 
+    let group_configs = if configs.is_empty() {
+        group_configs(target, meta.as_ref(), config::load_inventory(&config_path)?.as_ref())
+    } else {
+        Vec::new()
+    };
+    let configs = if group_configs.is_empty() {
+        configs
+    } else {
+        println!("  {} configs by group: {}", c!("→", yellow), group_configs.join(" "));
+        &group_configs[..]
+    };
+
     // Prepare config (artifacts + includes)
     let dek_config = config::load(&config_path)?;
     let prepared_config = prepare_config(&config_abs, &dek_config)?;
     let prepared_abs = std::fs::canonicalize(&prepared_config)?;
 
-    let payload = RemotePayload::prepare(&prepared_abs)?;
+    let payload = RemotePayload::prepare(&prepared_abs, meta.as_ref())?;
 
     // Show payload sizes
     let config_size = dir_size(&payload.prepared_dir);
@@ -463,7 +1194,13 @@ fn run_remote(target: &str, cmd: &str, config_path: Option<PathBuf>, configs: &[
     );
     println!();
 
-    let result = deploy_to_host(target, cmd, configs, &payload, None, remote_install, bin_name)?;
+    let ssh = opts.ssh(meta.as_ref());
+    let start = std::time::Instant::now();
+    let result = deploy_to_host(target, cmd, configs, &payload, DeployProgress::Direct, remote_install, bin_name, &ssh, opts.become_pass.as_deref());
+    if let Some(ref path) = opts.report {
+        write_report(path, &[ReportEntry::new(target, &result, start.elapsed())])?;
+    }
+    let result = result?;
 
     // Print full remote output for single-host
     for line in result.output.lines() {
@@ -486,83 +1223,84 @@ fn run_state_remote(
     target: &str,
     config_path: Option<PathBuf>,
     name: Option<String>,
-    json: bool,
+    format: state::StateFormat,
+    no_cache: bool,
     args: Vec<String>,
+    opts: &RemoteOpts,
 ) -> Result<()> {
-    let config_path = resolve_config(config_path)?;
+    // A packed config deploys as its extracted dir (rsync needs a directory)
+    let config_path = config::resolve_path(resolve_config(config_path)?)?;
     let config_abs = std::fs::canonicalize(&config_path)?;
 
+    let meta = config::load_meta(&config_path)?;
+
     output::print_header(&format!("state on {}", target));
     println!();
 
     let dek_config = config::load(&config_path)?;
     let prepared_config = prepare_config(&config_abs, &dek_config)?;
     let prepared_abs = std::fs::canonicalize(&prepared_config)?;
-    let payload = RemotePayload::prepare(&prepared_abs)?;
+    let payload = RemotePayload::prepare(&prepared_abs, meta.as_ref())?;
 
-    let remote_dir = "~/.cache/dek/remote";
-    let remote_bin = format!("{}/dek", remote_dir);
-    let remote_config = format!("{}/config/", remote_dir);
+    let ssh = opts.ssh(meta.as_ref()).start_clock();
+    let _master = ssh.master(target);
 
-    // Check connection + binary hash
-    let check_cmd = format!(
-        "mkdir -p {} && if [ -f {} ]; then md5sum {} | cut -d' ' -f1; fi",
-        remote_dir, remote_bin, remote_bin
-    );
-    let check_output = Command::new("ssh").args([target, &check_cmd]).output()?;
-    if !check_output.status.success() {
-        bail!("Failed to connect to {}", target);
-    }
-    let remote_hash = String::from_utf8_lossy(&check_output.stdout).trim().to_string();
+    // Check connection + binary/config hashes
+    let probe = RemoteProbe::run(&ssh, target)?;
 
     // Upload binary only if hash differs
-    if remote_hash != payload.bin_hash {
+    let (binary, bin_hash) = payload.binary_for(target, &probe)?;
+    if probe.bin_hash != bin_hash {
         println!("  {} uploading binary...", c!("→", yellow));
-        let scp = Command::new("scp")
-            .args(["-q", &payload.dek_binary.to_string_lossy(), &format!("{}:{}", target, remote_bin)])
-            .status()?;
-        if !scp.success() {
-            bail!("Failed to copy dek binary to {}", target);
-        }
+        payload.upload_binary(&ssh, target, binary)?;
     }
 
-    // Rsync config
-    let local_src = format!("{}/", payload.prepared_dir.display());
-    let remote_dest = format!("{}:{}", target, remote_config);
-    let rsync = Command::new("rsync")
-        .args(["-az", "--delete", &local_src, &remote_dest])
-        .output()?;
-    if !rsync.status.success() {
-        bail!("Failed to rsync config to {}: {}", target, String::from_utf8_lossy(&rsync.stderr).trim());
-    }
+    payload.sync_config(&ssh, target, &probe)?;
 
     // Build remote state command
     let mut parts = vec![
-        remote_bin,
+        REMOTE_BIN.to_string(),
         "--prepared".to_string(),
         "state".to_string(),
         "-C".to_string(),
-        remote_config,
+        REMOTE_CONFIG.to_string(),
     ];
     if let Some(n) = name {
         parts.push(n);
     }
-    if json {
-        parts.push("--json".to_string());
-    }
     for arg in args {
         parts.push(arg);
     }
-    let remote_cmd = parts.join(" ");
+    // After the args, where the remote's trailing args pick it up
+    let mut remote_cmd = parts.join(" ") + format.to_arg();
+    if no_cache {
+        remote_cmd.push_str(" --no-cache");
+    }
 
     // Stream output directly
-    let status = Command::new("ssh").args([target, &remote_cmd]).status()?;
+    let mut child = ssh.ssh(target).arg(&remote_cmd).spawn()?;
+    let _watchdog = ssh.watch(&child);
+    let status = child.wait()?;
+    if let (Some(timeout), true) = (ssh.host_timeout, ssh.timed_out()) {
+        bail!("State on {} timed out after {}", target, output::format_duration(timeout));
+    }
     if !status.success() {
         bail!("Remote state failed on {}", target);
     }
     Ok(())
 }
 
+/// How deploy progress and remote output are shown
+#[derive(Clone, Copy)]
+enum DeployProgress<'a> {
+    /// Single host: step messages printed, remote output inherited
+    Direct,
+    /// Multi-host: steps shown on the host's spinner, output captured for the summary
+    Spinner(&'a indicatif::ProgressBar),
+    /// Multi-host --stream: every line printed as it arrives, prefixed with [host]
+    Stream,
+}
+
 /// Result of deploying to a single host
 struct DeployResult {
     host: String,
@@ -571,62 +1309,96 @@ struct DeployResult {
     duration: std::time::Duration,
 }
 
+/// One host's entry in the --report JSON
+#[derive(serde::Serialize)]
+struct ReportEntry {
+    host: String,
+    success: bool,
+    duration_ms: u64,
+    summary: Option<String>,
+    /// What apply changed on the host ("package.apt installed htop")
+    changes: Vec<String>,
+}
+
+impl ReportEntry {
+    /// `elapsed` is used as the duration when the deploy errored out early
+    fn new(host: &str, result: &Result<DeployResult>, elapsed: std::time::Duration) -> Self {
+        match result {
+            Ok(r) => Self {
+                host: r.host.clone(),
+                success: r.success,
+                duration_ms: r.duration.as_millis() as u64,
+                summary: output::extract_summary_line(&r.output),
+                changes: output::extract_changes(&r.output),
+            },
+            Err(e) => Self {
+                host: host.to_string(),
+                success: false,
+                duration_ms: elapsed.as_millis() as u64,
+                summary: Some(e.to_string()),
+                changes: Vec::new(),
+            },
+        }
+    }
+}
+
+/// Write the --report JSON array
+fn write_report(path: &std::path::Path, entries: &[ReportEntry]) -> Result<()> {
+    let json = serde_json::to_string_pretty(entries)?;
+    std::fs::write(path, json + "\n")
+        .map_err(|e| anyhow::anyhow!("Failed to write report {}: {}", path.display(), e))
+}
+
+/// Deploy and run `cmd` on one host. With --host-timeout, whatever is still
+/// running when it runs out is killed and the host fails as timed out.
+#[allow(clippy::too_many_arguments)]
 fn deploy_to_host(
     target: &str, cmd: &str, configs: &[String], payload: &RemotePayload,
-    pb: Option<&indicatif::ProgressBar>, remote_install: bool, bin_name: &str,
+    progress: DeployProgress, remote_install: bool, bin_name: &str, ssh: &ssh::Ssh,
+    become_pass: Option<&str>,
+) -> Result<DeployResult> {
+    let ssh = ssh.start_clock();
+    let result = deploy_steps(target, cmd, configs, payload, progress, remote_install, bin_name, &ssh, become_pass);
+    match (result, ssh.host_timeout) {
+        (Ok(r), _) if r.success => Ok(r),
+        (_, Some(timeout)) if ssh.timed_out() => bail!("timed out after {}", output::format_duration(timeout)),
+        (result, _) => result,
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn deploy_steps(
+    target: &str, cmd: &str, configs: &[String], payload: &RemotePayload,
+    progress: DeployProgress, remote_install: bool, bin_name: &str, ssh: &ssh::Ssh,
+    become_pass: Option<&str>,
 ) -> Result<DeployResult> {
     let start = std::time::Instant::now();
-    let remote_dir = "~/.cache/dek/remote";
-    let remote_bin = format!("{}/dek", remote_dir);
-    let remote_config = format!("{}/config/", remote_dir);
     let mut log = String::new();
 
-    let update = |msg: &str| {
-        if let Some(pb) = pb {
-            pb.set_message(msg.to_string());
-        } else {
-            println!("  {} {}", c!("→", yellow), msg);
-        }
+    let update = |msg: &str| match progress {
+        DeployProgress::Direct => println!("  {} {}", c!("→", yellow), msg),
+        DeployProgress::Spinner(pb) => pb.set_message(msg.to_string()),
+        DeployProgress::Stream => println!("{} {}", output::host_prefix(target), c!(msg, dimmed)),
     };
 
-    // Setup remote dir + check if binary already exists with same hash
+    // Setup remote dir + check if binary/config already exist with same hash
     update("connecting...");
-    let check_cmd = format!(
-        "mkdir -p {} && if [ -f {} ]; then md5sum {} | cut -d' ' -f1; fi",
-        remote_dir, remote_bin, remote_bin
-    );
-    let check_output = Command::new("ssh")
-        .args([target, &check_cmd])
-        .output()?;
-    if !check_output.status.success() {
-        bail!("Failed to connect to {}", target);
-    }
-
-    let remote_hash = String::from_utf8_lossy(&check_output.stdout).trim().to_string();
+    let _master = ssh.master(target);
+    let probe = RemoteProbe::run(ssh, target)?;
 
     // Copy binary only if hash differs
-    if remote_hash != payload.bin_hash {
+    let (binary, bin_hash) = payload.binary_for(target, &probe)?;
+    if probe.bin_hash != bin_hash {
         update("uploading binary...");
-        let scp_bin = Command::new("scp")
-            .args(["-q", &payload.dek_binary.to_string_lossy(), &format!("{}:{}", target, remote_bin)])
-            .status()?;
-        if !scp_bin.success() {
-            bail!("Failed to copy dek binary to {}", target);
-        }
+        payload.upload_binary(ssh, target, binary)?;
     } else {
         update("binary cached");
     }
 
-    // Rsync config
+    // Rsync config only if it changed since the last deploy to this host
     update("syncing config...");
-    let local_src = format!("{}/", payload.prepared_dir.display());
-    let remote_dest = format!("{}:{}", target, remote_config);
-    let rsync = Command::new("rsync")
-        .args(["-az", "--delete", &local_src, &remote_dest])
-        .output()?;
-    if !rsync.status.success() {
-        let err = String::from_utf8_lossy(&rsync.stderr);
-        bail!("Failed to rsync config to {}: {}", target, err.trim());
+    if !payload.sync_config(ssh, target, &probe)? {
+        update("config cached");
     }
 
     // Symlink config + binary so `dek` works standalone on remote
@@ -635,37 +1407,60 @@ fn deploy_to_host(
             "mkdir -p ~/.config && ln -sfn {cfg} ~/.config/dek && \
              if [ \"$(id -u)\" = \"0\" ]; then ln -sf {bin} /usr/local/bin/{bin_name}; \
              else mkdir -p ~/.local/bin && ln -sf {bin} ~/.local/bin/{bin_name}; fi",
-            cfg = remote_config.trim_end_matches('/'),
-            bin = remote_bin,
+            cfg = REMOTE_CONFIG.trim_end_matches('/'),
+            bin = REMOTE_BIN,
             bin_name = bin_name,
         );
-        let _ = Command::new("ssh").args([target, &link_cmd]).output();
+        let _ = ssh.output(ssh.ssh(target).arg(&link_cmd));
     }
 
     // Run dek on remote
     update(&format!("running {}...", cmd));
     let configs_arg = configs.join(" ");
+    // The sudo password goes over the ssh channel's stdin, never on the command line
+    let pass_arg = if become_pass.is_some() { " --become-pass-stdin" } else { "" };
+    let force_arg = if cache::force() { " --force" } else { "" };
+    let insecure_arg = if util::insecure() { " --insecure" } else { "" };
+    let retries_arg = format!(" --retries {}", util::retries());
+    let verbose_arg = match output::verbosity() {
+        0 => String::new(),
+        n => format!(" -{}", "v".repeat(n as usize)),
+    };
 
-    let success = if pb.is_some() {
+    let quiet_arg = if matches!(progress, DeployProgress::Direct) { "" } else { " -q" };
+    let remote_cmd = format!("{}{}{} --prepared{}{}{}{} {} -C {} {}", REMOTE_BIN, quiet_arg, verbose_arg, pass_arg, force_arg, insecure_arg, retries_arg, cmd, REMOTE_CONFIG, configs_arg);
+    let mut remote = ssh.ssh(target);
+    remote.arg(&remote_cmd);
+
+    let success = match progress {
+        // Single-host: output goes straight to the terminal
+        DeployProgress::Direct => {
+            let mut child = spawn_with_become_pass(&mut remote, become_pass)?;
+            let _watchdog = ssh.watch(&child);
+            child.wait()?.success()
+        }
         // Multi-host: capture output for the progress display
-        let remote_cmd = format!("{} -q --prepared {} -C {} {}", remote_bin, cmd, remote_config, configs_arg);
-        let output = Command::new("ssh")
-            .args([target, &remote_cmd])
-            .output()?;
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        log.push_str(&stdout);
-        if !stderr.is_empty() {
-            log.push_str(&stderr);
+        DeployProgress::Spinner(_) => {
+            remote.stdout(Stdio::piped()).stderr(Stdio::piped());
+            let child = spawn_with_become_pass(&mut remote, become_pass)?;
+            let _watchdog = ssh.watch(&child);
+            let output = child.wait_with_output()?;
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            log.push_str(&stdout);
+            if !stderr.is_empty() {
+                log.push_str(&stderr);
+            }
+            output.status.success()
+        }
+        // Multi-host --stream: print lines as they arrive, still keep them for the summary
+        DeployProgress::Stream => {
+            remote.stdout(Stdio::piped()).stderr(Stdio::piped());
+            let mut child = spawn_with_become_pass(&mut remote, become_pass)?;
+            let _watchdog = ssh.watch(&child);
+            stream_host_output(&mut child, target, &mut log);
+            child.wait()?.success()
         }
-        output.status.success()
-    } else {
-        // Single-host: stream output directly
-        let remote_cmd = format!("{} --prepared {} -C {} {}", remote_bin, cmd, remote_config, configs_arg);
-        Command::new("ssh")
-            .args([target, &remote_cmd])
-            .status()?
-            .success()
     };
 
     Ok(DeployResult {
@@ -676,32 +1471,167 @@ fn deploy_to_host(
     })
 }
 
-fn run_remotes(pattern: &str, cmd: &str, config_path: Option<PathBuf>, configs: &[String]) -> Result<()> {
+/// Spawn a remote command, writing the sudo password (if any) as the first
+/// line of its stdin
+fn spawn_with_become_pass(cmd: &mut Command, become_pass: Option<&str>) -> Result<std::process::Child> {
+    use std::io::Write;
+
+    if become_pass.is_some() {
+        cmd.stdin(Stdio::piped());
+    }
+    let mut child = cmd.spawn()?;
+    if let (Some(pass), Some(mut stdin)) = (become_pass, child.stdin.take()) {
+        stdin.write_all(pass.as_bytes())?;
+        stdin.write_all(b"\n")?;
+    }
+    Ok(child)
+}
+
+/// Print each stdout/stderr line of a remote command prefixed with `[host]`
+/// as it arrives, collecting the lines into `log`
+fn stream_host_output(child: &mut std::process::Child, host: &str, log: &mut String) {
+    use std::io::{BufRead, BufReader};
+
+    let prefix = output::host_prefix(host);
+    let stdout = child.stdout.take();
+    let stderr = child.stderr.take();
+    let forward = |reader: Option<Box<dyn std::io::Read + Send>>| {
+        let mut collected = String::new();
+        if let Some(reader) = reader {
+            for line in BufReader::new(reader).lines().map_while(Result::ok) {
+                println!("{} {}", prefix, line);
+                collected.push_str(&line);
+                collected.push('\n');
+            }
+        }
+        collected
+    };
+
+    std::thread::scope(|s| {
+        let err = s.spawn(|| forward(stderr.map(|e| Box::new(e) as Box<dyn std::io::Read + Send>)));
+        log.push_str(&forward(stdout.map(|o| Box::new(o) as Box<dyn std::io::Read + Send>)));
+        log.push_str(&err.join().unwrap_or_default());
+    });
+}
+
+/// Trim matched hosts to `--limit`: a count (first N) or a comma-separated
+/// list of host names. Prints the hosts left out.
+fn apply_limit<T: AsRef<str>>(matched: Vec<T>, limit: &str) -> Result<Vec<T>> {
+    let (kept, excluded): (Vec<T>, Vec<T>) = if let Ok(n) = limit.parse::<usize>() {
+        if n == 0 {
+            bail!("--limit must be at least 1");
+        }
+        let mut kept = matched;
+        let excluded = kept.split_off(n.min(kept.len()));
+        (kept, excluded)
+    } else {
+        let names: Vec<&str> = limit.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()).collect();
+        for name in &names {
+            if !matched.iter().any(|h| h.as_ref() == *name) {
+                bail!("--limit host '{}' is not among the matched hosts", name);
+            }
+        }
+        matched.into_iter().partition(|h| names.contains(&h.as_ref()))
+    };
+
+    if !excluded.is_empty() {
+        let names: Vec<&str> = excluded.iter().map(|h| h.as_ref()).collect();
+        println!("{} Excluded by --limit ({}): {}", c!("::", yellow), names.len(), names.join(", "));
+    }
+    Ok(kept)
+}
+
+/// Host glob: `*` matches any run of characters, `?` any one, `[...]` one
+/// from a class (`web-0[1-3]`, `[!a]` for anything but a); all else is literal
+fn host_glob(pattern: &str, host: &str) -> bool {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut regex = String::from("^");
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            '[' => {
+                let negated = matches!(chars.get(i + 1), Some('!' | '^'));
+                let body = if negated { i + 2 } else { i + 1 };
+                // A `]` right after the opening is part of the class (`[]a]`)
+                let close = chars.iter().skip(body + 1).position(|&c| c == ']').map(|p| p + body + 1);
+                match close {
+                    Some(end) => {
+                        regex.push_str(if negated { "[^" } else { "[" });
+                        for &c in &chars[body..end] {
+                            if matches!(c, '\\' | '[' | ']' | '^' | '&' | '~') {
+                                regex.push('\\');
+                            }
+                            regex.push(c);
+                        }
+                        regex.push(']');
+                        i = end;
+                    }
+                    None => regex.push_str("\\["),
+                }
+            }
+            c => regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+        i += 1;
+    }
+    regex.push('$');
+    regex::Regex::new(&regex).is_ok_and(|re| re.is_match(host))
+}
+
+/// Selectors for a host run without explicit configs: those of every
+/// `[remote.groups.<name>]` it belongs to, in group order. Empty when it's in
+/// none, leaving the remote dek to its defaults.
+fn group_configs(host: &str, meta: Option<&config::Meta>, inventory: Option<&config::Inventory>) -> Vec<String> {
+    let Some(groups) = meta.and_then(|m| m.remote.as_ref()).map(|r| &r.groups) else {
+        return Vec::new();
+    };
+    let mut configs: Vec<String> = Vec::new();
+    for (name, group) in groups {
+        let listed = inventory.and_then(|inv| inv.groups.get(name)).is_some_and(|hosts| hosts.iter().any(|h| h == host));
+        if listed || group.hosts.iter().any(|p| host_glob(p, host)) {
+            for selector in &group.configs {
+                if !configs.contains(selector) {
+                    configs.push(selector.clone());
+                }
+            }
+        }
+    }
+    configs
+}
+
+fn run_remotes(pattern: &str, cmd: &str, config_path: Option<PathBuf>, configs: &[String], opts: &RemoteOpts) -> Result<()> {
     use std::io::{self, Write};
 
-    let config_path = resolve_config(config_path.clone())?;
+    // A packed config deploys as its extracted dir (rsync needs a directory)
+    let config_path = config::resolve_path(resolve_config(config_path)?)?;
     let config_abs = std::fs::canonicalize(&config_path)?;
     util::init_lib(&config_abs);
-    let meta = config::load_meta(&config_path);
+    let meta = config::load_meta(&config_path)?;
     let remote_install = meta.as_ref().map(|m| m.remote_install).unwrap_or(false);
     let bin_name = meta.as_ref().and_then(|m| m.bin_name.as_deref()).unwrap_or("dek");
-    let inventory = config::load_inventory(&config_path)
+    let inventory = config::load_inventory(&config_path)?
         .ok_or_else(|| anyhow::anyhow!("No inventory.ini found in config directory"))?;
 
     if inventory.hosts.is_empty() {
         bail!("No hosts defined in inventory");
     }
 
-    // Match hosts against pattern (simple glob: * matches any chars)
-    let regex_pattern = format!("^{}$", pattern.replace("*", ".*"));
-    let re = regex::Regex::new(&regex_pattern)
-        .map_err(|e| anyhow::anyhow!("Invalid pattern '{}': {}", pattern, e))?;
-
-    let matched: Vec<&String> = inventory.hosts.iter().filter(|h| re.is_match(h)).collect();
+    let mut matched: Vec<&String> = inventory.hosts.iter().filter(|h| host_glob(pattern, h)).collect();
 
     if matched.is_empty() {
         bail!("No hosts match pattern '{}'", pattern);
     }
+    if let Some(ref limit) = opts.limit {
+        matched = apply_limit(matched, limit)?;
+    }
+
+    // Configs given on the command line go to every host; otherwise each
+    // host gets its groups' selectors
+    let host_configs: Vec<Vec<String>> = matched
+        .iter()
+        .map(|h| if configs.is_empty() { group_configs(h, meta.as_ref(), Some(&inventory)) } else { configs.to_vec() })
+        .collect();
 
     // Load config to check for local commands and includes
     let dek_config = config::load(&config_path)?;
@@ -716,6 +1646,14 @@ fn run_remotes(pattern: &str, cmd: &str, config_path: Option<PathBuf>, configs:
     // Show plan
     let host_list: Vec<&str> = matched.iter().map(|h| h.as_str()).collect();
     println!("{} {} on {} host(s): {}", c!("::", blue), cmd, matched.len(), host_list.join(", "));
+    if configs.is_empty() && host_configs.iter().any(|c| !c.is_empty()) {
+        println!();
+        println!("{} Configs by group:", c!("::", blue));
+        for (host, selectors) in matched.iter().zip(&host_configs) {
+            let selectors = if selectors.is_empty() { "(defaults)".to_string() } else { selectors.join(" ") };
+            println!("  {} → {}", host, selectors);
+        }
+    }
     if !local_cmds.is_empty() {
         println!();
         println!("{} Local commands to run first:", c!("::", blue));
@@ -766,7 +1704,9 @@ fn run_remotes(pattern: &str, cmd: &str, config_path: Option<PathBuf>, configs:
     let prepared_abs = std::fs::canonicalize(&prepared_config)?;
 
     // Compute binary hash once
-    let payload = RemotePayload::prepare(&prepared_abs)?;
+    let payload = RemotePayload::prepare(&prepared_abs, meta.as_ref())?;
+    let ssh = opts.ssh(meta.as_ref());
+    let become_pass = opts.become_pass.as_deref();
 
     // Show payload sizes
     let config_size = dir_size(&payload.prepared_dir);
@@ -777,49 +1717,87 @@ fn run_remotes(pattern: &str, cmd: &str, config_path: Option<PathBuf>, configs:
         output::format_bytes(bin_size),
     );
 
-    // Deploy to all hosts in parallel
+    // Deploy to all hosts in parallel, at most `parallel` at a time
     let total = matched.len();
-    println!("{} Deploying to {} hosts...\n", c!("::", blue), total);
+    let workers = opts.parallel.clamp(1, total);
+    println!("{} Deploying to {} hosts ({} at a time)...\n", c!("::", blue), total, workers);
     let start = std::time::Instant::now();
 
-    let mp = indicatif::MultiProgress::new();
+    // With --stream, spinners are kept hidden and host output is printed instead
+    let stream = opts.stream;
+    let mp = if stream {
+        indicatif::MultiProgress::with_draw_target(indicatif::ProgressDrawTarget::hidden())
+    } else {
+        indicatif::MultiProgress::new()
+    };
     let spinners: Vec<_> = matched.iter()
         .map(|host| output::start_deploy_spinner(&mp, host))
         .collect();
+    for pb in &spinners {
+        pb.set_message("queued");
+    }
 
     let (tx, rx) = std::sync::mpsc::channel::<(usize, Result<DeployResult>)>();
+    let next = std::sync::atomic::AtomicUsize::new(0);
 
     std::thread::scope(|s| {
-        for (i, host) in matched.iter().enumerate() {
+        for _ in 0..workers {
             let tx = tx.clone();
             let payload = &payload;
-            let configs = configs;
-            let pb = &spinners[i];
-            s.spawn(move || {
-                let result = deploy_to_host(host, cmd, configs, payload, Some(pb), remote_install, bin_name);
+            let (matched, host_configs, spinners, next, ssh) = (&matched, &host_configs, &spinners, &next, &ssh);
+            s.spawn(move || loop {
+                let i = next.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                if i >= total {
+                    break;
+                }
+                let progress = if stream { DeployProgress::Stream } else { DeployProgress::Spinner(&spinners[i]) };
+                let result = deploy_to_host(matched[i], cmd, &host_configs[i], payload, progress, remote_install, bin_name, ssh, become_pass);
                 let _ = tx.send((i, result));
             });
         }
         drop(tx);
 
+        let finish_ok = |pb: &indicatif::ProgressBar, host: &str, summary: &str, d| {
+            if stream {
+                output::print_deploy_ok(host, summary, d);
+            } else {
+                output::finish_deploy_ok(pb, host, summary, d);
+            }
+        };
+        let finish_fail = |pb: &indicatif::ProgressBar, host: &str, err: &str, d| {
+            if stream {
+                output::print_deploy_fail(host, err, d);
+            } else {
+                output::finish_deploy_fail(pb, host, err, d);
+            }
+        };
+
         let mut failed_hosts: Vec<String> = Vec::new();
+        let mut report: Vec<(usize, ReportEntry)> = Vec::new();
         for (i, result) in rx {
             let pb = &spinners[i];
+            if opts.report.is_some() {
+                report.push((i, ReportEntry::new(matched[i], &result, start.elapsed())));
+            }
             match result {
                 Ok(r) => {
                     let summary = output::extract_summary_line(&r.output)
                         .unwrap_or_default();
                     if r.success {
-                        output::finish_deploy_ok(pb, &r.host, &summary, r.duration);
+                        finish_ok(pb, &r.host, &summary, r.duration);
+                        // Streamed output already showed them
+                        if !stream {
+                            output::print_deploy_changes(&mp, &output::extract_changes(&r.output));
+                        }
                     } else {
                         let err = output::extract_summary_line(&r.output)
                             .unwrap_or_else(|| "failed".to_string());
-                        output::finish_deploy_fail(pb, &r.host, &err, r.duration);
+                        finish_fail(pb, &r.host, &err, r.duration);
                         failed_hosts.push(r.host);
                     }
                 }
                 Err(e) => {
-                    output::finish_deploy_fail(pb, matched[i], &e.to_string(), start.elapsed());
+                    finish_fail(pb, matched[i], &e.to_string(), start.elapsed());
                     failed_hosts.push(matched[i].clone());
                 }
             }
@@ -839,6 +1817,14 @@ fn run_remotes(pattern: &str, cmd: &str, config_path: Option<PathBuf>, configs:
             }
         }
 
+        if let Some(ref path) = opts.report {
+            report.sort_by_key(|(i, _)| *i);
+            let entries: Vec<ReportEntry> = report.into_iter().map(|(_, e)| e).collect();
+            if let Err(e) = write_report(path, &entries) {
+                eprintln!("{} {}", c!("✗", red), e);
+            }
+        }
+
         if !failed_hosts.is_empty() {
             std::process::exit(1);
         }
@@ -884,8 +1870,9 @@ pub(crate) fn prepare_config(config_path: &std::path::Path, dek_config: &config:
 
     let has_artifacts = !dek_config.artifact.is_empty();
     let has_includes = dek_config.include.as_ref().map(|i| !i.is_empty()).unwrap_or(false);
+    let has_imports = config::has_external_imports(config_path)?;
 
-    if !has_artifacts && !has_includes {
+    if !has_artifacts && !has_includes && !has_imports {
         return Ok(config_path.to_path_buf());
     }
 
@@ -897,12 +1884,19 @@ pub(crate) fn prepare_config(config_path: &std::path::Path, dek_config: &config:
 
     // Create temp copy of config
     let temp_dir = tempfile::tempdir()?;
-    let temp_path = temp_dir.into_path();
+    let temp_path = temp_dir.keep();
     copy_dir_recursive(base_dir, &temp_path)?;
 
+    // Imports from outside the config dir wouldn't exist on the other end
+    if has_imports {
+        config::vendor_imports(config_path, &temp_path)?;
+    }
+
     // Resolve artifacts
     if has_artifacts {
         println!("{} Resolving artifacts...", c!("::", blue));
+        let mut builds = Vec::new();
+        let mut outputs = Vec::new();
         for artifact in &dek_config.artifact {
             let label = artifact.name.as_deref().unwrap_or(&artifact.dest);
 
@@ -925,7 +1919,9 @@ pub(crate) fn prepare_config(config_path: &std::path::Path, dek_config: &config:
             };
 
             // Determine if build is needed
-            let should_build = if !artifact.watch.is_empty() {
+            let should_build = if cache::force() {
+                true
+            } else if !artifact.watch.is_empty() {
                 // watch mode: hash watched paths, compare with cache
                 !artifact_watch_fresh(base_dir, artifact, &src_path)
             } else if let Some(ref cmd) = artifact.check {
@@ -941,28 +1937,24 @@ pub(crate) fn prepare_config(config_path: &std::path::Path, dek_config: &config:
             };
 
             if should_build {
-                resolve_artifact_deps(&artifact.deps)?;
-                let pb = output::start_artifact_spinner(label);
-                let result = util::run_cmd_live_dir("sh", &["-c", &artifact.build], &pb, base_dir)?;
-                if !result.status.success() {
-                    output::finish_artifact_fail(&pb, label, "build failed");
-                    bail!("Artifact build failed: {}", label);
-                }
-                output::finish_artifact_ok(&pb, label);
-                // Update watch cache after successful build
-                if !artifact.watch.is_empty() {
-                    artifact_watch_save(base_dir, artifact);
-                }
+                builds.push(artifact);
             } else {
                 println!("  {} {} {}", c!("•", dimmed), c!(label, dimmed), c!("(fresh)", dimmed));
             }
+            outputs.push((label, src_path, temp_path.join(&artifact.dest)));
+        }
+
+        // Deps first, one at a time: package managers can't run concurrently
+        for artifact in &builds {
+            resolve_artifact_deps(&artifact.deps)?;
+        }
+        build_artifacts(base_dir, &builds)?;
 
+        // Copy to dest in temp
+        for (label, src_path, dst_path) in outputs {
             if !src_path.exists() {
                 bail!("Artifact not found after build: {} (expected at {})", label, src_path.display());
             }
-
-            // Copy to dest in temp
-            let dst_path = temp_path.join(&artifact.dest);
             if let Some(parent) = dst_path.parent() {
                 fs::create_dir_all(parent)?;
             }
@@ -996,7 +1988,59 @@ pub(crate) fn prepare_config(config_path: &std::path::Path, dek_config: &config:
     Ok(temp_path)
 }
 
-fn copy_dir_recursive(src: &std::path::Path, dst: &std::path::Path) -> Result<()> {
+/// Build artifacts concurrently, one spinner each. Artifacts that share a dep
+/// usually share a toolchain and its build dirs, so those build one after
+/// another on the same thread.
+fn build_artifacts(base_dir: &std::path::Path, artifacts: &[&config::ArtifactConfig]) -> Result<()> {
+    let mut groups: Vec<Vec<&config::ArtifactConfig>> = Vec::new();
+    for &artifact in artifacts {
+        let mut chain = Vec::new();
+        let mut i = groups.len();
+        while i > 0 {
+            i -= 1;
+            if groups[i].iter().any(|a| a.deps.iter().any(|d| artifact.deps.contains(d))) {
+                chain.splice(0..0, groups.remove(i));
+            }
+        }
+        chain.push(artifact);
+        groups.push(chain);
+    }
+
+    let mp = indicatif::MultiProgress::new();
+    let results: Vec<Result<()>> = std::thread::scope(|s| {
+        let handles: Vec<_> = groups
+            .iter()
+            .map(|chain| {
+                let mp = &mp;
+                s.spawn(move || -> Result<()> {
+                    for artifact in chain {
+                        let label = artifact.name.as_deref().unwrap_or(&artifact.dest);
+                        let pb = output::start_artifact_spinner(mp, label);
+                        let result = util::run_cmd_live_dir("sh", &["-c", &artifact.build], &pb, base_dir)?;
+                        if !result.status.success() {
+                            output::finish_artifact_fail(&pb, label, "build failed");
+                            bail!("Artifact build failed: {}", label);
+                        }
+                        output::finish_artifact_ok(&pb, label);
+                        // Update watch cache after successful build
+                        if !artifact.watch.is_empty() {
+                            artifact_watch_save(base_dir, artifact);
+                        }
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| h.join().unwrap_or_else(|_| Err(anyhow::anyhow!("Artifact build thread panicked"))))
+            .collect()
+    });
+
+    results.into_iter().collect()
+}
+
+pub(crate) fn copy_dir_recursive(src: &std::path::Path, dst: &std::path::Path) -> Result<()> {
     use std::fs;
 
     fs::create_dir_all(dst)?;
@@ -1130,7 +2174,31 @@ fn dir_size(path: &std::path::Path) -> u64 {
     total
 }
 
-fn shell_escape(s: &str) -> String {
+/// md5 over every file's relative path and content under `path` (sorted, so
+/// stable across runs), plus `extra`
+fn dir_hash(path: &std::path::Path, extra: &str) -> String {
+    fn walk(dir: &std::path::Path, base: &std::path::Path, ctx: &mut md5::Context) {
+        let Ok(rd) = std::fs::read_dir(dir) else { return };
+        let mut entries: Vec<PathBuf> = rd.flatten().map(|e| e.path()).collect();
+        entries.sort();
+        for p in entries {
+            if p.is_dir() {
+                walk(&p, base, ctx);
+            } else if let Ok(data) = std::fs::read(&p) {
+                ctx.consume(p.strip_prefix(base).unwrap_or(&p).to_string_lossy().as_bytes());
+                ctx.consume([0]);
+                ctx.consume(&data);
+            }
+        }
+    }
+
+    let mut ctx = md5::Context::new();
+    walk(path, path, &mut ctx);
+    ctx.consume(extra.as_bytes());
+    format!("{:x}", ctx.compute())
+}
+
+pub(crate) fn shell_escape(s: &str) -> String {
     format!("'{}'", s.replace('\'', "'\\''"))
 }
 
@@ -1150,7 +2218,7 @@ fn collect_var_exports(meta: Option<&config::Meta>) -> String {
 
 fn run_command_remote(
     config_path: Option<PathBuf>, name: Option<String>, args: Vec<String>,
-    target: Option<String>, remotes: Option<String>,
+    target: Option<String>, remotes: Option<String>, opts: &RemoteOpts,
 ) -> Result<()> {
     use std::io::{self, Write};
 
@@ -1159,9 +2227,9 @@ fn run_command_remote(
     util::init_lib(&resolved_path);
 
     // Apply runtime vars from meta.toml
-    let meta = config::load_meta(&resolved_path);
+    let meta = config::load_meta(&resolved_path)?;
     check_min_version(meta.as_ref())?;
-    if let Some(ref vars) = meta.as_ref().and_then(|m| m.vars.as_ref()) {
+    if let Some(vars) = meta.as_ref().and_then(|m| m.vars.as_ref()) {
         config::apply_vars(vars, &[]);
     }
 
@@ -1186,45 +2254,66 @@ fn run_command_remote(
                 } else {
                     println!("  {}", c!(cmd_name, bold));
                 }
+                print_run_args(cmd_config);
             }
             return Ok(());
         }
     };
 
-    let run_config = cfg.run.as_ref()
-        .and_then(|r| r.get(&name))
-        .ok_or_else(|| anyhow::anyhow!("Command '{}' not found in config", name))?;
-
-    // Resolve the shell command
     let base_dir = if resolved_path.is_file() {
         resolved_path.parent().unwrap_or(std::path::Path::new(".")).to_path_buf()
     } else {
         resolved_path.clone()
     };
 
-    let shell_cmd = if let Some(ref cmd) = run_config.cmd {
-        cmd.clone()
-    } else if let Some(ref script_path) = run_config.script {
-        let full_path = base_dir.join(script_path);
-        std::fs::read_to_string(&full_path)
-            .map_err(|e| anyhow::anyhow!("Failed to read script '{}': {}", full_path.display(), e))?
-    } else {
-        bail!("Command '{}' has no cmd or script for remote execution", name);
-    };
+    // Chain `needs` prerequisites (no args) before the command, failing fast.
+    // Gates are evaluated locally, before connecting.
+    let order = run_needs_order(&name, cfg.run.as_ref())?;
+    let mut steps: Vec<(&String, &config::RunConfig, String)> = Vec::new();
+    let mut args = args;
+    for step in &order {
+        let run_config = &cfg.run.as_ref().expect("checked by run_needs_order")[step];
+        if !run_gates_pass(step, run_config, &cfg)? {
+            if *step == name {
+                return Ok(());
+            }
+            continue;
+        }
 
-    // Append extra args
-    let export_prefix = collect_var_exports(meta.as_ref());
-    let full_cmd = if args.is_empty() {
-        format!("{}{}", export_prefix, shell_cmd)
-    } else {
-        format!("{}{} {}", export_prefix, shell_cmd, args.join(" "))
+        // Resolve the shell command
+        let shell_cmd = if let Some(ref cmd) = run_config.cmd {
+            cmd.clone()
+        } else if let Some(ref script_path) = run_config.script {
+            let full_path = base_dir.join(script_path);
+            std::fs::read_to_string(&full_path)
+                .map_err(|e| anyhow::anyhow!("Failed to read script '{}': {}", full_path.display(), e))?
+        } else {
+            bail!("Command '{}' has no cmd or script for remote execution", step);
+        };
+
+        // Fill placeholders, append remaining args
+        let step_args = if *step == name { std::mem::take(&mut args) } else { Vec::new() };
+        let (shell_cmd, step_args) = render_run_cmd(step, run_config, &shell_cmd, step_args)?;
+        let shell_cmd = if step_args.is_empty() {
+            shell_cmd
+        } else {
+            format!("{} {}", shell_cmd, step_args.join(" "))
+        };
+        steps.push((step, run_config, shell_cmd));
+    }
+    let shell_cmd = match steps.as_slice() {
+        [(_, _, cmd)] => cmd.clone(),
+        _ => steps.iter().map(|(_, _, cmd)| format!("(\n{}\n)", cmd)).collect::<Vec<_>>().join(" && "),
     };
+    let export_prefix = collect_var_exports(meta.as_ref());
+    let full_cmd = format!("{}{}", export_prefix, shell_cmd);
+    let tty = steps.iter().any(|(_, c, _)| c.tty);
 
     // Resolve hosts
     let hosts: Vec<String> = if let Some(ref t) = target {
         vec![t.clone()]
     } else if let Some(ref pattern) = remotes {
-        let inventory = config::load_inventory(&path)
+        let inventory = config::load_inventory(&path)?
             .ok_or_else(|| anyhow::anyhow!("No inventory.ini found in config directory"))?;
         if inventory.hosts.is_empty() {
             bail!("No hosts defined in inventory");
@@ -1236,24 +2325,27 @@ fn run_command_remote(
         if matched.is_empty() {
             bail!("No hosts match pattern '{}'", pattern);
         }
-        matched
+        match opts.limit {
+            Some(ref limit) => apply_limit(matched, limit)?,
+            None => matched,
+        }
     } else {
         unreachable!()
     };
 
     // tty + -r → bail
-    if run_config.tty && remotes.is_some() {
+    if tty && remotes.is_some() {
         bail!("Command '{}' requires tty (ssh -t) and cannot be used with --remotes", name);
     }
 
     // Confirm
-    if run_config.confirm {
+    for (step, _, _) in steps.iter().filter(|(_, c, _)| c.confirm) {
         let target_desc = if hosts.len() == 1 {
             hosts[0].clone()
         } else {
             format!("{} hosts ({})", hosts.len(), hosts.join(", "))
         };
-        print!("Run {} on {}? [y/N] ", c!(&name, bold), target_desc);
+        print!("Run {} on {}? [y/N] ", c!(step, bold), target_desc);
         io::stdout().flush()?;
         let mut input = String::new();
         io::stdin().read_line(&mut input)?;
@@ -1263,24 +2355,35 @@ fn run_command_remote(
         }
     }
 
+    let ssh = opts.ssh(meta.as_ref());
+
     // Single host (-t)
     if target.is_some() {
         let host = &hosts[0];
-        if run_config.tty {
+        let ssh = ssh.start_clock();
+        let timed_out = || match ssh.host_timeout {
+            Some(timeout) if ssh.timed_out() => {
+                bail!("Command '{}' on {} timed out after {}", name, host, output::format_duration(timeout))
+            }
+            _ => Ok(()),
+        };
+        if tty {
             // ssh -t with inherited stdio
-            let status = Command::new("ssh")
-                .args(["-t", host, &full_cmd])
+            let mut child = ssh.ssh_tty(host)
+                .arg(&full_cmd)
                 .stdin(Stdio::inherit())
                 .stdout(Stdio::inherit())
                 .stderr(Stdio::inherit())
-                .status()?;
+                .spawn()?;
+            let _watchdog = ssh.watch(&child);
+            let status = child.wait()?;
+            timed_out()?;
             if !status.success() {
                 bail!("Command '{}' failed on {}", name, host);
             }
         } else {
-            let output = Command::new("ssh")
-                .args([host.as_str(), &full_cmd])
-                .output()?;
+            let output = ssh.output(ssh.ssh(host).arg(&full_cmd))?;
+            timed_out()?;
             let stdout = String::from_utf8_lossy(&output.stdout);
             let stderr = String::from_utf8_lossy(&output.stderr);
             if !stdout.is_empty() {
@@ -1296,8 +2399,9 @@ fn run_command_remote(
         return Ok(());
     }
 
-    // Multi-host (-r) — parallel with spinners
+    // Multi-host (-r) — parallel with spinners, at most `parallel` at a time
     let total = hosts.len();
+    let workers = opts.parallel.clamp(1, total);
     println!("{} Running '{}' on {} host(s)...\n", c!("::", blue), name, total);
     let start = std::time::Instant::now();
 
@@ -1305,22 +2409,33 @@ fn run_command_remote(
     let spinners: Vec<_> = hosts.iter()
         .map(|host| output::start_deploy_spinner(&mp, host))
         .collect();
+    for pb in &spinners {
+        pb.set_message("queued");
+    }
 
     let (tx, rx) = std::sync::mpsc::channel::<(usize, String, bool, std::time::Duration)>();
+    let next = std::sync::atomic::AtomicUsize::new(0);
 
     std::thread::scope(|s| {
-        for (i, host) in hosts.iter().enumerate() {
+        for _ in 0..workers {
             let tx = tx.clone();
             let cmd = &full_cmd;
-            let pb = &spinners[i];
-            s.spawn(move || {
+            let (hosts, spinners, next, ssh) = (&hosts, &spinners, &next, &ssh);
+            s.spawn(move || loop {
+                let i = next.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                if i >= total {
+                    break;
+                }
                 let t = std::time::Instant::now();
-                pb.set_message("running...");
-                let result = Command::new("ssh")
-                    .args([host.as_str(), cmd])
-                    .output();
+                spinners[i].set_message("running...");
+                let ssh = ssh.start_clock();
+                let result = ssh.output(ssh.ssh(&hosts[i]).arg(cmd));
                 let elapsed = t.elapsed();
                 match result {
+                    Ok(_) if ssh.timed_out() => {
+                        let timeout = output::format_duration(ssh.host_timeout.unwrap_or_default());
+                        let _ = tx.send((i, format!("timed out after {}", timeout), false, elapsed));
+                    }
                     Ok(out) => {
                         let stdout = String::from_utf8_lossy(&out.stdout).to_string();
                         let stderr = String::from_utf8_lossy(&out.stderr).to_string();
@@ -1371,17 +2486,15 @@ fn run_command_remote(
     Ok(())
 }
 
-fn run_command(config_path: Option<PathBuf>, name: Option<String>, args: Vec<String>) -> Result<()> {
-    use std::process::Command;
-
+fn run_command(config_path: Option<PathBuf>, name: Option<String>, mut args: Vec<String>) -> Result<()> {
     let path = resolve_config(config_path)?;
     let resolved_path = config::resolve_path(&path)?;
     util::init_lib(&resolved_path);
 
     // Apply runtime vars from meta.toml
-    let meta = config::load_meta(&resolved_path);
+    let meta = config::load_meta(&resolved_path)?;
     check_min_version(meta.as_ref())?;
-    if let Some(ref vars) = meta.as_ref().and_then(|m| m.vars.as_ref()) {
+    if let Some(vars) = meta.as_ref().and_then(|m| m.vars.as_ref()) {
         config::apply_vars(vars, &[]);
     }
 
@@ -1407,6 +2520,7 @@ fn run_command(config_path: Option<PathBuf>, name: Option<String>, args: Vec<Str
                 } else {
                     println!("  {}", c!(cmd_name, bold));
                 }
+                print_run_args(cmd_config);
             }
             return Ok(());
         }
@@ -1418,14 +2532,53 @@ fn run_command(config_path: Option<PathBuf>, name: Option<String>, args: Vec<Str
         resolved_path.clone()
     };
 
+    // Prerequisites from `needs` run first (dependency order, no args), failing fast
+    let order = run_needs_order(&name, config.run.as_ref())?;
+    for step in &order {
+        if order.len() > 1 {
+            println!("{} {}", c!("::", blue), c!(step, bold));
+        }
+        let step_args = if *step == name { std::mem::take(&mut args) } else { Vec::new() };
+        execute_run(step, &config, &resolved_path, &base_dir, step_args)?;
+    }
+    Ok(())
+}
+
+/// Run a single `[run.<name>]` command locally: gates, confirm, deps, file, cmd/script.
+fn execute_run(
+    name: &str, config: &config::Config, resolved_path: &std::path::Path,
+    base_dir: &std::path::Path, args: Vec<String>,
+) -> Result<()> {
     let run_config = config.run.as_ref()
-        .and_then(|r| r.get(&name))
+        .and_then(|r| r.get(name))
         .ok_or_else(|| anyhow::anyhow!("Command '{}' not found in config", name))?;
 
+    if !run_gates_pass(name, run_config, config)? {
+        return Ok(());
+    }
+
+    // Resolve cmd/script and fill placeholders before confirm, so bad args fail early
+    let shell = if let Some(ref cmd) = run_config.cmd {
+        Some(cmd.clone())
+    } else if let Some(ref script_path) = run_config.script {
+        let full_path = base_dir.join(script_path);
+        Some(std::fs::read_to_string(&full_path)
+            .map_err(|e| anyhow::anyhow!("Failed to read script '{}': {}", full_path.display(), e))?)
+    } else {
+        None
+    };
+    let (shell, args) = match shell {
+        Some(body) => {
+            let (body, args) = render_run_cmd(name, run_config, &body, args)?;
+            (Some(body), args)
+        }
+        None => (None, args),
+    };
+
     // Confirm
     if run_config.confirm {
         use std::io::{self, Write};
-        print!("Run {}? [y/N] ", c!(&name, bold));
+        print!("Run {}? [y/N] ", c!(name, bold));
         io::stdout().flush()?;
         let mut input = String::new();
         io::stdin().read_line(&mut input)?;
@@ -1444,7 +2597,8 @@ fn run_command(config_path: Option<PathBuf>, name: Option<String>, args: Vec<Str
         for dep in &run_config.deps {
             items.extend(parse_provider_spec(dep)?);
         }
-        let runner = runner::Runner::new(runner::Mode::Apply);
+        let runner = runner::Runner::new(runner::Mode::Apply, output::Format::Human)
+            .with_policy(providers::Policy::from_meta(config.meta.as_ref()));
         runner.run_items(&items)?;
         println!();
     }
@@ -1455,29 +2609,14 @@ fn run_command(config_path: Option<PathBuf>, name: Option<String>, args: Vec<Str
             file: Some(file_config.clone()),
             ..Default::default()
         };
-        let run = runner::Runner::new(runner::Mode::Apply);
-        run.run(&inline_config, &resolved_path)?;
+        let run = runner::Runner::new(runner::Mode::Apply, output::Format::Human)
+            .with_policy(providers::Policy::from_meta(config.meta.as_ref()));
+        run.run(&inline_config, resolved_path)?;
     }
 
-    // Run shell command if present
-    if let Some(ref cmd) = run_config.cmd {
-        let status = util::shell_cmd(cmd)
-            .arg("_")
-            .args(&args)
-            .stdin(Stdio::inherit())
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .status()?;
-
-        if !status.success() {
-            bail!("Command '{}' exited with status {}", name, status);
-        }
-    } else if let Some(ref script_path) = run_config.script {
-        let full_path = base_dir.join(script_path);
-        let script = std::fs::read_to_string(&full_path)
-            .map_err(|e| anyhow::anyhow!("Failed to read script '{}': {}", full_path.display(), e))?;
-
-        let status = util::shell_cmd(&script)
+    // Run shell command or script if present
+    if let Some(ref shell) = shell {
+        let status = util::shell_cmd(shell)
             .arg("_")
             .args(&args)
             .stdin(Stdio::inherit())
@@ -1495,6 +2634,148 @@ fn run_command(config_path: Option<PathBuf>, name: Option<String>, args: Vec<Str
     Ok(())
 }
 
+/// A `needs` cycle: the chain of commands from the one asked for, ending
+/// with the command that closes the loop
+#[derive(Debug)]
+struct RunNeedsCycle(Vec<String>);
+
+impl std::fmt::Display for RunNeedsCycle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Cycle in run needs: {}", self.0.join(" -> "))
+    }
+}
+
+impl std::error::Error for RunNeedsCycle {}
+
+/// `name` preceded by its transitive `needs`, in dependency order (each once).
+fn run_needs_order(
+    name: &str, commands: Option<&std::collections::HashMap<String, config::RunConfig>>,
+) -> Result<Vec<String>> {
+    fn visit(
+        name: &str, commands: Option<&std::collections::HashMap<String, config::RunConfig>>,
+        stack: &mut Vec<String>, order: &mut Vec<String>,
+    ) -> Result<()> {
+        if order.iter().any(|n| n == name) {
+            return Ok(());
+        }
+        if stack.iter().any(|n| n == name) {
+            let mut chain = stack.clone();
+            chain.push(name.to_string());
+            return Err(RunNeedsCycle(chain).into());
+        }
+        let run_config = commands
+            .and_then(|c| c.get(name))
+            .ok_or_else(|| anyhow::anyhow!("Command '{}' not found in config", name))?;
+        stack.push(name.to_string());
+        for need in &run_config.needs {
+            visit(need, commands, stack, order)?;
+        }
+        stack.pop();
+        order.push(name.to_string());
+        Ok(())
+    }
+
+    let mut order = Vec::new();
+    visit(name, commands, &mut Vec::new(), &mut order)?;
+    Ok(order)
+}
+
+/// List a run command's declared args under its name in `dek run`.
+fn print_run_args(run_config: &config::RunConfig) {
+    for arg in &run_config.args {
+        let flag = if arg.required {
+            format!("--{} <{}>", arg.name, arg.name)
+        } else {
+            format!("[--{} <{}>]", arg.name, arg.name)
+        };
+        match arg.description {
+            Some(ref desc) => println!("      {} {}", c!(flag, cyan), c!(desc, dimmed)),
+            None => println!("      {}", c!(flag, cyan)),
+        }
+    }
+}
+
+/// Fill `{{ name }}` (declared args, from `--name value`) and `{{ N }}` (positional)
+/// placeholders in a run command. Values are shell-quoted. Returns the rendered
+/// body and the positional args, which are still passed as `$@`.
+fn render_run_cmd(
+    name: &str, run_config: &config::RunConfig, body: &str, args: Vec<String>,
+) -> Result<(String, Vec<String>)> {
+    let positional_re = regex::Regex::new(r"\{\{\s*(\d+)\s*\}\}").unwrap();
+
+    // No schema: only positional placeholders, leave other `{{` untouched
+    if run_config.args.is_empty() {
+        let rendered = positional_re.replace_all(body, |caps: &regex::Captures| {
+            let n: usize = caps[1].parse().unwrap_or(0);
+            n.checked_sub(1).and_then(|i| args.get(i)).map(|a| shell_escape(a)).unwrap_or_default()
+        });
+        return Ok((rendered.into_owned(), args));
+    }
+
+    let mut named: std::collections::HashMap<String, String> = Default::default();
+    let mut positional = Vec::new();
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--" {
+            positional.extend(iter.by_ref());
+            break;
+        }
+        let flag = arg.strip_prefix("--").map(|f| f.split_once('=').unwrap_or((f, "")));
+        match flag {
+            Some((key, value)) if run_config.args.iter().any(|a| a.name == key) => {
+                let value = if arg.contains('=') {
+                    value.to_string()
+                } else {
+                    iter.next().ok_or_else(|| anyhow::anyhow!("--{} needs a value", key))?
+                };
+                named.insert(key.to_string(), value);
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    let mut ctx: std::collections::HashMap<String, minijinja::Value> = Default::default();
+    for arg in &run_config.args {
+        let value = match named.remove(&arg.name).or_else(|| arg.default.clone()) {
+            Some(v) => v,
+            None if arg.required => bail!("Command '{}' requires --{}", name, arg.name),
+            None => String::new(),
+        };
+        ctx.insert(arg.name.clone(), minijinja::Value::from(shell_escape(&value)));
+    }
+    let quoted: Vec<String> = positional.iter().map(|a| shell_escape(a)).collect();
+    ctx.insert("args".to_string(), minijinja::Value::from(quoted));
+
+    let body = positional_re.replace_all(body, |caps: &regex::Captures| {
+        let n: usize = caps[1].parse().unwrap_or(0);
+        format!("{{{{ args[{}] }}}}", n.saturating_sub(1))
+    });
+    let mut env = minijinja::Environment::new();
+    env.set_undefined_behavior(minijinja::UndefinedBehavior::Lenient);
+    let rendered = env.render_str(&body, &ctx)
+        .map_err(|e| anyhow::anyhow!("Command '{}': failed to render: {}", name, e))?;
+    Ok((rendered, positional))
+}
+
+/// Check a run command's `run_if` (false → skip) and `requires_state` (mismatch → error).
+fn run_gates_pass(name: &str, run_config: &config::RunConfig, cfg: &config::Config) -> Result<bool> {
+    if let Some(ref run_if) = run_config.run_if {
+        let ok = util::shell_cmd(run_if)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map(|s| s.success())
+            .unwrap_or(false);
+        if !ok {
+            println!("  {} {} {} {}", c!("•", dimmed), c!("run", dimmed), c!(name, dimmed), c!("(skipped)", dimmed));
+            return Ok(false);
+        }
+    }
+    state::require(&cfg.state, &run_config.requires_state)
+        .map_err(|e| anyhow::anyhow!("Command '{}' not run: {}", name, e))?;
+    Ok(true)
+}
+
 /// Parse "provider.pkg[,pkg2,...]" spec into one or more StateItems.
 /// Supports grouping: `pacman.grim,slurp,tesseract`
 fn parse_provider_spec(spec: &str) -> Result<Vec<providers::StateItem>> {
@@ -1506,18 +2787,121 @@ fn parse_provider_spec(spec: &str) -> Result<Vec<providers::StateItem>> {
         "os" => "package.os",
         "apt" => "package.apt",
         "pacman" | "pac" => "package.pacman",
+        "brew" => "package.brew",
         "cargo" => "package.cargo",
         "go" => "package.go",
         "npm" => "package.npm",
         "pip" => "package.pip",
         "webi" => "package.webi",
-        _ => bail!("Unknown provider '{}'. Use: os, apt, pacman (pac), cargo, go, npm, pip, webi", provider),
+        _ => bail!("Unknown provider '{}'. Use: os, apt, pacman (pac), brew, cargo, go, npm, pip, webi", provider),
     };
 
     Ok(packages.split(',').map(|pkg| providers::StateItem::new(kind, pkg.trim())).collect())
 }
 
-fn run_inline(specs: &[String]) -> Result<()> {
+/// `apply --changed`: keys of the configs whose file, or a file one of its
+/// items reads from, differs from git HEAD (untracked files count). None
+/// means apply everything: not a git repo, not a config dir, or meta.toml
+/// changed.
+fn changed_configs(config_path: Option<PathBuf>) -> Result<Option<Vec<String>>> {
+    let dir = config::resolve_path(resolve_config(config_path)?)?;
+    if !dir.is_dir() {
+        return Ok(None);
+    }
+    // NUL-separated, so paths git would quote still match
+    let git = |args: &[&str]| -> Option<Vec<String>> {
+        let output = Command::new("git").arg("-C").arg(&dir).args(args).stderr(Stdio::null()).output().ok()?;
+        output.status.success().then(|| {
+            String::from_utf8_lossy(&output.stdout).split('\0').filter(|f| !f.is_empty()).map(String::from).collect()
+        })
+    };
+    if git(&["rev-parse", "--is-inside-work-tree"]).is_none() {
+        eprintln!("{} not a git repository, applying everything", c!("!", yellow));
+        return Ok(None);
+    }
+    let Some(mut files) = git(&["diff", "-z", "--name-only", "--relative", "HEAD"]) else {
+        eprintln!("{} no commits yet, applying everything", c!("!", yellow));
+        return Ok(None);
+    };
+    files.extend(git(&["ls-files", "-z", "--others", "--exclude-standard"]).unwrap_or_default());
+
+    let canon = |p: PathBuf| std::fs::canonicalize(&p).unwrap_or(p);
+    let changed: std::collections::HashSet<PathBuf> = files.iter().map(|f| canon(dir.join(f))).collect();
+    if changed.contains(&canon(dir.join("meta.toml"))) {
+        eprintln!("{} meta.toml changed, applying everything", c!("!", yellow));
+        return Ok(None);
+    }
+
+    // Only what a plain `dek apply` would load: not optional/ configs nobody selects
+    let meta = config::load_meta(&dir)?;
+    let selected = config::apply_keys(&dir, &[], meta.as_ref())?;
+    let mut keys = Vec::new();
+    for file in config::config_files(&dir)? {
+        let key = config::file_key(&file);
+        if !selected.contains(&key) {
+            continue;
+        }
+        let hit = changed.contains(&canon(file.clone()))
+            || config::load(&file)
+                .map(|cfg| runner::source_files(&cfg, &dir).into_iter().any(|src| changed.contains(&canon(src))))
+                .unwrap_or(false);
+        if hit && !keys.contains(&key) {
+            keys.push(key);
+        }
+    }
+    Ok(Some(keys))
+}
+
+/// `--print-config`: the config after selectors, requires, imports, merging
+/// and vars, with file paths resolved the way items see them
+fn print_config(config_path: Option<PathBuf>, selectors: Vec<String>, format: ConfigFormat) -> Result<()> {
+    let path = resolve_config(config_path)?;
+    let (path, selectors) = match config::config_file_in_dir(&path) {
+        Some((dir, key)) if selectors.is_empty() => (dir, vec![key]),
+        _ => (path, selectors),
+    };
+    let resolved = config::resolve_path(&path)?;
+    let meta = config::load_meta(&resolved)?;
+    if let Some(vars) = meta.as_ref().and_then(|m| m.vars.as_ref()) {
+        let defaults = meta.as_ref().map(|m| &m.defaults[..]).unwrap_or(&[]);
+        config::apply_vars(vars, if selectors.is_empty() { defaults } else { &selectors });
+    }
+
+    let mut cfg = config::load_for_apply(&resolved, &selectors, meta.as_ref())?;
+    let base_dir = if resolved.is_file() {
+        resolved.parent().unwrap_or(std::path::Path::new(".")).to_path_buf()
+    } else {
+        resolved.clone()
+    };
+    runner::resolve_paths(&mut cfg, &base_dir);
+
+    let mut value = serde_json::to_value(&cfg)?;
+    drop_unset(&mut value);
+    match format {
+        ConfigFormat::Toml => print!("{}", toml::to_string_pretty(&toml::Value::try_from(&value)?)?),
+        ConfigFormat::Json => println!("{}", serde_json::to_string_pretty(&value)?),
+    }
+    Ok(())
+}
+
+/// Leave out what the config doesn't set: nulls, and empty lists and tables
+pub(crate) fn drop_unset(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            map.values_mut().for_each(drop_unset);
+            map.retain(|_, v| match v {
+                serde_json::Value::Null => false,
+                serde_json::Value::Array(a) => !a.is_empty(),
+                serde_json::Value::Object(o) => !o.is_empty(),
+                _ => true,
+            });
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(drop_unset),
+        _ => {}
+    }
+}
+
+fn run_inline(config_path: Option<PathBuf>, specs: &[String]) -> Result<()> {
     output::print_header("Installing");
     println!();
 
@@ -1525,33 +2909,80 @@ fn run_inline(specs: &[String]) -> Result<()> {
     for spec in specs {
         items.extend(parse_provider_spec(spec)?);
     }
-    let runner = runner::Runner::new(runner::Mode::Apply);
+    let runner = runner::Runner::new(runner::Mode::Apply, output::Format::Human).with_policy(inline_policy(config_path));
     runner.run_items(&items)
 }
 
+/// Inline installs go by the install settings of the config there is, if any
+fn inline_policy(config_path: Option<PathBuf>) -> providers::Policy {
+    let path = match config_path {
+        Some(path) => Some(path),
+        None => bake::check_embedded().or_else(config::find_default_config),
+    };
+    path.and_then(|p| config::resolve_path(&p).ok())
+        .and_then(|p| config::load_all(&p).ok())
+        .map(|c| providers::Policy::from_meta(c.meta.as_ref()))
+        .unwrap_or_default()
+}
+
 /// Derive the test container name from config metadata.
 fn test_container_name(config_path: Option<PathBuf>) -> Result<String> {
     let config_path = resolve_config(config_path)?;
     let resolved_path = config::resolve_path(&config_path)?;
-    let meta = config::load_meta(&resolved_path);
+    let meta = config::load_meta(&resolved_path)?;
     let config_name = meta.as_ref().and_then(|m| m.name.as_deref())
         .unwrap_or_else(|| {
             resolved_path.file_name()
                 .and_then(|n| n.to_str())
                 .unwrap_or("dek")
         });
-    let sanitized: String = config_name.to_lowercase().chars()
+    Ok(format!("dek-test-{}", sanitize_container_name(config_name)))
+}
+
+fn sanitize_container_name(name: &str) -> String {
+    let sanitized: String = name.to_lowercase().chars()
         .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
         .collect();
-    Ok(format!("dek-test-{}", sanitized.trim_matches('-')))
+    sanitized.trim_matches('-').to_string()
 }
 
-fn run_exec(config_path: Option<PathBuf>, cmd: Vec<String>) -> Result<()> {
-    if which::which("docker").is_err() {
-        bail!("docker not found in PATH");
-    }
+static CONTAINER_ENGINE: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+
+/// Pick the container CLI: $DEK_CONTAINER_ENGINE > [test] engine > docker > podman.
+fn init_container_engine(test_config: Option<&config::TestConfig>) -> Result<()> {
+    let configured = std::env::var("DEK_CONTAINER_ENGINE").ok()
+        .filter(|e| !e.is_empty())
+        .or_else(|| test_config.and_then(|t| t.engine.clone()));
+    let engine = match configured {
+        Some(engine) => {
+            if which::which(&engine).is_err() {
+                bail!("{} not found in PATH", engine);
+            }
+            engine
+        }
+        None => match ["docker", "podman"].into_iter().find(|e| which::which(e).is_ok()) {
+            Some(engine) => engine.to_string(),
+            None => bail!("docker or podman not found in PATH"),
+        },
+    };
+    let _ = CONTAINER_ENGINE.set(engine);
+    Ok(())
+}
+
+/// Container CLI used for all test container operations (docker-compatible).
+fn container_engine() -> &'static str {
+    CONTAINER_ENGINE.get().map(String::as_str).unwrap_or("docker")
+}
+
+fn run_exec(
+    config_path: Option<PathBuf>, user: Option<String>, workdir: Option<String>, cmd: Vec<String>,
+) -> Result<()> {
+    let config_path = resolve_config(config_path)?;
+    let resolved_path = config::resolve_path(&config_path)?;
+    let meta = config::load_meta(&resolved_path)?;
+    init_container_engine(meta.as_ref().and_then(|m| m.test.as_ref()))?;
 
-    let container_name = test_container_name(config_path)?;
+    let container_name = test_container_name(Some(resolved_path))?;
 
     if get_container_state(&container_name).as_deref() != Some("running") {
         bail!("Container '{}' is not running. Start it with: dek test", container_name);
@@ -1562,10 +2993,16 @@ fn run_exec(config_path: Option<PathBuf>, cmd: Vec<String>) -> Result<()> {
     if std::io::stdin().is_terminal() && std::io::stdout().is_terminal() {
         args.extend(["-it".to_string()]);
     }
+    if let Some(user) = user {
+        args.extend(["-u".to_string(), user]);
+    }
+    if let Some(workdir) = workdir {
+        args.extend(["-w".to_string(), workdir]);
+    }
     args.push(container_name);
     args.extend(cmd);
 
-    let status = Command::new("docker")
+    let status = Command::new(container_engine())
         .args(&args)
         .stdin(Stdio::inherit())
         .stdout(Stdio::inherit())
@@ -1578,19 +3015,34 @@ fn run_exec(config_path: Option<PathBuf>, cmd: Vec<String>) -> Result<()> {
     Ok(())
 }
 
-fn run_test(
-    config_path: Option<PathBuf>, image: Option<String>, rm: bool,
-    fresh: bool, attach: bool, selectors: Vec<String>,
-) -> Result<()> {
-    if which::which("docker").is_err() {
-        bail!("docker not found in PATH");
-    }
+/// `dek test` options from the command line
+struct TestOpts {
+    image: Option<String>,
+    rm: bool,
+    fresh: bool,
+    attach: bool,
+    rebuild: bool,
+    env: Vec<String>,
+    env_file: Vec<PathBuf>,
+    no_shell: bool,
+    /// --all: every `[test] images` entry
+    all: bool,
+    selectors: Vec<String>,
+}
 
+fn run_test(config_path: Option<PathBuf>, opts: TestOpts) -> Result<()> {
+    let TestOpts { image, rm, fresh, attach, rebuild, env, env_file, no_shell, all, selectors } = opts;
     let config_path = resolve_config(config_path)?;
     let resolved_path = config::resolve_path(&config_path)?;
-    let meta = config::load_meta(&resolved_path);
+    let meta = config::load_meta(&resolved_path)?;
     check_min_version(meta.as_ref())?;
     let test_config = meta.as_ref().and_then(|m| m.test.as_ref());
+    init_container_engine(test_config)?;
+    let env_args = test_env_args(test_config, &env, &env_file)?;
+
+    if all {
+        return run_test_matrix(&resolved_path, test_config, &env_args, rm, rebuild, selectors);
+    }
 
     // Derive image: CLI > meta.toml > "archlinux"
     let image = image
@@ -1616,12 +3068,9 @@ fn run_test(
     }
 
     // Handle --fresh: remove old container
-    if fresh {
-        if container_state.is_some() {
-            println!("  {} Removing old container...", c!("→", yellow));
-            let _ = Command::new("docker").args(["rm", "-f", &container_name])
-                .stdout(Stdio::null()).stderr(Stdio::null()).status();
-        }
+    if fresh && container_state.is_some() {
+        println!("  {} Removing old container...", c!("→", yellow));
+        docker_remove(&container_name);
     }
 
     let is_new = fresh || container_state.is_none();
@@ -1633,10 +3082,148 @@ fn run_test(
     }
     println!();
 
+    let baked_path = bake_test_binary(&resolved_path, &container_name, rebuild)?;
+    if is_new {
+        create_test_container(&container_name, &image, &resolved_path, test_config, &env_args)?;
+    }
+    let apply_status = apply_in_container(&container_name, &baked_path, &selectors, &env_args)?;
+
+    // --no-shell: propagate the apply result as our exit code (CI mode)
+    if no_shell {
+        if rm {
+            docker_remove(&container_name);
+        }
+        if !apply_status.success() {
+            std::process::exit(apply_status.code().unwrap_or(1));
+        }
+        return Ok(());
+    }
+
+    if !apply_status.success() {
+        println!();
+        println!("  {} Apply had errors, dropping into shell anyway", c!("!", yellow));
+    }
+
+    // Drop into shell
+    println!();
+    println!("Dropping into shell...");
+    docker_shell(&container_name)?;
+
+    if rm {
+        docker_remove(&container_name);
+        println!("Container removed: {}", container_name);
+    } else {
+        println!();
+        println!("Container kept: {}", c!(container_name, bold));
+        println!("  Rerun:     {}", c!("dek test", dimmed));
+        println!("  Attach:    {}", c!("dek test --attach", dimmed));
+        println!("  Fresh:     {}", c!("dek test --fresh", dimmed));
+        println!("  Remove:    {}", c!(format!("{} rm {}", container_engine(), container_name), dimmed));
+    }
+
+    Ok(())
+}
+
+/// Apply the config in a fresh container per `[test] images` entry and report pass/fail.
+fn run_test_matrix(
+    resolved_path: &std::path::Path, test_config: Option<&config::TestConfig>,
+    env_args: &[String], rm: bool, rebuild: bool, selectors: Vec<String>,
+) -> Result<()> {
+    let images = test_config.map(|t| t.images.clone()).unwrap_or_default();
+    if images.is_empty() {
+        bail!("No images to test: set [test] images = [...] in meta.toml");
+    }
+
+    let base_name = test_container_name(Some(resolved_path.to_path_buf()))?;
+    output::print_header(&format!("Testing {} images", images.len()));
+    println!();
+    let baked_path = bake_test_binary(resolved_path, &base_name, rebuild)?;
+
+    let mut results = Vec::new();
+    for image in &images {
+        let container_name = format!("{}-{}", base_name, sanitize_container_name(image));
+        println!();
+        output::print_header(&format!("Testing in {}", image));
+        println!();
+
+        if get_container_state(&container_name).is_some() {
+            docker_remove(&container_name);
+        }
+        let result = create_test_container(&container_name, image, resolved_path, test_config, env_args)
+            .and_then(|_| apply_in_container(&container_name, &baked_path, &selectors, env_args));
+        let passed = match result {
+            Ok(status) => status.success(),
+            Err(e) => {
+                println!("  {} {}", c!("✗", red), e);
+                false
+            }
+        };
+        if rm {
+            docker_remove(&container_name);
+        }
+        results.push((image, container_name, passed));
+    }
+
+    println!();
+    output::print_header("Results");
+    println!();
+    for (image, container_name, passed) in &results {
+        if *passed {
+            println!("  {} {}", c!("✓", green), image);
+        } else if rm {
+            println!("  {} {}", c!("✗", red), image);
+        } else {
+            println!("  {} {} {}", c!("✗", red), image,
+                c!(format!("{} exec -it {} sh", container_engine(), container_name), dimmed));
+        }
+    }
+
+    let failed = results.iter().filter(|(_, _, passed)| !passed).count();
+    if failed > 0 {
+        bail!("{} of {} images failed", failed, results.len());
+    }
+    Ok(())
+}
+
+/// `-e`/`--env-file` args for the container: meta.toml [test].env, then CLI (later wins).
+fn test_env_args(
+    test_config: Option<&config::TestConfig>, env: &[String], env_files: &[PathBuf],
+) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    for path in env_files {
+        if !path.is_file() {
+            bail!("env file not found: {}", path.display());
+        }
+        args.push("--env-file".to_string());
+        args.push(path.to_string_lossy().to_string());
+    }
+    if let Some(t) = test_config {
+        let mut vars: Vec<_> = t.env.iter().collect();
+        vars.sort();
+        for (key, value) in vars {
+            args.push("-e".to_string());
+            args.push(format!("{}={}", key, value));
+        }
+    }
+    for var in env {
+        args.push("-e".to_string());
+        args.push(var.clone());
+    }
+    Ok(args)
+}
+
+/// Build dek for containers and bake the config into `/tmp/<name>`.
+fn bake_test_binary(resolved_path: &std::path::Path, name: &str, rebuild: bool) -> Result<PathBuf> {
     // Build dek binary (musl for portability across containers/distros)
     let cwd = std::env::current_dir()?;
     let musl_target = "x86_64-unknown-linux-musl";
-    let dek_binary = if cwd.join("Cargo.toml").exists() {
+    let musl_binary = cwd.join(format!("target/{}/release/dek", musl_target));
+    let dek_binary = if !cwd.join("Cargo.toml").exists() {
+        std::env::current_exe()?
+    } else if !rebuild && musl_build_fresh(&cwd, &musl_binary) {
+        println!("  {} dek unchanged (cached)", c!("•", dimmed));
+        musl_binary
+    } else {
         println!("  {} Building dek (musl)...", c!("→", yellow));
         let build_status = Command::new("cargo")
             .args(["build", "--release", "--quiet", "--target", musl_target])
@@ -1644,119 +3231,129 @@ fn run_test(
         if !build_status.success() {
             bail!("cargo build failed (is the musl target installed? rustup target add {})", musl_target);
         }
-        cwd.join(format!("target/{}/release/dek", musl_target))
-    } else {
-        std::env::current_exe()?
+        musl_binary
     };
 
     // Prepare config (artifacts + includes)
-    let dek_config = config::load_all(&resolved_path)?;
-    let prepared_path = prepare_config(&resolved_path, &dek_config)?;
+    let dek_config = config::load_all(resolved_path)?;
+    let prepared_path = prepare_config(resolved_path, &dek_config)?;
 
     // Bake into standalone binary
-    let baked_path = PathBuf::from(format!("/tmp/{}", container_name));
+    let baked_path = PathBuf::from(format!("/tmp/{}", name));
     println!("  {} Baking config into binary...", c!("→", yellow));
-    bake::create_baked_binary(&prepared_path, &dek_binary, &baked_path)?;
+    bake::create_baked_binary(&prepared_path, &dek_binary, &baked_path, bake::Compression::default(), None)?;
+    Ok(baked_path)
+}
 
-    if is_new {
-        // Create new container with keep-alive process
-        println!("  {} Creating container...", c!("→", yellow));
-        let mut create_args = vec!["create", "--name", &container_name, "-w", "/root"];
-        let config_dir = if resolved_path.is_file() {
-            resolved_path.parent().unwrap_or(std::path::Path::new("."))
-        } else {
-            resolved_path.as_path()
-        };
-        let mounts: Vec<String> = test_config
-            .map(|t| t.mount.clone())
-            .unwrap_or_default()
-            .into_iter()
-            .map(|m| {
-                // Resolve relative host paths against config dir
-                if let Some((host, rest)) = m.split_once(':') {
-                    if !host.starts_with('/') && !host.starts_with('~') {
-                        let joined = config_dir.join(host);
-                        // Create dir so canonicalize works (Docker needs absolute paths)
-                        let _ = std::fs::create_dir_all(&joined);
-                        let abs = joined.canonicalize().unwrap_or(joined);
-                        return format!("{}:{}", abs.display(), rest);
-                    }
-                }
-                m
-            })
-            .collect();
-        for m in &mounts {
-            create_args.push("-v");
-            create_args.push(m);
-        }
-        create_args.extend_from_slice(&[&image, "tail", "-f", "/dev/null"]);
-        let create_status = Command::new("docker")
-            .args(&create_args)
-            .stdout(Stdio::null())
-            .stderr(Stdio::inherit())
-            .status()?;
-        if !create_status.success() {
-            bail!("Failed to create container");
+/// True if the musl binary is newer than everything under src/ and Cargo.toml/Cargo.lock.
+fn musl_build_fresh(cwd: &std::path::Path, binary: &std::path::Path) -> bool {
+    fn newest(path: &std::path::Path) -> Option<std::time::SystemTime> {
+        if !path.is_dir() {
+            return path.metadata().and_then(|m| m.modified()).ok();
         }
+        std::fs::read_dir(path).ok()?
+            .flatten()
+            .filter_map(|entry| newest(&entry.path()))
+            .max()
+    }
+    let Some(built) = binary.metadata().and_then(|m| m.modified()).ok() else {
+        return false;
+    };
+    ["src", "Cargo.toml", "Cargo.lock"].iter()
+        .filter_map(|p| newest(&cwd.join(p)))
+        .all(|t| t < built)
+}
+
+/// Create a test container with a keep-alive process and the `[test]` mounts.
+fn create_test_container(
+    name: &str, image: &str, resolved_path: &std::path::Path,
+    test_config: Option<&config::TestConfig>, env_args: &[String],
+) -> Result<()> {
+    println!("  {} Creating container...", c!("→", yellow));
+    let mut create_args = vec!["create", "--name", name, "-w", "/root"];
+    create_args.extend(env_args.iter().map(String::as_str));
+    let config_dir = if resolved_path.is_file() {
+        resolved_path.parent().unwrap_or(std::path::Path::new("."))
+    } else {
+        resolved_path
+    };
+    let mounts: Vec<String> = test_config
+        .map(|t| t.mount.clone())
+        .unwrap_or_default()
+        .into_iter()
+        .map(|m| {
+            // Resolve relative host paths against config dir
+            if let Some((host, rest)) = m.split_once(':') {
+                if !host.starts_with('/') && !host.starts_with('~') {
+                    let joined = config_dir.join(host);
+                    // Create dir so canonicalize works (Docker needs absolute paths)
+                    let _ = std::fs::create_dir_all(&joined);
+                    let abs = joined.canonicalize().unwrap_or(joined);
+                    return format!("{}:{}", abs.display(), rest);
+                }
+            }
+            m
+        })
+        .collect();
+    for m in &mounts {
+        create_args.push("-v");
+        create_args.push(m);
+    }
+    create_args.extend_from_slice(&[image, "tail", "-f", "/dev/null"]);
+    let create_status = Command::new(container_engine())
+        .args(&create_args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::inherit())
+        .status()?;
+    if !create_status.success() {
+        bail!("Failed to create container");
     }
+    Ok(())
+}
 
+/// Copy the baked binary into the container, start it, and run `dek apply`.
+fn apply_in_container(
+    name: &str, baked_path: &std::path::Path, selectors: &[String], env_args: &[String],
+) -> Result<std::process::ExitStatus> {
     // Copy baked binary into container
     println!("  {} Copying dek into container...", c!("→", yellow));
-    let cp_status = Command::new("docker")
-        .args(["cp", &baked_path.to_string_lossy(), &format!("{}:/usr/local/bin/dek", container_name)])
+    let cp_status = Command::new(container_engine())
+        .args(["cp", &baked_path.to_string_lossy(), &format!("{}:/usr/local/bin/dek", name)])
         .status()?;
     if !cp_status.success() {
         bail!("Failed to copy binary into container");
     }
 
     // Ensure container is running
-    if get_container_state(&container_name).as_deref() != Some("running") {
-        docker_start(&container_name)?;
+    if get_container_state(name).as_deref() != Some("running") {
+        docker_start(name)?;
     }
 
     // Apply config inside container
     println!("  {} Applying config...", c!("→", yellow));
     println!();
 
-    let mut apply_args = vec!["exec".to_string(), container_name.clone(),
-                              "dek".to_string(), "apply".to_string()];
-    apply_args.extend(selectors);
+    // Env is passed again on exec so reused containers pick up changes
+    let mut apply_args = vec!["exec".to_string()];
+    apply_args.extend(env_args.iter().cloned());
+    apply_args.extend([name.to_string(), "dek".to_string(), "apply".to_string()]);
+    apply_args.extend(selectors.iter().cloned());
 
-    let apply_status = Command::new("docker")
+    Ok(Command::new(container_engine())
         .args(&apply_args)
         .stdin(Stdio::inherit())
         .stdout(Stdio::inherit())
         .stderr(Stdio::inherit())
-        .status()?;
-
-    if !apply_status.success() {
-        println!();
-        println!("  {} Apply had errors, dropping into shell anyway", c!("!", yellow));
-    }
-
-    // Drop into shell
-    println!();
-    println!("Dropping into shell...");
-    docker_shell(&container_name)?;
-
-    if rm {
-        let _ = Command::new("docker").args(["rm", "-f", &container_name])
-            .stdout(Stdio::null()).stderr(Stdio::null()).status();
-        println!("Container removed: {}", container_name);
-    } else {
-        println!();
-        println!("Container kept: {}", c!(container_name, bold));
-        println!("  Rerun:     {}", c!("dek test", dimmed));
-        println!("  Attach:    {}", c!("dek test --attach", dimmed));
-        println!("  Fresh:     {}", c!("dek test --fresh", dimmed));
-        println!("  Remove:    {}", c!(format!("docker rm {}", container_name), dimmed));
-    }
+        .status()?)
+}
 
-    Ok(())
+fn docker_remove(name: &str) {
+    let _ = Command::new(container_engine()).args(["rm", "-f", name])
+        .stdout(Stdio::null()).stderr(Stdio::null()).status();
 }
 
 fn get_container_state(name: &str) -> Option<String> {
-    let output = Command::new("docker")
+    let output = Command::new(container_engine())
         .args(["inspect", "-f", "{{.State.Status}}", name])
         .stdout(Stdio::piped())
         .stderr(Stdio::null())
@@ -1770,7 +3367,7 @@ fn get_container_state(name: &str) -> Option<String> {
 }
 
 fn docker_start(name: &str) -> Result<()> {
-    let status = Command::new("docker")
+    let status = Command::new(container_engine())
         .args(["start", name])
         .stdout(Stdio::null())
         .stderr(Stdio::inherit())
@@ -1782,14 +3379,14 @@ fn docker_start(name: &str) -> Result<()> {
 }
 
 fn docker_shell(name: &str) -> Result<()> {
-    let status = Command::new("docker")
+    let status = Command::new(container_engine())
         .args(["exec", "-it", name, "bash", "-l"])
         .stdin(Stdio::inherit())
         .stdout(Stdio::inherit())
         .stderr(Stdio::inherit())
         .status()
         .or_else(|_| {
-            Command::new("docker")
+            Command::new(container_engine())
                 .args(["exec", "-it", name, "sh"])
                 .stdin(Stdio::inherit())
                 .stdout(Stdio::inherit())
@@ -1797,16 +3394,20 @@ fn docker_shell(name: &str) -> Result<()> {
                 .status()
         })?;
     if !status.success() {
-        bail!("docker exec exited with status {}", status);
+        bail!("{} exec exited with status {}", container_engine(), status);
     }
     Ok(())
 }
 
 
 
-fn run_setup() -> Result<()> {
+fn run_setup(uninstall: bool) -> Result<()> {
     use std::fs;
 
+    if uninstall {
+        return run_uninstall();
+    }
+
     output::print_header("Setting up dek");
     println!();
 
@@ -1818,59 +3419,123 @@ fn run_setup() -> Result<()> {
         util::Shell::Zsh => zsh_completions(),
         util::Shell::Bash => bash_completions(),
         util::Shell::Fish => fish_completions(),
+        util::Shell::Nushell => nushell_completions(),
+        util::Shell::Pwsh => powershell_completions(),
     };
 
     // Determine completions path and install
     let home = std::env::var("HOME")?;
-    let (comp_path, source_line) = match shell {
-        util::Shell::Zsh => {
-            let dir = format!("{}/.zsh/completions", home);
-            fs::create_dir_all(&dir)?;
-            (
-                format!("{}/_dek", dir),
-                Some("fpath=(~/.zsh/completions $fpath) && autoload -Uz compinit && compinit"),
-            )
-        }
-        util::Shell::Bash => {
-            let dir = format!("{}/.local/share/bash-completion/completions", home);
-            fs::create_dir_all(&dir)?;
-            (format!("{}/dek", dir), None)
-        }
-        util::Shell::Fish => {
-            let dir = format!("{}/.config/fish/completions", home);
-            fs::create_dir_all(&dir)?;
-            (format!("{}/dek.fish", dir), None)
-        }
-    };
+    let (comp_path, source_line) = completion_install(shell, &home);
+    if let Some(dir) = comp_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
 
-    fs::write(&comp_path, &completions_str)?;
-    println!("  {} Wrote completions to {}", c!("✓", green), comp_path);
+    let current = fs::read(&comp_path).map(|c| md5::compute(c) == md5::compute(&completions_str)).unwrap_or(false);
+    let mut changed = !current;
+    if current {
+        println!("  {} Completions already current in {}", c!("•", dimmed), comp_path.display());
+    } else {
+        fs::write(&comp_path, &completions_str)?;
+        println!("  {} Wrote completions to {}", c!("✓", green), comp_path.display());
+    }
 
-    // Ensure source line in rc if needed (for zsh)
-    if let Some(line) = source_line {
-        let rc_path = format!("{}/.zshrc", home);
+    // Ensure source line in rc if needed (zsh fpath, nushell/PowerShell load the script)
+    if let Some((line, marker)) = source_line {
+        let rc_path = util::expand_path(shell.rc_file());
+        let rc_name = rc_path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
         let rc_content = fs::read_to_string(&rc_path).unwrap_or_default();
 
-        if !rc_content.contains("/.zsh/completions") {
+        if !rc_content.contains(marker) {
             let mut new_content = rc_content;
             if !new_content.ends_with('\n') && !new_content.is_empty() {
                 new_content.push('\n');
             }
-            new_content.push_str(line);
+            new_content.push_str(&line);
             new_content.push('\n');
+            if let Some(dir) = rc_path.parent() {
+                fs::create_dir_all(dir)?;
+            }
             fs::write(&rc_path, &new_content)?;
-            println!("  {} Added completions to .zshrc", c!("✓", green));
+            changed = true;
+            println!("  {} Added completions to {}", c!("✓", green), rc_name);
         } else {
-            println!("  {} Completions already configured in .zshrc", c!("•", dimmed));
+            println!("  {} Completions already configured in {}", c!("•", dimmed), rc_name);
         }
     }
 
+    if changed {
+        println!();
+        println!("  {} Restart your shell or run: exec {}", c!("✓", green), shell.name());
+    }
+
+    Ok(())
+}
+
+/// Undo `dek setup`: remove the completions file and the rc line it added.
+fn run_uninstall() -> Result<()> {
+    use std::fs;
+
+    output::print_header("Removing dek completions");
     println!();
-    println!("  {} Restart your shell or run: exec {}", c!("✓", green), shell.name());
+
+    let shell = util::Shell::detect();
+    println!("  {} Detected shell: {}", c!("•", blue), shell.name());
+
+    let home = std::env::var("HOME")?;
+    let (comp_path, source_line) = completion_install(shell, &home);
+    if comp_path.exists() {
+        fs::remove_file(&comp_path)?;
+        println!("  {} Removed {}", c!("✓", green), comp_path.display());
+    } else {
+        println!("  {} No completions at {}", c!("•", dimmed), comp_path.display());
+    }
+
+    if let Some((line, _)) = source_line {
+        let rc_path = util::expand_path(shell.rc_file());
+        let rc_name = rc_path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        let rc_content = fs::read_to_string(&rc_path).unwrap_or_default();
+        if rc_content.lines().any(|l| l == line) {
+            let kept: Vec<&str> = rc_content.lines().filter(|l| *l != line).collect();
+            let mut new_content = kept.join("\n");
+            if !new_content.is_empty() {
+                new_content.push('\n');
+            }
+            fs::write(&rc_path, new_content)?;
+            println!("  {} Removed completions line from {}", c!("✓", green), rc_name);
+        }
+    }
 
     Ok(())
 }
 
+/// Where `dek setup` installs completions for `shell`, plus the line (and the marker
+/// that detects it) to add to the shell's rc file when the shell doesn't autoload them.
+fn completion_install(shell: util::Shell, home: &str) -> (PathBuf, Option<(String, &'static str)>) {
+    let home = std::path::Path::new(home);
+    match shell {
+        util::Shell::Zsh => (
+            home.join(".zsh/completions/_dek"),
+            Some((
+                "fpath=(~/.zsh/completions $fpath) && autoload -Uz compinit && compinit".to_string(),
+                "/.zsh/completions",
+            )),
+        ),
+        util::Shell::Bash => (home.join(".local/share/bash-completion/completions/dek"), None),
+        util::Shell::Fish => (home.join(".config/fish/completions/dek.fish"), None),
+        util::Shell::Nushell => {
+            let rc = util::expand_path(shell.rc_file());
+            let path = rc.with_file_name("completions").join("dek.nu");
+            let line = format!("source \"{}\"", path.display());
+            (path, Some((line, "completions/dek.nu")))
+        }
+        util::Shell::Pwsh => {
+            let path = util::expand_path(shell.rc_file()).with_file_name("dek-completions.ps1");
+            let line = format!(". \"{}\"", path.display());
+            (path, Some((line, "dek-completions.ps1")))
+        }
+    }
+}
+
 fn print_rich_help(meta: Option<&config::Meta>, config_path: &PathBuf) -> Result<()> {
     let exe_name = std::env::current_exe()
         .ok()
@@ -1940,13 +3605,19 @@ fn print_rich_help(meta: Option<&config::Meta>, config_path: &PathBuf) -> Result
     if !hidden("commands") {
         println!("  {}", c!("COMMANDS", dimmed));
         println!("    {} {}  {}", c!("apply", white), c!("a", dimmed), c!("Apply configuration", dimmed));
-        println!("    {} {}  {}", c!("check", white), c!("c", dimmed), c!("Check what would change (dry-run)", dimmed));
+        println!("    {} {}  {}", c!("check", white), c!("c", dimmed), c!("Check which items need changes", dimmed));
         println!("    {}  {}  {}", c!("plan", white), c!("p", dimmed), c!("List items from config", dimmed));
+        println!("    {}  {}  {}", c!("diff", white), c!(" ", dimmed), c!("Show file changes apply would make", dimmed));
         println!("    {}   {}  {}", c!("run", white), c!("r", dimmed), c!("Run a command from config", dimmed));
         println!("    {}  {}  {}", c!("test", white), c!("t", dimmed), c!("Test in container", dimmed));
         println!("    {} {}  {}", c!("exec", white), c!("dx", dimmed), c!("Run command in test container", dimmed));
         println!("    {} {}  {}", c!("state", white), c!("s", dimmed), c!("Query system state probes", dimmed));
         println!("    {}  {}  {}", c!("bake", white), c!(" ", dimmed), c!("Bake into standalone binary", dimmed));
+        println!("    {}  {}  {}", c!("pack", white), c!(" ", dimmed), c!("Pack config into a tar.gz", dimmed));
+        println!("    {}  {}  {}", c!("info", white), c!(" ", dimmed), c!("Inspect a baked binary", dimmed));
+        println!("    {} {}  {}", c!("cache", white), c!(" ", dimmed), c!("Show or clear local caches", dimmed));
+        println!("    {} {}  {}", c!("graph", white), c!(" ", dimmed), c!("Show probe and config dependencies", dimmed));
+        println!("    {} {}  {}", c!("validate", white), c!(" ", dimmed), c!("Lint config files", dimmed));
         println!();
     }
 
@@ -2014,42 +3685,61 @@ fn print_rich_help(meta: Option<&config::Meta>, config_path: &PathBuf) -> Result
     Ok(())
 }
 
-fn run_complete(config_path: Option<PathBuf>, what: &str) -> Result<()> {
+fn run_complete(config_path: Option<PathBuf>, what: &str, arg: Option<&str>) -> Result<()> {
     // Shell-agnostic check if completions are installed (for use in [[command]].check)
     if what == "check" {
         let home = std::env::var("HOME").unwrap_or_default();
-        let path = match util::Shell::detect() {
-            util::Shell::Zsh => format!("{}/.zsh/completions/_dek", home),
-            util::Shell::Bash => format!("{}/.local/share/bash-completion/completions/dek", home),
-            util::Shell::Fish => format!("{}/.config/fish/completions/dek.fish", home),
-        };
-        if !std::path::Path::new(&path).exists() {
+        let (path, _) = completion_install(util::Shell::detect(), &home);
+        if !path.exists() {
             std::process::exit(1);
         }
         return Ok(());
     }
 
+    // Whole command line up to the cursor (nushell/PowerShell scripts)
+    if what == "words" {
+        let line = arg.unwrap_or("");
+        let mut words: Vec<String> = line.split_whitespace().map(String::from).collect();
+        if line.is_empty() || line.ends_with(char::is_whitespace) {
+            words.push(String::new());
+        }
+        for item in complete_words(config_path, &words) {
+            println!("{}", item);
+        }
+        return Ok(());
+    }
+
+    for item in complete_items(config_path, what, arg) {
+        println!("{}", item);
+    }
+    Ok(())
+}
+
+/// Dynamic completion values: configs, labels, run, run-args <name>, state
+fn complete_items(config_path: Option<PathBuf>, what: &str, arg: Option<&str>) -> Vec<String> {
     let path = match config_path
         .or_else(bake::check_embedded)
         .or_else(config::find_default_config)
     {
         Some(p) => p,
-        None => return Ok(()),
+        None => return Vec::new(),
     };
     let resolved = config::resolve_path(&path).unwrap_or(path);
-    let meta = config::load_meta(&resolved);
+    // Completion has nowhere to report a broken meta.toml
+    let meta = config::load_meta(&resolved).unwrap_or_default();
 
+    let mut items = Vec::new();
     match what {
-        "configs" => {
+        "configs" | "labels" => {
             let configs = config::list_configs(&resolved, meta.as_ref()).unwrap_or_default();
-            for cfg in &configs {
-                println!("{}", cfg.key);
+            if what == "configs" {
+                items.extend(configs.iter().map(|cfg| cfg.key.clone()));
             }
             let mut seen = std::collections::HashSet::new();
             for cfg in &configs {
                 for l in &cfg.labels {
                     if seen.insert(l.clone()) {
-                        println!("@{}", l);
+                        items.push(format!("@{}", l));
                     }
                 }
             }
@@ -2057,22 +3747,120 @@ fn run_complete(config_path: Option<PathBuf>, what: &str) -> Result<()> {
         "run" => {
             let config = config::load_all(&resolved).unwrap_or_default();
             if let Some(run) = &config.run {
-                let mut cmds: Vec<_> = run.keys().collect();
+                let mut cmds: Vec<_> = run.keys().cloned().collect();
                 cmds.sort();
-                for cmd in cmds {
-                    println!("{}", cmd);
-                }
+                items.extend(cmds);
             }
         }
-        "state" => {
+        // Declared args of a run command: `_complete run-args <name>`
+        "run-args" => {
             let config = config::load_all(&resolved).unwrap_or_default();
-            for item in state::completions(&config.state) {
-                println!("{}", item);
+            if let Some(run_config) = arg.and_then(|name| config.run.as_ref()?.get(name)) {
+                items.extend(run_config.args.iter().map(|a| format!("--{}", a.name)));
             }
         }
+        "state" => {
+            let config = config::load_all(&resolved).unwrap_or_default();
+            items.extend(state::completions(&config.state));
+        }
         _ => {}
     }
-    Ok(())
+    items
+}
+
+/// Candidates for the last word of `words` (a `dek ...` command line), filtered by
+/// its prefix. Flags and subcommands come from the clap definition.
+fn complete_words(mut config_path: Option<PathBuf>, words: &[String]) -> Vec<String> {
+    let root = Cli::command();
+    let Some((cur, prior)) = words.split_last() else {
+        return Vec::new();
+    };
+    let prior = prior.get(1..).unwrap_or_default(); // skip "dek"
+
+    let takes_value = |cmd: &clap::Command, word: &str| {
+        cmd.get_arguments().any(|a| {
+            a.get_action().takes_values() && !a.is_positional()
+                && (a.get_long().is_some_and(|l| word == format!("--{}", l))
+                    || a.get_short().is_some_and(|s| word == format!("-{}", s)))
+        })
+    };
+    let flags = |cmd: &clap::Command| -> Vec<String> {
+        cmd.get_arguments()
+            .filter(|a| !a.is_hide_set())
+            .filter_map(|a| a.get_long().map(|l| format!("--{}", l)))
+            .collect()
+    };
+
+    // Find the subcommand and its positional args, skipping flag values
+    let mut sub: Option<&clap::Command> = None;
+    let mut positionals: Vec<&String> = Vec::new();
+    let mut iter = prior.iter();
+    let mut pending_flag: Option<&String> = None;
+    while let Some(word) = iter.next() {
+        pending_flag = None;
+        if word.starts_with('-') {
+            if takes_value(&root, word) || sub.is_some_and(|s| takes_value(s, word)) {
+                let value = iter.next();
+                if word == "-C" || word == "--config" {
+                    config_path = value.map(PathBuf::from);
+                }
+                if value.is_none() {
+                    pending_flag = Some(word);
+                }
+            }
+        } else if sub.is_none() {
+            sub = root.find_subcommand(word);
+            if sub.is_none() {
+                return Vec::new(); // inline provider specs
+            }
+        } else {
+            positionals.push(word);
+        }
+    }
+    // Completing a flag's value: offer its possible values, if it has any
+    if let Some(flag) = pending_flag {
+        let long = flag.trim_start_matches('-');
+        return root.get_arguments()
+            .chain(sub.into_iter().flat_map(|s| s.get_arguments()))
+            .filter(|a| a.get_long() == Some(long))
+            .flat_map(|a| a.get_possible_values())
+            .map(|v| v.get_name().to_string())
+            .filter(|v| v.starts_with(cur.as_str()))
+            .collect();
+    }
+
+    let candidates: Vec<String> = match sub {
+        None if cur.starts_with('-') => flags(&root),
+        None => root.get_subcommands()
+            .filter(|s| !s.is_hide_set())
+            .flat_map(|s| std::iter::once(s.get_name().to_string()).chain(s.get_all_aliases().map(String::from)))
+            .collect(),
+        Some(s) if cur.starts_with('-') => {
+            let mut out = Vec::new();
+            if s.get_name() == "run" {
+                if let Some(name) = positionals.first() {
+                    out.extend(complete_items(config_path, "run-args", Some(name)));
+                }
+            }
+            out.extend(flags(s));
+            out.extend(flags(&root).into_iter().filter(|f| root.get_arguments()
+                .any(|a| a.is_global_set() && a.get_long().is_some_and(|l| *f == format!("--{}", l)))));
+            out
+        }
+        Some(s) => match s.get_name() {
+            "apply" | "check" | "plan" | "diff" | "test" => {
+                let what = if cur.starts_with('@') { "labels" } else { "configs" };
+                complete_items(config_path, what, None)
+            }
+            "run" if positionals.is_empty() => complete_items(config_path, "run", None),
+            "state" if positionals.is_empty() => complete_items(config_path, "state", None),
+            "completions" if positionals.is_empty() => {
+                ["bash", "zsh", "fish", "powershell", "elvish"].map(String::from).to_vec()
+            }
+            _ => Vec::new(),
+        },
+    };
+    candidates.into_iter().filter(|c| c.starts_with(cur.as_str())).collect()
 }
 
 fn zsh_completions() -> String {
@@ -2080,7 +3868,11 @@ fn zsh_completions() -> String {
 
 _dek_configs() {
     local -a items
-    items=(${(f)"$(dek _complete configs 2>/dev/null)"})
+    if [[ $PREFIX == @* ]]; then
+        items=(${(f)"$(dek _complete labels 2>/dev/null)"})
+    else
+        items=(${(f)"$(dek _complete configs 2>/dev/null)"})
+    fi
     [[ -n "$items" ]] && compadd -- $items
 }
 
@@ -2090,6 +3882,12 @@ _dek_run_cmds() {
     [[ -n "$items" ]] && compadd -- $items
 }
 
+_dek_run_args() {
+    local -a items
+    items=(${(f)"$(dek _complete run-args ${words[2]} 2>/dev/null)"})
+    [[ -n "$items" ]] && compadd -- $items
+}
+
 _dek_state_probes() {
     local -a items
     items=(${(f)"$(dek _complete state 2>/dev/null)"})
@@ -2105,6 +3903,7 @@ _dek() {
         'c:Check what would change'
         'plan:List items from config'
         'p:List items from config'
+        'diff:Show file changes apply would make'
         'run:Run a command'
         'r:Run a command'
         'test:Test in container'
@@ -2112,6 +3911,11 @@ _dek() {
         'exec:Run in test container'
         'dx:Run in test container'
         'bake:Bake into standalone binary'
+        'pack:Pack config into a tar.gz'
+        'info:Inspect a baked binary'
+        'cache:Show or clear local caches'
+        'graph:Show probe and config dependencies'
+        'validate:Lint config files'
         'state:Query system state'
         's:Query system state'
         'setup:Install completions'
@@ -2123,6 +3927,22 @@ _dek() {
         '(-t --target)'{-t,--target}'[Remote target]:target:' \
         '(-r --remotes)'{-r,--remotes}'[Remote pattern]:pattern:' \
         '(-q --quiet)'{-q,--quiet}'[Suppress output]' \
+        '*'{-v,--verbose}'[Show full output of failing commands]' \
+        '--log[Append timestamped results to file]:path:_files' \
+        '--parallel[Max concurrent hosts]:n:' \
+        '--no-control-master[Disable ssh connection reuse]' \
+        '--jump[Jump/bastion host]:host:' \
+        '--connect-timeout[Seconds to wait for ssh connections]:secs:' \
+        '--host-timeout[Give up on a remote host after this long]:duration:' \
+        '--limit[Subset of matched hosts (N or host1,host2)]:limit:' \
+        '--stream[Print host output live, prefixed with host]' \
+        '--report[Write deploy results as JSON]:path:_files' \
+        '--ask-become-pass[Prompt for sudo password for remote hosts]' \
+        '--force[Ignore caches and freshness checks]' \
+        '--retries[Extra attempts for network steps]:n:' \
+        '--insecure[Run install scripts that fail their pinned sha256]' \
+        '--print-config=-[Print the merged config and exit]::format:(toml json)' \
+        '--no-progress[No spinners, one line per step]' \
         '--color[Color mode]:mode:(auto always never)' \
         '1:command:->cmd' \
         '*::arg:->args'
@@ -2133,14 +3953,48 @@ _dek() {
             ;;
         args)
             case ${words[1]} in
-                apply|a|check|c|plan|p)
-                    _dek_configs
+                apply|a)
+                    _arguments \
+                        '--json[Newline-delimited JSON output]' \
+                        '--only[Only these providers]:kinds:' \
+                        '--skip[Skip these providers]:kinds:' \
+                        '--watch[Re-apply when files change]' \
+                        '(-n --dry-run)'{-n,--dry-run}'[Show what would change without applying]' \
+                        '(-j --jobs)'{-j,--jobs}'[Apply independent items N at a time]:n:' \
+                        '--confirm-each[Ask before applying each item]' \
+                        '--incremental[Skip checking items unchanged since their last apply]' \
+                        '--prune[Remove what config no longer defines]' \
+                        '--yes-requirements[Install toolchains by script without asking]' \
+                        '--changed[Only configs changed since the last commit]' \
+                        '*:config:_dek_configs'
+                    ;;
+                check|c)
+                    _arguments \
+                        '--json[Newline-delimited JSON output]' \
+                        '--exit-code[Exit 2 when anything is missing]' \
+                        '--explain[Show why one item passes or fails]:item:' \
+                        '--only[Only these providers]:kinds:' \
+                        '--skip[Skip these providers]:kinds:' \
+                        '*:config:_dek_configs'
+                    ;;
+                plan|p|diff)
+                    _arguments '--json[Newline-delimited JSON output]' '*:config:_dek_configs'
                     ;;
                 run|r)
-                    (( CURRENT == 2 )) && _dek_run_cmds
+                    if (( CURRENT == 2 )); then
+                        _dek_run_cmds
+                    elif [[ $PREFIX == -* ]]; then
+                        _dek_run_args
+                    fi
                     ;;
                 state|s)
-                    (( CURRENT == 2 )) && _dek_state_probes
+                    _arguments \
+                        '(--format)--json[Output as JSON]' \
+                        '(--json)--format[Output format]:format:(table json yaml env)' \
+                        '--watch[Re-render on an interval]' \
+                        '--interval[Refresh interval for --watch]:interval:' \
+                        '--no-cache[Ignore cached probe values]' \
+                        '*:probe:_dek_state_probes'
                     ;;
                 test|t)
                     _arguments \
@@ -2148,16 +4002,43 @@ _dek() {
                         '(-r --rm)'{-r,--rm}'[Remove after exit]' \
                         '(-f --fresh)'{-f,--fresh}'[Force new container]' \
                         '(-a --attach)'{-a,--attach}'[Attach to existing]' \
+                        '--rebuild[Rebuild musl dek]' \
+                        '*'{-e,--env}'[Container env var]:KEY=VAL:' \
+                        '*--env-file[Container env file]:file:_files' \
+                        '--no-shell[Apply only, exit with apply status]' \
+                        '(--all --matrix)'{--all,--matrix}'[Test every [test] image]' \
                         '*:selector:_dek_configs'
                     ;;
                 exec|dx)
-                    _normal
+                    _arguments \
+                        '(-u --user)'{-u,--user}'[User to run as]:user:' \
+                        '(-w --workdir)'{-w,--workdir}'[Working directory]:dir:' \
+                        '*::command:_normal'
                     ;;
                 bake)
                     _arguments \
                         '(-o --output)'{-o,--output}'[Output path]:path:_files' \
+                        '--compression[Archive compression]:compression:(0 1 2 3 4 5 6 7 8 9 zstd)' \
+                        '--encrypt[Encrypt the embedded config]' \
+                        '*:config:_files'
+                    ;;
+                pack)
+                    _arguments \
+                        '(-o --output)'{-o,--output}'[Output archive]:path:_files' \
                         '*:config:_files'
                     ;;
+                info)
+                    _arguments '--binary[Baked binary to inspect]:path:_files'
+                    ;;
+                cache)
+                    _arguments '--list[List every cached path]' '--clear[Remove all cached data]'
+                    ;;
+                graph)
+                    _arguments '--format[Output format]:format:(tree dot)'
+                    ;;
+                setup)
+                    _arguments '--uninstall[Remove completions]'
+                    ;;
                 completions)
                     _arguments '1:shell:(bash zsh fish)'
                     ;;
@@ -2175,13 +4056,13 @@ fn bash_completions() -> String {
     local cur prev words cword
     _init_completion || return
 
-    local commands="apply a check c plan p run r state s test t exec dx bake setup completions"
+    local commands="apply a check c plan p diff run r state s test t exec dx bake pack info cache graph validate setup completions"
 
     # Find the subcommand
     local cmd="" cmd_idx=0
     for ((i=1; i<cword; i++)); do
         case "${words[i]}" in
-            -C|--config|-t|--target|-r|--remotes|--color) ((i++)); continue ;;
+            -C|--config|-t|--target|-r|--remotes|--parallel|--limit|--jump|--report|--log|--color|--retries|--connect-timeout|--host-timeout) ((i++)); continue ;;
             -*) continue ;;
             *) cmd="${words[i]}"; cmd_idx=$i; break ;;
         esac
@@ -2193,13 +4074,19 @@ fn bash_completions() -> String {
         return
     fi
 
+    # Selectors: config keys and @labels, only labels once "@" is typed
+    local selectors=configs
+    [[ $cur == @* ]] && selectors=labels
+
     case $cmd in
-        apply|a|check|c|plan|p)
-            COMPREPLY=($(compgen -W "$(dek _complete configs 2>/dev/null)" -- "$cur"))
+        apply|a|check|c|plan|p|diff)
+            COMPREPLY=($(compgen -W "$(dek _complete $selectors 2>/dev/null)" -- "$cur"))
             ;;
         run|r)
             if [[ $cword -eq $((cmd_idx+1)) ]]; then
                 COMPREPLY=($(compgen -W "$(dek _complete run 2>/dev/null)" -- "$cur"))
+            elif [[ $cur == -* ]]; then
+                COMPREPLY=($(compgen -W "$(dek _complete run-args "${words[cmd_idx+1]}" 2>/dev/null)" -- "$cur"))
             fi
             ;;
         state|s)
@@ -2209,14 +4096,50 @@ fn bash_completions() -> String {
             ;;
         test|t)
             case $prev in
-                -i|--image) return ;;
+                -i|--image|-e|--env) return ;;
+                --env-file) COMPREPLY=($(compgen -f -- "$cur")); return ;;
+            esac
+            if [[ $cur == -* ]]; then
+                COMPREPLY=($(compgen -W "-i --image -r --rm -f --fresh -a --attach --rebuild -e --env --env-file --no-shell --all --matrix" -- "$cur"))
+            else
+                COMPREPLY=($(compgen -W "$(dek _complete $selectors 2>/dev/null)" -- "$cur"))
+            fi
+            ;;
+        exec|dx)
+            case $prev in
+                -u|--user|-w|--workdir) return ;;
             esac
             if [[ $cur == -* ]]; then
-                COMPREPLY=($(compgen -W "-i --image -r --rm -f --fresh -a --attach" -- "$cur"))
+                COMPREPLY=($(compgen -W "-u --user -w --workdir" -- "$cur"))
+            fi
+            ;;
+        pack)
+            if [[ $prev == -o || $prev == --output || $cur != -* ]]; then
+                _filedir
+            else
+                COMPREPLY=($(compgen -W "-o --output" -- "$cur"))
+            fi
+            ;;
+        info)
+            if [[ $prev == --binary ]]; then
+                _filedir
+            else
+                COMPREPLY=($(compgen -W "--binary" -- "$cur"))
+            fi
+            ;;
+        cache)
+            COMPREPLY=($(compgen -W "--list --clear" -- "$cur"))
+            ;;
+        graph)
+            if [[ $prev == --format ]]; then
+                COMPREPLY=($(compgen -W "tree dot" -- "$cur"))
             else
-                COMPREPLY=($(compgen -W "$(dek _complete configs 2>/dev/null)" -- "$cur"))
+                COMPREPLY=($(compgen -W "--format" -- "$cur"))
             fi
             ;;
+        setup)
+            COMPREPLY=($(compgen -W "--uninstall" -- "$cur"))
+            ;;
         completions)
             COMPREPLY=($(compgen -W "bash zsh fish" -- "$cur"))
             ;;
@@ -2229,7 +4152,7 @@ complete -F _dek dek
 
 fn fish_completions() -> String {
     r#"# Subcommands
-set -l commands apply a check c plan p run r state s test t exec dx bake setup completions
+set -l commands apply a check c plan p diff run r state s test t exec dx bake pack info cache graph validate setup completions
 
 complete -c dek -n "not __fish_seen_subcommand_from $commands" -a apply -d 'Apply configuration'
 complete -c dek -n "not __fish_seen_subcommand_from $commands" -a a -d 'Apply configuration'
@@ -2237,6 +4160,7 @@ complete -c dek -n "not __fish_seen_subcommand_from $commands" -a check -d 'Chec
 complete -c dek -n "not __fish_seen_subcommand_from $commands" -a c -d 'Check what would change'
 complete -c dek -n "not __fish_seen_subcommand_from $commands" -a plan -d 'List items from config'
 complete -c dek -n "not __fish_seen_subcommand_from $commands" -a p -d 'List items from config'
+complete -c dek -n "not __fish_seen_subcommand_from $commands" -a diff -d 'Show file changes apply would make'
 complete -c dek -n "not __fish_seen_subcommand_from $commands" -a run -d 'Run a command'
 complete -c dek -n "not __fish_seen_subcommand_from $commands" -a r -d 'Run a command'
 complete -c dek -n "not __fish_seen_subcommand_from $commands" -a test -d 'Test in container'
@@ -2246,6 +4170,11 @@ complete -c dek -n "not __fish_seen_subcommand_from $commands" -a dx -d 'Run in
 complete -c dek -n "not __fish_seen_subcommand_from $commands" -a state -d 'Query system state'
 complete -c dek -n "not __fish_seen_subcommand_from $commands" -a s -d 'Query system state'
 complete -c dek -n "not __fish_seen_subcommand_from $commands" -a bake -d 'Bake into standalone binary'
+complete -c dek -n "not __fish_seen_subcommand_from $commands" -a pack -d 'Pack config into a tar.gz'
+complete -c dek -n "not __fish_seen_subcommand_from $commands" -a info -d 'Inspect a baked binary'
+complete -c dek -n "not __fish_seen_subcommand_from $commands" -a cache -d 'Show or clear local caches'
+complete -c dek -n "not __fish_seen_subcommand_from $commands" -a graph -d 'Show probe and config dependencies'
+complete -c dek -n "not __fish_seen_subcommand_from $commands" -a validate -d 'Lint config files'
 complete -c dek -n "not __fish_seen_subcommand_from $commands" -a setup -d 'Install completions'
 complete -c dek -n "not __fish_seen_subcommand_from $commands" -a completions -d 'Generate raw completions'
 
@@ -2254,21 +4183,67 @@ complete -c dek -s C -l config -d 'Config path' -r -F
 complete -c dek -s t -l target -d 'Remote target' -r
 complete -c dek -s r -l remotes -d 'Remote pattern' -r
 complete -c dek -s q -l quiet -d 'Suppress output'
+complete -c dek -s v -l verbose -d 'Show full output of failing commands'
+complete -c dek -l log -d 'Append timestamped results to file' -r -F
+complete -c dek -l parallel -d 'Max concurrent hosts' -r
+complete -c dek -l no-control-master -d 'Disable ssh connection reuse'
+complete -c dek -l jump -d 'Jump/bastion host' -r
+complete -c dek -l connect-timeout -d 'Seconds to wait for ssh connections' -r
+complete -c dek -l host-timeout -d 'Give up on a remote host after this long' -r
+complete -c dek -l limit -d 'Subset of matched hosts (N or host1,host2)' -r
+complete -c dek -l stream -d 'Print host output live, prefixed with host'
+complete -c dek -l report -d 'Write deploy results as JSON' -r -F
+complete -c dek -l ask-become-pass -d 'Prompt for sudo password for remote hosts'
+complete -c dek -l force -d 'Ignore caches and freshness checks'
+complete -c dek -l retries -d 'Extra attempts for network steps' -r
+complete -c dek -l insecure -d 'Run install scripts that fail their pinned sha256'
+complete -c dek -l print-config -d 'Print the merged config and exit'
+complete -c dek -l no-progress -d 'No spinners, one line per step'
 complete -c dek -l color -d 'Color mode' -r -a 'auto always never'
 
-# Dynamic completions for apply/check/plan and aliases
-for cmd in apply a check c plan p
-    complete -c dek -n "__fish_seen_subcommand_from $cmd" -a "(dek _complete configs 2>/dev/null)" -f
+# Dynamic completions for apply/check/plan/diff and aliases
+for cmd in apply a check c plan p diff
+    complete -c dek -n "__fish_seen_subcommand_from $cmd" -a "(dek _complete configs 2>/dev/null | string match -v '@*')" -d 'Config' -f
+    complete -c dek -n "__fish_seen_subcommand_from $cmd" -a "(dek _complete labels 2>/dev/null)" -d 'Label' -f
+    complete -c dek -n "__fish_seen_subcommand_from $cmd" -l json -d 'Newline-delimited JSON output'
 end
+for cmd in apply a check c
+    complete -c dek -n "__fish_seen_subcommand_from $cmd" -l only -d 'Only these providers' -r
+    complete -c dek -n "__fish_seen_subcommand_from $cmd" -l skip -d 'Skip these providers' -r
+end
+complete -c dek -n "__fish_seen_subcommand_from apply a" -l watch -d 'Re-apply when files change'
+complete -c dek -n "__fish_seen_subcommand_from apply a" -s n -l dry-run -d 'Show what would change without applying'
+complete -c dek -n "__fish_seen_subcommand_from apply a" -s j -l jobs -d 'Apply independent items N at a time' -r
+complete -c dek -n "__fish_seen_subcommand_from apply a" -l confirm-each -d 'Ask before applying each item'
+complete -c dek -n "__fish_seen_subcommand_from apply a" -l incremental -d 'Skip checking items unchanged since their last apply'
+complete -c dek -n "__fish_seen_subcommand_from apply a" -l prune -d 'Remove what config no longer defines'
+complete -c dek -n "__fish_seen_subcommand_from apply a" -l yes-requirements -d 'Install toolchains by script without asking'
+complete -c dek -n "__fish_seen_subcommand_from apply a" -l changed -d 'Only configs changed since the last commit'
+complete -c dek -n "__fish_seen_subcommand_from check c" -l exit-code -d 'Exit 2 when anything is missing'
+complete -c dek -n "__fish_seen_subcommand_from check c" -l explain -d 'Show why one item passes or fails' -r
 
 # Dynamic completions for run and alias
+function __fish_dek_run_name
+    set -l tokens (commandline -opc)
+    for i in (seq 2 (math (count $tokens) - 1))
+        if contains -- $tokens[$i] run r
+            echo $tokens[(math $i + 1)]
+            return
+        end
+    end
+end
 for cmd in run r
     complete -c dek -n "__fish_seen_subcommand_from $cmd" -a "(dek _complete run 2>/dev/null)" -f
+    complete -c dek -n "__fish_seen_subcommand_from $cmd; and string match -q -- '-*' (commandline -ct)" -a "(dek _complete run-args (__fish_dek_run_name) 2>/dev/null)" -f
 end
 
 # Dynamic completions for state and alias
 for cmd in state s
     complete -c dek -n "__fish_seen_subcommand_from $cmd" -a "(dek _complete state 2>/dev/null)" -f
+    complete -c dek -n "__fish_seen_subcommand_from $cmd" -l format -x -a "table json yaml env" -d 'Output format'
+    complete -c dek -n "__fish_seen_subcommand_from $cmd" -l watch -d 'Re-render on an interval'
+    complete -c dek -n "__fish_seen_subcommand_from $cmd" -l interval -x -d 'Refresh interval for --watch'
+    complete -c dek -n "__fish_seen_subcommand_from $cmd" -l no-cache -d 'Ignore cached probe values'
 end
 
 # Test flags and dynamic completions
@@ -2277,10 +4252,66 @@ for cmd in test t
     complete -c dek -n "__fish_seen_subcommand_from $cmd" -s r -l rm -d 'Remove after exit'
     complete -c dek -n "__fish_seen_subcommand_from $cmd" -s f -l fresh -d 'Force new container'
     complete -c dek -n "__fish_seen_subcommand_from $cmd" -s a -l attach -d 'Attach to existing'
-    complete -c dek -n "__fish_seen_subcommand_from $cmd" -a "(dek _complete configs 2>/dev/null)" -f
+    complete -c dek -n "__fish_seen_subcommand_from $cmd" -l rebuild -d 'Rebuild musl dek'
+    complete -c dek -n "__fish_seen_subcommand_from $cmd" -s e -l env -r -d 'Container env var'
+    complete -c dek -n "__fish_seen_subcommand_from $cmd" -l env-file -r -F -d 'Container env file'
+    complete -c dek -n "__fish_seen_subcommand_from $cmd" -l no-shell -d 'Apply only, exit with apply status'
+    complete -c dek -n "__fish_seen_subcommand_from $cmd" -l all -d 'Test every [test] image'
+    complete -c dek -n "__fish_seen_subcommand_from $cmd" -a "(dek _complete configs 2>/dev/null | string match -v '@*')" -d 'Config' -f
+    complete -c dek -n "__fish_seen_subcommand_from $cmd" -a "(dek _complete labels 2>/dev/null)" -d 'Label' -f
 end
 
+# Exec flags
+for cmd in exec dx
+    complete -c dek -n "__fish_seen_subcommand_from $cmd" -s u -l user -d 'User to run as' -r
+    complete -c dek -n "__fish_seen_subcommand_from $cmd" -s w -l workdir -d 'Working directory' -r
+end
+
+# Pack flags
+complete -c dek -n "__fish_seen_subcommand_from pack" -s o -l output -d 'Output archive' -r -F
+
+# Info flags
+complete -c dek -n "__fish_seen_subcommand_from info" -l binary -d 'Baked binary to inspect' -r -F
+
+# Cache flags
+complete -c dek -n "__fish_seen_subcommand_from cache" -l list -d 'List every cached path'
+complete -c dek -n "__fish_seen_subcommand_from cache" -l clear -d 'Remove all cached data'
+complete -c dek -n "__fish_seen_subcommand_from graph" -l format -d 'Output format' -xa 'tree dot'
+
+# Setup flags
+complete -c dek -n "__fish_seen_subcommand_from setup" -l uninstall -d 'Remove completions'
+
 # Completions subcommand
 complete -c dek -n "__fish_seen_subcommand_from completions" -a "bash zsh fish" -f
 "#.to_string()
 }
+
+fn nushell_completions() -> String {
+    r#"# dek completions for nushell (installed by `dek setup`)
+# Chains to any external completer configured before this file is sourced.
+let dek_fallback_completer = ($env.config.completions.external.completer? | default null)
+$env.config.completions.external.enable = true
+$env.config.completions.external.completer = {|spans|
+    if ($spans | first) == "dek" {
+        ^dek _complete words ($spans | str join " ") | lines | each {|v| {value: $v} }
+    } else if $dek_fallback_completer != null {
+        do $dek_fallback_completer $spans
+    }
+}
+"#.to_string()
+}
+
+fn powershell_completions() -> String {
+    r#"# dek completions for PowerShell (installed by `dek setup`)
+Register-ArgumentCompleter -Native -CommandName dek -ScriptBlock {
+    param($wordToComplete, $commandAst, $cursorPosition)
+    $line = $commandAst.ToString()
+    $end = [Math]::Min($cursorPosition - $commandAst.Extent.StartOffset, $line.Length)
+    $line = $line.Substring(0, $end)
+    if ($wordToComplete -eq '' -and -not $line.EndsWith(' ')) { $line += ' ' }
+    dek _complete words "$line" 2>$null | ForEach-Object {
+        [System.Management.Automation.CompletionResult]::new($_, $_, 'ParameterValue', $_)
+    }
+}
+"#.to_string()
+}
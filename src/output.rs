@@ -3,6 +3,60 @@ use indicatif::{MultiProgress, ProgressBar};
 use owo_colors::OwoColorize;
 use std::time::Duration;
 
+static PLAIN: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+/// Plain mode for CI / non-TTY output: no spinners, one static line per
+/// step, live command output printed as-is
+pub fn set_plain(plain: bool) {
+    PLAIN.store(plain, std::sync::atomic::Ordering::Relaxed);
+}
+
+pub fn plain() -> bool {
+    PLAIN.load(std::sync::atomic::Ordering::Relaxed)
+}
+
+static VERBOSITY: std::sync::atomic::AtomicU8 = std::sync::atomic::AtomicU8::new(0);
+
+/// Verbosity level (-v count). At 1+, failed items dump the full output of
+/// the command that failed.
+pub fn set_verbosity(level: u8) {
+    VERBOSITY.store(level, std::sync::atomic::Ordering::Relaxed);
+}
+
+pub fn verbosity() -> u8 {
+    VERBOSITY.load(std::sync::atomic::Ordering::Relaxed)
+}
+
+static LOG: std::sync::OnceLock<std::sync::Mutex<std::fs::File>> = std::sync::OnceLock::new();
+
+/// Append status lines to `path` (--log), uncolored and timestamped
+pub fn set_log(path: &std::path::Path) -> anyhow::Result<()> {
+    use anyhow::Context;
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open log file: {}", path.display()))?;
+    let _ = LOG.set(std::sync::Mutex::new(file));
+    Ok(())
+}
+
+fn logging() -> bool {
+    LOG.get().is_some()
+}
+
+/// Write a line to the --log file, if any. Multi-line messages get one
+/// timestamp per line.
+pub fn log(msg: &str) {
+    use std::io::Write;
+    let Some(file) = LOG.get() else { return };
+    let Ok(mut file) = file.lock() else { return };
+    let ts = chrono::Local::now().format("%Y-%m-%d %H:%M:%S");
+    for line in msg.lines() {
+        let _ = writeln!(file, "{} {}", ts, line);
+    }
+}
+
 pub fn format_duration(d: Duration) -> String {
     let secs = d.as_secs();
     if secs >= 60 {
@@ -40,6 +94,30 @@ pub fn print_check_result(item: &StateItem, result: &CheckResult) {
     }
 }
 
+/// Commands run by a check and what they printed, for `check --explain`.
+/// Long output is cut short unless -v.
+pub fn print_traced_commands(commands: &[crate::util::TracedCmd]) {
+    const MAX_LINES: usize = 20;
+    for cmd in commands {
+        let code = cmd.code.map_or("signal".to_string(), |c| format!("exit {}", c));
+        println!("    {} {} {}", c!("$", dimmed), cmd.command, c!(format!("({})", code), dimmed));
+        let lines: Vec<&str> = cmd.stdout.lines().chain(cmd.stderr.lines()).collect();
+        let shown = if verbosity() > 0 { lines.len() } else { lines.len().min(MAX_LINES) };
+        for line in &lines[..shown] {
+            println!("      {}", c!(line, dimmed));
+        }
+        if shown < lines.len() {
+            println!("      {}", c!(format!("… {} more lines (-v for all)", lines.len() - shown), dimmed));
+        }
+    }
+}
+
+pub fn print_explain_reasons(reasons: &[String]) {
+    for reason in reasons {
+        println!("    {} {}", c!("→", cyan), reason);
+    }
+}
+
 pub fn print_plan_item(item: &StateItem) {
     println!(
         "  {} {} {}",
@@ -155,6 +233,220 @@ pub fn print_plan_summary(total: usize) {
     println!("{} {} items", c!("•", blue), total);
 }
 
+pub fn print_diff_item(item: &StateItem, diff: &str) {
+    println!("  {} {} {}", c!("~", yellow), c!(item.kind, dimmed), c!(item.key, white));
+    for line in diff.lines() {
+        if line.starts_with("+++") || line.starts_with("---") {
+            println!("    {}", c!(line, bold));
+        } else if line.starts_with("@@") {
+            println!("    {}", c!(line, cyan));
+        } else if line.starts_with('+') {
+            println!("    {}", c!(line, green));
+        } else if line.starts_with('-') {
+            println!("    {}", c!(line, red));
+        } else {
+            println!("    {}", c!(line, dimmed));
+        }
+    }
+}
+
+pub fn print_diff_summary(changed: usize) {
+    println!();
+    if changed > 0 {
+        println!("{} {} item{} would change", c!("~", yellow), changed, if changed == 1 { "" } else { "s" });
+    } else {
+        println!("{} no changes", c!("✓", green));
+    }
+}
+
+/// "install htop", "write ~/.zshrc" — what apply would do to an item
+pub fn would_action(item: &StateItem) -> String {
+    let verb = match item.kind.as_str() {
+        k if k.starts_with("package.") => "install",
+        "file.symlink" => "link",
+        k if k.starts_with("file.") => "write",
+        "service" => "enable",
+        "alias" | "env" => "set",
+        "path" => "add to PATH",
+        "command" | "script" => "run",
+        _ => "apply",
+    };
+    format!("{} {}", verb, action_target(item))
+}
+
+/// What apply did to an item, for the "Changes" list: "installed ripgrep"
+pub fn done_action(item: &StateItem) -> String {
+    let verb = match item.kind.as_str() {
+        k if k.starts_with("package.") => "installed",
+        "file.symlink" => "linked",
+        k if k.starts_with("file.") => "wrote",
+        "service" => "enabled",
+        "alias" | "env" => "set",
+        "path" => "added to PATH",
+        "command" | "script" => "ran",
+        _ => "applied",
+    };
+    format!("{} {}", verb, action_target(item))
+}
+
+/// copy/symlink/fetch are keyed by source; the destination is in the value
+fn action_target(item: &StateItem) -> &str {
+    match item.kind.as_str() {
+        "file.copy" | "file.symlink" => item.value.as_deref().unwrap_or(&item.key),
+        "file.fetch" => item.value.as_deref().and_then(|v| v.split('\0').next()).unwrap_or(&item.key),
+        _ => &item.key,
+    }
+}
+
+/// End-of-apply list of what changed, above the summary line
+pub fn print_changes(items: &[&StateItem]) {
+    if items.is_empty() {
+        return;
+    }
+    println!();
+    println!("{}", c!("Changes", bold));
+    for item in items {
+        println!("  {} {} {}", c!("+", green), c!(item.kind, dimmed), done_action(item));
+    }
+}
+
+pub fn print_would_apply(item: &StateItem, detail: &str) {
+    println!(
+        "  {} {} would {} {}",
+        c!("~", yellow),
+        c!(item.kind, dimmed),
+        c!(would_action(item), white),
+        c!(format!("({})", detail), dimmed)
+    );
+}
+
+/// Answer to a `--confirm-each` prompt
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Confirm {
+    Yes,
+    No,
+    All,
+}
+
+/// Ask before applying an item: `[y/N/a]`, where a = yes to all remaining
+pub fn prompt_apply(item: &StateItem, detail: &str) -> std::io::Result<Confirm> {
+    use std::io::Write;
+    print!(
+        "  {} {} {} {} {} ",
+        c!("?", yellow),
+        c!(item.kind, dimmed),
+        c!(would_action(item), white),
+        c!(format!("({})", detail), dimmed),
+        c!("[y/N/a]", bold)
+    );
+    std::io::stdout().flush()?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    Ok(match input.trim().to_lowercase().as_str() {
+        "y" | "yes" => Confirm::Yes,
+        "a" | "all" => Confirm::All,
+        _ => Confirm::No,
+    })
+}
+
+pub fn print_declined(item: &StateItem) {
+    println!(
+        "  {} {} {} {}",
+        c!("•", dimmed),
+        c!(item.kind, dimmed),
+        c!(item.key, dimmed),
+        c!("(declined)", dimmed)
+    );
+}
+
+/// `apply --prune`: something dropped from config, removed or (dry run) listed
+pub fn print_pruned(kind: &str, what: &str, detail: &str, dry_run: bool) {
+    let mark = if dry_run { c!("~", yellow).to_string() } else { c!("-", red).to_string() };
+    let verb = if dry_run { "would remove" } else { "removed" };
+    println!("  {} {} {} {} {}", mark, c!(kind, dimmed), verb, c!(what, white), c!(format!("({})", detail), dimmed));
+}
+
+/// A leftover file apply won't remove without asking
+pub fn print_prune_kept(kind: &str, what: &str) {
+    println!(
+        "  {} {} {} {}",
+        c!("!", yellow),
+        c!(kind, dimmed),
+        c!(what, white),
+        c!("(no longer in config, left in place)", dimmed)
+    );
+}
+
+pub fn prompt_prune(kind: &str, what: &str) -> std::io::Result<bool> {
+    use std::io::Write;
+    print!(
+        "  {} {} remove {} {} {} ",
+        c!("?", yellow),
+        c!(kind, dimmed),
+        c!(what, white),
+        c!("(no longer in config)", dimmed),
+        c!("[y/N]", bold)
+    );
+    std::io::stdout().flush()?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    Ok(matches!(input.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+pub fn print_would_require(binary: &str, via: &str) {
+    println!(
+        "  {} would install requirement {} {}",
+        c!("~", yellow),
+        c!(binary, white),
+        c!(format!("(via {})", via), dimmed)
+    );
+}
+
+/// Ask before piping a requirement's install script into sh
+pub fn prompt_requirement(binary: &str, url: &str) -> std::io::Result<bool> {
+    use std::io::Write;
+    print!(
+        "  {} install requirement {} by running {} {} ",
+        c!("?", yellow),
+        c!(binary, white),
+        c!(url, dimmed),
+        c!("[y/N]", bold)
+    );
+    std::io::stdout().flush()?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    Ok(matches!(input.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+pub fn print_dry_run_summary(total: usize, changed: usize, issues: usize, elapsed: Duration) {
+    println!();
+    let timing = format!("({})", format_duration(elapsed));
+    let issues_part = if issues > 0 {
+        format!(", {} issues", c!(issues.to_string(), yellow))
+    } else {
+        String::new()
+    };
+    if changed > 0 || issues > 0 {
+        println!(
+            "{} {} total, {} would change{} {} {}",
+            c!("~", yellow),
+            total,
+            c!(changed.to_string(), yellow),
+            issues_part,
+            c!("(dry run)", dimmed),
+            c!(timing, dimmed)
+        );
+    } else {
+        println!(
+            "{} {} total, {} up to date {}",
+            c!("✓", green),
+            total,
+            c!("all", green),
+            c!(timing, dimmed)
+        );
+    }
+}
+
 pub fn print_resolving_requirements(count: usize) {
     println!(
         "  {} resolving {} requirement{}...",
@@ -165,6 +457,10 @@ pub fn print_resolving_requirements(count: usize) {
 }
 
 pub fn start_spinner(item: &StateItem) -> ProgressBar {
+    if plain() {
+        println!("  {} {} {}", c!("→", yellow), c!(item.kind, dimmed), c!(item.key, white));
+        return ProgressBar::hidden();
+    }
     let pb = ProgressBar::new_spinner();
     pb.set_style(
         indicatif::ProgressStyle::default_spinner()
@@ -178,6 +474,10 @@ pub fn start_spinner(item: &StateItem) -> ProgressBar {
 }
 
 pub fn update_spinner(pb: &ProgressBar, line: &str) {
+    if plain() {
+        println!("    {}", line.trim_end());
+        return;
+    }
     let line = line.trim();
     if line.is_empty() {
         return;
@@ -194,9 +494,14 @@ pub fn update_spinner(pb: &ProgressBar, line: &str) {
     pb.set_message(format!("{} {}", c!("›", dimmed), c!(truncated, dimmed)));
 }
 
-pub fn finish_spinner_done(pb: &ProgressBar, item: &StateItem) {
+pub fn finish_spinner_done(pb: &ProgressBar, item: &StateItem, duration: Duration) {
     pb.finish_and_clear();
-    print_apply_done(item);
+    if plain() {
+        let timing = format!("({})", format_duration(duration));
+        println!("  {} {} {} {}", c!("✓", green), c!(item.kind, dimmed), c!(item.key, white), c!(timing, dimmed));
+    } else {
+        print_apply_done(item);
+    }
 }
 
 pub fn finish_spinner_fail(pb: &ProgressBar, item: &StateItem, err: &str) {
@@ -204,6 +509,13 @@ pub fn finish_spinner_fail(pb: &ProgressBar, item: &StateItem, err: &str) {
     print_apply_fail(item, err);
 }
 
+/// Full captured output of a failed command, indented under its item
+pub fn print_command_output(output: &str) {
+    for line in output.lines() {
+        println!("      {} {}", c!("│", dimmed), line);
+    }
+}
+
 pub fn format_bytes(bytes: u64) -> String {
     if bytes >= 1_048_576 {
         format!("{:.1}M", bytes as f64 / 1_048_576.0)
@@ -228,7 +540,35 @@ pub fn extract_summary_line(output: &str) -> Option<String> {
     })
 }
 
+/// The "Changes" list from a remote run's output, as plain "kind action" lines
+pub fn extract_changes(output: &str) -> Vec<String> {
+    let ansi_re = regex::Regex::new(r"\x1b\[[0-9;]*m").unwrap();
+    output
+        .lines()
+        .map(|line| ansi_re.replace_all(line.trim(), "").to_string())
+        .skip_while(|line| line != "Changes")
+        .skip(1)
+        .map_while(|line| line.strip_prefix("+ ").map(str::to_string))
+        .collect()
+}
+
+/// A host's changes under its deploy line. Printed through `mp` while other
+/// hosts' spinners are still running.
+pub fn print_deploy_changes(mp: &MultiProgress, changes: &[String]) {
+    for change in changes {
+        let line = format!("      {} {}", c!("+", green), c!(change, dimmed));
+        if plain() {
+            println!("{}", line);
+        } else {
+            let _ = mp.println(line);
+        }
+    }
+}
+
 pub fn start_deploy_spinner(mp: &MultiProgress, host: &str) -> ProgressBar {
+    if plain() {
+        return ProgressBar::hidden();
+    }
     let pb = mp.add(ProgressBar::new_spinner());
     pb.set_style(
         indicatif::ProgressStyle::default_spinner()
@@ -242,21 +582,54 @@ pub fn start_deploy_spinner(mp: &MultiProgress, host: &str) -> ProgressBar {
 }
 
 pub fn finish_deploy_ok(pb: &ProgressBar, host: &str, summary: &str, duration: Duration) {
+    if plain() {
+        print_deploy_ok(host, summary, duration);
+        return;
+    }
     pb.set_style(indicatif::ProgressStyle::default_spinner().template("  {prefix}  {msg}").unwrap());
+    log(&format!("deploy {} ok: {} ({})", host, summary, format_duration(duration)));
     pb.set_prefix(format!("{} {}", c!("✓", green), c!(host, bold)));
     let timing = format!("({})", format_duration(duration));
     pb.finish_with_message(format!("{} {}", summary, c!(timing, dimmed)));
 }
 
 pub fn finish_deploy_fail(pb: &ProgressBar, host: &str, err: &str, duration: Duration) {
+    if plain() {
+        print_deploy_fail(host, err, duration);
+        return;
+    }
     pb.set_style(indicatif::ProgressStyle::default_spinner().template("  {prefix}  {msg}").unwrap());
+    log(&format!("deploy {} failed: {} ({})", host, err, format_duration(duration)));
     pb.set_prefix(format!("{} {}", c!("✗", red), c!(host, bold)));
     let timing = format!("({})", format_duration(duration));
     pb.finish_with_message(format!("{} {}", c!(err, red), c!(timing, dimmed)));
 }
 
-pub fn start_artifact_spinner(label: &str) -> ProgressBar {
-    let pb = ProgressBar::new_spinner();
+/// `[host]` prefix for streamed remote output
+pub fn host_prefix(host: &str) -> String {
+    c!(format!("[{}]", host), cyan).to_string()
+}
+
+/// Like `finish_deploy_ok`, for --stream / plain mode where there is no spinner
+pub fn print_deploy_ok(host: &str, summary: &str, duration: Duration) {
+    log(&format!("deploy {} ok: {} ({})", host, summary, format_duration(duration)));
+    let timing = format!("({})", format_duration(duration));
+    println!("  {} {}  {} {}", c!("✓", green), c!(host, bold), summary, c!(timing, dimmed));
+}
+
+/// Like `finish_deploy_fail`, for --stream / plain mode where there is no spinner
+pub fn print_deploy_fail(host: &str, err: &str, duration: Duration) {
+    log(&format!("deploy {} failed: {} ({})", host, err, format_duration(duration)));
+    let timing = format!("({})", format_duration(duration));
+    println!("  {} {}  {} {}", c!("✗", red), c!(host, bold), c!(err, red), c!(timing, dimmed));
+}
+
+pub fn start_artifact_spinner(mp: &MultiProgress, label: &str) -> ProgressBar {
+    if plain() {
+        println!("  {} {}", c!("→", yellow), label);
+        return ProgressBar::hidden();
+    }
+    let pb = mp.add(ProgressBar::new_spinner());
     pb.set_style(
         indicatif::ProgressStyle::default_spinner()
             .tick_chars("⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏")
@@ -269,13 +642,364 @@ pub fn start_artifact_spinner(label: &str) -> ProgressBar {
 }
 
 pub fn finish_artifact_ok(pb: &ProgressBar, label: &str) {
+    if plain() {
+        println!("  {} {}", c!("✓", green), label);
+        return;
+    }
     pb.set_style(indicatif::ProgressStyle::default_spinner().template("  {prefix}").unwrap());
     pb.set_prefix(format!("{} {}", c!("✓", green), label));
     pb.finish();
 }
 
 pub fn finish_artifact_fail(pb: &ProgressBar, label: &str, err: &str) {
+    if plain() {
+        println!("  {} {}  {}", c!("✗", red), label, c!(err, red));
+        return;
+    }
     pb.set_style(indicatif::ProgressStyle::default_spinner().template("  {prefix}  {msg}").unwrap());
     pb.set_prefix(format!("{} {}", c!("✗", red), label));
     pb.finish_with_message(format!("{}", c!(err, red)));
 }
+
+// =============================================================================
+// Emitters — how the runner reports items (human or NDJSON)
+// =============================================================================
+
+/// Output format for apply/check/plan
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Human,
+    Json,
+}
+
+/// Receives runner events. Chosen once per run from `Format`.
+pub trait Emitter: Sync {
+    fn no_items(&self);
+    fn resolving_requirements(&self, count: usize);
+    fn skip_run_if(&self, item: &StateItem);
+    fn plan_item(&self, item: &StateItem);
+    fn check_result(&self, item: &StateItem, result: &CheckResult, duration: Duration);
+    fn apply_skip(&self, item: &StateItem, duration: Duration);
+    /// Progress bar handed to `apply_live` while the item is applied
+    fn start_apply(&self, item: &StateItem) -> ProgressBar;
+    fn apply_done(&self, pb: &ProgressBar, item: &StateItem, duration: Duration);
+    /// `output` is the failing command's captured stdout+stderr (with -v)
+    fn apply_fail(&self, pb: &ProgressBar, item: &StateItem, err: &str, output: Option<&str>, duration: Duration);
+    /// Items apply changed, in order, right before the summary
+    fn changes(&self, items: &[&StateItem]);
+    fn summary(&self, total: usize, changed: usize, failed: usize, issues: usize, elapsed: Duration);
+    fn check_summary(&self, total: usize, satisfied: usize, missing: usize, elapsed: Duration);
+    fn plan_summary(&self, total: usize);
+    fn diff_item(&self, item: &StateItem, diff: &str);
+    fn diff_summary(&self, changed: usize);
+    /// Dry run: an item apply would change, with the check's detail
+    fn would_apply(&self, item: &StateItem, detail: &str);
+    /// Dry run: a missing provider requirement apply would install first
+    fn would_require(&self, binary: &str, via: &str);
+    fn dry_run_summary(&self, total: usize, changed: usize, issues: usize, elapsed: Duration);
+}
+
+pub fn emitter(format: Format) -> Box<dyn Emitter> {
+    let inner: Box<dyn Emitter> = match format {
+        Format::Human => Box::new(Human),
+        Format::Json => Box::new(Json),
+    };
+    if logging() {
+        Box::new(Logged(inner))
+    } else {
+        inner
+    }
+}
+
+/// Colored lines and spinners
+struct Human;
+
+impl Emitter for Human {
+    fn no_items(&self) {
+        println!("  No items");
+    }
+    fn resolving_requirements(&self, count: usize) {
+        print_resolving_requirements(count);
+    }
+    fn skip_run_if(&self, item: &StateItem) {
+        print_skip_run_if(item);
+    }
+    fn plan_item(&self, item: &StateItem) {
+        print_plan_item(item);
+    }
+    fn check_result(&self, item: &StateItem, result: &CheckResult, _: Duration) {
+        print_check_result(item, result);
+    }
+    fn apply_skip(&self, item: &StateItem, _: Duration) {
+        print_apply_skip(item);
+    }
+    fn start_apply(&self, item: &StateItem) -> ProgressBar {
+        start_spinner(item)
+    }
+    fn apply_done(&self, pb: &ProgressBar, item: &StateItem, duration: Duration) {
+        finish_spinner_done(pb, item, duration);
+    }
+    fn apply_fail(&self, pb: &ProgressBar, item: &StateItem, err: &str, output: Option<&str>, _: Duration) {
+        finish_spinner_fail(pb, item, err);
+        if let Some(output) = output {
+            print_command_output(output);
+        }
+    }
+    fn changes(&self, items: &[&StateItem]) {
+        print_changes(items);
+    }
+    fn summary(&self, total: usize, changed: usize, failed: usize, issues: usize, elapsed: Duration) {
+        print_summary(total, changed, failed, issues, elapsed);
+    }
+    fn check_summary(&self, total: usize, satisfied: usize, missing: usize, elapsed: Duration) {
+        print_check_summary(total, satisfied, missing, elapsed);
+    }
+    fn plan_summary(&self, total: usize) {
+        print_plan_summary(total);
+    }
+    fn diff_item(&self, item: &StateItem, diff: &str) {
+        print_diff_item(item, diff);
+    }
+    fn diff_summary(&self, changed: usize) {
+        print_diff_summary(changed);
+    }
+    fn would_apply(&self, item: &StateItem, detail: &str) {
+        print_would_apply(item, detail);
+    }
+    fn would_require(&self, binary: &str, via: &str) {
+        print_would_require(binary, via);
+    }
+    fn dry_run_summary(&self, total: usize, changed: usize, issues: usize, elapsed: Duration) {
+        print_dry_run_summary(total, changed, issues, elapsed);
+    }
+}
+
+/// One JSON object per line: `{kind, key, status, detail, duration_ms}` per
+/// item, then `{"summary": {...}}`
+struct Json;
+
+impl Json {
+    fn item(&self, item: &StateItem, status: &str, detail: Option<&str>, duration: Option<Duration>) {
+        let obj = serde_json::json!({
+            "kind": item.kind,
+            "key": item.key,
+            "status": status,
+            "detail": detail,
+            "duration_ms": duration.map(|d| d.as_millis() as u64),
+        });
+        println!("{}", obj);
+    }
+
+    fn summary_obj(&self, fields: serde_json::Value) {
+        println!("{}", serde_json::json!({ "summary": fields }));
+    }
+}
+
+impl Emitter for Json {
+    fn no_items(&self) {}
+    fn resolving_requirements(&self, _: usize) {}
+    fn skip_run_if(&self, item: &StateItem) {
+        self.item(item, "skipped", Some("run_if"), None);
+    }
+    fn plan_item(&self, item: &StateItem) {
+        self.item(item, "planned", None, None);
+    }
+    fn check_result(&self, item: &StateItem, result: &CheckResult, duration: Duration) {
+        match result {
+            CheckResult::Satisfied => self.item(item, "ok", None, Some(duration)),
+            CheckResult::Missing { detail } => self.item(item, "missing", Some(detail), Some(duration)),
+        }
+    }
+    fn apply_skip(&self, item: &StateItem, duration: Duration) {
+        self.item(item, "ok", None, Some(duration));
+    }
+    fn start_apply(&self, _: &StateItem) -> ProgressBar {
+        ProgressBar::hidden()
+    }
+    fn apply_done(&self, _: &ProgressBar, item: &StateItem, duration: Duration) {
+        self.item(item, "changed", None, Some(duration));
+    }
+    fn apply_fail(&self, _: &ProgressBar, item: &StateItem, err: &str, output: Option<&str>, duration: Duration) {
+        let mut obj = serde_json::json!({
+            "kind": item.kind,
+            "key": item.key,
+            "status": "failed",
+            "detail": err,
+            "duration_ms": duration.as_millis() as u64,
+        });
+        if let Some(output) = output {
+            obj["output"] = output.into();
+        }
+        println!("{}", obj);
+    }
+    fn changes(&self, items: &[&StateItem]) {
+        let changes: Vec<_> = items
+            .iter()
+            .map(|item| serde_json::json!({ "kind": item.kind, "key": item.key, "action": done_action(item) }))
+            .collect();
+        println!("{}", serde_json::json!({ "changes": changes }));
+    }
+    fn summary(&self, total: usize, changed: usize, failed: usize, issues: usize, elapsed: Duration) {
+        self.summary_obj(serde_json::json!({
+            "total": total,
+            "changed": changed,
+            "failed": failed,
+            "issues": issues,
+            "duration_ms": elapsed.as_millis() as u64,
+        }));
+    }
+    fn check_summary(&self, total: usize, satisfied: usize, missing: usize, elapsed: Duration) {
+        self.summary_obj(serde_json::json!({
+            "total": total,
+            "ok": satisfied,
+            "missing": missing,
+            "duration_ms": elapsed.as_millis() as u64,
+        }));
+    }
+    fn plan_summary(&self, total: usize) {
+        self.summary_obj(serde_json::json!({ "total": total }));
+    }
+    fn diff_item(&self, item: &StateItem, diff: &str) {
+        let obj = serde_json::json!({
+            "kind": item.kind,
+            "key": item.key,
+            "status": "changed",
+            "diff": diff,
+        });
+        println!("{}", obj);
+    }
+    fn diff_summary(&self, changed: usize) {
+        self.summary_obj(serde_json::json!({ "changed": changed }));
+    }
+    fn would_apply(&self, item: &StateItem, detail: &str) {
+        let obj = serde_json::json!({
+            "kind": item.kind,
+            "key": item.key,
+            "status": "would_change",
+            "detail": detail,
+            "action": would_action(item),
+        });
+        println!("{}", obj);
+    }
+    fn would_require(&self, binary: &str, via: &str) {
+        let obj = serde_json::json!({
+            "kind": "requirement",
+            "key": binary,
+            "status": "would_change",
+            "action": format!("install {}", binary),
+            "via": via,
+        });
+        println!("{}", obj);
+    }
+    fn dry_run_summary(&self, total: usize, changed: usize, issues: usize, elapsed: Duration) {
+        self.summary_obj(serde_json::json!({
+            "total": total,
+            "would_change": changed,
+            "issues": issues,
+            "duration_ms": elapsed.as_millis() as u64,
+        }));
+    }
+}
+
+/// Tees item results and summaries to the --log file, then hands every
+/// event to the wrapped emitter
+struct Logged(Box<dyn Emitter>);
+
+impl Logged {
+    fn item(&self, item: &StateItem, status: &str, detail: Option<&str>, duration: Option<Duration>) {
+        let mut line = format!("{} {} {}", status, item.kind, item.key);
+        if let Some(detail) = detail {
+            line.push_str(&format!(": {}", detail));
+        }
+        if let Some(d) = duration {
+            line.push_str(&format!(" ({})", format_duration(d)));
+        }
+        log(&line);
+    }
+}
+
+impl Emitter for Logged {
+    fn no_items(&self) {
+        self.0.no_items();
+    }
+    fn resolving_requirements(&self, count: usize) {
+        self.0.resolving_requirements(count);
+    }
+    fn skip_run_if(&self, item: &StateItem) {
+        self.item(item, "skipped", Some("run_if"), None);
+        self.0.skip_run_if(item);
+    }
+    fn plan_item(&self, item: &StateItem) {
+        self.item(item, "planned", None, None);
+        self.0.plan_item(item);
+    }
+    fn check_result(&self, item: &StateItem, result: &CheckResult, duration: Duration) {
+        match result {
+            CheckResult::Satisfied => self.item(item, "ok", None, Some(duration)),
+            CheckResult::Missing { detail } => self.item(item, "missing", Some(detail), Some(duration)),
+        }
+        self.0.check_result(item, result, duration);
+    }
+    fn apply_skip(&self, item: &StateItem, duration: Duration) {
+        self.item(item, "ok", None, Some(duration));
+        self.0.apply_skip(item, duration);
+    }
+    fn start_apply(&self, item: &StateItem) -> ProgressBar {
+        self.0.start_apply(item)
+    }
+    fn apply_done(&self, pb: &ProgressBar, item: &StateItem, duration: Duration) {
+        self.item(item, "changed", None, Some(duration));
+        self.0.apply_done(pb, item, duration);
+    }
+    fn apply_fail(&self, pb: &ProgressBar, item: &StateItem, err: &str, output: Option<&str>, duration: Duration) {
+        self.item(item, "failed", Some(err), Some(duration));
+        if let Some(output) = output {
+            log(output);
+        }
+        self.0.apply_fail(pb, item, err, output, duration);
+    }
+    fn changes(&self, items: &[&StateItem]) {
+        self.0.changes(items);
+    }
+    fn summary(&self, total: usize, changed: usize, failed: usize, issues: usize, elapsed: Duration) {
+        log(&format!(
+            "summary: {} total, {} changed, {} failed, {} issues ({})",
+            total, changed, failed, issues, format_duration(elapsed)
+        ));
+        self.0.summary(total, changed, failed, issues, elapsed);
+    }
+    fn check_summary(&self, total: usize, satisfied: usize, missing: usize, elapsed: Duration) {
+        log(&format!(
+            "summary: {} total, {} ok, {} missing ({})",
+            total, satisfied, missing, format_duration(elapsed)
+        ));
+        self.0.check_summary(total, satisfied, missing, elapsed);
+    }
+    fn plan_summary(&self, total: usize) {
+        log(&format!("summary: {} planned", total));
+        self.0.plan_summary(total);
+    }
+    fn diff_item(&self, item: &StateItem, diff: &str) {
+        self.item(item, "differs", None, None);
+        self.0.diff_item(item, diff);
+    }
+    fn diff_summary(&self, changed: usize) {
+        log(&format!("summary: {} would change", changed));
+        self.0.diff_summary(changed);
+    }
+    fn would_apply(&self, item: &StateItem, detail: &str) {
+        log(&format!("would {} ({})", would_action(item), detail));
+        self.0.would_apply(item, detail);
+    }
+    fn would_require(&self, binary: &str, via: &str) {
+        log(&format!("would install requirement {} (via {})", binary, via));
+        self.0.would_require(binary, via);
+    }
+    fn dry_run_summary(&self, total: usize, changed: usize, issues: usize, elapsed: Duration) {
+        log(&format!(
+            "summary: {} total, {} would change, {} issues ({})",
+            total, changed, issues, format_duration(elapsed)
+        ));
+        self.0.dry_run_summary(total, changed, issues, elapsed);
+    }
+}
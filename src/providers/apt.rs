@@ -0,0 +1,141 @@
+use super::system::write_root_file;
+use super::{CheckResult, Provider, StateItem};
+use crate::util::{command_exists, fetch_url, run_sudo};
+use anyhow::{bail, Result};
+use std::path::{Path, PathBuf};
+
+const SOURCES_DIR: &str = "/etc/apt/sources.list.d";
+const KEYRINGS_DIR: &str = "/etc/apt/keyrings";
+
+/// `[[apt_repo]]` settings packed into the item value: uri, suite, components
+/// (space-separated), key_url — separated by \x00
+struct RepoSpec {
+    uri: String,
+    suite: String,
+    components: String,
+    key_url: Option<String>,
+}
+
+impl RepoSpec {
+    fn parse(state: &StateItem) -> Self {
+        let value = state.value.as_deref().unwrap_or("");
+        let mut parts = value.split('\x00');
+        let mut next = || parts.next().unwrap_or("").to_string();
+        Self {
+            uri: next(),
+            suite: next(),
+            components: next(),
+            key_url: Some(next()).filter(|s| !s.is_empty()),
+        }
+    }
+
+    /// deb822 sources file, signed by `key` when there is one. The key's URL
+    /// goes in the header, so changing it shows up as drift.
+    fn sources(&self, key: Option<&Path>) -> String {
+        let mut out = "# dek-managed apt repository\n".to_string();
+        if let Some(ref url) = self.key_url {
+            out.push_str(&format!("# key: {}\n", url));
+        }
+        out.push_str(&format!("Types: deb\nURIs: {}\nSuites: {}\n", self.uri, self.suite));
+        if !self.components.is_empty() {
+            out.push_str(&format!("Components: {}\n", self.components));
+        }
+        if let Some(key) = key {
+            out.push_str(&format!("Signed-By: {}\n", key.display()));
+        }
+        out
+    }
+}
+
+fn sources_path(name: &str) -> PathBuf {
+    Path::new(SOURCES_DIR).join(format!("{}.sources", name))
+}
+
+/// apt tells armored from binary keyrings by extension
+fn key_path(name: &str, armored: bool) -> PathBuf {
+    Path::new(KEYRINGS_DIR).join(format!("{}.{}", name, if armored { "asc" } else { "gpg" }))
+}
+
+fn installed_key(name: &str) -> Option<PathBuf> {
+    [key_path(name, true), key_path(name, false)].into_iter().find(|p| p.exists())
+}
+
+pub struct AptRepoProvider;
+
+impl Provider for AptRepoProvider {
+    fn name(&self) -> &'static str {
+        "apt_repo"
+    }
+
+    fn needs_sudo(&self) -> bool {
+        true
+    }
+
+    fn check(&self, state: &StateItem) -> Result<CheckResult> {
+        let name = &state.key;
+        let spec = RepoSpec::parse(state);
+        let key = match spec.key_url {
+            Some(_) => match installed_key(name) {
+                Some(key) => Some(key),
+                None => return Ok(CheckResult::Missing { detail: "signing key not installed".to_string() }),
+            },
+            None => None,
+        };
+
+        let path = sources_path(name);
+        match std::fs::read_to_string(&path) {
+            Ok(current) if current == spec.sources(key.as_deref()) => Ok(CheckResult::Satisfied),
+            Ok(_) => Ok(CheckResult::Missing { detail: format!("{} differs", path.display()) }),
+            Err(_) => Ok(CheckResult::Missing { detail: format!("{} does not exist", path.display()) }),
+        }
+    }
+
+    fn apply(&self, state: &StateItem) -> Result<()> {
+        let name = &state.key;
+        let spec = RepoSpec::parse(state);
+        if !command_exists("apt-get") {
+            bail!("apt_repo needs apt-get");
+        }
+
+        let key = match spec.key_url {
+            Some(ref url) => {
+                let data = fetch_url(url, Some(std::time::Duration::ZERO))?;
+                let armored = data.starts_with(b"-----BEGIN PGP");
+                let path = key_path(name, armored);
+                if !Path::new(KEYRINGS_DIR).is_dir() {
+                    run_sudo("mkdir", &["-p", KEYRINGS_DIR])?;
+                }
+                write_root_file(&path.to_string_lossy(), &data)?;
+                // A key that switched format would otherwise linger under the old name
+                let stale = key_path(name, !armored);
+                if stale.exists() {
+                    run_sudo("rm", &["-f", &stale.to_string_lossy()])?;
+                }
+                Some(path)
+            }
+            None => None,
+        };
+
+        let path = sources_path(name);
+        write_root_file(&path.to_string_lossy(), spec.sources(key.as_deref()))?;
+
+        // Refresh just this repository's lists
+        let sourcelist = format!("Dir::Etc::sourcelist={}", path.display());
+        let output = run_sudo(
+            "apt-get",
+            &[
+                "update",
+                "-o",
+                &sourcelist,
+                "-o",
+                "Dir::Etc::sourceparts=-",
+                "-o",
+                "APT::Get::List-Cleanup=0",
+            ],
+        )?;
+        if !output.status.success() {
+            bail!("apt-get update failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+        }
+        Ok(())
+    }
+}
@@ -14,6 +14,7 @@ impl Provider for AssertProvider {
 
     fn check(&self, state: &StateItem) -> Result<CheckResult> {
         // Value encoding: command\x00mode\x00stdout_pattern\x00stderr_pattern\x00message
+        // (http mode: url\x00http\x00contains\x00status\x00message)
         let value = state.value.as_deref().unwrap_or("");
         let parts: Vec<&str> = value.splitn(5, '\x00').collect();
         let cmd = parts.first().copied().unwrap_or("");
@@ -22,7 +23,22 @@ impl Provider for AssertProvider {
         let stderr_pattern = parts.get(3).filter(|s| !s.is_empty()).copied();
         let message = parts.get(4).filter(|s| !s.is_empty()).copied();
 
-        if mode == "foreach" {
+        if mode == "http" {
+            let expected: u16 = parts.get(3).and_then(|s| s.parse().ok()).unwrap_or(200);
+            let contains = stdout_pattern;
+            let fail = |detail: String| Ok(CheckResult::Missing { detail: message.map_or(detail, String::from) });
+            let (status, body) = match crate::util::http_get(cmd) {
+                Ok(response) => response,
+                Err(e) => return fail(e.to_string()),
+            };
+            if status != expected {
+                return fail(format!("HTTP {}, expected {}", status, expected));
+            }
+            if let Some(text) = contains.filter(|t| !body.contains(t)) {
+                return fail(format!("body doesn't contain '{}'", text));
+            }
+            Ok(CheckResult::Satisfied)
+        } else if mode == "foreach" {
             let output = crate::util::shell_cmd(cmd).output()?;
             let stdout = String::from_utf8_lossy(&output.stdout);
             let lines: Vec<&str> = stdout.lines().filter(|l| !l.is_empty()).collect();
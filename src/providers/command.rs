@@ -1,8 +1,48 @@
-use super::{CheckResult, Provider, StateItem};
+use super::{CheckResult, Explanation, Provider, StateItem};
 use anyhow::{bail, Result};
 use indicatif::ProgressBar;
 use std::io::Write;
 
+/// `[[command]]` settings packed into the item value: check, apply, confirm,
+/// creates, removes — separated by \x00
+struct CommandSpec<'a> {
+    check: &'a str,
+    apply: &'a str,
+    confirm: bool,
+    creates: &'a str,
+    removes: &'a str,
+}
+
+impl<'a> CommandSpec<'a> {
+    fn parse(state: &'a StateItem) -> Self {
+        let value = state.value.as_deref().unwrap_or("");
+        let mut parts = value.split('\x00');
+        let mut next = || parts.next().unwrap_or("");
+        Self { check: next(), apply: next(), confirm: next() == "1", creates: next(), removes: next() }
+    }
+
+    /// Why `creates`/`removes` already count as done, if they do. Either one
+    /// is enough, like Ansible's.
+    fn done_by_path(&self) -> Option<String> {
+        if !self.creates.is_empty() && std::path::Path::new(self.creates).exists() {
+            return Some(format!("{} exists (creates)", self.creates));
+        }
+        if !self.removes.is_empty() && !std::path::Path::new(self.removes).exists() {
+            return Some(format!("{} is gone (removes)", self.removes));
+        }
+        None
+    }
+
+    /// Outcome when the paths don't settle it and there's no check script
+    fn path_detail(&self) -> String {
+        if !self.creates.is_empty() {
+            format!("{} does not exist", self.creates)
+        } else {
+            format!("{} still exists", self.removes)
+        }
+    }
+}
+
 pub struct CommandProvider;
 
 impl Provider for CommandProvider {
@@ -11,15 +51,18 @@ impl Provider for CommandProvider {
     }
 
     fn check(&self, state: &StateItem) -> Result<CheckResult> {
-        // Value contains check and apply separated by \x00
-        let value = state.value.as_ref()
-            .ok_or_else(|| anyhow::anyhow!("Command '{}' missing scripts", state.key))?;
-
-        let parts: Vec<&str> = value.splitn(2, '\x00').collect();
-        let check_script = parts.first()
-            .ok_or_else(|| anyhow::anyhow!("Command '{}' missing check script", state.key))?;
+        let spec = CommandSpec::parse(state);
+        if spec.done_by_path().is_some() {
+            return Ok(CheckResult::Satisfied);
+        }
+        if spec.check.is_empty() {
+            if spec.creates.is_empty() && spec.removes.is_empty() {
+                bail!("Command '{}' needs check, creates or removes", state.key);
+            }
+            return Ok(CheckResult::Missing { detail: spec.path_detail() });
+        }
 
-        let status = crate::util::shell_cmd(check_script)
+        let status = crate::util::shell_cmd(spec.check)
             .stdout(std::process::Stdio::null())
             .stderr(std::process::Stdio::null())
             .status()?;
@@ -33,16 +76,46 @@ impl Provider for CommandProvider {
         }
     }
 
-    fn apply_live(&self, state: &StateItem, pb: &ProgressBar) -> Result<()> {
-        let value = state.value.as_ref()
-            .ok_or_else(|| anyhow::anyhow!("Command '{}' missing scripts", state.key))?;
+    fn explain(&self, state: &StateItem) -> Result<Explanation> {
+        let spec = CommandSpec::parse(state);
+        let mut reasons = Vec::new();
+        if let Some(reason) = spec.done_by_path() {
+            reasons.push(format!("{}, so the check script is skipped", reason));
+            return Ok(Explanation { result: CheckResult::Satisfied, reasons });
+        }
+        if !spec.creates.is_empty() || !spec.removes.is_empty() {
+            reasons.push(spec.path_detail());
+        }
+        if spec.check.is_empty() {
+            let result = self.check(state)?;
+            reasons.push("no check script, so apply would run the apply script".to_string());
+            return Ok(Explanation { result, reasons });
+        }
 
-        let parts: Vec<&str> = value.splitn(3, '\x00').collect();
-        let apply_script = parts.get(1)
-            .ok_or_else(|| anyhow::anyhow!("Command '{}' missing apply script", state.key))?;
-        let confirm = parts.get(2).map(|s| *s == "1").unwrap_or(false);
+        // Same as check, but keep what the script printed
+        let output = crate::util::shell_cmd(spec.check).output()?;
+        let code = output.status.code().unwrap_or(-1);
+        reasons.push(format!("ran check: {}", spec.check.trim()));
+        for (name, bytes) in [("stdout", &output.stdout), ("stderr", &output.stderr)] {
+            let text = String::from_utf8_lossy(bytes);
+            for line in text.trim_end().lines() {
+                reasons.push(format!("{}: {}", name, line));
+            }
+        }
+        let result = if output.status.success() {
+            reasons.push("exited 0, so nothing to do".to_string());
+            CheckResult::Satisfied
+        } else {
+            reasons.push(format!("exited {}, so apply would run the apply script", code));
+            CheckResult::Missing { detail: format!("check failed (exit {})", code) }
+        };
+        Ok(Explanation { result, reasons })
+    }
 
-        if confirm {
+    fn apply_live(&self, state: &StateItem, pb: &ProgressBar) -> Result<()> {
+        let spec = CommandSpec::parse(state);
+
+        if spec.confirm {
             use owo_colors::OwoColorize;
             let proceed = pb.suspend(|| -> Result<bool> {
                 print!("Apply {}? [y/N] ", c!(state.key, bold));
@@ -56,10 +129,11 @@ impl Provider for CommandProvider {
             }
         }
 
-        let status = crate::util::shell_cmd(apply_script)
+        let mut child = crate::util::timeout_group(&mut crate::util::shell_cmd(spec.apply))
             .stdout(std::process::Stdio::inherit())
             .stderr(std::process::Stdio::inherit())
-            .status()?;
+            .spawn()?;
+        let status = crate::util::wait_child(&mut child)?;
 
         if !status.success() {
             bail!("apply failed (exit {})", status.code().unwrap_or(-1));
@@ -0,0 +1,177 @@
+use super::{CheckResult, Provider, StateItem};
+use crate::util::{command_exists, run_cmd};
+use anyhow::{bail, Result};
+use std::path::PathBuf;
+
+/// Trailing comment that marks a crontab line as ours: `# dek:<name>`
+fn marker(name: &str) -> String {
+    format!("# dek:{}", name)
+}
+
+/// `[[cron]]` settings packed into the item value: schedule, command, timer — separated by \x00
+struct CronSpec {
+    schedule: String,
+    command: String,
+    timer: bool,
+}
+
+impl CronSpec {
+    fn parse(state: &StateItem) -> Self {
+        let value = state.value.as_deref().unwrap_or("");
+        let mut parts = value.split('\x00');
+        Self {
+            schedule: parts.next().unwrap_or("").to_string(),
+            command: parts.next().unwrap_or("").to_string(),
+            timer: parts.next() == Some("true"),
+        }
+    }
+
+    /// The job's crontab line. cron reads an unescaped `%` as a newline.
+    fn crontab_line(&self, name: &str) -> String {
+        format!("{} {} {}", self.schedule, self.command.replace('%', "\\%"), marker(name))
+    }
+}
+
+pub struct CronProvider;
+
+impl Provider for CronProvider {
+    fn name(&self) -> &'static str {
+        "cron"
+    }
+
+    fn check(&self, state: &StateItem) -> Result<CheckResult> {
+        let name = &state.key;
+        let spec = CronSpec::parse(state);
+        if spec.timer {
+            return check_timer(name, &spec);
+        }
+
+        let crontab = read_crontab()?;
+        let line = spec.crontab_line(name);
+        if crontab.lines().any(|l| l == line) {
+            Ok(CheckResult::Satisfied)
+        } else if crontab.lines().any(|l| l.ends_with(&marker(name))) {
+            Ok(CheckResult::Missing { detail: "crontab entry differs".to_string() })
+        } else {
+            Ok(CheckResult::Missing { detail: "not in crontab".to_string() })
+        }
+    }
+
+    fn apply(&self, state: &StateItem) -> Result<()> {
+        let name = &state.key;
+        let spec = CronSpec::parse(state);
+        if spec.timer {
+            return apply_timer(name, &spec);
+        }
+
+        // Replace our line for this job, keeping everything else as-is
+        let crontab = read_crontab()?;
+        let mut lines: Vec<&str> = crontab.lines().filter(|l| !l.ends_with(&marker(name))).collect();
+        let line = spec.crontab_line(name);
+        lines.push(&line);
+        write_crontab(&(lines.join("\n") + "\n"))
+    }
+}
+
+/// Current user's crontab; having none yet counts as empty. Any other
+/// failure is an error: apply writes back the whole crontab.
+fn read_crontab() -> Result<String> {
+    if !command_exists("crontab") {
+        bail!("crontab not found");
+    }
+    let output = run_cmd("crontab", &["-l"])?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        // cronie/vixie, then busybox
+        if stderr.contains("no crontab for") || stderr.contains("No such file or directory") {
+            return Ok(String::new());
+        }
+        bail!("crontab -l failed: {}", stderr.trim());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+fn write_crontab(content: &str) -> Result<()> {
+    use std::io::Write;
+    let mut child = std::process::Command::new("crontab")
+        .arg("-")
+        .stdin(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(content.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        bail!("crontab failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(())
+}
+
+// systemd user timers (`timer = true`): dek-<name>.service + dek-<name>.timer
+
+fn unit_dir() -> PathBuf {
+    std::env::var("XDG_CONFIG_HOME")
+        .ok()
+        .filter(|s| !s.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| crate::util::expand_path("~/.config"))
+        .join("systemd/user")
+}
+
+fn timer_units(name: &str, spec: &CronSpec) -> [(PathBuf, String); 2] {
+    let dir = unit_dir();
+    let service = format!(
+        "[Unit]\nDescription=dek job {name}\n\n[Service]\nType=oneshot\nExecStart=/bin/sh -c {cmd}\n",
+        cmd = systemd_quote(&spec.command),
+    );
+    let timer = format!(
+        "[Unit]\nDescription=dek timer {name}\n\n[Timer]\nOnCalendar={}\nPersistent=true\n\n[Install]\nWantedBy=timers.target\n",
+        spec.schedule,
+    );
+    [
+        (dir.join(format!("dek-{}.service", name)), service),
+        (dir.join(format!("dek-{}.timer", name)), timer),
+    ]
+}
+
+/// One ExecStart= word: C-style escapes in double quotes, `$`/`%` doubled so systemd leaves them alone
+fn systemd_quote(s: &str) -> String {
+    let escaped = s
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('$', "$$")
+        .replace('%', "%%");
+    format!("\"{}\"", escaped)
+}
+
+fn check_timer(name: &str, spec: &CronSpec) -> Result<CheckResult> {
+    for (path, content) in timer_units(name, spec) {
+        if std::fs::read_to_string(&path).ok().as_deref() != Some(content.as_str()) {
+            return Ok(CheckResult::Missing { detail: format!("{} out of date", path.display()) });
+        }
+    }
+    let timer = format!("dek-{}.timer", name);
+    if !run_cmd("systemctl", &["--user", "is-enabled", &timer])?.status.success() {
+        return Ok(CheckResult::Missing { detail: format!("{} not enabled", timer) });
+    }
+    Ok(CheckResult::Satisfied)
+}
+
+fn apply_timer(name: &str, spec: &CronSpec) -> Result<()> {
+    if !command_exists("systemctl") {
+        bail!("timer = true needs systemd");
+    }
+    std::fs::create_dir_all(unit_dir())?;
+    for (path, content) in timer_units(name, spec) {
+        std::fs::write(&path, content)?;
+    }
+    let timer = format!("dek-{}.timer", name);
+    for args in [&["--user", "daemon-reload"][..], &["--user", "enable", "--now", &timer]] {
+        let output = run_cmd("systemctl", args)?;
+        if !output.status.success() {
+            bail!("systemctl {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim());
+        }
+    }
+    Ok(())
+}
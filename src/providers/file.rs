@@ -1,8 +1,29 @@
 use super::{CheckResult, Provider, StateItem};
 use crate::util::expand_path;
 use anyhow::{bail, Context, Result};
+use indicatif::ProgressBar;
 use std::fs;
 use std::os::unix::fs as unix_fs;
+use std::path::Path;
+
+/// Current content of `path`, empty if it doesn't exist yet
+fn read_or_empty(path: &Path) -> Result<String> {
+    if !path.exists() {
+        return Ok(String::new());
+    }
+    fs::read_to_string(path).with_context(|| format!("failed to read: {}", path.display()))
+}
+
+/// Like `util::unified_diff`, for raw bytes (binary files get a one-line note)
+fn bytes_diff(path: &Path, current: &[u8], desired: &[u8]) -> Option<String> {
+    if current == desired {
+        return None;
+    }
+    match (std::str::from_utf8(current), std::str::from_utf8(desired)) {
+        (Ok(current), Ok(desired)) => crate::util::unified_diff(path, current, desired),
+        _ => Some(format!("Binary files differ: {}\n", path.display())),
+    }
+}
 
 // =============================================================================
 // COPY
@@ -63,6 +84,24 @@ impl Provider for CopyProvider {
 
         Ok(())
     }
+
+    fn diff(&self, state: &StateItem) -> Result<Option<String>> {
+        let src = expand_path(&state.key);
+        let dst = expand_path(state.value.as_deref().unwrap_or(""));
+
+        if dst.as_os_str().is_empty() {
+            bail!("file.copy: destination not specified for '{}'", state.key);
+        }
+
+        let desired = fs::read(&src)
+            .with_context(|| format!("failed to read source: {}", src.display()))?;
+        let current = if dst.exists() {
+            fs::read(&dst).with_context(|| format!("failed to read destination: {}", dst.display()))?
+        } else {
+            Vec::new()
+        };
+        Ok(bytes_diff(&dst, &current, &desired))
+    }
 }
 
 // =============================================================================
@@ -83,6 +122,31 @@ fn parse_fetch_value(state: &StateItem) -> (&str, Option<std::time::Duration>) {
     (path, ttl)
 }
 
+/// Download the item's URL to its destination, with byte progress on `pb`
+fn fetch_to_dest(state: &StateItem, pb: Option<&ProgressBar>) -> Result<()> {
+    let url = &state.key;
+    let (path, ttl) = parse_fetch_value(state);
+    let dst = expand_path(path);
+
+    if dst.as_os_str().is_empty() {
+        bail!("file.fetch: destination not specified for '{}'", url);
+    }
+
+    if let Some(parent) = dst.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create parent dirs for: {}", dst.display()))?;
+    }
+
+    let content = match pb {
+        Some(pb) => crate::util::fetch_url_live(url, ttl, pb)?,
+        None => crate::util::fetch_url(url, ttl)?,
+    };
+    fs::write(&dst, &content)
+        .with_context(|| format!("failed to write: {}", dst.display()))?;
+
+    Ok(())
+}
+
 impl Provider for FetchProvider {
     fn name(&self) -> &'static str {
         "file.fetch"
@@ -117,6 +181,14 @@ impl Provider for FetchProvider {
     }
 
     fn apply(&self, state: &StateItem) -> Result<()> {
+        fetch_to_dest(state, None)
+    }
+
+    fn apply_live(&self, state: &StateItem, pb: &ProgressBar) -> Result<()> {
+        fetch_to_dest(state, Some(pb))
+    }
+
+    fn diff(&self, state: &StateItem) -> Result<Option<String>> {
         let url = &state.key;
         let (path, ttl) = parse_fetch_value(state);
         let dst = expand_path(path);
@@ -125,16 +197,13 @@ impl Provider for FetchProvider {
             bail!("file.fetch: destination not specified for '{}'", url);
         }
 
-        if let Some(parent) = dst.parent() {
-            fs::create_dir_all(parent)
-                .with_context(|| format!("failed to create parent dirs for: {}", dst.display()))?;
-        }
-
-        let content = crate::util::fetch_url(url, ttl)?;
-        fs::write(&dst, &content)
-            .with_context(|| format!("failed to write: {}", dst.display()))?;
-
-        Ok(())
+        let desired = crate::util::fetch_url(url, ttl)?;
+        let current = if dst.exists() {
+            fs::read(&dst).with_context(|| format!("failed to read destination: {}", dst.display()))?
+        } else {
+            Vec::new()
+        };
+        Ok(bytes_diff(&dst, &current, &desired))
     }
 }
 
@@ -269,32 +338,111 @@ impl Provider for EnsureLineProvider {
                 .with_context(|| format!("failed to create parent dirs for: {}", file_path.display()))?;
         }
 
-        let mut content = if file_path.exists() {
-            fs::read_to_string(&file_path)
-                .with_context(|| format!("failed to read: {}", file_path.display()))?
-        } else {
-            String::new()
-        };
+        let current = read_or_empty(&file_path)?;
+        let content = with_lines(current.clone(), &lines_to_ensure);
 
-        let mut modified = false;
-        for line in lines_to_ensure {
-            if !content.contains(line) {
-                if !content.is_empty() && !content.ends_with('\n') {
-                    content.push('\n');
-                }
-                content.push_str(line);
+        if content != current {
+            fs::write(&file_path, &content)
+                .with_context(|| format!("failed to write: {}", file_path.display()))?;
+        }
+
+        Ok(())
+    }
+
+    fn diff(&self, state: &StateItem) -> Result<Option<String>> {
+        let file_path = expand_path(&state.key);
+        let lines_to_ensure: Vec<&str> = state
+            .value
+            .as_deref()
+            .unwrap_or("")
+            .lines()
+            .collect();
+
+        let current = read_or_empty(&file_path)?;
+        let content = with_lines(current.clone(), &lines_to_ensure);
+        Ok(crate::util::unified_diff(&file_path, &current, &content))
+    }
+}
+
+/// Append each line not already present in `content`
+fn with_lines(mut content: String, lines: &[&str]) -> String {
+    for line in lines {
+        if !content.contains(line) {
+            if !content.is_empty() && !content.ends_with('\n') {
                 content.push('\n');
-                modified = true;
             }
+            content.push_str(line);
+            content.push('\n');
         }
+    }
+    content
+}
 
-        if modified {
+// =============================================================================
+// ENSURE_ABSENT - remove lines that must not be present
+// =============================================================================
+
+pub struct EnsureAbsentProvider;
+
+impl Provider for EnsureAbsentProvider {
+    fn name(&self) -> &'static str {
+        "file.ensure_absent"
+    }
+
+    fn check(&self, state: &StateItem) -> Result<CheckResult> {
+        let file_path = expand_path(&state.key);
+        let lines_to_remove: Vec<&str> = state.value.as_deref().unwrap_or("").lines().collect();
+
+        // No file, nothing to remove
+        let current = read_or_empty(&file_path)?;
+        let present = current.lines().filter(|l| is_absent_match(l, &lines_to_remove)).count();
+
+        if present == 0 {
+            Ok(CheckResult::Satisfied)
+        } else {
+            Ok(CheckResult::Missing {
+                detail: format!("{} line(s) to remove in '{}'", present, file_path.display()),
+            })
+        }
+    }
+
+    fn apply(&self, state: &StateItem) -> Result<()> {
+        let file_path = expand_path(&state.key);
+        let lines_to_remove: Vec<&str> = state.value.as_deref().unwrap_or("").lines().collect();
+
+        let current = read_or_empty(&file_path)?;
+        let content = without_lines(&current, &lines_to_remove);
+
+        if content != current {
             fs::write(&file_path, &content)
                 .with_context(|| format!("failed to write: {}", file_path.display()))?;
         }
 
         Ok(())
     }
+
+    fn diff(&self, state: &StateItem) -> Result<Option<String>> {
+        let file_path = expand_path(&state.key);
+        let lines_to_remove: Vec<&str> = state.value.as_deref().unwrap_or("").lines().collect();
+
+        let current = read_or_empty(&file_path)?;
+        let content = without_lines(&current, &lines_to_remove);
+        Ok(crate::util::unified_diff(&file_path, &current, &content))
+    }
+}
+
+/// Whole-line match, ignoring surrounding whitespace (and a CRLF's `\r`).
+/// Blank entries never match, so they can't wipe every empty line.
+fn is_absent_match(line: &str, lines: &[&str]) -> bool {
+    lines.iter().any(|l| !l.trim().is_empty() && line.trim() == l.trim())
+}
+
+/// Drop every line of `content` matching one of `lines`
+fn without_lines(content: &str, lines: &[&str]) -> String {
+    content
+        .split_inclusive('\n')
+        .filter(|l| !is_absent_match(l, lines))
+        .collect()
 }
 
 // =============================================================================
@@ -312,6 +460,9 @@ impl Provider for TemplateProvider {
         let dst = expand_path(&state.key);
         let rendered = state.value.as_deref().unwrap_or("");
 
+        if let Some(failed) = rendered.strip_prefix('\x00') {
+            return Ok(CheckResult::Missing { detail: failed.to_string() });
+        }
         if !dst.exists() {
             return Ok(CheckResult::Missing {
                 detail: format!("destination '{}' does not exist", dst.display()),
@@ -321,19 +472,32 @@ impl Provider for TemplateProvider {
         let current = fs::read_to_string(&dst)
             .with_context(|| format!("failed to read: {}", dst.display()))?;
 
-        if current == rendered {
-            Ok(CheckResult::Satisfied)
-        } else {
-            Ok(CheckResult::Missing {
+        if !matches_masked(&current, rendered) {
+            return Ok(CheckResult::Missing {
                 detail: format!("contents differ for '{}'", dst.display()),
-            })
+            });
+        }
+        // Masked secrets could hold anything: only trust the file if it's
+        // what apply, which has the real values, last left there
+        if rendered.contains(crate::secrets::MASK) {
+            if crate::cache::get_written(&dst).as_deref() != Some(&content_hash(&current)) {
+                return Ok(CheckResult::Missing {
+                    detail: format!("can't compare secrets without fetching them, and '{}' changed since apply", dst.display()),
+                });
+            }
+        } else {
+            remember_written(&dst, rendered);
         }
+        Ok(CheckResult::Satisfied)
     }
 
     fn apply(&self, state: &StateItem) -> Result<()> {
         let dst = expand_path(&state.key);
         let rendered = state.value.as_deref().unwrap_or("");
 
+        if let Some(failed) = rendered.strip_prefix('\x00') {
+            bail!("{}", failed);
+        }
         if let Some(parent) = dst.parent() {
             fs::create_dir_all(parent)
                 .with_context(|| format!("failed to create parent dirs for: {}", dst.display()))?;
@@ -341,9 +505,64 @@ impl Provider for TemplateProvider {
 
         fs::write(&dst, rendered)
             .with_context(|| format!("failed to write: {}", dst.display()))?;
+        remember_written(&dst, rendered);
 
         Ok(())
     }
+
+    fn diff(&self, state: &StateItem) -> Result<Option<String>> {
+        let dst = expand_path(&state.key);
+        let rendered = state.value.as_deref().unwrap_or("");
+        let current = read_or_empty(&dst)?;
+        // Only secrets can differ, and diff doesn't print them
+        if matches_masked(&current, rendered) {
+            return Ok(None);
+        }
+        // Unfetched secrets show as the template reference they came from
+        let rendered = rendered
+            .replace(crate::secrets::MASK, "{{ secret.")
+            .replace(crate::secrets::MASK_END, " }}");
+        Ok(crate::util::unified_diff(&dst, &current, &rendered))
+    }
+}
+
+fn content_hash(content: &str) -> String {
+    use sha2::Digest;
+    sha2::Sha256::digest(content).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Record a fully rendered template as written, for `check` runs that only
+/// see its secrets masked
+fn remember_written(dst: &Path, rendered: &str) {
+    let hash = content_hash(rendered);
+    if crate::cache::get_written(dst).as_deref() != Some(&hash) {
+        crate::cache::set_written(dst, &hash);
+    }
+}
+
+/// `current == rendered`, where each masked secret in `rendered` matches
+/// whatever the file has in its place. Only the rest of the file is
+/// compared: `check` also wants the file's hash from the last apply.
+fn matches_masked(current: &str, rendered: &str) -> bool {
+    if !rendered.contains(crate::secrets::MASK) {
+        return current == rendered;
+    }
+    // Literal text between the masks, in order
+    let mut parts = Vec::new();
+    let mut rest = rendered;
+    while let Some((before, after)) = rest.split_once(crate::secrets::MASK) {
+        parts.push(before);
+        rest = after.split_once(crate::secrets::MASK_END).map_or("", |(_, after)| after);
+    }
+    let Some((first, parts)) = parts.split_first() else { return false };
+    let Some(mut current) = current.strip_prefix(first) else { return false };
+    for part in parts {
+        match current.find(part) {
+            Some(i) => current = &current[i + part.len()..],
+            None => return false,
+        }
+    }
+    current.ends_with(rest)
 }
 
 // =============================================================================
@@ -362,6 +581,17 @@ impl Provider for FileLineProvider {
         let value = state.value.as_deref().unwrap_or("");
         let line = value.split('\x01').next().unwrap_or("");
 
+        if is_delete(value) {
+            let (_, removed) = without_matching(&read_or_empty(&file_path)?, value)?;
+            return Ok(if removed == 0 {
+                CheckResult::Satisfied
+            } else {
+                CheckResult::Missing {
+                    detail: format!("{} line(s) to delete in '{}'", removed, file_path.display()),
+                }
+            });
+        }
+
         if !file_path.exists() {
             return Ok(CheckResult::Missing {
                 detail: format!("file '{}' does not exist", file_path.display()),
@@ -383,86 +613,153 @@ impl Provider for FileLineProvider {
     fn apply(&self, state: &StateItem) -> Result<()> {
         let file_path = expand_path(&state.key);
         let value = state.value.as_deref().unwrap_or("");
-        let parts: Vec<&str> = value.splitn(4, '\x01').collect();
-        let line = parts[0];
-        let original = parts.get(1).filter(|s| !s.is_empty()).copied();
-        let mode = parts.get(2).copied().unwrap_or("replace");
-        let match_type = parts.get(3).copied().unwrap_or("literal");
+
+        if is_delete(value) {
+            let current = read_or_empty(&file_path)?;
+            let (content, removed) = without_matching(&current, value)?;
+            if removed > 0 {
+                fs::write(&file_path, &content)
+                    .with_context(|| format!("failed to write: {}", file_path.display()))?;
+            }
+            return Ok(());
+        }
 
         if let Some(parent) = file_path.parent() {
             fs::create_dir_all(parent)
                 .with_context(|| format!("failed to create parent dirs for: {}", file_path.display()))?;
         }
 
-        let mut content = if file_path.exists() {
-            fs::read_to_string(&file_path)
-                .with_context(|| format!("failed to read: {}", file_path.display()))?
+        let current = read_or_empty(&file_path)?;
+        let content = with_line(current.clone(), value)?;
+        if content == current {
+            return Ok(());
+        }
+
+        fs::write(&file_path, &content)
+            .with_context(|| format!("failed to write: {}", file_path.display()))?;
+
+        Ok(())
+    }
+
+    fn diff(&self, state: &StateItem) -> Result<Option<String>> {
+        let file_path = expand_path(&state.key);
+        let value = state.value.as_deref().unwrap_or("");
+        let current = read_or_empty(&file_path)?;
+        let content = if is_delete(value) {
+            without_matching(&current, value)?.0
         } else {
-            String::new()
+            with_line(current.clone(), value)?
         };
+        Ok(crate::util::unified_diff(&file_path, &current, &content))
+    }
+}
 
-        if content.contains(line) {
-            return Ok(());
+fn is_delete(value: &str) -> bool {
+    value.split('\x01').nth(2) == Some("delete")
+}
+
+/// Matcher for a file.line `original`: a regex, or a trimmed whole-line literal
+enum LineMatcher {
+    Regex(regex::Regex),
+    Literal(String),
+}
+
+impl LineMatcher {
+    fn new(pattern: &str, match_type: &str) -> Result<Self> {
+        if match_type == "regex" {
+            let re = regex::Regex::new(pattern)
+                .map_err(|e| anyhow::anyhow!("Invalid original_regex '{}': {}", pattern, e))?;
+            Ok(Self::Regex(re))
+        } else {
+            Ok(Self::Literal(pattern.trim().to_string()))
         }
+    }
 
-        if let Some(pattern) = original {
-            let file_lines: Vec<&str> = content.lines().collect();
-            let mut new_lines: Vec<String> = Vec::with_capacity(file_lines.len() + 1);
-            let mut found = false;
+    fn matches(&self, line: &str) -> bool {
+        match self {
+            Self::Regex(re) => re.is_match(line),
+            Self::Literal(pattern) => line.trim() == pattern,
+        }
+    }
+}
 
-            // Build matcher based on type
-            let is_regex = match_type == "regex";
-            let re = if is_regex {
-                Some(regex::Regex::new(pattern)
-                    .map_err(|e| anyhow::anyhow!("Invalid original_regex '{}': {}", pattern, e))?)
-            } else {
-                None
-            };
-
-            for file_line in &file_lines {
-                let matches = if let Some(ref re) = re {
-                    re.is_match(file_line)
-                } else {
-                    file_line.trim() == pattern.trim()
-                };
-
-                if !found && matches {
-                    found = true;
-                    match mode {
-                        "below" => {
-                            new_lines.push(file_line.to_string());
-                            new_lines.push(line.to_string());
-                        }
-                        _ => new_lines.push(line.to_string()),
+/// Drop every line matching `original` from a delete-mode file.line value,
+/// returning the new content and how many lines were removed
+fn without_matching(content: &str, value: &str) -> Result<(String, usize)> {
+    let parts: Vec<&str> = value.splitn(4, '\x01').collect();
+    let original = parts.get(1).copied().unwrap_or("");
+    if original.is_empty() {
+        bail!("mode = \"delete\" needs original or original_regex");
+    }
+    let matcher = LineMatcher::new(original, parts.get(3).copied().unwrap_or("literal"))?;
+
+    let mut removed = 0;
+    let kept = content
+        .split_inclusive('\n')
+        .filter(|l| {
+            let hit = matcher.matches(l.trim_end_matches(['\n', '\r']));
+            removed += hit as usize;
+            !hit
+        })
+        .collect();
+    Ok((kept, removed))
+}
+
+/// Place the line from a file.line value ("line\x01original\x01mode\x01match")
+/// into `content`: replacing or below the first line matching `original`,
+/// appended otherwise. Unchanged if the line is already present.
+fn with_line(mut content: String, value: &str) -> Result<String> {
+    let parts: Vec<&str> = value.splitn(4, '\x01').collect();
+    let line = parts[0];
+    let original = parts.get(1).filter(|s| !s.is_empty()).copied();
+    let mode = parts.get(2).copied().unwrap_or("replace");
+    let match_type = parts.get(3).copied().unwrap_or("literal");
+
+    if content.contains(line) {
+        return Ok(content);
+    }
+
+    if let Some(pattern) = original {
+        let file_lines: Vec<&str> = content.lines().collect();
+        let mut new_lines: Vec<String> = Vec::with_capacity(file_lines.len() + 1);
+        let mut found = false;
+
+        let matcher = LineMatcher::new(pattern, match_type)?;
+
+        for file_line in &file_lines {
+            if !found && matcher.matches(file_line) {
+                found = true;
+                match mode {
+                    "below" => {
+                        new_lines.push(file_line.to_string());
+                        new_lines.push(line.to_string());
                     }
-                } else {
-                    new_lines.push(file_line.to_string());
+                    _ => new_lines.push(line.to_string()),
                 }
+            } else {
+                new_lines.push(file_line.to_string());
             }
+        }
 
-            if found {
-                content = new_lines.join("\n");
-                if !content.ends_with('\n') {
-                    content.push('\n');
-                }
-            } else {
-                if !content.ends_with('\n') {
-                    content.push('\n');
-                }
-                content.push_str(line);
+        if found {
+            content = new_lines.join("\n");
+            if !content.ends_with('\n') {
                 content.push('\n');
             }
         } else {
-            if !content.is_empty() && !content.ends_with('\n') {
+            if !content.ends_with('\n') {
                 content.push('\n');
             }
             content.push_str(line);
             content.push('\n');
         }
-
-        fs::write(&file_path, &content)
-            .with_context(|| format!("failed to write: {}", file_path.display()))?;
-
-        Ok(())
+    } else {
+        if !content.is_empty() && !content.ends_with('\n') {
+            content.push('\n');
+        }
+        content.push_str(line);
+        content.push('\n');
     }
+
+    Ok(content)
 }
@@ -1,12 +1,16 @@
+pub mod apt;
 pub mod assert;
 pub mod command;
+pub mod cron;
 pub mod file;
 pub mod package;
+pub mod pacman;
 pub mod script;
 pub mod service;
 pub mod shell;
+pub mod system;
 
-use crate::util::{command_exists, run_cmd, run_install_script, SysPkgManager};
+use crate::util::{command_exists, run_cmd_retry, run_install_script, SysPkgManager};
 use anyhow::{bail, Result};
 use indicatif::ProgressBar;
 use std::collections::HashSet;
@@ -25,6 +29,13 @@ impl CheckResult {
     }
 }
 
+/// A check result with how it was reached (`dek check --explain`)
+pub struct Explanation {
+    pub result: CheckResult,
+    /// What was looked at and what it meant, in order
+    pub reasons: Vec<String>,
+}
+
 impl fmt::Display for CheckResult {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -42,6 +53,12 @@ pub struct StateItem {
     pub value: Option<String>,
     pub run_if: Option<String>,
     pub cache_key: Option<String>,
+    /// Kill the apply's commands after this long
+    pub timeout: Option<std::time::Duration>,
+    /// Assertion that gates the apply: checked before anything changes
+    pub required: bool,
+    /// Reuse a satisfied check for this long instead of running it again
+    pub check_ttl: Option<std::time::Duration>,
 }
 
 impl StateItem {
@@ -52,6 +69,9 @@ impl StateItem {
             value: None,
             run_if: None,
             cache_key: None,
+            timeout: None,
+            required: false,
+            check_ttl: None,
         }
     }
 
@@ -65,6 +85,23 @@ impl StateItem {
         self
     }
 
+    /// `timeout = "10m"` from the config, checked when it was loaded
+    pub fn with_timeout(mut self, timeout: Option<&str>) -> Self {
+        self.timeout = timeout.and_then(|t| crate::util::parse_duration(t).ok());
+        self
+    }
+
+    /// `check_ttl = "5m"` from the config, checked when it was loaded
+    pub fn with_check_ttl(mut self, ttl: Option<&str>) -> Self {
+        self.check_ttl = ttl.and_then(|t| crate::util::parse_duration(t).ok());
+        self
+    }
+
+    pub fn with_required(mut self, required: bool) -> Self {
+        self.required = required;
+        self
+    }
+
     pub fn with_cache_key(mut self, cache_key: Option<String>, cache_key_cmd: Option<String>) -> Self {
         self.cache_key = resolve_cache_key(cache_key, cache_key_cmd);
         self
@@ -137,25 +174,54 @@ impl Requirement {
         command_exists(self.binary)
     }
 
-    pub fn satisfy(&self) -> Result<()> {
+    /// The script `satisfy` pipes into sh, for the methods that work that way
+    pub fn script_url(&self) -> Option<String> {
+        match &self.install {
+            InstallMethod::Rustup => Some("https://sh.rustup.rs".to_string()),
+            InstallMethod::CargoBinstall => Some(
+                "https://raw.githubusercontent.com/cargo-bins/cargo-binstall/main/install-from-binstall-release.sh"
+                    .to_string(),
+            ),
+            InstallMethod::Webi(pkg) => Some(format!("https://webi.sh/{}", pkg)),
+            _ => None,
+        }
+    }
+
+    /// How `satisfy` would install it, for reports and prompts
+    pub fn via(&self) -> String {
+        match &self.install {
+            InstallMethod::Rustup | InstallMethod::CargoBinstall | InstallMethod::Webi(_) => {
+                self.script_url().unwrap_or_default()
+            }
+            InstallMethod::Cargo(pkg) => format!("cargo install {}", pkg),
+            InstallMethod::System(pkg) => format!("system package {}", pkg),
+            InstallMethod::Go(pkg) => format!("go install {}", pkg),
+            InstallMethod::Npm(pkg) => format!("npm install -g {}", pkg),
+            InstallMethod::Pip(pkg) => format!("pip install --user {}", pkg),
+        }
+    }
+
+    /// Run the install script from `script_url`
+    fn run_script(&self, args: &[&str], policy: &Policy) -> Result<()> {
+        let url = self.script_url().expect("only called for script install methods");
+        run_install_script(&url, args, &policy.script_pins)
+    }
+
+    pub fn satisfy(&self, policy: &Policy) -> Result<()> {
         if self.is_satisfied() {
             return Ok(());
         }
 
         use owo_colors::OwoColorize;
-        println!("    {} installing {}...", c!("→", yellow), self.binary);
+        println!("    {} installing {} {}...", c!("→", yellow), self.binary, c!(format!("via {}", self.via()), dimmed));
 
         match &self.install {
             InstallMethod::Rustup => {
-                run_install_script("https://sh.rustup.rs", &["-y"])?;
+                self.run_script(&["-y"], policy)?;
                 // Add to PATH for this process and child processes
                 if let Ok(home) = std::env::var("HOME") {
                     let cargo_bin = format!("{}/.cargo/bin", home);
-                    if let Ok(path) = std::env::var("PATH") {
-                        std::env::set_var("PATH", format!("{}:{}", cargo_bin, path));
-                    } else {
-                        std::env::set_var("PATH", &cargo_bin);
-                    }
+                    crate::util::add_to_path(std::path::Path::new(&cargo_bin), false);
                     // Verify binary exists directly (don't rely on which)
                     let binary_path = format!("{}/{}", cargo_bin, self.binary);
                     if std::path::Path::new(&binary_path).exists() {
@@ -164,18 +230,11 @@ impl Requirement {
                 }
             }
             InstallMethod::CargoBinstall => {
-                run_install_script(
-                    "https://raw.githubusercontent.com/cargo-bins/cargo-binstall/main/install-from-binstall-release.sh",
-                    &[],
-                )?;
+                self.run_script(&[], policy)?;
                 // Add cargo bin to PATH and verify
                 if let Ok(home) = std::env::var("HOME") {
                     let cargo_bin = format!("{}/.cargo/bin", home);
-                    if let Ok(path) = std::env::var("PATH") {
-                        if !path.contains(&cargo_bin) {
-                            std::env::set_var("PATH", format!("{}:{}", cargo_bin, path));
-                        }
-                    }
+                    crate::util::add_to_path(std::path::Path::new(&cargo_bin), false);
                     let binary_path = format!("{}/{}", cargo_bin, self.binary);
                     if std::path::Path::new(&binary_path).exists() {
                         return Ok(());
@@ -191,7 +250,7 @@ impl Requirement {
                 } else {
                     "cargo".to_string()
                 };
-                let output = run_cmd(&cargo, &["install", pkg])?;
+                let output = run_cmd_retry(&cargo, &["install", pkg])?;
                 if !output.status.success() {
                     bail!("cargo install {} failed", pkg);
                 }
@@ -206,30 +265,29 @@ impl Requirement {
             InstallMethod::System(pkg) => {
                 let pm = SysPkgManager::detect()
                     .ok_or_else(|| anyhow::anyhow!("No supported package manager"))?;
-                pm.install(pkg)?;
+                pm.install_with_aur(pkg, policy.aur_helper)?;
             }
             InstallMethod::Go(pkg) => {
-                let output = run_cmd("go", &["install", pkg])?;
+                let output = run_cmd_retry("go", &["install", pkg])?;
                 if !output.status.success() {
                     bail!("go install {} failed", pkg);
                 }
             }
             InstallMethod::Npm(pkg) => {
-                let output = run_cmd("npm", &["install", "-g", pkg])?;
+                let output = run_cmd_retry("npm", &["install", "-g", pkg])?;
                 if !output.status.success() {
                     bail!("npm install -g {} failed", pkg);
                 }
             }
             InstallMethod::Pip(pkg) => {
                 let pip = if command_exists("pip3") { "pip3" } else { "pip" };
-                let output = run_cmd(pip, &["install", "--user", pkg])?;
+                let output = run_cmd_retry(pip, &["install", "--user", pkg])?;
                 if !output.status.success() {
                     bail!("pip install {} failed", pkg);
                 }
             }
-            InstallMethod::Webi(pkg) => {
-                let url = format!("https://webi.sh/{}", pkg);
-                run_install_script(&url, &[])?;
+            InstallMethod::Webi(_) => {
+                self.run_script(&[], policy)?;
                 // Webi installs to various paths, ensure they're in PATH
                 if let Ok(home) = std::env::var("HOME") {
                     let webi_paths = [
@@ -237,14 +295,8 @@ impl Requirement {
                         format!("{}/.local/opt/go/bin", home),
                         format!("{}/go/bin", home),
                     ];
-                    if let Ok(path) = std::env::var("PATH") {
-                        let mut new_path = path.clone();
-                        for p in &webi_paths {
-                            if !new_path.contains(p) {
-                                new_path = format!("{}:{}", p, new_path);
-                            }
-                        }
-                        std::env::set_var("PATH", new_path);
+                    for p in &webi_paths {
+                        crate::util::add_to_path(std::path::Path::new(p), false);
                     }
                 }
             }
@@ -259,13 +311,13 @@ impl Requirement {
 }
 
 /// Resolve all requirements, installing missing ones
-pub fn resolve_requirements(reqs: &[Requirement]) -> Result<()> {
+pub fn resolve_requirements(reqs: &[Requirement], policy: &Policy) -> Result<()> {
     // Dedupe and preserve order
     let mut seen = HashSet::new();
     let unique: Vec<_> = reqs.iter().filter(|r| seen.insert((*r).clone())).collect();
 
     for req in unique {
-        req.satisfy()?;
+        req.satisfy(policy)?;
     }
     Ok(())
 }
@@ -275,7 +327,7 @@ pub fn resolve_requirements(reqs: &[Requirement]) -> Result<()> {
 // =============================================================================
 
 /// Provider trait for checking and applying state
-pub trait Provider {
+pub trait Provider: Sync {
     fn check(&self, state: &StateItem) -> Result<CheckResult>;
     fn apply(&self, state: &StateItem) -> Result<()>;
     fn name(&self) -> &'static str;
@@ -299,28 +351,72 @@ pub trait Provider {
     fn apply_live(&self, state: &StateItem, _pb: &ProgressBar) -> Result<()> {
         self.apply(state)
     }
+
+    /// What apply would change, as a unified diff (`dek diff`). None when
+    /// already up to date or the provider doesn't manage file content.
+    fn diff(&self, _state: &StateItem) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    /// check() with its reasoning spelled out. Default: check's result alone.
+    fn explain(&self, state: &StateItem) -> Result<Explanation> {
+        Ok(Explanation { result: self.check(state)?, reasons: vec![] })
+    }
+}
+
+/// Run-wide install settings from the config's `[meta]`, handed to the
+/// providers that use them
+#[derive(Debug, Clone, Default)]
+pub struct Policy {
+    /// `aur_helper`: None auto-detects
+    pub aur_helper: Option<crate::config::AurHelper>,
+    /// `cargo_prefer`: None tries binstall first
+    pub cargo_prefer: Option<crate::config::CargoPrefer>,
+    /// `install_script_sha256` and `pinned_install_scripts`
+    pub script_pins: crate::util::ScriptPins,
+}
+
+impl Policy {
+    pub fn from_meta(meta: Option<&crate::config::ConfigMeta>) -> Self {
+        let Some(meta) = meta else { return Self::default() };
+        Self {
+            aur_helper: meta.aur_helper,
+            cargo_prefer: meta.cargo_prefer,
+            script_pins: crate::util::ScriptPins {
+                sha256: meta.install_script_sha256.clone(),
+                required: meta.pinned_install_scripts,
+            },
+        }
+    }
 }
 
 /// Registry of all providers
 pub struct ProviderRegistry {
     providers: Vec<Box<dyn Provider>>,
+    policy: Policy,
 }
 
 impl Default for ProviderRegistry {
     fn default() -> Self {
-        Self::new()
+        Self::new(Policy::default())
     }
 }
 
 impl ProviderRegistry {
-    pub fn new() -> Self {
+    pub fn new(policy: Policy) -> Self {
         let providers: Vec<Box<dyn Provider>> = vec![
-            Box::new(package::OsProvider),
+            Box::new(apt::AptRepoProvider),
+            Box::new(pacman::PacmanRepoProvider),
+            Box::new(package::OsProvider {
+                aur_helper: policy.aur_helper,
+                webi: package::WebiProvider { pins: policy.script_pins.clone() },
+            }),
             Box::new(package::AptProvider),
-            Box::new(package::PacmanProvider),
-            Box::new(package::CargoProvider),
+            Box::new(package::PacmanProvider { aur_helper: policy.aur_helper }),
+            Box::new(package::BrewProvider),
+            Box::new(package::CargoProvider { prefer: policy.cargo_prefer }),
             Box::new(package::GoProvider),
-            Box::new(package::WebiProvider),
+            Box::new(package::WebiProvider { pins: policy.script_pins.clone() }),
             Box::new(package::NpmProvider),
             Box::new(package::PipProvider),
             Box::new(package::PipxProvider),
@@ -329,16 +425,27 @@ impl ProviderRegistry {
             Box::new(file::FetchProvider),
             Box::new(file::SymlinkProvider),
             Box::new(file::EnsureLineProvider),
+            Box::new(file::EnsureAbsentProvider),
             Box::new(file::FileLineProvider),
             Box::new(file::TemplateProvider),
             Box::new(shell::AliasProvider),
             Box::new(shell::EnvProvider),
+            Box::new(shell::PathProvider),
+            Box::new(system::TimezoneProvider),
+            Box::new(system::HostnameProvider),
+            Box::new(system::SysctlProvider),
+            Box::new(system::UserProvider),
+            Box::new(cron::CronProvider),
             Box::new(command::CommandProvider),
             Box::new(script::ScriptProvider),
             Box::new(assert::AssertProvider),
         ];
 
-        Self { providers }
+        Self { providers, policy }
+    }
+
+    pub fn policy(&self) -> &Policy {
+        &self.policy
     }
 
     pub fn get(&self, kind: &str) -> Option<&dyn Provider> {
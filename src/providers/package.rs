@@ -1,13 +1,58 @@
-use super::{CheckResult, InstallMethod, Provider, Requirement, StateItem};
-use crate::util::{command_exists, install_with_yay_live, run_cmd, run_cmd_live, run_cmd_ok, run_sudo, run_sudo_live, SysPkgManager};
+use super::{CheckResult, Explanation, InstallMethod, Provider, Requirement, StateItem};
+use crate::config::{AurHelper, CargoPrefer};
+use crate::util::{
+    command_exists, install_from_aur_live, run_cmd, run_cmd_live, run_cmd_live_retry, run_cmd_ok, run_cmd_retry, run_sudo,
+    run_sudo_live, SysPkgManager,
+};
 use anyhow::{bail, Result};
 use indicatif::ProgressBar;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// =============================================================================
+// Listings (`cargo install --list`, `pip list`, ...) run once per run
+// =============================================================================
+
+static LISTINGS: Mutex<Option<HashMap<&'static str, Arc<String>>>> = Mutex::new(None);
+
+/// Stdout of a listing command, run on first use and shared by every later
+/// check for the same manager. Empty when the command fails.
+fn listing(manager: &'static str, cmd: &str, args: &[&str]) -> Arc<String> {
+    if let Some(cached) = LISTINGS.lock().unwrap().as_ref().and_then(|m| m.get(manager)) {
+        return cached.clone();
+    }
+    let stdout = run_cmd(cmd, args)
+        .ok()
+        .filter(|o| o.status.success())
+        .map(|o| String::from_utf8_lossy(&o.stdout).into_owned())
+        .unwrap_or_default();
+    let stdout = Arc::new(stdout);
+    LISTINGS.lock().unwrap().get_or_insert_with(HashMap::new).insert(manager, stdout.clone());
+    stdout
+}
+
+/// Drop a manager's listing before installing through it, so it's re-read
+fn forget_listing(manager: &str) {
+    if let Some(m) = LISTINGS.lock().unwrap().as_mut() {
+        m.remove(manager);
+    }
+}
+
+/// Drop all listings (start of a run: `--watch` runs many in one process)
+pub fn clear_listings() {
+    *LISTINGS.lock().unwrap() = None;
+}
 
 // =============================================================================
 // OS (auto-detect system package manager)
 // =============================================================================
 
-pub struct OsProvider;
+pub struct OsProvider {
+    /// `[meta] aur_helper` for packages pacman can't find; None auto-detects
+    pub aur_helper: Option<AurHelper>,
+    /// Where packages go without a system package manager
+    pub webi: WebiProvider,
+}
 
 impl Provider for OsProvider {
     fn name(&self) -> &'static str {
@@ -20,20 +65,11 @@ impl Provider for OsProvider {
 
     fn check(&self, state: &StateItem) -> Result<CheckResult> {
         let Some(pm) = SysPkgManager::detect() else {
-            return WebiProvider.check(state);
+            return self.webi.check(state);
         };
 
         let (pkg_name, _) = crate::util::parse_spec(&state.key);
-        let installed = match pm {
-            SysPkgManager::Pacman => run_cmd_ok("pacman", &["-Q", &pkg_name]),
-            SysPkgManager::Apt => {
-                let output = run_cmd("dpkg-query", &["-W", "-f=${Status}", &pkg_name])?;
-                String::from_utf8_lossy(&output.stdout).contains("install ok installed")
-            }
-            SysPkgManager::Brew => run_cmd_ok("brew", &["list", &pkg_name]),
-        };
-
-        if installed {
+        if pm.is_installed(&pkg_name)? {
             Ok(CheckResult::Satisfied)
         } else {
             Ok(CheckResult::Missing {
@@ -44,30 +80,23 @@ impl Provider for OsProvider {
 
     fn apply(&self, state: &StateItem) -> Result<()> {
         let Some(pm) = SysPkgManager::detect() else {
-            return WebiProvider.apply(state);
+            return self.webi.apply(state);
         };
 
         let (pkg_name, _) = crate::util::parse_spec(&state.key);
-        pm.install(&pkg_name)
+        pm.install_with_aur(&pkg_name, self.aur_helper)
     }
 
     fn apply_live(&self, state: &StateItem, pb: &ProgressBar) -> Result<()> {
         let Some(pm) = SysPkgManager::detect() else {
-            return WebiProvider.apply(state);
+            return self.webi.apply(state);
         };
 
         let (pkg_name, _) = crate::util::parse_spec(&state.key);
-        let output = match pm {
-            SysPkgManager::Pacman => {
-                let out = run_sudo_live("pacman", &["-S", "--noconfirm", &pkg_name], pb)?;
-                if !out.status.success() {
-                    return install_with_yay_live(&pkg_name, pb);
-                }
-                return Ok(());
-            }
-            SysPkgManager::Apt => run_sudo_live("apt-get", &["install", "-y", &pkg_name], pb)?,
-            SysPkgManager::Brew => run_cmd_live("brew", &["install", &pkg_name], pb)?,
-        };
+        let output = pm.install_live(&pkg_name, pb)?;
+        if !output.status.success() && pm == SysPkgManager::Pacman {
+            return install_from_aur_live(&pkg_name, self.aur_helper, pb);
+        }
         if !output.status.success() {
             bail!("Failed to install '{}': {}", pkg_name, String::from_utf8_lossy(&output.stderr));
         }
@@ -127,7 +156,10 @@ impl Provider for AptProvider {
 // PACMAN
 // =============================================================================
 
-pub struct PacmanProvider;
+pub struct PacmanProvider {
+    /// `[meta] aur_helper` for packages pacman can't find; None auto-detects
+    pub aur_helper: Option<AurHelper>,
+}
 
 impl Provider for PacmanProvider {
     fn name(&self) -> &'static str {
@@ -154,7 +186,7 @@ impl Provider for PacmanProvider {
         let (pkg_name, _) = crate::util::parse_spec(&state.key);
         let output = run_sudo("pacman", &["-S", "--noconfirm", &pkg_name])?;
         if !output.status.success() {
-            return crate::util::install_with_yay(&pkg_name);
+            return crate::util::install_from_aur(&pkg_name, self.aur_helper);
         }
         Ok(())
     }
@@ -163,7 +195,62 @@ impl Provider for PacmanProvider {
         let (pkg_name, _) = crate::util::parse_spec(&state.key);
         let output = run_sudo_live("pacman", &["-S", "--noconfirm", &pkg_name], pb)?;
         if !output.status.success() {
-            return install_with_yay_live(&pkg_name, pb);
+            return install_from_aur_live(&pkg_name, self.aur_helper, pb);
+        }
+        Ok(())
+    }
+}
+
+// =============================================================================
+// BREW
+// =============================================================================
+
+/// Value of a brew item that is a cask (GUI app) rather than a formula
+pub const BREW_CASK: &str = "cask";
+
+pub struct BrewProvider;
+
+impl BrewProvider {
+    fn kind_flag(state: &StateItem) -> &'static str {
+        if state.value.as_deref() == Some(BREW_CASK) {
+            "--cask"
+        } else {
+            "--formula"
+        }
+    }
+}
+
+impl Provider for BrewProvider {
+    fn name(&self) -> &'static str {
+        "package.brew"
+    }
+
+    fn check(&self, state: &StateItem) -> Result<CheckResult> {
+        let (pkg_name, _) = crate::util::parse_spec(&state.key);
+        let flag = Self::kind_flag(state);
+        if run_cmd_ok("brew", &["list", flag, &pkg_name]) {
+            Ok(CheckResult::Satisfied)
+        } else {
+            Ok(CheckResult::Missing {
+                detail: format!("brew {} '{}' not installed", flag.trim_start_matches('-'), pkg_name),
+            })
+        }
+    }
+
+    fn apply(&self, state: &StateItem) -> Result<()> {
+        let (pkg_name, _) = crate::util::parse_spec(&state.key);
+        let output = run_cmd("brew", &["install", Self::kind_flag(state), &pkg_name])?;
+        if !output.status.success() {
+            bail!("brew install failed: {}", String::from_utf8_lossy(&output.stderr));
+        }
+        Ok(())
+    }
+
+    fn apply_live(&self, state: &StateItem, pb: &ProgressBar) -> Result<()> {
+        let (pkg_name, _) = crate::util::parse_spec(&state.key);
+        let output = run_cmd_live("brew", &["install", Self::kind_flag(state), &pkg_name], pb)?;
+        if !output.status.success() {
+            bail!("brew install failed: {}", String::from_utf8_lossy(&output.stderr));
         }
         Ok(())
     }
@@ -173,7 +260,16 @@ impl Provider for PacmanProvider {
 // CARGO
 // =============================================================================
 
-pub struct CargoProvider;
+pub struct CargoProvider {
+    /// Install strategy (`[meta] cargo_prefer`); None is the default
+    pub prefer: Option<CargoPrefer>,
+}
+
+impl CargoProvider {
+    fn prefers(&self, prefer: CargoPrefer) -> bool {
+        self.prefer == Some(prefer)
+    }
+}
 
 impl Provider for CargoProvider {
     fn name(&self) -> &'static str {
@@ -181,36 +277,76 @@ impl Provider for CargoProvider {
     }
 
     fn requires(&self) -> Vec<Requirement> {
-        vec![
-            Requirement::binary("cargo", InstallMethod::Rustup),
-            Requirement::binary("cargo-binstall", InstallMethod::CargoBinstall),
-        ]
+        let mut reqs = vec![Requirement::binary("cargo", InstallMethod::Rustup)];
+        if !self.prefers(CargoPrefer::Source) {
+            reqs.push(Requirement::binary("cargo-binstall", InstallMethod::CargoBinstall));
+        }
+        reqs
     }
 
     fn check(&self, state: &StateItem) -> Result<CheckResult> {
-        let (pkg_name, _) = crate::util::parse_spec(&state.key);
-        // cargo install --list outputs "pkg_name vX.Y.Z:" for installed crates
-        if let Ok(output) = run_cmd("cargo", &["install", "--list"]) {
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            if stdout.lines().any(|l| l.starts_with(&format!("{} ", pkg_name))) {
-                return Ok(CheckResult::Satisfied);
+        self.explain(state).map(|e| e.result)
+    }
+
+    fn explain(&self, state: &StateItem) -> Result<Explanation> {
+        let (pkg_name, flags) = cargo_parse_spec(&state.key);
+        // Installed source to match: git URL or local path, if the spec pins one
+        let source = flags
+            .windows(2)
+            .find(|w| w[0] == "--git" || w[0] == "--path")
+            .map(|w| w[1].trim_end_matches('/').trim_end_matches(".git").to_string());
+        // cargo install --list outputs "pkg_name vX.Y.Z:" for registry crates and
+        // "pkg_name vX.Y.Z (<git url>#<rev>):" or "(<path>)" for the others
+        let list = listing("cargo", "cargo", &["install", "--list"]);
+        let prefix = format!("{} ", pkg_name);
+        let mut reasons = vec![match source {
+            Some(ref s) => format!("looking for a '{}v…' line mentioning {} in `cargo install --list`", prefix, s),
+            None => format!("looking for a '{}v…' line in `cargo install --list`", prefix),
+        }];
+        let found: Vec<&str> = list.lines().filter(|l| l.starts_with(&prefix)).collect();
+        let missing = |reasons| Explanation {
+            result: CheckResult::Missing { detail: format!("cargo package '{}' not installed", pkg_name) },
+            reasons,
+        };
+
+        if found.is_empty() {
+            reasons.push(format!("no such line among {} installed crates", list.lines().filter(|l| !l.starts_with(' ')).count()));
+            return Ok(missing(reasons));
+        }
+        match found.iter().find(|l| source.as_ref().is_none_or(|s| l.contains(s.as_str()))) {
+            Some(line) => {
+                reasons.push(format!("found {}", line.trim_end_matches(':')));
+                Ok(Explanation { result: CheckResult::Satisfied, reasons })
+            }
+            None => {
+                for line in found {
+                    reasons.push(format!("installed from elsewhere: {}", line.trim_end_matches(':')));
+                }
+                Ok(missing(reasons))
             }
         }
-        Ok(CheckResult::Missing {
-            detail: format!("cargo package '{}' not installed", pkg_name),
-        })
     }
 
     fn apply(&self, state: &StateItem) -> Result<()> {
-        let (pkg_name, _) = crate::util::parse_spec(&state.key);
-
-        // Try binstall first (pre-compiled), fall back to install (compile)
-        let output = run_cmd("cargo", &["binstall", "-y", &pkg_name])?;
-        if output.status.success() {
-            return Ok(());
+        forget_listing("cargo");
+        let (pkg_name, flags) = cargo_parse_spec(&state.key);
+
+        // Try binstall first (pre-compiled), fall back to install (compile),
+        // unless cargo_prefer says otherwise. Specs with flags (--git, --path,
+        // --features) always compile.
+        if flags.is_empty() && !self.prefers(CargoPrefer::Source) {
+            let output = run_cmd("cargo", &["binstall", "-y", &pkg_name])?;
+            if output.status.success() {
+                return Ok(());
+            }
+            if self.prefers(CargoPrefer::Binstall) {
+                bail!("cargo binstall failed (cargo_prefer = \"binstall\"): {}", String::from_utf8_lossy(&output.stderr).trim());
+            }
         }
 
-        let output = run_cmd("cargo", &["install", &pkg_name])?;
+        let mut args = vec!["install", pkg_name.as_str()];
+        args.extend(flags.iter().map(String::as_str));
+        let output = run_cmd_retry("cargo", &args)?;
         if !output.status.success() {
             bail!("cargo install failed: {}", String::from_utf8_lossy(&output.stderr));
         }
@@ -218,14 +354,22 @@ impl Provider for CargoProvider {
     }
 
     fn apply_live(&self, state: &StateItem, pb: &ProgressBar) -> Result<()> {
-        let (pkg_name, _) = crate::util::parse_spec(&state.key);
+        forget_listing("cargo");
+        let (pkg_name, flags) = cargo_parse_spec(&state.key);
 
-        let output = run_cmd_live("cargo", &["binstall", "-y", &pkg_name], pb)?;
-        if output.status.success() {
-            return Ok(());
+        if flags.is_empty() && !self.prefers(CargoPrefer::Source) {
+            let output = run_cmd_live("cargo", &["binstall", "-y", &pkg_name], pb)?;
+            if output.status.success() {
+                return Ok(());
+            }
+            if self.prefers(CargoPrefer::Binstall) {
+                bail!("cargo binstall failed (cargo_prefer = \"binstall\"): {}", String::from_utf8_lossy(&output.stderr).trim());
+            }
         }
 
-        let output = run_cmd_live("cargo", &["install", &pkg_name], pb)?;
+        let mut args = vec!["install", pkg_name.as_str()];
+        args.extend(flags.iter().map(String::as_str));
+        let output = run_cmd_live_retry("cargo", &args, pb)?;
         if !output.status.success() {
             bail!("cargo install failed: {}", String::from_utf8_lossy(&output.stderr));
         }
@@ -233,6 +377,16 @@ impl Provider for CargoProvider {
     }
 }
 
+/// Parse cargo spec: "crate[:bin] [cargo install flags]", e.g.
+/// "ripgrep --git https://github.com/BurntSushi/ripgrep" or "mytool --path ./mytool".
+/// Returns (crate name, flags).
+pub fn cargo_parse_spec(spec: &str) -> (String, Vec<String>) {
+    let mut parts = spec.split_whitespace();
+    let first = parts.next().unwrap_or_default();
+    let (pkg_name, _) = crate::util::parse_spec(first);
+    (pkg_name, parts.map(str::to_string).collect())
+}
+
 // =============================================================================
 // GO
 // =============================================================================
@@ -261,7 +415,7 @@ impl Provider for GoProvider {
 
     fn apply(&self, state: &StateItem) -> Result<()> {
         let (pkg_name, _) = go_parse_spec(&state.key);
-        let output = run_cmd("go", &["install", &pkg_name])?;
+        let output = run_cmd_retry("go", &["install", &pkg_name])?;
         if !output.status.success() {
             bail!("go install failed: {}", String::from_utf8_lossy(&output.stderr));
         }
@@ -270,7 +424,7 @@ impl Provider for GoProvider {
 
     fn apply_live(&self, state: &StateItem, pb: &ProgressBar) -> Result<()> {
         let (pkg_name, _) = go_parse_spec(&state.key);
-        let output = run_cmd_live("go", &["install", &pkg_name], pb)?;
+        let output = run_cmd_live_retry("go", &["install", &pkg_name], pb)?;
         if !output.status.success() {
             bail!("go install failed: {}", String::from_utf8_lossy(&output.stderr));
         }
@@ -306,7 +460,10 @@ fn go_bin_from_path(pkg: &str) -> String {
 // WEBI
 // =============================================================================
 
-pub struct WebiProvider;
+pub struct WebiProvider {
+    /// Pins the webi.sh scripts are checked against
+    pub pins: crate::util::ScriptPins,
+}
 
 impl Provider for WebiProvider {
     fn name(&self) -> &'static str {
@@ -327,7 +484,7 @@ impl Provider for WebiProvider {
     fn apply(&self, state: &StateItem) -> Result<()> {
         let (pkg_name, _) = crate::util::parse_spec(&state.key);
         let url = format!("https://webi.sh/{}", pkg_name);
-        crate::util::run_install_script(&url, &[])?;
+        crate::util::run_install_script(&url, &[], &self.pins)?;
 
         // Webi installs to various paths, ensure they're in PATH
         if let Ok(home) = std::env::var("HOME") {
@@ -336,14 +493,8 @@ impl Provider for WebiProvider {
                 format!("{}/.local/opt/go/bin", home),
                 format!("{}/go/bin", home),
             ];
-            if let Ok(path) = std::env::var("PATH") {
-                let mut new_path = path.clone();
-                for p in &webi_paths {
-                    if !new_path.contains(p) {
-                        new_path = format!("{}:{}", p, new_path);
-                    }
-                }
-                std::env::set_var("PATH", new_path);
+            for p in &webi_paths {
+                crate::util::add_to_path(std::path::Path::new(p), false);
             }
         }
         Ok(())
@@ -367,8 +518,7 @@ impl Provider for NpmProvider {
 
     fn check(&self, state: &StateItem) -> Result<CheckResult> {
         let (pkg_name, _) = crate::util::parse_spec(&state.key);
-        let ok = run_cmd_ok("npm", &["list", "-g", &pkg_name, "--depth=0"]);
-        if ok {
+        if npm_installed().contains(&pkg_name) {
             Ok(CheckResult::Satisfied)
         } else {
             Ok(CheckResult::Missing {
@@ -379,7 +529,8 @@ impl Provider for NpmProvider {
 
     fn apply(&self, state: &StateItem) -> Result<()> {
         let (pkg_name, _) = crate::util::parse_spec(&state.key);
-        let output = run_cmd("npm", &["install", "-g", &pkg_name])?;
+        forget_listing("npm");
+        let output = run_cmd_retry("npm", &["install", "-g", &pkg_name])?;
         if !output.status.success() {
             bail!("npm install failed: {}", String::from_utf8_lossy(&output.stderr));
         }
@@ -388,7 +539,8 @@ impl Provider for NpmProvider {
 
     fn apply_live(&self, state: &StateItem, pb: &ProgressBar) -> Result<()> {
         let (pkg_name, _) = crate::util::parse_spec(&state.key);
-        let output = run_cmd_live("npm", &["install", "-g", &pkg_name], pb)?;
+        forget_listing("npm");
+        let output = run_cmd_live_retry("npm", &["install", "-g", &pkg_name], pb)?;
         if !output.status.success() {
             bail!("npm install failed: {}", String::from_utf8_lossy(&output.stderr));
         }
@@ -400,8 +552,28 @@ impl Provider for NpmProvider {
 // PIP
 // =============================================================================
 
+/// Value of a pip item whose key is a requirements file rather than a spec
+pub const PIP_REQUIREMENTS: &str = "requirements";
+
 pub struct PipProvider;
 
+impl PipProvider {
+    fn is_requirements(state: &StateItem) -> bool {
+        state.value.as_deref() == Some(PIP_REQUIREMENTS)
+    }
+
+    fn install_args(state: &StateItem) -> Vec<String> {
+        let mut args = vec!["install".to_string(), "--user".to_string()];
+        if Self::is_requirements(state) {
+            args.push("-r".to_string());
+            args.push(crate::util::expand_path(&state.key).to_string_lossy().to_string());
+        } else {
+            args.push(pip_parse_spec(&state.key).0);
+        }
+        args
+    }
+}
+
 impl Provider for PipProvider {
     fn name(&self) -> &'static str {
         "package.pip"
@@ -412,22 +584,39 @@ impl Provider for PipProvider {
     }
 
     fn check(&self, state: &StateItem) -> Result<CheckResult> {
-        let (pkg_name, _) = crate::util::parse_spec(&state.key);
-        let ok = run_cmd_ok("pip3", &["show", &pkg_name])
-            || run_cmd_ok("pip", &["show", &pkg_name]);
-        if ok {
+        let names = if Self::is_requirements(state) {
+            let path = crate::util::expand_path(&state.key);
+            let content = std::fs::read_to_string(&path)
+                .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))?;
+            requirements_names(&content)
+        } else {
+            vec![pip_parse_spec(&state.key).1]
+        };
+        if names.is_empty() {
+            return Ok(CheckResult::Satisfied);
+        }
+
+        let installed = pip_installed();
+        let missing: Vec<String> = names.into_iter().filter(|n| !installed.contains(&normalize_pip(n))).collect();
+        if missing.is_empty() {
             Ok(CheckResult::Satisfied)
+        } else if Self::is_requirements(state) {
+            Ok(CheckResult::Missing {
+                detail: format!("{}: not installed: {}", state.key, missing.join(", ")),
+            })
         } else {
             Ok(CheckResult::Missing {
-                detail: format!("pip package '{}' not installed", pkg_name),
+                detail: format!("pip package '{}' not installed", missing.join(", ")),
             })
         }
     }
 
     fn apply(&self, state: &StateItem) -> Result<()> {
-        let (pkg_name, _) = crate::util::parse_spec(&state.key);
+        forget_listing("pip");
         let pip = if command_exists("pip3") { "pip3" } else { "pip" };
-        let output = run_cmd(pip, &["install", "--user", &pkg_name])?;
+        let args = Self::install_args(state);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let output = run_cmd_retry(pip, &args)?;
         if !output.status.success() {
             bail!("pip install failed: {}", String::from_utf8_lossy(&output.stderr));
         }
@@ -435,9 +624,11 @@ impl Provider for PipProvider {
     }
 
     fn apply_live(&self, state: &StateItem, pb: &ProgressBar) -> Result<()> {
-        let (pkg_name, _) = crate::util::parse_spec(&state.key);
+        forget_listing("pip");
         let pip = if command_exists("pip3") { "pip3" } else { "pip" };
-        let output = run_cmd_live(pip, &["install", "--user", &pkg_name], pb)?;
+        let args = Self::install_args(state);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let output = run_cmd_live_retry(pip, &args, pb)?;
         if !output.status.success() {
             bail!("pip install failed: {}", String::from_utf8_lossy(&output.stderr));
         }
@@ -445,12 +636,132 @@ impl Provider for PipProvider {
     }
 }
 
+/// Parse a pip spec: "httpie[socks]", "black==24.1.0", "pkg @ https://..."
+/// with an optional ":bin" suffix. Returns (spec to install, distribution name).
+fn pip_parse_spec(spec: &str) -> (String, String) {
+    let spec = match spec.rsplit_once(':') {
+        Some((pkg, bin))
+            if !spec.contains("://")
+                && !bin.is_empty()
+                && bin.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c)) =>
+        {
+            pkg
+        }
+        _ => spec,
+    };
+    (spec.trim().to_string(), pip_name(spec))
+}
+
+/// Distribution name of a pip spec, without extras, version or markers
+pub fn pip_name(spec: &str) -> String {
+    spec.split(|c: char| "[=<>!~;@ ".contains(c)).next().unwrap_or(spec).trim().to_string()
+}
+
+/// Package names listed in a requirements file. Options (-r, -e, --index-url)
+/// and bare URLs are skipped, so only named requirements are checked.
+fn requirements_names(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .map(|l| l.split(" #").next().unwrap_or(l).trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('#') && !l.starts_with('-'))
+        .filter(|l| !l.contains("://") || l.contains('@'))
+        .map(pip_name)
+        .filter(|n| !n.is_empty())
+        .collect()
+}
+
+/// pip compares names ignoring case and -/_/.
+fn normalize_pip(name: &str) -> String {
+    name.to_lowercase().replace(['_', '.'], "-")
+}
+
+/// Normalized names of installed distributions (`pip list --format=freeze`: "name==1.2.3")
+fn pip_installed() -> Vec<String> {
+    let pip = if command_exists("pip3") { "pip3" } else { "pip" };
+    listing("pip", pip, &["list", "--format=freeze"])
+        .lines()
+        .filter_map(|l| l.split("==").next())
+        .map(|n| normalize_pip(n.trim()))
+        .collect()
+}
+
+/// Names of globally installed npm packages (`npm ls -g --depth=0 --json`)
+fn npm_installed() -> Vec<String> {
+    let stdout = listing("npm", "npm", &["ls", "-g", "--depth=0", "--json"]);
+    serde_json::from_str::<serde_json::Value>(&stdout)
+        .ok()
+        .and_then(|v| v.get("dependencies")?.as_object().map(|deps| deps.keys().cloned().collect()))
+        .unwrap_or_default()
+}
+
+/// Names not in `pip show` output. pip only fails when none are found, so
+/// compare against the "Name:" lines (pip normalizes case and -/_/.).
+fn missing_from_show(output: Option<std::process::Output>, names: &[String]) -> Vec<String> {
+    let stdout = output.map(|o| String::from_utf8_lossy(&o.stdout).to_string()).unwrap_or_default();
+    let shown: Vec<String> = stdout
+        .lines()
+        .filter_map(|l| l.strip_prefix("Name:"))
+        .map(|n| normalize_pip(n.trim()))
+        .collect();
+    names.iter().filter(|n| !shown.contains(&normalize_pip(n))).cloned().collect()
+}
+
 // =============================================================================
 // PIPX
 // =============================================================================
 
 pub struct PipxProvider;
 
+impl PipxProvider {
+    /// Options carried in the item's value: "<include_deps>\x00<inject>,<inject>..."
+    fn options(state: &StateItem) -> (bool, Vec<String>) {
+        let Some((include_deps, inject)) = state.value.as_deref().and_then(|v| v.split_once('\x00')) else {
+            return (false, Vec::new());
+        };
+        let inject = inject.split(',').filter(|s| !s.is_empty()).map(str::to_string).collect();
+        (include_deps == "true", inject)
+    }
+
+    fn is_installed(name: &str) -> bool {
+        // pipx list --short outputs "package_name 1.2.3" per line
+        listing("pipx", "pipx", &["list", "--short"])
+            .lines()
+            .any(|l| l.split_whitespace().next() == Some(name))
+    }
+
+    /// Injected packages missing from the app's venv
+    fn missing_injects(name: &str, inject: &[String]) -> Vec<String> {
+        if inject.is_empty() {
+            return Vec::new();
+        }
+        let names: Vec<String> = inject.iter().map(|s| pip_name(s)).collect();
+        let mut args = vec!["runpip", name, "show"];
+        args.extend(names.iter().map(String::as_str));
+        missing_from_show(run_cmd("pipx", &args).ok(), &names)
+    }
+
+    /// Commands that bring the item up to date: install, then inject
+    fn commands(state: &StateItem) -> Vec<Vec<String>> {
+        let (spec, name) = pip_parse_spec(&state.key);
+        let (include_deps, inject) = Self::options(state);
+        let mut cmds = Vec::new();
+        if !Self::is_installed(&name) {
+            let mut install = vec!["install".to_string()];
+            if include_deps {
+                install.push("--include-deps".to_string());
+            }
+            install.push(spec);
+            cmds.push(install);
+        }
+        if !inject.is_empty() {
+            let mut cmd = vec!["inject".to_string(), name];
+            cmd.extend(inject);
+            cmds.push(cmd);
+        }
+        cmds
+    }
+}
+
 impl Provider for PipxProvider {
     fn name(&self) -> &'static str {
         "package.pipx"
@@ -461,35 +772,43 @@ impl Provider for PipxProvider {
     }
 
     fn check(&self, state: &StateItem) -> Result<CheckResult> {
-        let (pkg_name, _) = crate::util::parse_spec(&state.key);
-        // pipx list --short outputs "package_name 1.2.3" per line
-        if let Ok(output) = run_cmd("pipx", &["list", "--short"]) {
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            if stdout.lines().any(|l| {
-                l.split_whitespace().next().map(|name| name == pkg_name).unwrap_or(false)
-            }) {
-                return Ok(CheckResult::Satisfied);
-            }
+        let (_, name) = pip_parse_spec(&state.key);
+        if !Self::is_installed(&name) {
+            return Ok(CheckResult::Missing {
+                detail: format!("pipx package '{}' not installed", name),
+            });
+        }
+        let missing = Self::missing_injects(&name, &Self::options(state).1);
+        if !missing.is_empty() {
+            return Ok(CheckResult::Missing {
+                detail: format!("pipx package '{}' missing injected {}", name, missing.join(", ")),
+            });
         }
-        Ok(CheckResult::Missing {
-            detail: format!("pipx package '{}' not installed", pkg_name),
-        })
+        Ok(CheckResult::Satisfied)
     }
 
     fn apply(&self, state: &StateItem) -> Result<()> {
-        let (pkg_name, _) = crate::util::parse_spec(&state.key);
-        let output = run_cmd("pipx", &["install", &pkg_name])?;
-        if !output.status.success() {
-            bail!("pipx install failed: {}", String::from_utf8_lossy(&output.stderr));
+        let commands = Self::commands(state);
+        forget_listing("pipx");
+        for args in commands {
+            let args: Vec<&str> = args.iter().map(String::as_str).collect();
+            let output = run_cmd_retry("pipx", &args)?;
+            if !output.status.success() {
+                bail!("pipx {} failed: {}", args[0], String::from_utf8_lossy(&output.stderr));
+            }
         }
         Ok(())
     }
 
     fn apply_live(&self, state: &StateItem, pb: &ProgressBar) -> Result<()> {
-        let (pkg_name, _) = crate::util::parse_spec(&state.key);
-        let output = run_cmd_live("pipx", &["install", &pkg_name], pb)?;
-        if !output.status.success() {
-            bail!("pipx install failed: {}", String::from_utf8_lossy(&output.stderr));
+        let commands = Self::commands(state);
+        forget_listing("pipx");
+        for args in commands {
+            let args: Vec<&str> = args.iter().map(String::as_str).collect();
+            let output = run_cmd_live_retry("pipx", &args, pb)?;
+            if !output.status.success() {
+                bail!("pipx {} failed: {}", args[0], String::from_utf8_lossy(&output.stderr));
+            }
         }
         Ok(())
     }
@@ -0,0 +1,119 @@
+use super::system::write_root_file;
+use super::{CheckResult, Provider, StateItem};
+use crate::util::{command_exists, run_sudo};
+use anyhow::{bail, Result};
+
+const PACMAN_CONF: &str = "/etc/pacman.conf";
+
+/// Section body lines for a `[[pacman_repo]]`. The item value packs sig_level,
+/// include, then the servers — separated by \x00
+fn section_body(state: &StateItem) -> Vec<String> {
+    let value = state.value.as_deref().unwrap_or("");
+    let mut parts = value.split('\x00');
+    let sig_level = parts.next().unwrap_or("");
+    let include = parts.next().unwrap_or("");
+
+    let mut body = Vec::new();
+    if !sig_level.is_empty() {
+        body.push(format!("SigLevel = {}", sig_level));
+    }
+    body.extend(parts.filter(|s| !s.is_empty()).map(|s| format!("Server = {}", s)));
+    if !include.is_empty() {
+        body.push(format!("Include = {}", include));
+    }
+    body
+}
+
+/// Line range of `[name]`: its header up to the next section (or EOF)
+fn section_range(lines: &[&str], name: &str) -> Option<(usize, usize)> {
+    let header = format!("[{}]", name);
+    let start = lines.iter().position(|l| l.trim() == header)?;
+    let end = lines[start + 1..]
+        .iter()
+        .position(|l| l.trim_start().starts_with('['))
+        .map_or(lines.len(), |i| start + 1 + i);
+    Some((start, end))
+}
+
+/// `Key = value` with the spacing around `=` normalized
+fn normalize(line: &str) -> String {
+    match line.split_once('=') {
+        Some((k, v)) => format!("{} = {}", k.trim(), v.trim()),
+        None => line.trim().to_string(),
+    }
+}
+
+pub struct PacmanRepoProvider;
+
+impl Provider for PacmanRepoProvider {
+    fn name(&self) -> &'static str {
+        "pacman_repo"
+    }
+
+    fn needs_sudo(&self) -> bool {
+        true
+    }
+
+    fn check(&self, state: &StateItem) -> Result<CheckResult> {
+        let conf = std::fs::read_to_string(PACMAN_CONF).unwrap_or_default();
+        let lines: Vec<&str> = conf.lines().collect();
+        let Some((start, end)) = section_range(&lines, &state.key) else {
+            return Ok(CheckResult::Missing { detail: format!("not in {}", PACMAN_CONF) });
+        };
+        let current: Vec<String> = lines[start + 1..end]
+            .iter()
+            .map(|l| l.trim())
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(normalize)
+            .collect();
+        if current == section_body(state) {
+            Ok(CheckResult::Satisfied)
+        } else {
+            Ok(CheckResult::Missing { detail: format!("[{}] differs", state.key) })
+        }
+    }
+
+    fn apply(&self, state: &StateItem) -> Result<()> {
+        if !command_exists("pacman") {
+            bail!("pacman_repo needs pacman");
+        }
+        let conf = std::fs::read_to_string(PACMAN_CONF)?;
+        let lines: Vec<&str> = conf.lines().collect();
+        let header = format!("[{}]", state.key);
+        let body = section_body(state);
+        let block = std::iter::once(header.as_str()).chain(body.iter().map(String::as_str));
+
+        // Replace the section where it is (order is priority), else append it
+        let mut out: Vec<&str> = Vec::new();
+        match section_range(&lines, &state.key) {
+            Some((start, end)) => {
+                // Blank lines and comments trailing the section belong to what follows
+                let end = (start + 1..end)
+                    .rev()
+                    .find(|&i| !lines[i].trim().is_empty() && !lines[i].trim_start().starts_with('#'))
+                    .map_or(start + 1, |i| i + 1);
+                out.extend(&lines[..start]);
+                out.extend(block);
+                out.extend(&lines[end..]);
+            }
+            None => {
+                out.extend(&lines);
+                while out.last().is_some_and(|l| l.trim().is_empty()) {
+                    out.pop();
+                }
+                out.push("");
+                out.extend(block);
+            }
+        }
+        write_root_file(PACMAN_CONF, out.join("\n") + "\n")?;
+
+        // Fetch the new repo's database so its packages can be installed.
+        // A bare -Sy followed by installs is a partial upgrade, which Arch
+        // doesn't support, so upgrade along with the refresh.
+        let output = run_sudo("pacman", &["-Syu", "--noconfirm"])?;
+        if !output.status.success() {
+            bail!("pacman -Syu failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+        }
+        Ok(())
+    }
+}
@@ -1,6 +1,6 @@
 use super::{CheckResult, Provider, StateItem};
 use crate::util::expand_path;
-use anyhow::Result;
+use anyhow::{bail, Result};
 use std::fs;
 use std::os::unix::fs::PermissionsExt;
 use std::path::PathBuf;
@@ -27,16 +27,24 @@ impl Provider for ScriptProvider {
             });
         }
 
-        // Check if content matches (if we have source content)
+        // Check if content matches (if we have source content). Compared as
+        // bytes, so an installed file that isn't UTF-8 still counts as different.
         if let Some(source_content) = &state.value {
-            let target_content = fs::read_to_string(&target).unwrap_or_default();
-            if target_content != *source_content {
+            let target_content = fs::read(&target).unwrap_or_default();
+            if target_content != source_content.as_bytes() {
                 return Ok(CheckResult::Missing {
                     detail: "content differs".to_string(),
                 });
             }
         }
 
+        let mode = fs::metadata(&target)?.permissions().mode() & 0o777;
+        if mode != 0o755 {
+            return Ok(CheckResult::Missing {
+                detail: format!("mode {:o}, want 755", mode),
+            });
+        }
+
         Ok(CheckResult::Satisfied)
     }
 
@@ -45,6 +53,11 @@ impl Provider for ScriptProvider {
         let content = state.value.as_ref()
             .ok_or_else(|| anyhow::anyhow!("Script '{}' missing content", state.key))?;
 
+        // Without one, running it by name fails with "Exec format error"
+        if !content.starts_with("#!") {
+            bail!("Script '{}' has no shebang line (e.g. #!/usr/bin/env bash)", state.key);
+        }
+
         // Ensure ~/.local/bin exists
         if let Some(parent) = target.parent() {
             fs::create_dir_all(parent)?;
@@ -60,4 +73,13 @@ impl Provider for ScriptProvider {
 
         Ok(())
     }
+
+    fn diff(&self, state: &StateItem) -> Result<Option<String>> {
+        let Some(desired) = &state.value else {
+            return Ok(None);
+        };
+        let target = Self::target_path(&state.key);
+        let current = fs::read(&target).map(|b| String::from_utf8_lossy(&b).into_owned()).unwrap_or_default();
+        Ok(crate::util::unified_diff(&target, &current, desired))
+    }
 }
@@ -1,7 +1,8 @@
 use super::{CheckResult, Provider, StateItem};
-use crate::util::expand_path;
-use anyhow::{Context, Result};
+use crate::util::{expand_path, Shell};
+use anyhow::{bail, Context, Result};
 use std::fs;
+use std::path::{Path, PathBuf};
 
 /// Configuration for shell variable providers (aliases and env vars)
 struct ShellVarConfig {
@@ -31,6 +32,88 @@ const ENV_CONFIG: ShellVarConfig = ShellVarConfig {
     format_prefix: |k| format!("export {}=", k),
 };
 
+const FISH_ALIAS_CONFIG: ShellVarConfig = ShellVarConfig {
+    name: "alias",
+    file: "~/.dek_aliases.fish",
+    source_line: "test -f ~/.dek_aliases.fish; and source ~/.dek_aliases.fish",
+    header: "# dek-managed aliases\n",
+    format_line: |k, v| format!("abbr -a {} '{}'", k, fish_quoted(v)),
+    format_prefix: |k| format!("abbr -a {} ", k),
+};
+
+const FISH_ENV_CONFIG: ShellVarConfig = ShellVarConfig {
+    name: "env",
+    file: "~/.dek_env.fish",
+    source_line: "test -f ~/.dek_env.fish; and source ~/.dek_env.fish",
+    header: "# dek-managed environment variables\n",
+    format_line: |k, v| format!("set -gx {} '{}'", k, fish_quoted(v)),
+    format_prefix: |k| format!("set -gx {} ", k),
+};
+
+// nushell's `source` needs the file at parse time; apply writes it before
+// adding the source line
+const NU_ALIAS_CONFIG: ShellVarConfig = ShellVarConfig {
+    name: "alias",
+    file: "~/.dek_aliases.nu",
+    source_line: "source ~/.dek_aliases.nu",
+    header: "# dek-managed aliases\n",
+    format_line: |k, v| format!("alias {} = {}", k, v),
+    format_prefix: |k| format!("alias {} = ", k),
+};
+
+const NU_ENV_CONFIG: ShellVarConfig = ShellVarConfig {
+    name: "env",
+    file: "~/.dek_env.nu",
+    source_line: "source ~/.dek_env.nu",
+    header: "# dek-managed environment variables\n",
+    format_line: |k, v| format!("$env.{} = r#'{}'#", k, v),
+    format_prefix: |k| format!("$env.{} = ", k),
+};
+
+const PWSH_ALIAS_CONFIG: ShellVarConfig = ShellVarConfig {
+    name: "alias",
+    file: "~/.dek_aliases.ps1",
+    source_line: "if (Test-Path ~/.dek_aliases.ps1) { . ~/.dek_aliases.ps1 }",
+    header: "# dek-managed aliases\n",
+    format_line: |k, v| format!("function {} {{ {} @args }}", k, v),
+    format_prefix: |k| format!("function {} {{", k),
+};
+
+const PWSH_ENV_CONFIG: ShellVarConfig = ShellVarConfig {
+    name: "env",
+    file: "~/.dek_env.ps1",
+    source_line: "if (Test-Path ~/.dek_env.ps1) { . ~/.dek_env.ps1 }",
+    header: "# dek-managed environment variables\n",
+    format_line: |k, v| format!("$env:{} = '{}'", k, v.replace('\'', "''")),
+    format_prefix: |k| format!("$env:{} = ", k),
+};
+
+/// Alias file settings for the user's shell
+fn alias_config() -> &'static ShellVarConfig {
+    match Shell::detect() {
+        Shell::Zsh | Shell::Bash => &ALIAS_CONFIG,
+        Shell::Fish => &FISH_ALIAS_CONFIG,
+        Shell::Nushell => &NU_ALIAS_CONFIG,
+        Shell::Pwsh => &PWSH_ALIAS_CONFIG,
+    }
+}
+
+/// Env file settings for the user's shell
+fn env_config() -> &'static ShellVarConfig {
+    match Shell::detect() {
+        Shell::Zsh | Shell::Bash => &ENV_CONFIG,
+        Shell::Fish => &FISH_ENV_CONFIG,
+        Shell::Nushell => &NU_ENV_CONFIG,
+        Shell::Pwsh => &PWSH_ENV_CONFIG,
+    }
+}
+
+/// Escape for a fish single-quoted string. Env values are already expanded
+/// by dek, so they're written literally.
+fn fish_quoted(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('\'', "\\'")
+}
+
 fn check_shell_var(cfg: &ShellVarConfig, state: &StateItem) -> Result<CheckResult> {
     let file_path = expand_path(cfg.file);
     let key = &state.key;
@@ -55,19 +138,22 @@ fn check_shell_var(cfg: &ShellVarConfig, state: &StateItem) -> Result<CheckResul
     }
 }
 
-fn apply_shell_var(cfg: &ShellVarConfig, state: &StateItem) -> Result<()> {
+/// Current and new content of the alias/env file with this item's line
+/// (re)defined. Current is empty when the file doesn't exist yet.
+fn shell_var_content(cfg: &ShellVarConfig, state: &StateItem) -> Result<(PathBuf, String, String)> {
     let file_path = expand_path(cfg.file);
     let key = &state.key;
     let value = state.value.as_deref().unwrap_or("");
     let new_line = (cfg.format_line)(key, value);
     let prefix = (cfg.format_prefix)(key);
 
-    let content = if file_path.exists() {
+    let current = if file_path.exists() {
         fs::read_to_string(&file_path)
             .with_context(|| format!("failed to read: {}", file_path.display()))?
     } else {
-        String::from(cfg.header)
+        String::new()
     };
+    let content = if file_path.exists() { current.as_str() } else { cfg.header };
 
     // Remove existing definition if present
     let lines: Vec<_> = content
@@ -82,6 +168,12 @@ fn apply_shell_var(cfg: &ShellVarConfig, state: &StateItem) -> Result<()> {
     new_content.push_str(&new_line);
     new_content.push('\n');
 
+    Ok((file_path, current, new_content))
+}
+
+fn apply_shell_var(cfg: &ShellVarConfig, state: &StateItem) -> Result<()> {
+    let (file_path, _, new_content) = shell_var_content(cfg, state)?;
+
     fs::write(&file_path, &new_content)
         .with_context(|| format!("failed to write: {}", file_path.display()))?;
 
@@ -89,19 +181,70 @@ fn apply_shell_var(cfg: &ShellVarConfig, state: &StateItem) -> Result<()> {
     Ok(())
 }
 
+fn diff_shell_var(cfg: &ShellVarConfig, state: &StateItem) -> Result<Option<String>> {
+    // apply rewrites the line at the end; don't report that as a change
+    if check_shell_var(cfg, state)?.is_satisfied() {
+        return Ok(None);
+    }
+    let (file_path, current, new_content) = shell_var_content(cfg, state)?;
+    Ok(crate::util::unified_diff(&file_path, &current, &new_content))
+}
+
+/// `apply --prune`: drop the lines of the alias and env files that no item in
+/// `items` accounts for (definitions of names no longer configured, PATH
+/// lines for dirs no longer listed). Comments and blank lines stay. Returns
+/// the (file, line) pairs removed, or that would be with `dry_run`.
+pub fn prune_lines(items: &[StateItem], dry_run: bool) -> Result<Vec<(PathBuf, String)>> {
+    let shell = Shell::detect();
+    let keys = |kind: &str| -> Vec<&str> { items.iter().filter(|i| i.kind == kind).map(|i| i.key.as_str()).collect() };
+    let path_lines: Vec<String> = items
+        .iter()
+        .filter(|i| i.kind == "path")
+        .flat_map(|i| [path_line(shell, &i.key, false), path_line(shell, &i.key, true)])
+        .collect();
+
+    let mut pruned = Vec::new();
+    for (cfg, keys) in [(alias_config(), keys("alias")), (env_config(), keys("env"))] {
+        let file_path = expand_path(cfg.file);
+        let Ok(content) = fs::read_to_string(&file_path) else {
+            continue;
+        };
+        let (keep, drop): (Vec<&str>, Vec<&str>) = content.lines().partition(|line| {
+            let trimmed = line.trim();
+            trimmed.is_empty()
+                || trimmed.starts_with('#')
+                || keys.iter().any(|k| line.starts_with(&(cfg.format_prefix)(k)))
+                || path_lines.iter().any(|p| p == line)
+        });
+        if drop.is_empty() {
+            continue;
+        }
+        if !dry_run {
+            fs::write(&file_path, keep.join("\n") + "\n")
+                .with_context(|| format!("failed to write: {}", file_path.display()))?;
+        }
+        pruned.extend(drop.into_iter().map(|l| (file_path.clone(), l.to_string())));
+    }
+    Ok(pruned)
+}
+
 pub struct AliasProvider;
 
 impl Provider for AliasProvider {
     fn name(&self) -> &'static str {
-        ALIAS_CONFIG.name
+        alias_config().name
     }
 
     fn check(&self, state: &StateItem) -> Result<CheckResult> {
-        check_shell_var(&ALIAS_CONFIG, state)
+        check_shell_var(alias_config(), state)
     }
 
     fn apply(&self, state: &StateItem) -> Result<()> {
-        apply_shell_var(&ALIAS_CONFIG, state)
+        apply_shell_var(alias_config(), state)
+    }
+
+    fn diff(&self, state: &StateItem) -> Result<Option<String>> {
+        diff_shell_var(alias_config(), state)
     }
 }
 
@@ -109,15 +252,136 @@ pub struct EnvProvider;
 
 impl Provider for EnvProvider {
     fn name(&self) -> &'static str {
-        ENV_CONFIG.name
+        env_config().name
     }
 
     fn check(&self, state: &StateItem) -> Result<CheckResult> {
-        check_shell_var(&ENV_CONFIG, state)
+        // A failing cmd is this item's problem; apply reports it as a failure
+        match resolve_env(state) {
+            Ok(resolved) => check_shell_var(env_config(), &resolved),
+            Err(e) => Ok(CheckResult::Missing { detail: e.to_string() }),
+        }
     }
 
     fn apply(&self, state: &StateItem) -> Result<()> {
-        apply_shell_var(&ENV_CONFIG, state)
+        apply_shell_var(env_config(), &resolve_env(state)?)
+    }
+
+    fn diff(&self, state: &StateItem) -> Result<Option<String>> {
+        diff_shell_var(env_config(), &resolve_env(state)?)
+    }
+}
+
+/// The env item with its value final. A `{ cmd = "..." }` value arrives as
+/// "\x00<cmd>"; the command runs now and its trimmed stdout is the value.
+fn resolve_env(state: &StateItem) -> Result<StateItem> {
+    let Some(cmd) = state.value.as_deref().and_then(|v| v.strip_prefix('\x00')) else {
+        return Ok(state.clone());
+    };
+    let output = crate::util::shell_cmd(cmd)
+        .output()
+        .with_context(|| format!("failed to run cmd for env {}", state.key))?;
+    if !output.status.success() {
+        bail!(
+            "env {}: `{}` failed (exit {}): {}",
+            state.key,
+            cmd,
+            output.status.code().unwrap_or(-1),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let mut resolved = state.clone();
+    resolved.value = Some(String::from_utf8_lossy(&output.stdout).trim().to_string());
+    Ok(resolved)
+}
+
+/// PATH entry line for the env file: `state.value` is "prepend" or "append"
+fn path_line(shell: Shell, dir: &str, append: bool) -> String {
+    match (shell, append) {
+        (Shell::Zsh | Shell::Bash, false) => format!("export PATH=\"{}:$PATH\"", dir),
+        (Shell::Zsh | Shell::Bash, true) => format!("export PATH=\"$PATH:{}\"", dir),
+        (Shell::Fish, false) => format!("set -gx PATH '{}' $PATH", fish_quoted(dir)),
+        (Shell::Fish, true) => format!("set -gx PATH $PATH '{}'", fish_quoted(dir)),
+        (Shell::Nushell, false) => format!("$env.PATH = ($env.PATH | prepend r#'{}'#)", dir),
+        (Shell::Nushell, true) => format!("$env.PATH = ($env.PATH | append r#'{}'#)", dir),
+        (Shell::Pwsh, false) => format!("$env:PATH = '{}' + [IO.Path]::PathSeparator + $env:PATH", dir.replace('\'', "''")),
+        (Shell::Pwsh, true) => format!("$env:PATH = $env:PATH + [IO.Path]::PathSeparator + '{}'", dir.replace('\'', "''")),
+    }
+}
+
+/// Current and new env file content with `dir` (re)placed on PATH
+fn path_content(state: &StateItem) -> Result<(PathBuf, String, String)> {
+    let shell = Shell::detect();
+    let cfg = env_config();
+    let file_path = expand_path(cfg.file);
+    let append = state.value.as_deref() == Some("append");
+    let new_line = path_line(shell, &state.key, append);
+    let other_line = path_line(shell, &state.key, !append);
+
+    let current = if file_path.exists() {
+        fs::read_to_string(&file_path)
+            .with_context(|| format!("failed to read: {}", file_path.display()))?
+    } else {
+        String::new()
+    };
+    let content = if file_path.exists() { current.as_str() } else { cfg.header };
+
+    let lines: Vec<_> = content.lines().filter(|l| *l != new_line && *l != other_line).collect();
+    let mut new_content = lines.join("\n");
+    if !new_content.is_empty() && !new_content.ends_with('\n') {
+        new_content.push('\n');
+    }
+    new_content.push_str(&new_line);
+    new_content.push('\n');
+
+    Ok((file_path, current, new_content))
+}
+
+/// `[path]` entries, written to the env file as PATH updates
+pub struct PathProvider;
+
+impl Provider for PathProvider {
+    fn name(&self) -> &'static str {
+        "path"
+    }
+
+    fn check(&self, state: &StateItem) -> Result<CheckResult> {
+        let file_path = expand_path(env_config().file);
+        let append = state.value.as_deref() == Some("append");
+        let expected = path_line(Shell::detect(), &state.key, append);
+        let content = if file_path.exists() {
+            fs::read_to_string(&file_path)
+                .with_context(|| format!("failed to read: {}", file_path.display()))?
+        } else {
+            String::new()
+        };
+
+        if content.lines().any(|l| l == expected) {
+            Ok(CheckResult::Satisfied)
+        } else {
+            Ok(CheckResult::Missing {
+                detail: format!("'{}' not on PATH in '{}'", state.key, file_path.display()),
+            })
+        }
+    }
+
+    fn apply(&self, state: &StateItem) -> Result<()> {
+        let (file_path, _, new_content) = path_content(state)?;
+        fs::write(&file_path, &new_content)
+            .with_context(|| format!("failed to write: {}", file_path.display()))?;
+        ensure_sourced_in_rc(env_config().source_line)?;
+
+        // Later items in this run (and their commands) see it too
+        crate::util::add_to_path(Path::new(&state.key), state.value.as_deref() == Some("append"));
+        Ok(())
+    }
+
+    fn diff(&self, state: &StateItem) -> Result<Option<String>> {
+        if self.check(state)?.is_satisfied() {
+            return Ok(None);
+        }
+        let (file_path, current, new_content) = path_content(state)?;
+        Ok(crate::util::unified_diff(&file_path, &current, &new_content))
     }
 }
 
@@ -143,6 +407,10 @@ fn ensure_sourced_in_rc(line: &str) -> Result<()> {
     new_content.push_str(line);
     new_content.push('\n');
 
+    if let Some(parent) = rc_path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create parent dirs for: {}", rc_path.display()))?;
+    }
     fs::write(&rc_path, &new_content)
         .with_context(|| format!("failed to write: {}", rc_path.display()))?;
 
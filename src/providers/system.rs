@@ -0,0 +1,341 @@
+use super::{CheckResult, Provider, StateItem};
+use crate::util::{command_exists, run_cmd, run_sudo};
+use anyhow::{bail, Result};
+use std::path::Path;
+
+pub struct TimezoneProvider;
+
+impl Provider for TimezoneProvider {
+    fn name(&self) -> &'static str {
+        "timezone"
+    }
+
+    fn needs_sudo(&self) -> bool {
+        true
+    }
+
+    fn check(&self, state: &StateItem) -> Result<CheckResult> {
+        let current = current_timezone();
+        if current.as_deref() == Some(state.key.as_str()) {
+            Ok(CheckResult::Satisfied)
+        } else {
+            Ok(CheckResult::Missing {
+                detail: format!("timezone is {}", current.as_deref().unwrap_or("unknown")),
+            })
+        }
+    }
+
+    fn apply(&self, state: &StateItem) -> Result<()> {
+        let tz = &state.key;
+        let zoneinfo = Path::new("/usr/share/zoneinfo").join(tz);
+        if !zoneinfo.is_file() {
+            bail!("unknown timezone '{}' (no {})", tz, zoneinfo.display());
+        }
+
+        // timedatectl needs a running systemd; containers get the symlink
+        let output = if command_exists("timedatectl") && run_cmd("timedatectl", &["show"])?.status.success() {
+            run_sudo("timedatectl", &["set-timezone", tz])?
+        } else {
+            run_sudo("ln", &["-sf", &zoneinfo.to_string_lossy(), "/etc/localtime"])?
+        };
+        if !output.status.success() {
+            bail!("failed to set timezone: {}", String::from_utf8_lossy(&output.stderr).trim());
+        }
+        Ok(())
+    }
+}
+
+/// Timezone from timedatectl, else the /etc/localtime symlink
+fn current_timezone() -> Option<String> {
+    if let Ok(output) = run_cmd("timedatectl", &["show", "-p", "Timezone", "--value"]) {
+        let tz = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if output.status.success() && !tz.is_empty() {
+            return Some(tz);
+        }
+    }
+    let target = std::fs::read_link("/etc/localtime").ok()?;
+    let target = target.to_string_lossy();
+    target.split_once("zoneinfo/").map(|(_, tz)| tz.to_string())
+}
+
+pub struct HostnameProvider;
+
+impl Provider for HostnameProvider {
+    fn name(&self) -> &'static str {
+        "hostname"
+    }
+
+    fn needs_sudo(&self) -> bool {
+        true
+    }
+
+    fn check(&self, state: &StateItem) -> Result<CheckResult> {
+        let current = current_hostname();
+        if current == state.key {
+            Ok(CheckResult::Satisfied)
+        } else {
+            Ok(CheckResult::Missing { detail: format!("hostname is {}", current) })
+        }
+    }
+
+    fn apply(&self, state: &StateItem) -> Result<()> {
+        let name = &state.key;
+        let valid = !name.is_empty()
+            && name.len() <= 253
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.');
+        if !valid {
+            bail!("invalid hostname '{}'", name);
+        }
+
+        let output = if command_exists("hostnamectl") && run_cmd("hostnamectl", &["--static"])?.status.success() {
+            run_sudo("hostnamectl", &["set-hostname", name])?
+        } else {
+            let script = format!("echo {} > /etc/hostname && hostname {}", name, name);
+            run_sudo("sh", &["-c", &script])?
+        };
+        if !output.status.success() {
+            bail!("failed to set hostname: {}", String::from_utf8_lossy(&output.stderr).trim());
+        }
+        Ok(())
+    }
+}
+
+/// Static hostname from hostnamectl, else /etc/hostname, else the kernel's
+fn current_hostname() -> String {
+    if let Ok(output) = run_cmd("hostnamectl", &["--static"]) {
+        let name = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if output.status.success() && !name.is_empty() {
+            return name;
+        }
+    }
+    std::fs::read_to_string("/etc/hostname")
+        .map(|s| s.trim().to_string())
+        .ok()
+        .filter(|s| !s.is_empty())
+        .or_else(|| hostname::get().ok().map(|h| h.to_string_lossy().to_string()))
+        .unwrap_or_default()
+}
+
+/// Drop-in that keeps `[sysctl]` values across reboots
+const SYSCTL_DROP_IN: &str = "/etc/sysctl.d/99-dek.conf";
+
+pub struct SysctlProvider;
+
+impl Provider for SysctlProvider {
+    fn name(&self) -> &'static str {
+        "sysctl"
+    }
+
+    fn needs_sudo(&self) -> bool {
+        true
+    }
+
+    fn check(&self, state: &StateItem) -> Result<CheckResult> {
+        let key = &state.key;
+        let want = normalize_sysctl(state.value.as_deref().unwrap_or(""));
+        let output = run_cmd("sysctl", &["-n", key])?;
+        if !output.status.success() {
+            return Ok(CheckResult::Missing { detail: format!("unknown sysctl key '{}'", key) });
+        }
+        let current = normalize_sysctl(&String::from_utf8_lossy(&output.stdout));
+        if current != want {
+            return Ok(CheckResult::Missing { detail: format!("{} is {}", key, current) });
+        }
+
+        let persisted = std::fs::read_to_string(SYSCTL_DROP_IN).unwrap_or_default();
+        if !persisted.lines().any(|l| l == sysctl_line(key, &want)) {
+            return Ok(CheckResult::Missing { detail: format!("not persisted in {}", SYSCTL_DROP_IN) });
+        }
+        Ok(CheckResult::Satisfied)
+    }
+
+    fn apply(&self, state: &StateItem) -> Result<()> {
+        let key = &state.key;
+        let value = normalize_sysctl(state.value.as_deref().unwrap_or(""));
+
+        let output = run_sudo("sysctl", &["-w", &format!("{}={}", key, value)])?;
+        if !output.status.success() {
+            bail!("sysctl -w failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+        }
+
+        // Replace this key's line in the drop-in, keeping the others
+        let current = std::fs::read_to_string(SYSCTL_DROP_IN).unwrap_or_default();
+        let mut lines: Vec<String> = current
+            .lines()
+            .filter(|l| l.split('=').next().map(str::trim) != Some(key.as_str()))
+            .map(String::from)
+            .collect();
+        if lines.is_empty() {
+            lines.push("# dek-managed kernel parameters".to_string());
+        }
+        lines.push(sysctl_line(key, &value));
+        if !Path::new("/etc/sysctl.d").is_dir() {
+            run_sudo("mkdir", &["-p", "/etc/sysctl.d"])?;
+        }
+        write_root_file(SYSCTL_DROP_IN, &(lines.join("\n") + "\n"))
+    }
+}
+
+/// sysctl prints multi-value keys tab-separated; compare on single spaces
+fn normalize_sysctl(value: &str) -> String {
+    value.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn sysctl_line(key: &str, value: &str) -> String {
+    format!("{} = {}", key, value)
+}
+
+/// Write a file owned by root: directly when we can, else through `run_sudo`.
+/// A new file is world-readable (0644), like the config files this writes.
+pub fn write_root_file(path: &str, content: impl AsRef<[u8]>) -> Result<()> {
+    let content = content.as_ref();
+    if std::fs::write(path, content).is_ok() {
+        return Ok(());
+    }
+    // Stage it as us, then copy it into place as root
+    let mut staged = tempfile::NamedTempFile::new()?;
+    std::io::Write::write_all(&mut staged, content)?;
+    staged.as_file().set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o644))?;
+    let output = crate::util::run_sudo("cp", &[&staged.path().to_string_lossy(), path])?;
+    if !output.status.success() {
+        bail!("failed to write {}: {}", path, String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(())
+}
+
+pub struct UserProvider;
+
+/// `[[user]]` settings packed into the item value: groups (comma-separated),
+/// shell, home, system — separated by \x00
+struct UserSpec {
+    groups: Vec<String>,
+    shell: Option<String>,
+    home: Option<String>,
+    system: bool,
+}
+
+impl UserSpec {
+    fn parse(state: &StateItem) -> Self {
+        let value = state.value.as_deref().unwrap_or("");
+        let parts: Vec<&str> = value.split('\x00').collect();
+        let field = |i: usize| parts.get(i).filter(|s| !s.is_empty()).map(|s| s.to_string());
+        Self {
+            groups: field(0).map(|g| g.split(',').map(String::from).collect()).unwrap_or_default(),
+            shell: field(1),
+            home: field(2),
+            system: parts.get(3) == Some(&"true"),
+        }
+    }
+}
+
+/// passwd entry fields (name:x:uid:gid:gecos:home:shell), None if no such user
+fn getent_passwd(name: &str) -> Result<Option<Vec<String>>> {
+    let output = run_cmd("getent", &["passwd", name])?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+    let line = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    Ok(Some(line.split(':').map(String::from).collect()))
+}
+
+fn user_groups(name: &str) -> Result<Vec<String>> {
+    let output = run_cmd("id", &["-nG", name])?;
+    Ok(String::from_utf8_lossy(&output.stdout).split_whitespace().map(String::from).collect())
+}
+
+impl Provider for UserProvider {
+    fn name(&self) -> &'static str {
+        "user"
+    }
+
+    fn needs_sudo(&self) -> bool {
+        true
+    }
+
+    fn check(&self, state: &StateItem) -> Result<CheckResult> {
+        let name = &state.key;
+        let spec = UserSpec::parse(state);
+        let Some(entry) = getent_passwd(name)? else {
+            return Ok(CheckResult::Missing { detail: format!("user '{}' does not exist", name) });
+        };
+
+        if let Some(ref home) = spec.home {
+            if entry.get(5) != Some(home) {
+                return Ok(CheckResult::Missing {
+                    detail: format!("home is {}", entry.get(5).map(String::as_str).unwrap_or("")),
+                });
+            }
+        }
+        if let Some(ref shell) = spec.shell {
+            if entry.get(6) != Some(shell) {
+                return Ok(CheckResult::Missing {
+                    detail: format!("shell is {}", entry.get(6).map(String::as_str).unwrap_or("")),
+                });
+            }
+        }
+        let current = user_groups(name)?;
+        let missing: Vec<&str> = spec.groups.iter().filter(|g| !current.contains(g)).map(String::as_str).collect();
+        if !missing.is_empty() {
+            return Ok(CheckResult::Missing { detail: format!("not in {}", missing.join(", ")) });
+        }
+        Ok(CheckResult::Satisfied)
+    }
+
+    fn apply(&self, state: &StateItem) -> Result<()> {
+        let name = &state.key;
+        let spec = UserSpec::parse(state);
+
+        // Groups have to exist before useradd/usermod can add to them
+        for group in &spec.groups {
+            if !run_cmd("getent", &["group", group])?.status.success() {
+                let mut args = vec![group.as_str()];
+                if spec.system {
+                    args.insert(0, "--system");
+                }
+                sudo_ok(run_sudo("groupadd", &args)?, "groupadd")?;
+            }
+        }
+
+        let groups = spec.groups.join(",");
+        let mut args: Vec<&str> = Vec::new();
+        match getent_passwd(name)? {
+            None => {
+                args.push(if spec.system { "--system" } else { "--create-home" });
+                if let Some(ref shell) = spec.shell {
+                    args.extend(["--shell", shell]);
+                }
+                if let Some(ref home) = spec.home {
+                    args.extend(["--home-dir", home]);
+                }
+                if !groups.is_empty() {
+                    args.extend(["--groups", &groups]);
+                }
+                args.push(name);
+                sudo_ok(run_sudo("useradd", &args)?, "useradd")
+            }
+            Some(entry) => {
+                if let Some(shell) = spec.shell.as_ref().filter(|s| entry.get(6) != Some(s)) {
+                    args.extend(["--shell", shell]);
+                }
+                if let Some(home) = spec.home.as_ref().filter(|h| entry.get(5) != Some(h)) {
+                    args.extend(["--home", home, "--move-home"]);
+                }
+                if !groups.is_empty() {
+                    args.extend(["--append", "--groups", &groups]);
+                }
+                if args.is_empty() {
+                    return Ok(());
+                }
+                args.push(name);
+                sudo_ok(run_sudo("usermod", &args)?, "usermod")
+            }
+        }
+    }
+}
+
+fn sudo_ok(output: std::process::Output, what: &str) -> Result<()> {
+    if !output.status.success() {
+        bail!("{} failed: {}", what, String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(())
+}
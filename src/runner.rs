@@ -1,6 +1,6 @@
-use crate::config::Config;
+use crate::config::{when_matches, Config};
 use crate::output;
-use crate::providers::{resolve_requirements, ProviderRegistry, Requirement, StateItem};
+use crate::providers::{resolve_requirements, CheckResult, Policy, ProviderRegistry, Requirement, StateItem};
 use anyhow::{bail, Context, Result};
 use std::collections::{HashMap, HashSet};
 use std::path::Path;
@@ -12,21 +12,144 @@ pub enum Mode {
     Apply,
     Check,
     Plan,
+    Diff,
+}
+
+/// Provider kind filters (--only / --skip). A pattern matches a kind equal
+/// to it or nested under it: "file" matches "file.copy", "package.cargo"
+/// only itself.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    pub only: Vec<String>,
+    pub skip: Vec<String>,
+}
+
+impl Filter {
+    fn matches(pattern: &str, kind: &str) -> bool {
+        kind == pattern || kind.strip_prefix(pattern).is_some_and(|rest| rest.starts_with('.'))
+    }
+
+    pub fn allows(&self, kind: &str) -> bool {
+        (self.only.is_empty() || self.only.iter().any(|p| Self::matches(p, kind)))
+            && !self.skip.iter().any(|p| Self::matches(p, kind))
+    }
+
+    /// Flags to pass the same filter on to a remote dek
+    pub fn to_args(&self) -> String {
+        let mut args = String::new();
+        if !self.only.is_empty() {
+            args.push_str(&format!(" --only {}", self.only.join(",")));
+        }
+        if !self.skip.is_empty() {
+            args.push_str(&format!(" --skip {}", self.skip.join(",")));
+        }
+        args
+    }
 }
 
 pub struct Runner {
     registry: ProviderRegistry,
     mode: Mode,
+    out: Box<dyn output::Emitter>,
+    filter: Filter,
+    dry_run: bool,
+    exit_code: bool,
+    jobs: usize,
+    confirm_each: bool,
+    /// Skip the check of items unchanged since they were last applied
+    incremental: bool,
+    /// `check --explain`: items to explain instead of a full check
+    explain: Option<String>,
+    /// `apply --prune`: the whole config, to find what's no longer in it
+    prune: Option<Config>,
+    /// Install requirements by script without asking first
+    yes_requirements: bool,
+    /// Set once "a" is answered: stop prompting for the rest of the run
+    confirm_all: std::sync::atomic::AtomicBool,
 }
 
 impl Runner {
-    pub fn new(mode: Mode) -> Self {
+    pub fn new(mode: Mode, format: output::Format) -> Self {
         Self {
-            registry: ProviderRegistry::new(),
+            registry: ProviderRegistry::default(),
             mode,
+            out: output::emitter(format),
+            filter: Filter::default(),
+            dry_run: false,
+            exit_code: false,
+            jobs: 1,
+            confirm_each: false,
+            incremental: false,
+            explain: None,
+            prune: None,
+            yes_requirements: false,
+            confirm_all: std::sync::atomic::AtomicBool::new(false),
         }
     }
 
+    /// Install settings from the config's `[meta]` (AUR helper, cargo
+    /// strategy, install script pins)
+    pub fn with_policy(mut self, policy: Policy) -> Self {
+        self.registry = ProviderRegistry::new(policy);
+        self
+    }
+
+    pub fn with_filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Apply mode only: check every item and report what would change, but
+    /// stop short of installing requirements or calling `apply_live`
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Exit with status 2 from check when anything is missing
+    pub fn with_exit_code(mut self, exit_code: bool) -> Self {
+        self.exit_code = exit_code;
+        self
+    }
+
+    /// Apply independent items (different package managers, files, aliases,
+    /// env) on up to `jobs` threads
+    pub fn with_jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs.max(1);
+        self
+    }
+
+    /// Prompt `[y/N/a]` before applying each item that needs a change
+    pub fn with_confirm_each(mut self, confirm_each: bool) -> Self {
+        self.confirm_each = confirm_each;
+        self
+    }
+
+    /// Skip checking the items unchanged since their last apply
+    pub fn with_incremental(mut self, incremental: bool) -> Self {
+        self.incremental = incremental;
+        self
+    }
+
+    /// In check mode, explain the items matching this spec instead
+    pub fn with_explain(mut self, explain: Option<String>) -> Self {
+        self.explain = explain;
+        self
+    }
+
+    /// After applying, remove what `all` no longer defines: alias/env/PATH
+    /// lines dek wrote, and (asking first) files it copied or linked
+    pub fn with_prune(mut self, all: Option<Config>) -> Self {
+        self.prune = all;
+        self
+    }
+
+    /// Skip the prompt before installing requirements by script
+    pub fn with_yes_requirements(mut self, yes: bool) -> Self {
+        self.yes_requirements = yes;
+        self
+    }
+
     pub fn run(&self, config: &Config, config_path: &Path) -> Result<()> {
         // Apply proxy settings early so all commands inherit them
         if let Some(ref proxy) = config.proxy {
@@ -38,32 +161,132 @@ impl Runner {
         } else {
             config_path
         };
-        let items = collect_state_items(config, base_dir);
-        self.run_items(&items)
+        // Secrets are only fetched when they'll be written, once per run
+        let fetch = matches!(self.mode, Mode::Apply) && !self.dry_run;
+        let secrets = crate::config::load_meta(base_dir)?
+            .and_then(|m| m.secrets)
+            .map(|s| crate::secrets::Secrets::new(s, base_dir, fetch).value());
+        let items = collect_state_items(config, base_dir, secrets.as_ref());
+        self.run_items(&items)?;
+        if let (Mode::Apply, Some(all)) = (self.mode, &self.prune) {
+            self.prune(&collect_state_items(all, base_dir, secrets.as_ref()))?;
+        }
+        Ok(())
+    }
+
+    /// Remove leftovers of items no longer in `items`
+    fn prune(&self, items: &[StateItem]) -> Result<()> {
+        let lines = crate::providers::shell::prune_lines(items, self.dry_run)?;
+
+        let current: HashSet<std::path::PathBuf> = items
+            .iter()
+            .filter(|i| matches!(i.kind.as_str(), "file.copy" | "file.symlink"))
+            .filter_map(|i| i.value.as_deref().map(crate::util::expand_path))
+            .collect();
+        let mut files = Vec::new();
+        for (kind, dest) in crate::cache::managed() {
+            if current.contains(&dest) {
+                continue;
+            }
+            // Gone already, or no longer the link we made: nothing of ours to remove
+            match std::fs::symlink_metadata(&dest) {
+                Ok(meta) if kind != "file.symlink" || meta.file_type().is_symlink() => files.push((kind, dest, meta)),
+                _ => crate::cache::remove_managed(&dest),
+            }
+        }
+        if lines.is_empty() && files.is_empty() {
+            return Ok(());
+        }
+
+        println!();
+        for (file, line) in lines {
+            output::print_pruned("shell", &line, &file.display().to_string(), self.dry_run);
+        }
+        let interactive = std::io::IsTerminal::is_terminal(&std::io::stdin())
+            && std::io::IsTerminal::is_terminal(&std::io::stdout());
+        for (kind, dest, meta) in files {
+            let what = dest.display().to_string();
+            if self.dry_run {
+                output::print_pruned(&kind, &what, "no longer in config", true);
+            } else if interactive {
+                if output::prompt_prune(&kind, &what)? {
+                    if meta.is_dir() {
+                        std::fs::remove_dir_all(&dest)
+                    } else {
+                        std::fs::remove_file(&dest)
+                    }
+                    .with_context(|| format!("failed to remove {}", what))?;
+                    output::print_pruned(&kind, &what, "no longer in config", false);
+                }
+                crate::cache::remove_managed(&dest);
+            } else {
+                output::print_prune_kept(&kind, &what);
+            }
+        }
+        Ok(())
     }
 
     pub fn run_items(&self, items: &[StateItem]) -> Result<()> {
+        crate::providers::package::clear_listings();
+        let filtered: Vec<StateItem>;
+        let items = if self.filter.only.is_empty() && self.filter.skip.is_empty() {
+            items
+        } else {
+            filtered = items.iter().filter(|i| self.filter.allows(&i.kind)).cloned().collect();
+            &filtered
+        };
+
         if items.is_empty() {
-            println!("  No items");
+            self.out.no_items();
             return Ok(());
         }
 
+        if let (Mode::Check, Some(spec)) = (self.mode, &self.explain) {
+            return self.explain_all(items, spec);
+        }
+
         match self.mode {
             Mode::Plan => self.plan_all(items),
             Mode::Check => self.check_all(items),
             Mode::Apply => self.apply_all(items),
+            Mode::Diff => self.diff_all(items),
         }
     }
 
+    /// Render what apply would write for file-content items, touching nothing
+    fn diff_all(&self, items: &[StateItem]) -> Result<()> {
+        let mut changed = 0;
+
+        for item in items {
+            let provider = self
+                .registry
+                .get(&item.kind)
+                .ok_or_else(|| anyhow::anyhow!("Unknown provider: {}", item.kind))?;
+
+            if !should_run(item) {
+                continue;
+            }
+
+            if let Some(diff) = provider.diff(item)? {
+                self.out.diff_item(item, &diff);
+                changed += 1;
+            }
+        }
+
+        self.out.diff_summary(changed);
+        Ok(())
+    }
+
     fn plan_all(&self, items: &[StateItem]) -> Result<()> {
         for item in items {
             if !should_run(item) {
-                output::print_skip_run_if(item);
+                self.out.skip_run_if(item);
                 continue;
             }
-            output::print_plan_item(item);
+            self.out.plan_item(item);
         }
-        output::print_plan_summary(items.len());
+        self.report_requirements(items)?;
+        self.out.plan_summary(items.len());
         Ok(())
     }
 
@@ -76,7 +299,7 @@ impl Runner {
 
         for item in items {
             if !should_run(item) {
-                output::print_skip_run_if(item);
+                self.out.skip_run_if(item);
                 skipped += 1;
                 continue;
             }
@@ -86,8 +309,9 @@ impl Runner {
                 .get(&item.kind)
                 .ok_or_else(|| anyhow::anyhow!("Unknown provider: {}", item.kind))?;
 
-            let result = provider.check(item)?;
-            output::print_check_result(item, &result);
+            let item_start = Instant::now();
+            let result = check_cached(provider, item)?;
+            self.out.check_result(item, &result, item_start.elapsed());
 
             if result.is_satisfied() {
                 satisfied += 1;
@@ -95,106 +319,412 @@ impl Runner {
                 missing += 1;
             }
         }
+        self.report_requirements(items)?;
 
-        output::print_check_summary(
+        self.out.check_summary(
             items.len() - skipped,
             satisfied,
             missing,
             start.elapsed(),
         );
+        if self.exit_code && missing > 0 {
+            std::process::exit(2);
+        }
+        Ok(())
+    }
+
+    /// Check just the items matching `spec` and show how each result was
+    /// reached: the commands check ran, their output and its reasoning
+    fn explain_all(&self, items: &[StateItem], spec: &str) -> Result<()> {
+        let matched: Vec<&StateItem> = items.iter().filter(|i| explain_matches(i, spec)).collect();
+        if matched.is_empty() {
+            bail!("No item matches '{}' (expected <kind>.<key>, e.g. cargo.ripgrep)", spec);
+        }
+
+        for (n, item) in matched.into_iter().enumerate() {
+            if n > 0 {
+                println!();
+            }
+            let provider = self
+                .registry
+                .get(&item.kind)
+                .ok_or_else(|| anyhow::anyhow!("Unknown provider: {}", item.kind))?;
+
+            let mut reasons = Vec::new();
+            if let Some(ref run_if) = item.run_if {
+                if !should_run(item) {
+                    output::print_skip_run_if(item);
+                    output::print_explain_reasons(&[format!("run_if failed, so the item is skipped: {}", run_if)]);
+                    continue;
+                }
+                reasons.push(format!("run_if passed: {}", run_if));
+            }
+
+            let (explanation, commands) = crate::util::trace_commands(|| provider.explain(item));
+            let explanation = explanation?;
+            reasons.extend(explanation.reasons);
+
+            // What apply does with the result beyond check itself
+            if explanation.result.is_satisfied() && item.cache_key.is_some() && !is_cache_fresh(item) {
+                reasons.push("cache_key changed since the last apply, so apply would run anyway".to_string());
+            }
+            if is_incremental(item, provider)
+                && crate::cache::get_applied(&cache_item_id(item)) == Some(item_hash(item))
+            {
+                reasons.push("unchanged since its last apply, so `apply --incremental` skips this check".to_string());
+            }
+
+            output::print_check_result(item, &explanation.result);
+            output::print_traced_commands(&commands);
+            output::print_explain_reasons(&reasons);
+        }
         Ok(())
     }
 
     fn apply_all(&self, items: &[StateItem]) -> Result<()> {
         let start = Instant::now();
 
+        // Preflight: every assertion is checked before requirements, sudo or
+        // any change. All failures are shown; only required ones stop the run.
+        let mut tally = Tally::default();
+        let mut required_failed = 0;
+        for item in items.iter().filter(|i| self.is_preflight(i)) {
+            let outcome = self.apply_item(item, None)?;
+            if item.required && matches!(outcome, Outcome::Issue) {
+                required_failed += 1;
+            }
+            tally.add(item, outcome);
+        }
+        if required_failed > 0 {
+            let checked = items.iter().filter(|i| self.is_preflight(i)).count() - tally.skipped;
+            self.out.summary(checked, 0, 0, tally.issues, start.elapsed());
+            return Err(AssertionsFailed(required_failed).into());
+        }
+
         // Collect and resolve requirements from all providers
         let requirements = self.collect_requirements(items)?;
-        if !requirements.is_empty() {
-            output::print_resolving_requirements(requirements.len());
-            resolve_requirements(&requirements)?;
+        if self.dry_run {
+            self.report_requirements(items)?;
+        } else if !requirements.is_empty() {
+            self.confirm_requirements(&requirements)?;
+            self.out.resolving_requirements(requirements.len());
+            resolve_requirements(&requirements, self.registry.policy())?;
         }
 
         // Pre-authenticate sudo once if any provider will need it
-        if self.any_needs_sudo(items) {
-            Command::new("sudo")
-                .arg("-v")
-                .status()
-                .context("Failed to authenticate sudo")?;
+        let sudo_items = self.sudo_items(items);
+        if !self.dry_run && !sudo_items.is_empty() {
+            match sudo_access() {
+                SudoAccess::Ready => {}
+                SudoAccess::NeedsPassword => {
+                    if let Some(pass) = crate::util::become_pass() {
+                        sudo_auth_stdin(pass)?;
+                    } else {
+                        let status = Command::new("sudo")
+                            .arg("-v")
+                            .status()
+                            .context("Failed to authenticate sudo")?;
+                        if !status.success() {
+                            bail!("Failed to authenticate sudo");
+                        }
+                    }
+                }
+                SudoAccess::Unavailable(reason) => {
+                    // Only a problem if something root-owned actually has to change
+                    let pending: Vec<_> = sudo_items
+                        .into_iter()
+                        .filter(|item| should_run(item) && !self.is_satisfied(item))
+                        .collect();
+                    if !pending.is_empty() {
+                        let list: Vec<String> =
+                            pending.iter().map(|i| format!("  {} {}", i.kind, i.key)).collect();
+                        bail!("sudo is unavailable ({}), but these items need root:\n{}", reason, list.join("\n"));
+                    }
+                }
+            }
         }
 
-        let mut changed = 0;
-        let mut failed = 0;
-        let mut skipped = 0;
-        let mut issues = 0;
-
-        for item in items {
-            if !should_run(item) {
-                output::print_skip_run_if(item);
-                skipped += 1;
-                continue;
+        if self.jobs > 1 && !self.dry_run {
+            let mp = indicatif::MultiProgress::new();
+            for wave in self.waves(items) {
+                // A lone item (barrier) runs like in a serial apply
+                let outcomes = match wave.as_slice() {
+                    [lane] if lane.len() == 1 => vec![(lane[0], self.apply_item(lane[0], None)?)],
+                    _ => self.apply_wave(&wave, &mp)?,
+                };
+                outcomes.into_iter().for_each(|(item, o)| tally.add(item, o));
             }
+        } else {
+            for item in items.iter().filter(|i| !self.is_preflight(i)) {
+                tally.add(item, self.apply_item(item, None)?);
+            }
+        }
+        let Tally { changed, failed, skipped, issues, .. } = tally;
 
-            let provider = self
-                .registry
-                .get(&item.kind)
-                .ok_or_else(|| anyhow::anyhow!("Unknown provider: {}", item.kind))?;
+        if self.dry_run {
+            self.out.dry_run_summary(items.len() - skipped, changed, issues, start.elapsed());
+            return Ok(());
+        }
 
-            let check = provider.check(item)?;
+        // Changed items in config order, whichever thread applied them
+        let mut changes = tally.changes;
+        changes.sort_by_key(|item| items.iter().position(|i| std::ptr::eq(i, *item)));
+        self.out.changes(&changes);
+        self.out.summary(items.len() - skipped, changed, failed, issues, start.elapsed());
 
-            if check.is_satisfied() {
-                // Cache key present and stale → re-apply (config changed).
-                // No cache key, or cache fresh → skip.
-                if !item.cache_key.is_some() || is_cache_fresh(item) {
-                    output::print_apply_skip(item);
-                    continue;
+        if failed > 0 {
+            bail!("{} items failed to apply", failed);
+        }
+
+        Ok(())
+    }
+
+    /// Check one item and apply it if needed. With `mp`, spinners are drawn
+    /// through it and lines printed around it, so concurrent items don't clash.
+    fn apply_item(&self, item: &StateItem, mp: Option<&indicatif::MultiProgress>) -> Result<Outcome> {
+        let print = |f: &dyn Fn()| match mp {
+            Some(mp) => mp.suspend(f),
+            None => f(),
+        };
+
+        if !should_run(item) {
+            print(&|| self.out.skip_run_if(item));
+            return Ok(Outcome::Skipped);
+        }
+
+        let provider = self
+            .registry
+            .get(&item.kind)
+            .ok_or_else(|| anyhow::anyhow!("Unknown provider: {}", item.kind))?;
+
+        let item_start = Instant::now();
+        let incremental = self.incremental && is_incremental(item, provider);
+        if incremental && crate::cache::get_applied(&cache_item_id(item)) == Some(item_hash(item)) {
+            track_managed(item);
+            print(&|| self.out.apply_skip(item, item_start.elapsed()));
+            return Ok(Outcome::Unchanged);
+        }
+
+        let check = check_fresh(provider, item)?;
+
+        if check.is_satisfied() {
+            // Cache key present and stale → re-apply (config changed).
+            // No cache key, or cache fresh → skip.
+            if item.cache_key.is_none() || is_cache_fresh(item) {
+                if incremental {
+                    crate::cache::set_applied(&cache_item_id(item), &item_hash(item));
                 }
-                // fall through to apply
+                track_managed(item);
+                print(&|| self.out.apply_skip(item, item_start.elapsed()));
+                return Ok(Outcome::Unchanged);
             }
+            // fall through to apply
+        } else {
+            // Drifted: forget it until it's applied again
+            crate::cache::clear_applied(&cache_item_id(item));
+        }
 
-            // Check failed — if cache is fresh, something was removed/changed
-            // externally. Apply will run and cache updates on success.
+        // Check failed — if cache is fresh, something was removed/changed
+        // externally. Apply will run and cache updates on success.
 
-            if provider.is_check_only() {
-                output::print_check_result(item, &check);
-                issues += 1;
-                continue;
-            }
+        if provider.is_check_only() {
+            print(&|| self.out.check_result(item, &check, item_start.elapsed()));
+            return Ok(Outcome::Issue);
+        }
 
-            let pb = output::start_spinner(item);
+        if self.dry_run {
+            let detail = match check {
+                CheckResult::Missing { ref detail } => detail.as_str(),
+                CheckResult::Satisfied => "cache_key changed",
+            };
+            self.out.would_apply(item, detail);
+            return Ok(Outcome::Changed);
+        }
 
-            match provider.apply_live(item, &pb) {
-                Ok(()) => {
-                    update_cache(item);
-                    output::finish_spinner_done(&pb, item);
-                    changed += 1;
+        if self.confirm_each && !self.confirm_all.load(std::sync::atomic::Ordering::SeqCst) {
+            let detail = match check {
+                CheckResult::Missing { ref detail } => detail.as_str(),
+                CheckResult::Satisfied => "cache_key changed",
+            };
+            match output::prompt_apply(item, detail)? {
+                output::Confirm::Yes => {}
+                output::Confirm::All => self.confirm_all.store(true, std::sync::atomic::Ordering::SeqCst),
+                output::Confirm::No => {
+                    output::print_declined(item);
+                    return Ok(Outcome::Skipped);
                 }
-                Err(e) => {
-                    output::finish_spinner_fail(&pb, item, &e.to_string());
-                    failed += 1;
+            }
+        }
+
+        let pb = match mp {
+            Some(mp) => {
+                let pb = mp.suspend(|| self.out.start_apply(item));
+                if pb.is_hidden() { pb } else { mp.add(pb) }
+            }
+            None => self.out.start_apply(item),
+        };
+        crate::util::take_last_output();
+
+        crate::util::set_timeout(item.timeout);
+        let result = provider.apply_live(item, &pb);
+        crate::util::set_timeout(None);
+        // Detach from the multi-progress first: finishing a bar inside suspend would deadlock
+        if let Some(mp) = mp {
+            mp.remove(&pb);
+        }
+        match result {
+            Ok(()) => {
+                update_cache(item);
+                track_managed(item);
+                if is_incremental(item, provider) {
+                    crate::cache::set_applied(&cache_item_id(item), &item_hash(item));
                 }
+                print(&|| self.out.apply_done(&pb, item, item_start.elapsed()));
+                Ok(Outcome::Changed)
+            }
+            Err(e) => {
+                let output = if output::verbosity() > 0 {
+                    crate::util::take_last_output().filter(|o| !o.trim().is_empty())
+                } else {
+                    None
+                };
+                print(&|| self.out.apply_fail(&pb, item, &e.to_string(), output.as_deref(), item_start.elapsed()));
+                Ok(Outcome::Failed)
             }
         }
+    }
 
-        output::print_summary(items.len() - skipped, changed, failed, issues, start.elapsed());
+    /// Lane an item can share with others under `--jobs`: items in different
+    /// lanes run concurrently, items in one lane in order. None for items that
+    /// may depend on everything before them (commands, services, scripts, ...).
+    fn lane(&self, item: &StateItem) -> Option<String> {
+        let sudo = self.registry.get(&item.kind).is_some_and(|p| p.needs_sudo());
+        let kind = item.kind.as_str();
+        match kind {
+            // One sudo package manager at a time (apt/pacman hold a lock);
+            // package.os without sudo is brew
+            _ if kind.starts_with("package.") && sudo => Some("sudo".to_string()),
+            "package.os" => Some("package.brew".to_string()),
+            _ if kind.starts_with("package.") => Some(kind.to_string()),
+            // Several file kinds can touch the same path; path lines live in the env file
+            _ if kind.starts_with("file.") => Some("file".to_string()),
+            "env" | "path" => Some("env".to_string()),
+            "alias" => Some("alias".to_string()),
+            _ => None,
+        }
+    }
 
-        if failed > 0 {
-            bail!("{} items failed to apply", failed);
+    /// Split items into waves: a run of items that have a lane is one wave of
+    /// lanes, any other item is a wave on its own, so config order still holds
+    /// across barriers
+    fn waves<'a>(&self, items: &'a [StateItem]) -> Vec<Vec<Vec<&'a StateItem>>> {
+        let mut waves = Vec::new();
+        let mut lanes: Vec<(String, Vec<&StateItem>)> = Vec::new();
+        // Assertions already ran in the preflight
+        for item in items.iter().filter(|i| !self.is_preflight(i)) {
+            match self.lane(item) {
+                Some(lane) => match lanes.iter_mut().find(|(l, _)| *l == lane) {
+                    Some((_, lane_items)) => lane_items.push(item),
+                    None => lanes.push((lane, vec![item])),
+                },
+                None => {
+                    if !lanes.is_empty() {
+                        waves.push(lanes.drain(..).map(|(_, v)| v).collect());
+                    }
+                    waves.push(vec![vec![item]]);
+                }
+            }
+        }
+        if !lanes.is_empty() {
+            waves.push(lanes.into_iter().map(|(_, v)| v).collect());
         }
+        waves
+    }
 
-        Ok(())
+    /// Run a wave's lanes on up to `jobs` threads
+    fn apply_wave<'a>(
+        &self,
+        lanes: &[Vec<&'a StateItem>],
+        mp: &indicatif::MultiProgress,
+    ) -> Result<Vec<(&'a StateItem, Outcome)>> {
+        let next = std::sync::atomic::AtomicUsize::new(0);
+        let workers = self.jobs.clamp(1, lanes.len());
+        std::thread::scope(|s| {
+            let handles: Vec<_> = (0..workers)
+                .map(|_| {
+                    s.spawn(|| -> Result<Vec<(&StateItem, Outcome)>> {
+                        let mut outcomes = Vec::new();
+                        loop {
+                            let i = next.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                            let Some(lane) = lanes.get(i) else { break };
+                            for item in lane {
+                                outcomes.push((*item, self.apply_item(item, Some(mp))?));
+                            }
+                        }
+                        Ok(outcomes)
+                    })
+                })
+                .collect();
+            let mut outcomes = Vec::new();
+            for handle in handles {
+                outcomes.extend(handle.join().map_err(|_| anyhow::anyhow!("apply worker panicked"))??);
+            }
+            Ok(outcomes)
+        })
     }
 
-    fn any_needs_sudo(&self, items: &[StateItem]) -> bool {
+    /// Check-only items (assertions), evaluated before anything is applied
+    fn is_preflight(&self, item: &StateItem) -> bool {
+        self.registry.get(&item.kind).is_some_and(|p| p.is_check_only())
+    }
+
+    /// Items whose provider runs through sudo; none when already root
+    fn sudo_items<'a>(&self, items: &'a [StateItem]) -> Vec<&'a StateItem> {
         if unsafe { libc::geteuid() } == 0 {
-            return false;
+            return Vec::new();
         }
-        items.iter().any(|item| {
-            self.registry
-                .get(&item.kind)
-                .map(|p| p.needs_sudo())
-                .unwrap_or(false)
-        })
+        items
+            .iter()
+            .filter(|item| self.registry.get(&item.kind).is_some_and(|p| p.needs_sudo()))
+            .collect()
+    }
+
+    /// Check failures count as unsatisfied, so the item still gets reported
+    fn is_satisfied(&self, item: &StateItem) -> bool {
+        self.registry
+            .get(&item.kind)
+            .and_then(|p| check_fresh(p, item).ok())
+            .is_some_and(|r| r.is_satisfied())
+    }
+
+    /// Check, plan and dry runs: list the requirements apply would install
+    fn report_requirements(&self, items: &[StateItem]) -> Result<()> {
+        let mut seen = HashSet::new();
+        for req in self.collect_requirements(items)? {
+            if seen.insert(req.binary) && !req.is_satisfied() {
+                self.out.would_require(req.binary, &req.via());
+            }
+        }
+        Ok(())
+    }
+
+    /// Before piping install scripts into sh, ask on a terminal unless
+    /// `--yes-requirements` was given. Without a terminal apply goes ahead
+    /// as before; `[meta] pinned_install_scripts` is the gate there.
+    fn confirm_requirements(&self, requirements: &[Requirement]) -> Result<()> {
+        let interactive = std::io::IsTerminal::is_terminal(&std::io::stdin())
+            && std::io::IsTerminal::is_terminal(&std::io::stdout());
+        if self.yes_requirements || !interactive {
+            return Ok(());
+        }
+        let mut seen = HashSet::new();
+        for req in requirements.iter().filter(|r| seen.insert(r.binary) && !r.is_satisfied()) {
+            let Some(url) = req.script_url() else { continue };
+            if !output::prompt_requirement(req.binary, &url)? {
+                bail!("Not installing {}: install it yourself, or pass --yes-requirements", req.binary);
+            }
+        }
+        Ok(())
     }
 
     fn collect_requirements(&self, items: &[StateItem]) -> Result<Vec<Requirement>> {
@@ -216,11 +746,85 @@ impl Runner {
     }
 }
 
+/// What happened to one item during apply
+enum Outcome {
+    Skipped,
+    Unchanged,
+    Changed,
+    Failed,
+    Issue,
+}
+
+/// Required assertions failed, so apply stopped before changing anything
+#[derive(Debug)]
+pub struct AssertionsFailed(pub usize);
+
+impl std::fmt::Display for AssertionsFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = if self.0 == 1 { "" } else { "s" };
+        write!(f, "{} required assertion{} failed, nothing was applied", self.0, s)
+    }
+}
+
+impl std::error::Error for AssertionsFailed {}
+
+#[derive(Default)]
+struct Tally<'a> {
+    changed: usize,
+    failed: usize,
+    skipped: usize,
+    issues: usize,
+    changes: Vec<&'a StateItem>,
+}
+
+impl<'a> Tally<'a> {
+    fn add(&mut self, item: &'a StateItem, outcome: Outcome) {
+        match outcome {
+            Outcome::Skipped => self.skipped += 1,
+            Outcome::Unchanged => {}
+            Outcome::Changed => {
+                self.changed += 1;
+                self.changes.push(item);
+            }
+            Outcome::Failed => self.failed += 1,
+            Outcome::Issue => self.issues += 1,
+        }
+    }
+}
+
 /// Returns the cache state item ID for a given item
 fn cache_item_id(item: &StateItem) -> String {
     format!("{}:{}", item.kind, item.key)
 }
 
+/// Cache key for an item's `check_ttl` passes
+fn check_key(item: &StateItem) -> String {
+    format!("check:{}:{}:{}", item.kind, item.key, item.value.as_deref().unwrap_or(""))
+}
+
+/// `provider.check`, except an item with a `check_ttl` that was satisfied
+/// within it counts as satisfied without asking again. Only passes are
+/// kept: anything missing is looked at every time. For check only; apply
+/// uses `check_fresh`.
+fn check_cached(provider: &dyn crate::providers::Provider, item: &StateItem) -> Result<CheckResult> {
+    if let Some(ttl) = item.check_ttl {
+        if crate::cache::get(&check_key(item), Some(ttl)).is_some() {
+            return Ok(CheckResult::Satisfied);
+        }
+    }
+    check_fresh(provider, item)
+}
+
+/// `provider.check`, always run, keeping a pass for later `check_cached`
+/// calls when the item has a `check_ttl`
+fn check_fresh(provider: &dyn crate::providers::Provider, item: &StateItem) -> Result<CheckResult> {
+    let result = provider.check(item)?;
+    if item.check_ttl.is_some() && result.is_satisfied() {
+        crate::cache::set(&check_key(item), b"ok");
+    }
+    Ok(result)
+}
+
 /// Check if cache_key is fresh (value unchanged since last apply).
 /// Returns true if the item should be skipped.
 fn is_cache_fresh(item: &StateItem) -> bool {
@@ -232,6 +836,26 @@ fn is_cache_fresh(item: &StateItem) -> bool {
     crate::cache::get_state(&id).as_deref() == Some(key.as_str())
 }
 
+/// Whether apply may skip `check` for an item unchanged since it was last
+/// applied. Not for assertions (they exist to be re-evaluated) or for copies,
+/// downloads and `{ cmd }` env values, whose content can change under the
+/// same item.
+fn is_incremental(item: &StateItem, provider: &dyn crate::providers::Provider) -> bool {
+    let env_cmd = item.kind == "env" && item.value.as_deref().is_some_and(|v| v.starts_with('\x00'));
+    !provider.is_check_only() && !env_cmd && !matches!(item.kind.as_str(), "file.copy" | "file.fetch")
+}
+
+/// Everything that defines an item's desired state
+fn item_hash(item: &StateItem) -> String {
+    let parts = [
+        item.kind.as_str(),
+        item.key.as_str(),
+        item.value.as_deref().unwrap_or(""),
+        item.cache_key.as_deref().unwrap_or(""),
+    ];
+    format!("{:x}", md5::compute(parts.join("\x00")))
+}
+
 /// Store cache_key value after successful apply
 fn update_cache(item: &StateItem) {
     if let Some(ref key) = item.cache_key {
@@ -239,6 +863,83 @@ fn update_cache(item: &StateItem) {
     }
 }
 
+/// Remember where copies and symlinks went, for `apply --prune`
+fn track_managed(item: &StateItem) {
+    if let ("file.copy" | "file.symlink", Some(dest)) = (item.kind.as_str(), &item.value) {
+        crate::cache::add_managed(&item.kind, &crate::util::expand_path(dest));
+    }
+}
+
+enum SudoAccess {
+    /// Cached credentials or NOPASSWD
+    Ready,
+    NeedsPassword,
+    /// Not installed, or the user isn't allowed to use it
+    Unavailable(String),
+}
+
+/// Probe sudo without prompting: `sudo -n true` either succeeds, asks for a
+/// password, or refuses outright (not a sudoer)
+fn sudo_access() -> SudoAccess {
+    if !crate::util::command_exists("sudo") {
+        return SudoAccess::Unavailable("sudo not found".to_string());
+    }
+    let output = match Command::new("sudo").args(["-n", "true"]).output() {
+        Ok(output) => output,
+        Err(e) => return SudoAccess::Unavailable(e.to_string()),
+    };
+    if output.status.success() {
+        return SudoAccess::Ready;
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let refused = ["not in the sudoers", "may not run sudo", "is not allowed to"];
+    match stderr.lines().find(|l| refused.iter().any(|r| l.contains(r))) {
+        Some(line) => SudoAccess::Unavailable(line.trim().trim_start_matches("sudo: ").to_string()),
+        // Anything else (usually "a password is required"): let `sudo -v` prompt
+        None => SudoAccess::NeedsPassword,
+    }
+}
+
+/// Cache sudo credentials using a forwarded password (`sudo -S`), for remote
+/// runs where there is no TTY to prompt on
+fn sudo_auth_stdin(pass: &str) -> Result<()> {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let mut child = Command::new("sudo")
+        .args(["-S", "-p", "", "-v"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .context("Failed to authenticate sudo")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(pass.as_bytes())?;
+        stdin.write_all(b"\n")?;
+    }
+    if !child.wait()?.success() {
+        bail!("Failed to authenticate sudo: incorrect password");
+    }
+    Ok(())
+}
+
+/// `--explain` target: "<kind>.<key>" or a bare key. The "package." prefix
+/// is optional, and a package's name alone stands for its whole spec, so
+/// "cargo.ripgrep" matches "ripgrep --git <url>" and "ripgrep:rg" too.
+fn explain_matches(item: &StateItem, spec: &str) -> bool {
+    let short = item.kind.strip_prefix("package.").unwrap_or(&item.kind);
+    let mut keys = vec![item.key.as_str()];
+    if item.kind.starts_with("package.") {
+        let word = item.key.split_whitespace().next().unwrap_or("");
+        keys.push(word.split(':').next().unwrap_or(word));
+    }
+    let key = [item.kind.as_str(), short]
+        .iter()
+        .find_map(|kind| spec.strip_prefix(kind).and_then(|rest| rest.strip_prefix('.')))
+        .unwrap_or(spec);
+    keys.contains(&key)
+}
+
 fn should_run(item: &StateItem) -> bool {
     match &item.run_if {
         None => true,
@@ -251,6 +952,18 @@ fn should_run(item: &StateItem) -> bool {
     }
 }
 
+/// `vm.swappiness = 10` in TOML is a nested table; join the keys back up
+fn flatten_sysctl(prefix: &str, table: &toml::Table, out: &mut Vec<(String, String)>) {
+    for (k, v) in table {
+        let key = if prefix.is_empty() { k.clone() } else { format!("{}.{}", prefix, k) };
+        match v {
+            toml::Value::Table(t) => flatten_sysctl(&key, t, out),
+            toml::Value::String(s) => out.push((key, ev(s))),
+            other => out.push((key, other.to_string())),
+        }
+    }
+}
+
 fn ev(s: &str) -> String {
     crate::util::expand_vars(s)
 }
@@ -264,6 +977,100 @@ fn resolve_source_path(src: &str, base_dir: &Path) -> String {
     }
 }
 
+/// Cargo spec with a relative `--path` resolved against the config dir
+fn cargo_spec(spec: &str, base_dir: &Path) -> String {
+    let mut parts: Vec<String> = spec.split_whitespace().map(str::to_string).collect();
+    for i in 1..parts.len() {
+        if parts[i - 1] == "--path" {
+            // cargo install --list shows the path cleaned up, so drop "./"
+            parts[i] = resolve_source_path(parts[i].trim_start_matches("./"), base_dir);
+        }
+    }
+    parts.join(" ")
+}
+
+/// Rewrite file paths the way items get them: `$VARS` expanded, sources
+/// resolved against the config dir. For `--print-config`.
+pub fn resolve_paths(config: &mut Config, base_dir: &Path) {
+    let Some(ref mut file) = config.file else { return };
+    for map in [&mut file.copy, &mut file.symlink].into_iter().flatten() {
+        *map = map.drain().map(|(src, dst)| (resolve_source_path(&src, base_dir), ev(&dst))).collect();
+    }
+    if let Some(ref mut fetch) = file.fetch {
+        for target in fetch.values_mut() {
+            match target {
+                crate::config::FetchTarget::Path(path) | crate::config::FetchTarget::WithOptions { path, .. } => {
+                    *path = ev(path)
+                }
+            }
+        }
+    }
+    for map in [&mut file.ensure_line, &mut file.ensure_absent].into_iter().flatten() {
+        *map = map.drain().map(|(path, lines)| (ev(&path), lines)).collect();
+    }
+    for entry in &mut file.line {
+        entry.path = ev(&entry.path);
+    }
+    for tmpl in &mut file.template {
+        tmpl.src = resolve_source_path(&tmpl.src, base_dir);
+        tmpl.dest = ev(&tmpl.dest);
+        tmpl.vars.iter_mut().for_each(|v| *v = resolve_source_path(v, base_dir));
+    }
+    file.vars.iter_mut().for_each(|v| *v = resolve_source_path(v, base_dir));
+}
+
+/// Local files the config's items read from (copy/symlink sources, templates
+/// and their vars files), for `apply --watch`
+pub fn source_files(config: &Config, base_dir: &Path) -> Vec<std::path::PathBuf> {
+    let mut srcs: Vec<&String> = Vec::new();
+    if let Some(ref file) = config.file {
+        srcs.extend(file.copy.iter().flat_map(|m| m.keys()));
+        srcs.extend(file.symlink.iter().flat_map(|m| m.keys()));
+        srcs.extend(&file.vars);
+        for tmpl in &file.template {
+            srcs.push(&tmpl.src);
+            srcs.extend(&tmpl.vars);
+        }
+    }
+    if let Some(pip) = config.package.as_ref().and_then(|p| p.pip.as_ref()) {
+        srcs.extend(&pip.requirements);
+    }
+    srcs.into_iter()
+        .map(|s| crate::util::expand_path(resolve_source_path(s, base_dir)))
+        .collect()
+}
+
+/// What a template's `foreach` iterates: the list itself, or what its
+/// expression evaluates to. A string (e.g. a state's raw output) gives one
+/// item per non-empty line; anything else not iterable is a single item, and
+/// none gives no items. An expression that fails or names nothing is an error.
+fn foreach_items(
+    env: &minijinja::Environment,
+    foreach: &crate::config::TemplateForeach,
+    ctx: &HashMap<String, minijinja::Value>,
+) -> Result<Vec<minijinja::Value>, String> {
+    let value = match foreach {
+        crate::config::TemplateForeach::List(list) => return Ok(list.iter().map(minijinja::Value::from_serialize).collect()),
+        crate::config::TemplateForeach::Expr(expr) => {
+            let value = env.compile_expression(expr).and_then(|e| e.eval(ctx)).map_err(|e| e.to_string())?;
+            if value.is_undefined() {
+                return Err(format!("'{}' is undefined", expr));
+            }
+            value
+        }
+    };
+    if let Some(s) = value.as_str() {
+        return Ok(s.lines().map(str::trim).filter(|l| !l.is_empty()).map(minijinja::Value::from).collect());
+    }
+    if value.is_none() {
+        return Ok(Vec::new());
+    }
+    Ok(match value.try_iter() {
+        Ok(iter) => iter.collect(),
+        Err(_) => vec![value],
+    })
+}
+
 /// Load vars files (YAML or TOML) and return merged key→Value map.
 /// Later files override earlier ones.
 fn load_vars_files(paths: &[String], base_dir: &Path) -> HashMap<String, minijinja::Value> {
@@ -293,66 +1100,148 @@ fn load_vars_files(paths: &[String], base_dir: &Path) -> HashMap<String, minijin
     merged
 }
 
-fn collect_state_items(config: &Config, base_dir: &Path) -> Vec<StateItem> {
+fn collect_state_items(config: &Config, base_dir: &Path, secrets: Option<&minijinja::Value>) -> Vec<StateItem> {
     let mut items = Vec::new();
 
+    // Package repositories, before the packages that come from them
+    for repo in config.apt_repo.iter().filter(|r| when_matches(&r.when)) {
+        let value = format!(
+            "{}\x00{}\x00{}\x00{}",
+            ev(&repo.uri),
+            ev(&repo.suite),
+            repo.components.join(" "),
+            repo.key_url.as_deref().map(ev).unwrap_or_default(),
+        );
+        items.push(StateItem::new("apt_repo", &repo.name).with_value(value).with_run_if(repo.run_if.clone()));
+    }
+
+    for repo in config.pacman_repo.iter().filter(|r| when_matches(&r.when)) {
+        let value = format!(
+            "{}\x00{}\x00{}",
+            repo.sig_level.as_deref().unwrap_or(""),
+            repo.include.as_deref().map(ev).unwrap_or_default(),
+            repo.server.iter().map(|s| ev(s)).collect::<Vec<_>>().join("\x00"),
+        );
+        items.push(StateItem::new("pacman_repo", &repo.name).with_value(value).with_run_if(repo.run_if.clone()));
+    }
+
     // Packages
     if let Some(ref pkg) = config.package {
-        if let Some(ref os) = pkg.os {
+        if let Some(os) = pkg.os.as_ref().filter(|l| when_matches(&l.when)) {
             for item in &os.items {
-                items.push(StateItem::new("package.os", item).with_run_if(os.run_if.clone()));
+                items.push(
+                    StateItem::new("package.os", item)
+                        .with_run_if(os.run_if.clone())
+                        .with_timeout(os.timeout.as_deref()),
+                );
             }
         }
-        if let Some(ref apt) = pkg.apt {
+        if let Some(apt) = pkg.apt.as_ref().filter(|l| when_matches(&l.when)) {
             for item in &apt.items {
-                items.push(StateItem::new("package.apt", item).with_run_if(apt.run_if.clone()));
+                items.push(
+                    StateItem::new("package.apt", item)
+                        .with_run_if(apt.run_if.clone())
+                        .with_timeout(apt.timeout.as_deref()),
+                );
             }
         }
-        if let Some(ref pacman) = pkg.pacman {
+        if let Some(pacman) = pkg.pacman.as_ref().filter(|l| when_matches(&l.when)) {
             for item in &pacman.items {
                 items.push(
-                    StateItem::new("package.pacman", item).with_run_if(pacman.run_if.clone()),
+                    StateItem::new("package.pacman", item)
+                        .with_run_if(pacman.run_if.clone())
+                        .with_timeout(pacman.timeout.as_deref()),
+                );
+            }
+        }
+        if let Some(brew) = pkg.brew.as_ref().filter(|l| when_matches(&l.when)) {
+            for item in &brew.items {
+                items.push(
+                    StateItem::new("package.brew", item)
+                        .with_run_if(brew.run_if.clone())
+                        .with_timeout(brew.timeout.as_deref()),
+                );
+            }
+            for item in &brew.cask {
+                items.push(
+                    StateItem::new("package.brew", item)
+                        .with_value(crate::providers::package::BREW_CASK)
+                        .with_run_if(brew.run_if.clone())
+                        .with_timeout(brew.timeout.as_deref()),
                 );
             }
         }
-        if let Some(ref cargo) = pkg.cargo {
+        if let Some(cargo) = pkg.cargo.as_ref().filter(|l| when_matches(&l.when)) {
             for item in &cargo.items {
                 items.push(
-                    StateItem::new("package.cargo", item).with_run_if(cargo.run_if.clone()),
+                    StateItem::new("package.cargo", cargo_spec(item, base_dir))
+                        .with_run_if(cargo.run_if.clone())
+                        .with_timeout(cargo.timeout.as_deref()),
                 );
             }
         }
-        if let Some(ref go) = pkg.go {
+        if let Some(go) = pkg.go.as_ref().filter(|l| when_matches(&l.when)) {
             for item in &go.items {
-                items.push(StateItem::new("package.go", item).with_run_if(go.run_if.clone()));
+                items.push(
+                    StateItem::new("package.go", item)
+                        .with_run_if(go.run_if.clone())
+                        .with_timeout(go.timeout.as_deref()),
+                );
             }
         }
-        if let Some(ref npm) = pkg.npm {
+        if let Some(npm) = pkg.npm.as_ref().filter(|l| when_matches(&l.when)) {
             for item in &npm.items {
-                items.push(StateItem::new("package.npm", item).with_run_if(npm.run_if.clone()));
+                items.push(
+                    StateItem::new("package.npm", item)
+                        .with_run_if(npm.run_if.clone())
+                        .with_timeout(npm.timeout.as_deref()),
+                );
             }
         }
-        if let Some(ref pip) = pkg.pip {
+        if let Some(pip) = pkg.pip.as_ref().filter(|l| when_matches(&l.when)) {
             for item in &pip.items {
-                items.push(StateItem::new("package.pip", item).with_run_if(pip.run_if.clone()));
+                items.push(
+                    StateItem::new("package.pip", item)
+                        .with_run_if(pip.run_if.clone())
+                        .with_timeout(pip.timeout.as_deref()),
+                );
             }
-        }
-        if let Some(ref pipx) = pkg.pipx {
-            for item in &pipx.items {
+            for file in &pip.requirements {
                 items.push(
-                    StateItem::new("package.pipx", item).with_run_if(pipx.run_if.clone()),
+                    StateItem::new("package.pip", resolve_source_path(file, base_dir))
+                        .with_value(crate::providers::package::PIP_REQUIREMENTS)
+                        .with_run_if(pip.run_if.clone())
+                        .with_timeout(pip.timeout.as_deref()),
                 );
             }
         }
-        if let Some(ref webi) = pkg.webi {
+        if let Some(pipx) = pkg.pipx.as_ref().filter(|l| when_matches(&l.when)) {
+            for item in &pipx.items {
+                let name = crate::providers::package::pip_name(item);
+                let inject = pipx.inject.get(&name).or_else(|| pipx.inject.get(item));
+                let mut state = StateItem::new("package.pipx", item)
+                    .with_run_if(pipx.run_if.clone())
+                    .with_timeout(pipx.timeout.as_deref());
+                if pipx.include_deps || inject.is_some() {
+                    let inject = inject.map(|i| i.join(",")).unwrap_or_default();
+                    state = state.with_value(format!("{}\x00{}", pipx.include_deps, inject));
+                }
+                items.push(state);
+            }
+        }
+        if let Some(webi) = pkg.webi.as_ref().filter(|l| when_matches(&l.when)) {
             for item in &webi.items {
-                items.push(StateItem::new("package.webi", item).with_run_if(webi.run_if.clone()));
+                items.push(
+                    StateItem::new("package.webi", item)
+                        .with_run_if(webi.run_if.clone())
+                        .with_timeout(webi.timeout.as_deref()),
+                );
             }
         }
     }
 
     // Services
-    for svc in &config.service {
+    for svc in config.service.iter().filter(|s| when_matches(&s.when)) {
         let value = format!("state={},enabled={},scope={}", svc.state, svc.enabled, svc.scope);
         items.push(
             StateItem::new("service", &svc.name)
@@ -373,7 +1262,7 @@ fn collect_state_items(config: &Config, base_dir: &Path) -> Vec<StateItem> {
         if let Some(ref fetch) = file.fetch {
             for (url, target) in fetch {
                 let value = format!("{}\x00{}", ev(target.path()), target.ttl().unwrap_or(""));
-                items.push(StateItem::new("file.fetch", ev(url)).with_value(value));
+                items.push(StateItem::new("file.fetch", ev(url)).with_value(value).with_check_ttl(target.check_ttl()));
             }
         }
         if let Some(ref symlink) = file.symlink {
@@ -388,11 +1277,18 @@ fn collect_state_items(config: &Config, base_dir: &Path) -> Vec<StateItem> {
                 items.push(StateItem::new("file.ensure_line", ev(file)).with_value(value));
             }
         }
-        for entry in &file.line {
+        if let Some(ref ensure_absent) = file.ensure_absent {
+            for (file, lines) in ensure_absent {
+                let value = lines.join("\n");
+                items.push(StateItem::new("file.ensure_absent", ev(file)).with_value(value));
+            }
+        }
+        for entry in file.line.iter().filter(|e| when_matches(&e.when)) {
             use crate::config::FileLineMode;
             let mode = match entry.mode {
                 FileLineMode::Replace => "replace",
                 FileLineMode::Below => "below",
+                FileLineMode::Delete => "delete",
             };
             let (original, match_type) = if let Some(ref re) = entry.original_regex {
                 (re.as_str(), "regex")
@@ -423,19 +1319,16 @@ fn collect_state_items(config: &Config, base_dir: &Path) -> Vec<StateItem> {
             let state_results = if needed.is_empty() {
                 HashMap::new()
             } else {
-                crate::state::eval_states(&config.state, &needed).unwrap_or_default()
+                crate::state::eval_states(&config.state, &needed, false).unwrap_or_default()
             };
 
             // Load shared vars files
             let shared_vars = load_vars_files(&file.vars, base_dir);
 
             // Build built-in context values
-            let hostname = hostname::get()
-                .map(|h| h.to_string_lossy().to_string())
-                .unwrap_or_default();
-            let user = std::env::var("USER").unwrap_or_default();
-            let os = std::env::consts::OS.to_string();
-            let arch = std::env::consts::ARCH.to_string();
+            let builtins = crate::util::builtin_vars();
+            // A broken meta.toml already failed Runner::run
+            let inventory = crate::config::load_inventory(base_dir).ok().flatten().unwrap_or_default();
 
             for tmpl in &file.template {
                 let src_path = resolve_source_path(&tmpl.src, base_dir);
@@ -446,13 +1339,14 @@ fn collect_state_items(config: &Config, base_dir: &Path) -> Vec<StateItem> {
 
                 // Build context: built-ins first
                 let mut ctx = HashMap::new();
-                ctx.insert(
-                    "hostname".to_string(),
-                    minijinja::Value::from(hostname.clone()),
-                );
-                ctx.insert("user".to_string(), minijinja::Value::from(user.clone()));
-                ctx.insert("os".to_string(), minijinja::Value::from(os.clone()));
-                ctx.insert("arch".to_string(), minijinja::Value::from(arch.clone()));
+                for (k, v) in &builtins {
+                    ctx.insert(k.to_string(), minijinja::Value::from(v.clone()));
+                }
+                ctx.insert("hosts".to_string(), minijinja::Value::from_serialize(&inventory.hosts));
+                ctx.insert("groups".to_string(), minijinja::Value::from_serialize(&inventory.groups));
+                if let Some(secrets) = secrets {
+                    ctx.insert("secret".to_string(), secrets.clone());
+                }
 
                 // Layer shared vars
                 for (k, v) in &shared_vars {
@@ -489,16 +1383,57 @@ fn collect_state_items(config: &Config, base_dir: &Path) -> Vec<StateItem> {
                 // Render
                 let mut env = minijinja::Environment::new();
                 env.set_undefined_behavior(minijinja::UndefinedBehavior::Lenient);
+                crate::state::add_filters(&mut env);
                 env.add_template("_tmpl", &src_content).ok();
-                let rendered = env
-                    .get_template("_tmpl")
-                    .and_then(|t| t.render(&ctx))
-                    .unwrap_or_default();
+                // A failed secret lookup fails the item (\x00 + why) instead of rendering empty
+                let store = secrets.and_then(|s| s.downcast_object_ref::<crate::secrets::Secrets>());
+                let render = |ctx: &HashMap<String, minijinja::Value>| {
+                    store.map(|s| s.take_failures());
+                    let rendered = env.get_template("_tmpl").and_then(|t| t.render(ctx)).unwrap_or_default();
+                    match store.map(|s| s.take_failures()).unwrap_or_default() {
+                        failures if failures.is_empty() => rendered,
+                        failures => format!("\x00{}", failures.join(", ")),
+                    }
+                };
 
-                let dest = ev(&tmpl.dest);
-                items.push(
-                    StateItem::new("file.template", &dest).with_value(rendered),
-                );
+                let Some(ref foreach) = tmpl.foreach else {
+                    let dest = ev(&tmpl.dest);
+                    items.push(StateItem::new("file.template", &dest).with_value(render(&ctx)));
+                    continue;
+                };
+                // One output per item, its dest rendered too (`{{ item }}.service`).
+                // Failures become items that fail (\x00 + why) under the unrendered dest.
+                let fail = |why: String| StateItem::new("file.template", ev(&tmpl.dest)).with_value(format!("\x00{}", why));
+                let values = match foreach_items(&env, foreach, &ctx) {
+                    Ok(values) => values,
+                    Err(e) => {
+                        items.push(fail(format!("foreach: {}", e)));
+                        continue;
+                    }
+                };
+                let mut seen: HashMap<String, usize> = HashMap::new();
+                for item in values {
+                    let label = item.to_string();
+                    ctx.insert("item".to_string(), item);
+                    let dest = match env.render_str(&tmpl.dest, &ctx) {
+                        Ok(dest) if !dest.trim().is_empty() => ev(&dest),
+                        Ok(_) => {
+                            items.push(fail(format!("dest is empty for item '{}'", label)));
+                            continue;
+                        }
+                        Err(e) => {
+                            items.push(fail(format!("dest for item '{}': {}", label, e)));
+                            continue;
+                        }
+                    };
+                    // Two items writing one file would fight over it: write neither
+                    if let Some(&i) = seen.get(&dest) {
+                        items[i].value = Some(format!("\x00foreach: more than one item renders dest '{}'", dest));
+                        continue;
+                    }
+                    seen.insert(dest.clone(), items.len());
+                    items.push(StateItem::new("file.template", &dest).with_value(render(&ctx)));
+                }
             }
         }
     }
@@ -513,7 +1448,60 @@ fn collect_state_items(config: &Config, base_dir: &Path) -> Vec<StateItem> {
     // Env
     if let Some(ref env) = config.env {
         for (name, value) in env {
-            items.push(StateItem::new("env", name).with_value(ev(value)));
+            // A leading \x00 marks a command, run by the provider
+            let value = match value {
+                crate::config::EnvValue::Value(v) => ev(v),
+                crate::config::EnvValue::Cmd { cmd } => format!("\x00{}", cmd),
+            };
+            items.push(StateItem::new("env", name).with_value(value));
+        }
+    }
+
+    // System
+    if let Some(ref tz) = config.timezone {
+        items.push(StateItem::new("timezone", ev(tz)));
+    }
+    if let Some(ref name) = config.hostname {
+        items.push(StateItem::new("hostname", ev(name)));
+    }
+    for user in config.user.iter().filter(|u| when_matches(&u.when)) {
+        let value = format!(
+            "{}\x00{}\x00{}\x00{}",
+            user.groups.join(","),
+            user.shell.as_deref().unwrap_or(""),
+            user.home.as_deref().map(ev).unwrap_or_default(),
+            user.system,
+        );
+        items.push(StateItem::new("user", &user.name).with_value(value).with_run_if(user.run_if.clone()));
+    }
+    for job in config.cron.iter().filter(|j| when_matches(&j.when)) {
+        let value = format!("{}\x00{}\x00{}", job.schedule, job.command, job.timer);
+        items.push(StateItem::new("cron", &job.name).with_value(value).with_run_if(job.run_if.clone()));
+    }
+    if let Some(ref sysctl) = config.sysctl {
+        let mut entries = Vec::new();
+        flatten_sysctl("", sysctl, &mut entries);
+        for (key, value) in entries {
+            items.push(StateItem::new("sysctl", key).with_value(value));
+        }
+    }
+
+    // PATH entries. Each prepend line goes in front of the ones before it, so
+    // they're written last-first to keep the configured order.
+    if let Some(ref path) = config.path {
+        let mut seen = HashSet::new();
+        let dirs = |list: &[String]| -> Vec<String> {
+            list.iter().map(|d| crate::util::expand_path(ev(d)).to_string_lossy().to_string()).collect()
+        };
+        for dir in dirs(&path.prepend).into_iter().rev() {
+            if seen.insert(dir.clone()) {
+                items.push(StateItem::new("path", dir).with_value("prepend"));
+            }
+        }
+        for dir in dirs(&path.append) {
+            if seen.insert(dir.clone()) {
+                items.push(StateItem::new("path", dir).with_value("append"));
+            }
         }
     }
 
@@ -536,19 +1524,38 @@ fn collect_state_items(config: &Config, base_dir: &Path) -> Vec<StateItem> {
     }
 
     // Commands (check/apply)
-    for cmd in &config.command {
-        // Encode check and apply with null separator
-        let value = format!("{}\x00{}\x00{}", cmd.check, cmd.apply, if cmd.confirm { "1" } else { "" });
+    for cmd in config.command.iter().filter(|c| when_matches(&c.when)) {
+        // Encode check, apply, confirm, creates and removes with null separator
+        let path = |p: &Option<String>| {
+            p.as_deref().map(|p| crate::util::expand_path(ev(p)).to_string_lossy().to_string()).unwrap_or_default()
+        };
+        let value = format!(
+            "{}\x00{}\x00{}\x00{}\x00{}",
+            cmd.check.as_deref().unwrap_or(""),
+            cmd.apply,
+            if cmd.confirm { "1" } else { "" },
+            path(&cmd.creates),
+            path(&cmd.removes),
+        );
         items.push(
             StateItem::new("command", &cmd.name)
                 .with_value(value)
                 .with_run_if(cmd.run_if.clone())
-                .with_cache_key(cmd.cache_key.clone(), cmd.cache_key_cmd.clone()),
+                .with_cache_key(cmd.cache_key.clone(), cmd.cache_key_cmd.clone())
+                .with_timeout(cmd.timeout.as_deref())
+                .with_check_ttl(cmd.check_ttl.as_deref()),
         );
     }
 
     // Scripts
     if let Some(ref scripts) = config.script {
+        // They're run by name, so ~/.local/bin has to be on PATH. Always
+        // an item: dek's own process PATH says nothing about the shell's.
+        let bin = crate::util::expand_path("~/.local/bin");
+        let configured = items.iter().any(|i| i.kind == "path" && Path::new(&i.key) == bin);
+        if !scripts.is_empty() && !configured {
+            items.push(StateItem::new("path", bin.to_string_lossy().to_string()).with_value("append"));
+        }
         for (name, path) in scripts {
             let script_path = base_dir.join(path);
             if let Ok(content) = std::fs::read_to_string(&script_path) {
@@ -558,23 +1565,33 @@ fn collect_state_items(config: &Config, base_dir: &Path) -> Vec<StateItem> {
     }
 
     // Assertions
-    for assertion in &config.assert {
+    for assertion in config.assert.iter().filter(|a| when_matches(&a.when)) {
         let (cmd, mode) = if let Some(ref foreach) = assertion.foreach {
             (foreach.as_str(), "foreach")
         } else if let Some(ref check) = assertion.check {
             (check.as_str(), "check")
+        } else if let Some(ref url) = assertion.url {
+            (url.as_str(), "http")
         } else {
-            continue; // skip invalid: neither check nor foreach
+            continue; // skip invalid: no check, foreach or url
         };
         let key = assertion.name.as_deref().unwrap_or(cmd);
-        let stdout = assertion.stdout.as_deref().unwrap_or("");
-        let stderr = assertion.stderr.as_deref().unwrap_or("");
         let message = assertion.message.as_deref().unwrap_or("");
-        let value = format!("{}\x00{}\x00{}\x00{}\x00{}", cmd, mode, stdout, stderr, message);
+        let value = if mode == "http" {
+            let contains = assertion.contains.as_deref().unwrap_or("");
+            let status = assertion.status.unwrap_or(200);
+            format!("{}\x00{}\x00{}\x00{}\x00{}", ev(cmd), mode, contains, status, message)
+        } else {
+            let stdout = assertion.stdout.as_deref().unwrap_or("");
+            let stderr = assertion.stderr.as_deref().unwrap_or("");
+            format!("{}\x00{}\x00{}\x00{}\x00{}", cmd, mode, stdout, stderr, message)
+        };
         items.push(
             StateItem::new("assert", key)
                 .with_value(value)
-                .with_run_if(assertion.run_if.clone()),
+                .with_run_if(assertion.run_if.clone())
+                .with_required(assertion.required)
+                .with_check_ttl(assertion.check_ttl.as_deref()),
         );
     }
 
@@ -0,0 +1,121 @@
+use crate::config::SecretsConfig;
+use anyhow::{bail, Context, Result};
+use minijinja::value::{Object, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// A secret that wasn't fetched renders as `MASK`, its name, then `MASK_END`
+pub const MASK: char = '\x02';
+pub const MASK_END: char = '\x03';
+
+/// `secret` in the template context: each `secret.<name>` is fetched from the
+/// `[secrets]` backend on first use and kept in memory only. Unless `fetch`
+/// is set, nothing is fetched and every secret renders as a mask instead.
+#[derive(Debug)]
+pub struct Secrets {
+    config: SecretsConfig,
+    base_dir: PathBuf,
+    fetch: bool,
+    values: Mutex<HashMap<String, Result<String, String>>>,
+    failures: Mutex<Vec<String>>,
+}
+
+impl Secrets {
+    pub fn new(config: SecretsConfig, base_dir: &Path, fetch: bool) -> Self {
+        Self {
+            config,
+            base_dir: base_dir.to_path_buf(),
+            fetch,
+            values: Mutex::new(HashMap::new()),
+            failures: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Lookups that failed since the last call, as `secret '<name>': <error>`
+    pub fn take_failures(&self) -> Vec<String> {
+        std::mem::take(&mut *self.failures.lock().unwrap())
+    }
+
+    /// Template value for the context (`secret`)
+    pub fn value(self) -> Value {
+        Value::from_object(self)
+    }
+
+    fn resolve(&self, name: &str) -> Result<String> {
+        let key = self.config.keys.get(name).map(String::as_str).unwrap_or(name);
+
+        if let Some(ref file) = self.config.env_file {
+            let path = crate::util::expand_path(crate::util::expand_vars(file));
+            let path = if path.is_absolute() { path } else { self.base_dir.join(path) };
+            let content = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read secrets file: {}", path.display()))?;
+            if let Some(value) = parse_env_file(&content).remove(key) {
+                return Ok(value);
+            }
+        }
+
+        let Some(template) = self.command() else {
+            bail!("not in env_file, and no command or backend to fetch it");
+        };
+        let cmd = template.replace("{{key}}", &format!("'{}'", key.replace('\'', "'\\''")));
+        let output = crate::util::shell_cmd(&cmd)
+            .stdin(std::process::Stdio::inherit())
+            .stderr(std::process::Stdio::inherit())
+            .output()?;
+        if !output.status.success() {
+            bail!("`{}` exited with {}", cmd, output.status.code().unwrap_or(-1));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim_end_matches(['\n', '\r']).to_string())
+    }
+
+    /// Shell command template: explicit `command`, else the backend preset
+    fn command(&self) -> Option<String> {
+        if let Some(ref cmd) = self.config.command {
+            return Some(cmd.clone());
+        }
+        let preset = match self.config.backend.as_deref()? {
+            "pass" => "pass show {{key}} | head -n1",
+            "1password" | "op" => "op read {{key}}",
+            "vault" => "vault kv get -field=value {{key}}",
+            _ => return None,
+        };
+        Some(preset.to_string())
+    }
+}
+
+impl Object for Secrets {
+    fn get_value(self: &Arc<Self>, key: &Value) -> Option<Value> {
+        let name = key.as_str()?;
+        if !self.fetch {
+            return Some(Value::from(format!("{}{}{}", MASK, name, MASK_END)));
+        }
+        let mut values = self.values.lock().unwrap();
+        let value = values.entry(name.to_string()).or_insert_with(|| self.resolve(name).map_err(|e| e.to_string()));
+        match value {
+            Ok(v) => Some(Value::from(v.clone())),
+            Err(e) => {
+                self.failures.lock().unwrap().push(format!("secret '{}': {}", name, e));
+                None
+            }
+        }
+    }
+}
+
+/// KEY=value lines; blank lines, comments and an `export ` prefix are ignored
+fn parse_env_file(content: &str) -> HashMap<String, String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .filter_map(|l| {
+            let (k, v) = l.strip_prefix("export ").unwrap_or(l).split_once('=')?;
+            let v = v.trim();
+            let v = v
+                .strip_prefix('"').and_then(|v| v.strip_suffix('"'))
+                .or_else(|| v.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))
+                .unwrap_or(v);
+            Some((k.trim().to_string(), v.to_string()))
+        })
+        .collect()
+}
@@ -0,0 +1,209 @@
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+/// ssh errors no retry will fix
+const FATAL_ERRORS: &[&str] = &[
+    "Permission denied",
+    "Host key verification failed",
+    "REMOTE HOST IDENTIFICATION HAS CHANGED",
+    "Too many authentication failures",
+];
+
+/// Whether a failed ssh/scp/rsync run looks like a network blip worth
+/// retrying, rather than an auth failure or a failing remote command
+pub fn is_transient(output: &Output) -> bool {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if FATAL_ERRORS.iter().any(|e| stderr.contains(e)) {
+        return false;
+    }
+    // 255: ssh's own errors; rsync 10/12/30/35: socket I/O, protocol stream, timeouts
+    matches!(output.status.code(), Some(255 | 10 | 12 | 30 | 35))
+        || ["lost connection", "Connection timed out", "Connection reset", "Connection closed", "Connection refused"]
+            .iter()
+            .any(|e| stderr.contains(e))
+}
+
+/// How unknown host keys are handled (`[remote] host_key_checking`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HostKeyChecking {
+    /// Trust and remember a new host's key; refuse one that changed
+    #[default]
+    AcceptNew,
+    /// Trust any key and remember none (throwaway hosts)
+    Off,
+    /// Only hosts already in known_hosts
+    Strict,
+}
+
+/// Connection settings shared by every ssh/scp/rsync call to a remote host
+#[derive(Debug, Clone, Default)]
+pub struct Ssh {
+    /// Reuse one master connection per host (ControlMaster/ControlPath)
+    pub control_master: bool,
+    /// Jump/bastion host (ssh -J)
+    pub jump: Option<String>,
+    /// Seconds to wait for each connection to be established (ssh -o ConnectTimeout)
+    pub connect_timeout: Option<u64>,
+    /// StrictHostKeyChecking for every connection; a prompt would hang the deploy
+    pub host_key_checking: HostKeyChecking,
+    /// Time budget for everything done on one host, counted from `start_clock`
+    pub host_timeout: Option<Duration>,
+    /// When the host timeout runs out (set by `start_clock`)
+    pub deadline: Option<Instant>,
+}
+
+impl Ssh {
+    /// Socket path for multiplexed connections. %C is expanded by ssh to a
+    /// hash of the connection params; the pid keeps concurrent dek runs apart.
+    fn control_path(&self) -> String {
+        format!("/tmp/dek-ssh-{}-%C", std::process::id())
+    }
+
+    /// Options passed to every ssh/scp invocation
+    fn opts(&self) -> Vec<String> {
+        let mut opts = Vec::new();
+        if let Some(ref jump) = self.jump {
+            opts.push("-J".to_string());
+            opts.push(jump.clone());
+        }
+        if self.control_master {
+            opts.push("-o".to_string());
+            opts.push(format!("ControlPath={}", self.control_path()));
+        }
+        if let Some(secs) = self.connect_timeout {
+            opts.push("-o".to_string());
+            opts.push(format!("ConnectTimeout={}", secs));
+        }
+        let host_keys: &[&str] = match self.host_key_checking {
+            HostKeyChecking::AcceptNew => &["StrictHostKeyChecking=accept-new"],
+            HostKeyChecking::Off => &["StrictHostKeyChecking=no", "UserKnownHostsFile=/dev/null", "LogLevel=ERROR"],
+            HostKeyChecking::Strict => &["StrictHostKeyChecking=yes"],
+        };
+        for opt in host_keys {
+            opts.push("-o".to_string());
+            opts.push(opt.to_string());
+        }
+        opts
+    }
+
+    /// Copy of these settings whose host timeout starts now. Commands run
+    /// through `output` or guarded by `watch` are killed once it runs out.
+    pub fn start_clock(&self) -> Self {
+        Self { deadline: self.host_timeout.map(|t| Instant::now() + t), ..self.clone() }
+    }
+
+    /// Whether the host timeout started by `start_clock` has run out
+    pub fn timed_out(&self) -> bool {
+        self.deadline.is_some_and(|d| Instant::now() >= d)
+    }
+
+    /// Kill `child` if it's still running when the host timeout runs out.
+    /// Disarmed when the returned guard is dropped.
+    pub fn watch(&self, child: &Child) -> Watchdog {
+        let Some(deadline) = self.deadline else {
+            return Watchdog { _disarm: None };
+        };
+        let (tx, rx) = mpsc::channel::<()>();
+        let pid = child.id() as libc::pid_t;
+        std::thread::spawn(move || {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if let Err(mpsc::RecvTimeoutError::Timeout) = rx.recv_timeout(remaining) {
+                // Take down its whole group when it leads one (see `output`)
+                let target = if unsafe { libc::getpgid(pid) } == pid { -pid } else { pid };
+                unsafe { libc::kill(target, libc::SIGKILL) };
+            }
+        });
+        Watchdog { _disarm: Some(tx) }
+    }
+
+    /// `cmd.output()`, killed when the host timeout runs out. Runs in its own
+    /// process group, so helpers it spawns (ssh -J's proxy) die with it and
+    /// release the output pipes.
+    pub fn output(&self, cmd: &mut Command) -> std::io::Result<Output> {
+        use std::os::unix::process::CommandExt;
+        let child = cmd
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .process_group(0)
+            .spawn()?;
+        let _watchdog = self.watch(&child);
+        child.wait_with_output()
+    }
+
+    /// `ssh [opts] <host>` — append the remote command with `.arg()`
+    pub fn ssh(&self, host: &str) -> Command {
+        let mut cmd = Command::new("ssh");
+        cmd.args(self.opts()).arg(host);
+        cmd
+    }
+
+    /// `ssh -t [opts] <host>` — allocates a TTY for interactive commands
+    pub fn ssh_tty(&self, host: &str) -> Command {
+        let mut cmd = Command::new("ssh");
+        cmd.arg("-t").args(self.opts()).arg(host);
+        cmd
+    }
+
+    /// `scp [opts]` — append sources and destination with `.args()`
+    pub fn scp(&self) -> Command {
+        let mut cmd = Command::new("scp");
+        cmd.args(self.opts());
+        cmd
+    }
+
+    /// `rsync -e '<ssh [opts]>'` — append flags, source and destination with `.args()`
+    pub fn rsync(&self) -> Command {
+        let mut cmd = Command::new("rsync");
+        let opts = self.opts();
+        if !opts.is_empty() {
+            cmd.arg("-e").arg(format!("ssh {}", opts.join(" ")));
+        }
+        cmd
+    }
+
+    /// Open a background master connection to `host`, closed again when the
+    /// returned guard is dropped. Later calls reuse it through the control
+    /// socket. No-op when multiplexing is disabled; if the master fails to
+    /// start, later calls simply open their own connections.
+    pub fn master(&self, host: &str) -> Master<'_> {
+        if self.control_master {
+            let _ = Command::new("ssh")
+                .args(self.opts())
+                .args(["-o", "ControlMaster=yes", "-o", "ControlPersist=yes", "-fN", host])
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status();
+        }
+        Master { ssh: self, host: host.to_string() }
+    }
+}
+
+/// Guard returned by `Ssh::watch`; dropping it disarms the kill
+pub struct Watchdog {
+    _disarm: Option<mpsc::Sender<()>>,
+}
+
+/// Master connection guard returned by `Ssh::master`
+pub struct Master<'a> {
+    ssh: &'a Ssh,
+    host: String,
+}
+
+impl Drop for Master<'_> {
+    fn drop(&mut self) {
+        if !self.ssh.control_master {
+            return;
+        }
+        let _ = Command::new("ssh")
+            .args(self.ssh.opts())
+            .args(["-O", "exit", &self.host])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+    }
+}
@@ -1,10 +1,49 @@
 use anyhow::{bail, Result};
+use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
-use std::process::{Command, Stdio};
+use std::process::Stdio;
 
 use crate::config;
 use crate::config::StateConfig;
 
+/// Probe cmd timeout when the state doesn't set one
+const DEFAULT_PROBE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum StateFormat {
+    /// Aligned names and values (a single query prints just the value)
+    Table,
+    Json,
+    Yaml,
+    /// NAME='value' lines, for `eval "$(dek state --format env)"`
+    Env,
+}
+
+impl StateFormat {
+    /// Flag to pass the same format on to a remote dek
+    pub fn to_arg(self) -> &'static str {
+        match self {
+            Self::Table => "",
+            Self::Json => " --format json",
+            Self::Yaml => " --format yaml",
+            Self::Env => " --format env",
+        }
+    }
+}
+
+/// `dotfiles.dirty` → `DOTFILES_DIRTY`
+fn env_name(label: &str) -> String {
+    let name: String = label
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect();
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{}", name)
+    } else {
+        name
+    }
+}
+
 pub struct StateResult {
     pub name: String,
     pub original: Option<String>,
@@ -15,21 +54,34 @@ pub struct StateResult {
 }
 
 impl StateResult {
-    fn get_variant(&self, variant: Option<&str>) -> Option<&str> {
+    fn get_variant(&self, variant: Option<&str>) -> Option<Cow<'_, str>> {
         match variant {
-            None | Some("raw") => Some(&self.raw),
-            Some("original") => self.original.as_deref().or(Some(&self.raw)),
-            Some(v) => self.templates.get(v).map(|s| s.as_str()),
+            None | Some("raw") => Some(Cow::Borrowed(&self.raw)),
+            Some("original") => Some(Cow::Borrowed(self.original.as_deref().unwrap_or(&self.raw))),
+            Some(v) => self
+                .templates
+                .get(v)
+                .map(|s| Cow::Borrowed(s.as_str()))
+                .or_else(|| self.json_path(v).map(Cow::Owned)),
         }
     }
 
-    /// Return raw as minijinja Value — parsed object if json, string otherwise
-    pub fn raw_value(&self) -> minijinja::Value {
-        if let Some(ref v) = self.raw_parsed {
-            minijinja::Value::from_serialize(v)
-        } else {
-            minijinja::Value::from(self.raw.clone())
+    /// Walk the parsed JSON along a dotted path like `a.b.0.c` (numeric
+    /// segments index arrays). Strings are returned unquoted, anything else
+    /// as compact JSON.
+    fn json_path(&self, path: &str) -> Option<String> {
+        let mut cur = self.raw_parsed.as_ref()?;
+        for seg in path.split('.') {
+            cur = match cur {
+                serde_json::Value::Object(map) => map.get(seg)?,
+                serde_json::Value::Array(arr) => arr.get(seg.parse::<usize>().ok()?)?,
+                _ => return None,
+            };
         }
+        Some(match cur {
+            serde_json::Value::String(s) => s.clone(),
+            v => v.to_string(),
+        })
     }
 
     /// Return raw as serde_json Value — object if json, string otherwise
@@ -61,7 +113,7 @@ fn parse_query(s: &str) -> StateQuery {
 }
 
 // Kahn's algorithm — returns layers of indices for parallel eval
-fn topo_sort(states: &[StateConfig]) -> Result<Vec<Vec<usize>>> {
+pub fn topo_sort(states: &[StateConfig]) -> Result<Vec<Vec<usize>>> {
     let name_to_idx: HashMap<&str, usize> = states
         .iter()
         .enumerate()
@@ -109,25 +161,53 @@ fn topo_sort(states: &[StateConfig]) -> Result<Vec<Vec<usize>>> {
     }
 
     if processed != n {
-        bail!("Cycle detected in state dependencies");
+        let stuck: Vec<&str> = (0..n)
+            .filter(|&i| in_degree[i] > 0)
+            .map(|i| states[i].name.as_str())
+            .collect();
+        bail!("Cycle detected in state dependencies (unresolved: {})", stuck.join(", "));
     }
     Ok(layers)
 }
 
-fn add_filters(env: &mut minijinja::Environment) {
+/// Register dek's filters and functions, shared by state probes and file templates
+pub fn add_filters(env: &mut minijinja::Environment) {
+    use minijinja::{Error, ErrorKind, Value};
+
+    env.add_filter("fromjson", |s: String| -> Result<Value, Error> {
+        serde_json::from_str::<serde_json::Value>(&s)
+            .map(|v| Value::from_serialize(&v))
+            .map_err(|e| Error::new(ErrorKind::InvalidOperation, e.to_string()))
+    });
+    env.add_filter("to_json", |v: Value| -> Result<String, Error> {
+        serde_json::to_string(&v).map_err(|e| Error::new(ErrorKind::InvalidOperation, e.to_string()))
+    });
+    // The builtin's signature (`default(other, true)` also replaces anything
+    // falsy), but it always falls back on none and empty strings (e.g. a probe
+    // with no output)
+    env.add_filter("default", |v: Value, fallback: Option<Value>, boolean: Option<bool>| -> Value {
+        let falsy = boolean.unwrap_or(false) && !v.is_true();
+        if v.is_undefined() || v.is_none() || v.as_str() == Some("") || falsy {
+            fallback.unwrap_or_else(|| Value::from(""))
+        } else {
+            v
+        }
+    });
     env.add_filter(
-        "fromjson",
-        |s: String| -> Result<minijinja::Value, minijinja::Error> {
-            serde_json::from_str::<serde_json::Value>(&s)
-                .map(|v| minijinja::Value::from_serialize(&v))
-                .map_err(|e| {
-                    minijinja::Error::new(minijinja::ErrorKind::InvalidOperation, e.to_string())
-                })
+        "regex_replace",
+        |s: String, pattern: String, replacement: String| -> Result<String, Error> {
+            let re = regex::Regex::new(&pattern)
+                .map_err(|e| Error::new(ErrorKind::InvalidOperation, e.to_string()))?;
+            Ok(re.replace_all(&s, replacement.as_str()).to_string())
         },
     );
+    env.add_filter("b64encode", |s: String| crate::util::base64_encode(s.as_bytes()));
+    env.add_function("env", |name: String, fallback: Option<String>| {
+        std::env::var(&name).ok().or(fallback).unwrap_or_default()
+    });
 }
 
-fn eval_single(state: &StateConfig, dep_results: &HashMap<String, &StateResult>) -> StateResult {
+fn eval_single(state: &StateConfig, dep_results: &HashMap<String, &StateResult>, bypass_cache: bool) -> StateResult {
     // Run cmd if present, with optional TTL cache
     let ttl = state
         .ttl
@@ -136,18 +216,39 @@ fn eval_single(state: &StateConfig, dep_results: &HashMap<String, &StateResult>)
     let cache_key = format!("state-probe:{}", state.name);
 
     let cmd_output = state.cmd.as_ref().map(|cmd| {
-        // Check cache first
-        if let Some(max_age) = ttl {
+        // Check cache first (bypassing it still refreshes it below)
+        if let Some(max_age) = ttl.filter(|_| !bypass_cache) {
             if let Some(cached) = crate::cache::get(&cache_key, Some(max_age)) {
                 return String::from_utf8_lossy(&cached).to_string();
             }
         }
 
-        let output = crate::util::shell_cmd(cmd)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::null())
-            .output()
-            .ok();
+        let timeout = state
+            .timeout
+            .as_deref()
+            .and_then(|s| crate::util::parse_duration(s).ok())
+            .unwrap_or(DEFAULT_PROBE_TIMEOUT);
+        let output = crate::util::output_with_timeout(
+            crate::util::shell_cmd(cmd)
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null()),
+            timeout,
+        );
+        let output = match output {
+            Ok(Some(o)) => Some(o),
+            Ok(None) => {
+                use owo_colors::OwoColorize;
+                eprintln!(
+                    "{} state '{}' timed out after {}",
+                    c!("!", yellow),
+                    state.name,
+                    crate::output::format_duration(timeout)
+                );
+                // Don't cache a timed-out probe
+                return String::new();
+            }
+            Err(_) => None,
+        };
         let result = output
             .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
             .unwrap_or_default();
@@ -168,6 +269,9 @@ fn eval_single(state: &StateConfig, dep_results: &HashMap<String, &StateResult>)
             env.set_undefined_behavior(minijinja::UndefinedBehavior::Lenient);
             add_filters(&mut env);
             let mut ctx = HashMap::new();
+            for (k, v) in crate::util::builtin_vars() {
+                ctx.insert(k.to_string(), minijinja::Value::from(v));
+            }
             // cmd output available as `raw` in expr context
             if state.json {
                 if let Ok(v) = serde_json::from_str::<serde_json::Value>(&cmd_raw) {
@@ -225,6 +329,9 @@ fn eval_single(state: &StateConfig, dep_results: &HashMap<String, &StateResult>)
         add_filters(&mut env);
 
         let mut ctx = HashMap::new();
+        for (k, v) in crate::util::builtin_vars() {
+            ctx.insert(k.to_string(), minijinja::Value::from(v));
+        }
         // Use parsed JSON for raw if available
         if let Some(ref v) = raw_parsed {
             ctx.insert("raw".to_string(), minijinja::Value::from_serialize(v));
@@ -270,7 +377,7 @@ fn eval_single(state: &StateConfig, dep_results: &HashMap<String, &StateResult>)
     }
 }
 
-fn eval_all(states: &[StateConfig]) -> Result<Vec<StateResult>> {
+fn eval_all(states: &[StateConfig], bypass_cache: bool) -> Result<Vec<StateResult>> {
     let layers = topo_sort(states)?;
     let mut results: HashMap<String, StateResult> = HashMap::new();
 
@@ -284,7 +391,7 @@ fn eval_all(states: &[StateConfig]) -> Result<Vec<StateResult>> {
                 .iter()
                 .filter_map(|d| results.get(d).map(|r| (d.clone(), r)))
                 .collect();
-            let result = eval_single(state, &dep_results);
+            let result = eval_single(state, &dep_results, bypass_cache);
             results.insert(result.name.clone(), result);
         } else {
             // Parallel eval within layer
@@ -298,7 +405,7 @@ fn eval_all(states: &[StateConfig]) -> Result<Vec<StateResult>> {
                             .iter()
                             .filter_map(|d| results.get(d).map(|r| (d.clone(), r)))
                             .collect();
-                        s.spawn(move || eval_single(state, &dep_results))
+                        s.spawn(move || eval_single(state, &dep_results, bypass_cache))
                     })
                     .collect();
                 handles.into_iter().map(|h| h.join().unwrap()).collect()
@@ -319,14 +426,17 @@ fn eval_all(states: &[StateConfig]) -> Result<Vec<StateResult>> {
 pub fn run(
     config_path: Option<std::path::PathBuf>,
     name: Option<String>,
-    json: bool,
+    format: StateFormat,
+    watch: bool,
+    interval: String,
+    no_cache: bool,
     args: Vec<String>,
 ) -> Result<()> {
     let path = crate::resolve_config(config_path)?;
     let resolved_path = config::resolve_path(&path)?;
     crate::util::init_lib(&resolved_path);
-    let meta = config::load_meta(&resolved_path);
-    if let Some(ref vars) = meta.as_ref().and_then(|m| m.vars.as_ref()) {
+    let meta = config::load_meta(&resolved_path)?;
+    if let Some(vars) = meta.as_ref().and_then(|m| m.vars.as_ref()) {
         config::apply_vars(vars, &[]);
     }
     let cfg = config::load_all(&resolved_path)?;
@@ -335,9 +445,40 @@ pub fn run(
         bail!("No state probes defined in config");
     }
 
-    // --json may end up in args due to trailing_var_arg
-    let json = json || args.iter().any(|a| a == "--json");
-    let args: Vec<String> = args.into_iter().filter(|a| a != "--json").collect();
+    // Flags may end up in args due to trailing_var_arg
+    let mut format = format;
+    let mut watch = watch;
+    let mut no_cache = no_cache;
+    let mut interval = interval;
+    let mut rest = Vec::new();
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--watch" {
+            watch = true;
+            continue;
+        }
+        if arg == "--no-cache" {
+            no_cache = true;
+            continue;
+        }
+        if arg == "--interval" || arg.starts_with("--interval=") {
+            interval = match arg.strip_prefix("--interval=") {
+                Some(v) => v.to_string(),
+                None => iter.next().ok_or_else(|| anyhow::anyhow!("Missing value after --interval"))?,
+            };
+            continue;
+        }
+        let value = match arg.as_str() {
+            "--json" => Some("json".to_string()),
+            "--format" => Some(iter.next().ok_or_else(|| anyhow::anyhow!("Missing value after --format"))?),
+            _ => arg.strip_prefix("--format=").map(String::from),
+        };
+        match value {
+            Some(v) => format = clap::ValueEnum::from_str(&v, true).map_err(|_| anyhow::anyhow!("Unknown state format '{}' (expected table, json, yaml or env)", v))?,
+            None => rest.push(arg),
+        }
+    }
+    let args = rest;
 
     // Parse the first name for dot notation
     let query = name.as_ref().map(|n| parse_query(n));
@@ -366,7 +507,35 @@ pub fn run(
     };
     let _ = needed_names; // We always eval all for simplicity with deps
 
-    let results = eval_all(&cfg.state)?;
+    if watch {
+        if has_op {
+            bail!("--watch can't be combined with is/isnot/get");
+        }
+        let every = format!("Every {}: dek state", interval);
+        let interval = crate::util::parse_duration(&interval)?;
+        if interval.is_zero() {
+            bail!("--interval must be greater than zero");
+        }
+        let is_tty = std::io::IsTerminal::is_terminal(&std::io::stdout());
+        loop {
+            // Probes with a ttl come from the cache until it expires
+            let results = eval_all(&cfg.state, no_cache)?;
+            if is_tty {
+                print!("\x1b[2J\x1b[H");
+            }
+            if format == StateFormat::Table {
+                use owo_colors::OwoColorize;
+                let now = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+                println!("{}  {}", c!(every, bold), c!(now, dimmed));
+                println!();
+            }
+            render(&results, &queries, format)?;
+            std::io::Write::flush(&mut std::io::stdout())?;
+            std::thread::sleep(interval);
+        }
+    }
+
+    let results = eval_all(&cfg.state, no_cache)?;
     let result_map: HashMap<&str, &StateResult> =
         results.iter().map(|r| (r.name.as_str(), r)).collect();
 
@@ -392,7 +561,7 @@ pub fn run(
                 let expected = args
                     .get(1)
                     .ok_or_else(|| anyhow::anyhow!("Missing value after 'is'"))?;
-                if value != *expected {
+                if value.as_ref() != expected.as_str() {
                     std::process::exit(1);
                 }
             }
@@ -400,7 +569,7 @@ pub fn run(
                 let expected = args
                     .get(1)
                     .ok_or_else(|| anyhow::anyhow!("Missing value after 'isnot'"))?;
-                if value == *expected {
+                if value.as_ref() == expected.as_str() {
                     std::process::exit(1);
                 }
             }
@@ -410,7 +579,7 @@ pub fn run(
                 }
                 let allowed = &args[1..args.len() - 1];
                 let fallback = &args[args.len() - 1];
-                if allowed.iter().any(|a| a == value) {
+                if allowed.iter().any(|a| a.as_str() == value.as_ref()) {
                     print!("{}", value);
                 } else {
                     print!("{}", fallback);
@@ -421,16 +590,24 @@ pub fn run(
         return Ok(());
     }
 
+    render(&results, &queries, format)
+}
+
+/// Print probe results (all, or just the queried ones) in the given format
+fn render(results: &[StateResult], queries: &[StateQuery], format: StateFormat) -> Result<()> {
+    let result_map: HashMap<&str, &StateResult> =
+        results.iter().map(|r| (r.name.as_str(), r)).collect();
+
     // Filter to requested queries
-    let display_results: Vec<(&str, &str, Option<&str>)> = if queries.is_empty() {
+    let display_results: Vec<(&str, Cow<str>, Option<&str>)> = if queries.is_empty() {
         // All probes, raw values
         results
             .iter()
-            .map(|r| (r.name.as_str(), r.raw.as_str(), None))
+            .map(|r| (r.name.as_str(), Cow::Borrowed(r.raw.as_str()), None))
             .collect()
     } else {
         let mut out = Vec::new();
-        for q in &queries {
+        for q in queries {
             let result = result_map
                 .get(q.name.as_str())
                 .ok_or_else(|| anyhow::anyhow!("Unknown state probe: {}", q.name))?;
@@ -452,17 +629,40 @@ pub fn run(
         out
     };
 
-    // Single query, no json → plain value
-    if display_results.len() == 1 && !json && !queries.is_empty() {
+    // Single query as a table → plain value
+    if display_results.len() == 1 && format == StateFormat::Table && !queries.is_empty() {
         println!("{}", display_results[0].1);
         return Ok(());
     }
 
-    if json {
+    if format == StateFormat::Env {
+        let label = |name: &str, variant: Option<&str>| match variant {
+            Some(v) => format!("{}.{}", name, v),
+            None => name.to_string(),
+        };
+        if queries.is_empty() {
+            // Every probe's raw value, then its original and templates
+            for r in results {
+                println!("{}={}", env_name(&r.name), crate::shell_escape(&r.raw));
+                if let Some(ref orig) = r.original {
+                    println!("{}={}", env_name(&label(&r.name, Some("original"))), crate::shell_escape(orig));
+                }
+                let mut templates: Vec<_> = r.templates.iter().collect();
+                templates.sort();
+                for (k, v) in templates {
+                    println!("{}={}", env_name(&label(&r.name, Some(k))), crate::shell_escape(v));
+                }
+            }
+        } else {
+            for (name, value, variant) in &display_results {
+                println!("{}={}", env_name(&label(name, *variant)), crate::shell_escape(value));
+            }
+        }
+    } else if matches!(format, StateFormat::Json | StateFormat::Yaml) {
         let mut map = serde_json::Map::new();
         if queries.is_empty() {
             // Full JSON with nested objects
-            for r in &results {
+            for r in results {
                 let mut obj = serde_json::Map::new();
                 obj.insert("raw".to_string(), r.raw_json());
                 if let Some(ref orig) = r.original {
@@ -486,7 +686,12 @@ pub fn run(
                 map.insert(key, serde_json::Value::String(value.to_string()));
             }
         }
-        println!("{}", serde_json::Value::Object(map));
+        let value = serde_json::Value::Object(map);
+        if format == StateFormat::Yaml {
+            print!("{}", serde_yml::to_string(&value)?);
+        } else {
+            println!("{}", value);
+        }
     } else {
         let max_name = display_results
             .iter()
@@ -505,7 +710,6 @@ pub fn run(
                 None => name.to_string(),
             };
             use owo_colors::OwoColorize;
-            let lines: Vec<&str> = value.lines().collect();
             let mut lines = value.lines();
             if let Some(first) = lines.next() {
                 println!(
@@ -527,6 +731,7 @@ pub fn run(
 pub fn eval_states(
     states: &[StateConfig],
     needed: &[String],
+    bypass_cache: bool,
 ) -> Result<HashMap<String, StateResult>> {
     // Compute transitive deps
     let name_set: HashMap<&str, &StateConfig> =
@@ -551,10 +756,38 @@ pub fn eval_states(
         .cloned()
         .collect();
 
-    let results = eval_all(&filtered)?;
+    let results = eval_all(&filtered, bypass_cache)?;
     Ok(results.into_iter().map(|r| (r.name.clone(), r)).collect())
 }
 
+/// Bail unless every probe query ("name" or "name.variant") has the expected value
+pub fn require(states: &[StateConfig], required: &HashMap<String, String>) -> Result<()> {
+    if required.is_empty() {
+        return Ok(());
+    }
+    let mut queries: Vec<_> = required.iter().collect();
+    queries.sort();
+    let needed: Vec<String> = queries.iter().map(|(q, _)| parse_query(q).name).collect();
+    let results = eval_states(states, &needed, false)?;
+    for (query, expected) in queries {
+        let q = parse_query(query);
+        let result = results
+            .get(&q.name)
+            .ok_or_else(|| anyhow::anyhow!("Unknown state probe: {}", q.name))?;
+        let value = result.get_variant(q.variant.as_deref()).ok_or_else(|| {
+            anyhow::anyhow!(
+                "Unknown variant '{}' for state '{}'",
+                q.variant.as_deref().unwrap_or(""),
+                q.name
+            )
+        })?;
+        if value.as_ref() != expected.as_str() {
+            bail!("state '{}' is '{}', requires '{}'", query, value, expected);
+        }
+    }
+    Ok(())
+}
+
 pub fn completions(states: &[StateConfig]) -> Vec<String> {
     let mut items = Vec::new();
     for s in states {
@@ -1,5 +1,7 @@
+use crate::config::AurHelper;
 use anyhow::{Context, Result};
 use indicatif::ProgressBar;
+use std::collections::HashMap;
 use std::fs;
 use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
@@ -19,9 +21,9 @@ pub fn expand_path<P: AsRef<Path>>(path: P) -> PathBuf {
     let path = path.as_ref();
     let path_str = path.to_string_lossy();
 
-    if path_str.starts_with("~/") {
+    if let Some(rest) = path_str.strip_prefix("~/") {
         if let Some(home) = std::env::var_os("HOME") {
-            return PathBuf::from(home).join(&path_str[2..]);
+            return PathBuf::from(home).join(rest);
         }
     } else if path_str == "~" {
         if let Some(home) = std::env::var_os("HOME") {
@@ -34,19 +36,81 @@ pub fn expand_path<P: AsRef<Path>>(path: P) -> PathBuf {
 
 /// Run a command and return output
 pub fn run_cmd(cmd: &str, args: &[&str]) -> Result<Output> {
-    Command::new(cmd)
+    let output = Command::new(cmd)
         .args(args)
         .output()
-        .with_context(|| format!("Failed to run: {} {}", cmd, args.join(" ")))
+        .with_context(|| format!("Failed to run: {} {}", cmd, args.join(" ")))?;
+    TRACE.with(|t| {
+        if let Some(traced) = t.borrow_mut().as_mut() {
+            traced.push(TracedCmd {
+                command: std::iter::once(cmd).chain(args.iter().copied()).collect::<Vec<_>>().join(" "),
+                code: output.status.code(),
+                stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+                stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            });
+        }
+    });
+    Ok(output)
+}
+
+/// A command run under `trace_commands`, with what it printed
+pub struct TracedCmd {
+    pub command: String,
+    pub code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+thread_local! {
+    static TRACE: std::cell::RefCell<Option<Vec<TracedCmd>>> = const { std::cell::RefCell::new(None) };
+}
+
+/// Run `f`, recording every `run_cmd` it makes on this thread
+pub fn trace_commands<T>(f: impl FnOnce() -> T) -> (T, Vec<TracedCmd>) {
+    TRACE.with(|t| *t.borrow_mut() = Some(Vec::new()));
+    let result = f();
+    let traced = TRACE.with(|t| t.borrow_mut().take()).unwrap_or_default();
+    (result, traced)
+}
+
+/// Run a command to completion, killing its whole process group if it takes
+/// longer than `timeout`. Returns None on timeout.
+pub fn output_with_timeout(cmd: &mut Command, timeout: std::time::Duration) -> Result<Option<Output>> {
+    use std::os::unix::process::CommandExt;
+
+    let child = cmd.process_group(0).spawn()?;
+    let pgid = child.id() as libc::pid_t;
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let _ = tx.send(child.wait_with_output());
+    });
+
+    match rx.recv_timeout(timeout) {
+        Ok(output) => Ok(Some(output?)),
+        Err(_) => {
+            unsafe { libc::kill(-pgid, libc::SIGKILL) };
+            // Let the waiter reap it; don't hang on strays that left the group
+            let _ = rx.recv_timeout(std::time::Duration::from_secs(1));
+            Ok(None)
+        }
+    }
 }
 
 /// Run a command and check if it succeeded
 pub fn run_cmd_ok(cmd: &str, args: &[&str]) -> bool {
-    Command::new(cmd)
-        .args(args)
-        .output()
-        .map(|o| o.status.success())
-        .unwrap_or(false)
+    run_cmd(cmd, args).map(|o| o.status.success()).unwrap_or(false)
+}
+
+static BECOME_PASS: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+
+/// Set the sudo password forwarded by a remote deploy (kept in memory only)
+pub fn set_become_pass(pass: String) {
+    let _ = BECOME_PASS.set(pass);
+}
+
+/// sudo password forwarded by a remote deploy, if any
+pub fn become_pass() -> Option<&'static str> {
+    BECOME_PASS.get().map(|s| s.as_str())
 }
 
 /// Run a command with sudo (or directly if already root)
@@ -60,10 +124,69 @@ pub fn run_sudo(cmd: &str, args: &[&str]) -> Result<Output> {
     run_cmd("sudo", &sudo_args)
 }
 
+thread_local! {
+    static LAST_OUTPUT: std::cell::RefCell<Option<Vec<u8>>> = const { std::cell::RefCell::new(None) };
+}
+
+/// Take the stdout+stderr captured by the last `run_cmd_live*` call on this
+/// thread, leaving nothing behind
+pub fn take_last_output() -> Option<String> {
+    LAST_OUTPUT
+        .with(|o| o.borrow_mut().take())
+        .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+}
+
+thread_local! {
+    /// Deadline for commands started on this thread, and the timeout it came from
+    static DEADLINE: std::cell::Cell<Option<(std::time::Instant, std::time::Duration)>> =
+        const { std::cell::Cell::new(None) };
+}
+
+/// Limit commands started on this thread (an item's `timeout`) to `timeout`
+/// from now; None lifts the limit
+pub fn set_timeout(timeout: Option<std::time::Duration>) {
+    DEADLINE.with(|d| d.set(timeout.map(|t| (std::time::Instant::now() + t, t))));
+}
+
+/// Under a timeout, start the command in its own process group, so it can
+/// be killed along with whatever it spawned
+pub fn timeout_group(cmd: &mut Command) -> &mut Command {
+    if DEADLINE.with(|d| d.get()).is_some() {
+        use std::os::unix::process::CommandExt;
+        cmd.process_group(0);
+    }
+    cmd
+}
+
+/// `child.wait()`, except that past the deadline the child's process group
+/// gets SIGTERM, then SIGKILL, and a timeout error is returned
+pub fn wait_child(child: &mut std::process::Child) -> Result<std::process::ExitStatus> {
+    let Some((deadline, timeout)) = DEADLINE.with(|d| d.get()) else {
+        return Ok(child.wait()?);
+    };
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if std::time::Instant::now() >= deadline {
+            let pgid = -(child.id() as i32);
+            unsafe { libc::kill(pgid, libc::SIGTERM) };
+            let grace = std::time::Instant::now() + std::time::Duration::from_secs(2);
+            while child.try_wait()?.is_none() && std::time::Instant::now() < grace {
+                std::thread::sleep(std::time::Duration::from_millis(50));
+            }
+            unsafe { libc::kill(pgid, libc::SIGKILL) };
+            let _ = child.kill();
+            let _ = child.wait();
+            anyhow::bail!("timed out after {}", crate::output::format_duration(timeout));
+        }
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+}
+
 /// Run a command with piped output, updating a spinner with each line
 pub fn run_cmd_live(cmd: &str, args: &[&str], pb: &ProgressBar) -> Result<Output> {
-    let child = Command::new(cmd)
-        .args(args)
+    let child = timeout_group(Command::new(cmd).args(args))
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
         .spawn()
@@ -73,8 +196,7 @@ pub fn run_cmd_live(cmd: &str, args: &[&str], pb: &ProgressBar) -> Result<Output
 
 /// Run a command with piped output and custom working directory
 pub fn run_cmd_live_dir(cmd: &str, args: &[&str], pb: &ProgressBar, dir: &Path) -> Result<Output> {
-    let child = Command::new(cmd)
-        .args(args)
+    let child = timeout_group(Command::new(cmd).args(args))
         .current_dir(dir)
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
@@ -87,31 +209,30 @@ fn run_cmd_live_inner(mut child: std::process::Child, pb: &ProgressBar) -> Resul
     let stdout = child.stdout.take().unwrap();
     let stderr = child.stderr.take().unwrap();
 
-    let pb2 = pb.clone();
-    let stderr_thread = std::thread::spawn(move || {
-        let mut collected = Vec::new();
-        for line in BufReader::new(stderr).lines() {
-            if let Ok(line) = line {
-                crate::output::update_spinner(&pb2, &line);
+    // Both streams are read on their own threads so this one can enforce the timeout
+    let read_lines = |stream: Box<dyn std::io::Read + Send>, pb: ProgressBar| {
+        std::thread::spawn(move || {
+            let mut collected = Vec::new();
+            for line in BufReader::new(stream).lines().map_while(Result::ok) {
+                crate::output::update_spinner(&pb, &line);
                 collected.extend(line.as_bytes().iter().copied());
                 collected.push(b'\n');
             }
-        }
-        collected
-    });
-
-    let mut stdout_bytes = Vec::new();
-    for line in BufReader::new(stdout).lines() {
-        if let Ok(line) = line {
-            crate::output::update_spinner(pb, &line);
-            stdout_bytes.extend(line.as_bytes().iter().copied());
-            stdout_bytes.push(b'\n');
-        }
-    }
+            collected
+        })
+    };
+    let stdout_thread = read_lines(Box::new(stdout), pb.clone());
+    let stderr_thread = read_lines(Box::new(stderr), pb.clone());
 
-    let status = child.wait()?;
+    let status = wait_child(&mut child)?;
+    let stdout_bytes = stdout_thread.join().unwrap_or_default();
     let stderr_bytes = stderr_thread.join().unwrap_or_default();
 
+    // Kept for -v, which dumps it if the item ends up failing
+    let mut captured = stdout_bytes.clone();
+    captured.extend_from_slice(&stderr_bytes);
+    LAST_OUTPUT.with(|o| *o.borrow_mut() = Some(captured));
+
     Ok(Output {
         status,
         stdout: stdout_bytes,
@@ -142,23 +263,58 @@ pub fn command_exists(cmd: &str) -> bool {
     which::which(cmd).is_ok()
 }
 
+/// PATH is read, changed and written back by items on every --jobs lane
+static PATH_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+/// Add `dir` to this process's PATH, so later items and the commands they
+/// run find what was just installed: in front, or last with `append`.
+/// Nothing changes when it's already there.
+pub fn add_to_path(dir: &Path, append: bool) {
+    let _guard = PATH_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let current = std::env::var_os("PATH").unwrap_or_default();
+    let mut parts: Vec<PathBuf> = std::env::split_paths(&current).collect();
+    if parts.iter().any(|p| p == dir) {
+        return;
+    }
+    if append {
+        parts.push(dir.to_path_buf());
+    } else {
+        parts.insert(0, dir.to_path_buf());
+    }
+    if let Ok(joined) = std::env::join_paths(parts) {
+        std::env::set_var("PATH", joined);
+    }
+}
+
 /// User's shell type
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Shell {
     Zsh,
     Bash,
     Fish,
+    Nushell,
+    Pwsh,
 }
 
 impl Shell {
-    /// Detect the user's shell from $SHELL
+    /// Detect the user's shell: running inside nushell, then $SHELL, then PowerShell
     pub fn detect() -> Self {
+        if std::env::var_os("NU_VERSION").is_some() {
+            return Self::Nushell;
+        }
         if let Ok(shell) = std::env::var("SHELL") {
+            let name = Path::new(&shell).file_stem().and_then(|n| n.to_str()).unwrap_or("");
             if shell.contains("zsh") {
                 return Self::Zsh;
             } else if shell.contains("fish") {
                 return Self::Fish;
+            } else if name == "nu" {
+                return Self::Nushell;
+            } else if name == "pwsh" || name == "powershell" {
+                return Self::Pwsh;
             }
+        } else if std::env::var_os("PSModulePath").is_some() {
+            return Self::Pwsh;
         }
         Self::Bash
     }
@@ -169,6 +325,8 @@ impl Shell {
             Self::Zsh => "zsh",
             Self::Bash => "bash",
             Self::Fish => "fish",
+            Self::Nushell => "nu",
+            Self::Pwsh => "pwsh",
         }
     }
 
@@ -178,6 +336,9 @@ impl Shell {
             Self::Zsh => "~/.zshrc",
             Self::Bash => "~/.bashrc",
             Self::Fish => "~/.config/fish/config.fish",
+            Self::Nushell if cfg!(target_os = "macos") => "~/Library/Application Support/nushell/config.nu",
+            Self::Nushell => "~/.config/nushell/config.nu",
+            Self::Pwsh => "~/.config/powershell/Microsoft.PowerShell_profile.ps1",
         }
     }
 }
@@ -204,14 +365,58 @@ impl SysPkgManager {
         }
     }
 
+    /// Short name, as exposed to templates (`pkgmgr`)
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Apt => "apt",
+            Self::Pacman => "pacman",
+            Self::Brew => "brew",
+        }
+    }
+
+    /// Whether `pkg` is installed
+    pub fn is_installed(&self, pkg: &str) -> Result<bool> {
+        Ok(match self {
+            Self::Pacman => run_cmd_ok("pacman", &["-Q", pkg]),
+            Self::Apt => {
+                let output = run_cmd("dpkg-query", &["-W", "-f=${Status}", pkg])?;
+                String::from_utf8_lossy(&output.stdout).contains("install ok installed")
+            }
+            Self::Brew => run_cmd_ok("brew", &["list", pkg]),
+        })
+    }
+
+    /// Install command and args (without sudo) for `pkg`
+    fn install_args<'a>(&self, pkg: &'a str) -> (&'static str, Vec<&'a str>) {
+        match self {
+            Self::Pacman => ("pacman", vec!["-S", "--noconfirm", pkg]),
+            Self::Apt => ("apt-get", vec!["install", "-y", pkg]),
+            Self::Brew => ("brew", vec!["install", pkg]),
+        }
+    }
+
+    /// Install `pkg`, streaming output to the spinner
+    pub fn install_live(&self, pkg: &str, pb: &ProgressBar) -> Result<Output> {
+        let (cmd, args) = self.install_args(pkg);
+        match self {
+            Self::Pacman | Self::Apt => run_sudo_live(cmd, &args, pb),
+            Self::Brew => run_cmd_live(cmd, &args, pb),
+        }
+    }
+
     /// Install a package using this package manager
     pub fn install(&self, pkg: &str) -> Result<()> {
+        self.install_with_aur(pkg, None)
+    }
+
+    /// `install`, with pacman falling back to this AUR helper (None auto-detects)
+    pub fn install_with_aur(&self, pkg: &str, aur_helper: Option<AurHelper>) -> Result<()> {
         let output = match self {
             Self::Pacman => {
                 let out = run_sudo("pacman", &["-S", "--noconfirm", pkg])?;
                 if !out.status.success() {
-                    // Pacman failed - try yay for AUR packages
-                    return install_with_yay(pkg);
+                    // Pacman failed - try the AUR helper
+                    return install_from_aur(pkg, aur_helper);
                 }
                 return Ok(());
             }
@@ -229,7 +434,10 @@ impl SysPkgManager {
                 }
                 run_sudo("apt-get", &["install", "-y", pkg])?
             }
-            Self::Brew => run_cmd("brew", &["install", pkg])?,
+            Self::Brew => {
+                let (cmd, args) = self.install_args(pkg);
+                run_cmd(cmd, &args)?
+            }
         };
 
         if !output.status.success() {
@@ -243,108 +451,378 @@ impl SysPkgManager {
     }
 }
 
-/// Install a package via yay (AUR helper), installing yay first if needed
-pub fn install_with_yay(pkg: &str) -> Result<()> {
-    if !command_exists("yay") {
-        install_yay()?;
+/// Unified diff of a file's current vs desired content (3 lines of context),
+/// or None when they're equal
+pub fn unified_diff(path: &Path, current: &str, desired: &str) -> Option<String> {
+    if current == desired {
+        return None;
+    }
+    let name = path.display().to_string();
+    let diff = similar::TextDiff::from_lines(current, desired)
+        .unified_diff()
+        .context_radius(3)
+        .header(&name, &name)
+        .to_string();
+    Some(diff)
+}
+
+/// Standard base64 (with padding)
+pub fn base64_encode(data: &[u8]) -> String {
+    const CHARS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | *chunk.get(2).unwrap_or(&0) as u32;
+        out.push(CHARS[(n >> 18) as usize & 63] as char);
+        out.push(CHARS[(n >> 12) as usize & 63] as char);
+        out.push(if chunk.len() > 1 { CHARS[(n >> 6) as usize & 63] as char } else { '=' });
+        out.push(if chunk.len() > 2 { CHARS[n as usize & 63] as char } else { '=' });
+    }
+    out
+}
+
+/// Distro ID from /etc/os-release (e.g. "arch", "ubuntu"), falling back to the OS name
+pub fn distro_id() -> String {
+    fs::read_to_string("/etc/os-release")
+        .ok()
+        .and_then(|content| {
+            content.lines().find_map(|line| {
+                line.strip_prefix("ID=")
+                    .map(|id| id.trim().trim_matches('"').to_string())
+            })
+        })
+        .unwrap_or_else(|| std::env::consts::OS.to_string())
+}
+
+/// Distro ID plus its ID_LIKE parents (e.g. ["ubuntu", "debian"])
+pub fn distro_ids() -> Vec<String> {
+    let mut ids = vec![distro_id()];
+    if let Ok(content) = fs::read_to_string("/etc/os-release") {
+        if let Some(like) = content.lines().find_map(|l| l.strip_prefix("ID_LIKE=")) {
+            ids.extend(like.trim().trim_matches('"').split_whitespace().map(String::from));
+        }
+    }
+    ids
+}
+
+/// Built-in variables available to file templates and state expressions
+pub fn builtin_vars() -> Vec<(&'static str, String)> {
+    vec![
+        (
+            "hostname",
+            hostname::get()
+                .map(|h| h.to_string_lossy().to_string())
+                .unwrap_or_default(),
+        ),
+        ("user", std::env::var("USER").unwrap_or_default()),
+        ("os", std::env::consts::OS.to_string()),
+        ("arch", std::env::consts::ARCH.to_string()),
+        ("distro", distro_id()),
+        (
+            "pkgmgr",
+            SysPkgManager::detect().map(|p| p.name().to_string()).unwrap_or_default(),
+        ),
+    ]
+}
+
+/// The configured helper (`[meta] aur_helper`), else the first one
+/// installed, else yay
+fn aur_helper(configured: Option<AurHelper>) -> &'static str {
+    configured
+        .or_else(|| AurHelper::ALL.into_iter().find(|h| command_exists(h.as_str())))
+        .unwrap_or(AurHelper::Yay)
+        .as_str()
+}
+
+/// Install a package via the AUR helper, installing the helper first if needed
+pub fn install_from_aur(pkg: &str, helper: Option<AurHelper>) -> Result<()> {
+    let helper = aur_helper(helper);
+    if !command_exists(helper) {
+        install_aur_helper(helper)?;
     }
-    let output = run_cmd("yay", &["-S", "--noconfirm", pkg])?;
+    let output = run_cmd(helper, &["-S", "--noconfirm", pkg])?;
     if !output.status.success() {
         anyhow::bail!(
-            "Failed to install '{}' via yay: {}",
+            "Failed to install '{}' via {}: {}",
             pkg,
+            helper,
             String::from_utf8_lossy(&output.stderr)
         );
     }
     Ok(())
 }
 
-/// Install a package via yay with live progress
-pub fn install_with_yay_live(pkg: &str, pb: &ProgressBar) -> Result<()> {
-    if !command_exists("yay") {
-        install_yay()?;
+/// Install a package via the AUR helper with live progress
+pub fn install_from_aur_live(pkg: &str, helper: Option<AurHelper>, pb: &ProgressBar) -> Result<()> {
+    let helper = aur_helper(helper);
+    if !command_exists(helper) {
+        install_aur_helper(helper)?;
     }
-    let output = run_cmd_live("yay", &["-S", "--noconfirm", pkg], pb)?;
+    let output = run_cmd_live(helper, &["-S", "--noconfirm", pkg], pb)?;
     if !output.status.success() {
         anyhow::bail!(
-            "Failed to install '{}' via yay: {}",
+            "Failed to install '{}' via {}: {}",
             pkg,
+            helper,
             String::from_utf8_lossy(&output.stderr)
         );
     }
     Ok(())
 }
 
-/// Install yay from AUR
-fn install_yay() -> Result<()> {
+/// Build and install an AUR helper from its AUR package
+fn install_aur_helper(helper: &str) -> Result<()> {
     use owo_colors::OwoColorize;
-    println!("    {} installing yay...", c!("→", yellow));
+    println!("    {} installing {}...", c!("→", yellow), helper);
 
     // Ensure base-devel and git
     let _ = run_sudo("pacman", &["-S", "--needed", "--noconfirm", "git", "base-devel"]);
 
-    let tmp = "/tmp/dek-yay-install";
-    let _ = std::fs::remove_dir_all(tmp);
+    let tmp = format!("/tmp/dek-{}-install", helper);
+    let _ = std::fs::remove_dir_all(&tmp);
 
     let clone = Command::new("git")
-        .args(["clone", "https://aur.archlinux.org/yay.git", tmp])
+        .args(["clone", &format!("https://aur.archlinux.org/{}.git", helper), &tmp])
         .output()
-        .context("Failed to clone yay")?;
+        .with_context(|| format!("Failed to clone {}", helper))?;
     if !clone.status.success() {
-        anyhow::bail!("Failed to clone yay from AUR");
+        anyhow::bail!("Failed to clone {} from AUR", helper);
     }
 
     let build = Command::new("makepkg")
         .args(["-si", "--noconfirm"])
-        .current_dir(tmp)
+        .current_dir(&tmp)
         .status()
-        .context("Failed to build yay")?;
+        .with_context(|| format!("Failed to build {}", helper))?;
     if !build.success() {
-        anyhow::bail!("Failed to build/install yay");
+        anyhow::bail!("Failed to build/install {}", helper);
     }
 
     let _ = std::fs::remove_dir_all(tmp);
     Ok(())
 }
 
-/// Run a script from a URL via curl | sh
-pub fn run_install_script(url: &str, args: &[&str]) -> Result<()> {
-    // Ensure curl is available — install via system package manager if missing
-    if !command_exists("curl") {
-        if let Some(pm) = SysPkgManager::detect() {
-            pm.install("curl")?;
-        } else {
-            anyhow::bail!("curl not found and no package manager available to install it");
+static RETRIES: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(2);
+
+/// First wait between attempts; doubles after each one
+const RETRY_BACKOFF: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Extra attempts for network-bound steps (--retries)
+pub fn set_retries(n: u32) {
+    RETRIES.store(n, std::sync::atomic::Ordering::Relaxed);
+}
+
+pub fn retries() -> u32 {
+    RETRIES.load(std::sync::atomic::Ordering::Relaxed)
+}
+
+/// An error retrying won't fix (bad URL, 404, auth failure); `retry` gives up on it
+#[derive(Debug)]
+pub struct Fatal(pub String);
+
+impl std::fmt::Display for Fatal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Fatal {}
+
+/// Run `f`, retrying up to `attempts` more times with exponential backoff
+/// unless it fails with `Fatal`
+pub fn retry<T>(attempts: u32, backoff: std::time::Duration, mut f: impl FnMut() -> Result<T>) -> Result<T> {
+    let mut delay = backoff;
+    for _ in 0..attempts {
+        match f() {
+            Err(e) if !e.is::<Fatal>() => {
+                std::thread::sleep(delay);
+                delay *= 2;
+            }
+            result => return result,
         }
     }
+    f().map_err(|e| if attempts > 0 { anyhow::anyhow!("{} (after {} attempts)", e, attempts + 1) } else { e })
+}
+
+/// Run a command (`run` spawns it and collects its output), re-running it
+/// with backoff while it fails in a way `retryable` says may be transient.
+/// Returns the last attempt's output; spawn errors are returned right away.
+pub fn retry_output(mut run: impl FnMut() -> Result<Output>, retryable: impl Fn(&Output) -> bool) -> Result<Output> {
+    let mut delay = RETRY_BACKOFF;
+    for _ in 0..retries() {
+        let output = run()?;
+        if output.status.success() || !retryable(&output) {
+            return Ok(output);
+        }
+        std::thread::sleep(delay);
+        delay *= 2;
+    }
+    run()
+}
+
+/// What cargo, go, npm and pip print when the network, not the package, failed
+const NETWORK_ERRORS: &[&str] = &[
+    // curl/libc, as surfaced by cargo and others
+    "Could not resolve host",
+    "Couldn't resolve host",
+    "Temporary failure in name resolution",
+    "Timeout was reached",
+    "timed out",
+    "Connection reset",
+    "Connection refused",
+    "connection reset",
+    "connection refused",
+    "spurious network error",
+    "failed to download",
+    // go
+    "dial tcp",
+    "i/o timeout",
+    "TLS handshake timeout",
+    // npm
+    "ETIMEDOUT",
+    "ECONNRESET",
+    "ECONNREFUSED",
+    "EAI_AGAIN",
+    "socket hang up",
+    // pip
+    "ConnectTimeoutError",
+    "ReadTimeoutError",
+    "NewConnectionError",
+    "Connection aborted",
+];
+
+/// Whether an installer's failure looks like a network blip worth retrying,
+/// rather than a compile error or a package that doesn't exist
+fn is_network_failure(output: &Output) -> bool {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    NETWORK_ERRORS.iter().any(|e| stderr.contains(e) || stdout.contains(e))
+}
 
-    let curl = Command::new("curl")
-        .args(["-fsSL", url])
+/// `run_cmd`, retried while it fails on the network — for installers
+/// (cargo, go, npm, pip)
+pub fn run_cmd_retry(cmd: &str, args: &[&str]) -> Result<Output> {
+    retry_output(|| run_cmd(cmd, args), is_network_failure)
+}
+
+/// `run_cmd_live`, retried while it fails on the network
+pub fn run_cmd_live_retry(cmd: &str, args: &[&str], pb: &ProgressBar) -> Result<Output> {
+    retry_output(|| run_cmd_live(cmd, args, pb), is_network_failure)
+}
+
+/// curl -fsSL with `--retries` attempts. Malformed URLs and 4xx responses
+/// fail right away as `Fatal`.
+fn curl(url: &str, extra: &[&str]) -> Result<Output> {
+    retry(retries(), RETRY_BACKOFF, || {
+        let output = Command::new("curl")
+            .args(["-fsSL", "--show-error"])
+            .args(extra)
+            .arg(url)
+            .output()
+            .with_context(|| format!("Failed to fetch: {}", url))?;
+        if output.status.success() {
+            return Ok(output);
+        }
+        let err = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        let msg = format!("Failed to download {}: {}", url, err.trim_start_matches("curl: "));
+        // 3: malformed URL; 22 with a 4xx: the server said no
+        if output.status.code() == Some(3) || (output.status.code() == Some(22) && err.contains("error: 4")) {
+            return Err(Fatal(msg).into());
+        }
+        anyhow::bail!(msg)
+    })
+}
+
+/// GET a URL without failing on HTTP errors: (final status, body). Redirects
+/// are followed; gives up after 30s.
+pub fn http_get(url: &str) -> Result<(u16, String)> {
+    if !command_exists("curl") {
+        anyhow::bail!("curl not found");
+    }
+    let output = Command::new("curl")
+        .args(["-sSL", "--max-time", "30", "-w", "\n%{http_code}", url])
         .output()
-        .context("Failed to download install script")?;
+        .with_context(|| format!("Failed to fetch: {}", url))?;
+    if !output.status.success() {
+        let err = String::from_utf8_lossy(&output.stderr);
+        let err = err.trim().trim_start_matches("curl: ");
+        // Drop curl's "(7) " exit code prefix
+        let err = err.strip_prefix('(').and_then(|e| e.split_once(") ")).map_or(err, |(_, msg)| msg);
+        anyhow::bail!("{}", err);
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let (body, code) = stdout.rsplit_once('\n').unwrap_or(("", &stdout));
+    let status = code.trim().parse().with_context(|| format!("Bad status from curl: {}", code))?;
+    Ok((status, body.to_string()))
+}
+
+/// Pinned install script hashes (`[meta] install_script_sha256`), and
+/// whether unpinned scripts may run (`pinned_install_scripts`)
+#[derive(Debug, Clone, Default)]
+pub struct ScriptPins {
+    pub sha256: HashMap<String, String>,
+    pub required: bool,
+}
+
+static INSECURE: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+/// Run install scripts whatever their hash (--insecure)
+pub fn set_insecure(insecure: bool) {
+    INSECURE.store(insecure, std::sync::atomic::Ordering::Relaxed);
+}
+
+pub fn insecure() -> bool {
+    INSECURE.load(std::sync::atomic::Ordering::Relaxed)
+}
 
-    if !curl.status.success() {
-        anyhow::bail!("Failed to download: {}", url);
+/// Check a downloaded install script against its pinned sha256, if any
+fn verify_install_script(url: &str, script: &[u8], pins: &ScriptPins) -> Result<()> {
+    use sha2::Digest;
+    if insecure() {
+        return Ok(());
     }
+    let got: String = sha2::Sha256::digest(script).iter().map(|b| format!("{:02x}", b)).collect();
+    match pins.sha256.get(url) {
+        Some(want) if !want.trim_start_matches("sha256:").eq_ignore_ascii_case(&got) => anyhow::bail!(
+            "Install script {} doesn't match its pinned sha256 (got {}); pass --insecure to run it anyway",
+            url,
+            got
+        ),
+        None if pins.required => anyhow::bail!(
+            "Install script {} isn't pinned; add \"{}\" = \"{}\" to [meta] install_script_sha256, or pass --insecure",
+            url,
+            url,
+            got
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// Run a script from a URL via curl | sh, once it passes `verify_install_script`
+pub fn run_install_script(url: &str, args: &[&str], pins: &ScriptPins) -> Result<()> {
+    // Ensure curl is available — install via system package manager if missing
+    ensure_curl()?;
+
+    let curl = curl(url, &[]).context("Failed to download install script")?;
+    verify_install_script(url, &curl.stdout, pins)?;
 
     let mut sh_args = vec!["-s", "--"];
     sh_args.extend(args);
 
-    let sh = Command::new("sh")
-        .args(&sh_args)
+    let mut child = timeout_group(Command::new("sh").args(&sh_args))
         .stdin(std::process::Stdio::piped())
         .stdout(std::process::Stdio::inherit())
         .stderr(std::process::Stdio::inherit())
         .spawn()
         .context("Failed to spawn shell")?;
 
-    let mut child = sh;
     if let Some(mut stdin) = child.stdin.take() {
         use std::io::Write;
         stdin.write_all(&curl.stdout)?;
     }
 
-    let status = child.wait()?;
+    let status = wait_child(&mut child)?;
     if !status.success() {
         anyhow::bail!("Install script failed");
     }
@@ -355,6 +833,12 @@ pub fn run_install_script(url: &str, args: &[&str]) -> Result<()> {
 /// Expand environment variables in a string: $VAR and ${VAR}.
 /// Only expands $NAME and ${NAME} patterns. Other $ uses ($(...), $$, etc.) are preserved.
 pub fn expand_vars(s: &str) -> String {
+    expand_with(s, |name| std::env::var(name).ok())
+}
+
+/// `$NAME` / `${NAME}` expansion with values from `lookup`; names it doesn't
+/// know are left as written
+pub fn expand_with(s: &str, mut lookup: impl FnMut(&str) -> Option<String>) -> String {
     let mut result = String::with_capacity(s.len());
     let mut chars = s.chars().peekable();
     while let Some(c) = chars.next() {
@@ -362,9 +846,9 @@ pub fn expand_vars(s: &str) -> String {
             if chars.peek() == Some(&'{') {
                 chars.next(); // skip {
                 let name: String = chars.by_ref().take_while(|&c| c != '}').collect();
-                match std::env::var(&name) {
-                    Ok(val) => result.push_str(&val),
-                    Err(_) => {
+                match lookup(&name) {
+                    Some(val) => result.push_str(&val),
+                    None => {
                         result.push('$');
                         result.push('{');
                         result.push_str(&name);
@@ -381,9 +865,9 @@ pub fn expand_vars(s: &str) -> String {
                         break;
                     }
                 }
-                match std::env::var(&name) {
-                    Ok(val) => result.push_str(&val),
-                    Err(_) => {
+                match lookup(&name) {
+                    Some(val) => result.push_str(&val),
+                    None => {
                         result.push('$');
                         result.push_str(&name);
                     }
@@ -432,6 +916,97 @@ pub fn fetch_url(url: &str, max_age: Option<std::time::Duration>) -> Result<Vec<
     if let Some(data) = crate::cache::get(url, max_age) {
         return Ok(data);
     }
+    ensure_curl()?;
+    let output = curl(url, &[])?;
+    crate::cache::set(url, &output.stdout);
+    Ok(output.stdout)
+}
+
+/// Like `fetch_url`, but shows downloaded/total bytes and an ETA on the
+/// item's spinner. Only when the server reports a content-length and stdout
+/// is a terminal; otherwise the spinner just ticks as before.
+pub fn fetch_url_live(url: &str, max_age: Option<std::time::Duration>, pb: &ProgressBar) -> Result<Vec<u8>> {
+    if let Some(data) = crate::cache::get(url, max_age) {
+        return Ok(data);
+    }
+    if pb.is_hidden() || !console::Term::stdout().is_term() {
+        return fetch_url(url, max_age);
+    }
+    ensure_curl()?;
+    let Some(total) = content_length(url) else {
+        return fetch_url(url, max_age);
+    };
+
+    // Created (exclusively, with a random name) before curl writes to it
+    let tmp = tempfile::Builder::new().prefix("dek-fetch-").tempfile()?;
+    retry(retries(), RETRY_BACKOFF, || download_with_progress(url, total, tmp.path(), pb))?;
+    let data = fs::read(tmp.path()).with_context(|| format!("Failed to read download of {}", url))?;
+    crate::cache::set(url, &data);
+    Ok(data)
+}
+
+/// Download `url` to `tmp`, showing progress towards `total` bytes on `pb`
+fn download_with_progress(url: &str, total: u64, tmp: &Path, pb: &ProgressBar) -> Result<()> {
+    use owo_colors::OwoColorize;
+
+    let mut child = Command::new("curl")
+        .args(["-fsSL", "-o"])
+        .arg(tmp)
+        .arg(url)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .with_context(|| format!("Failed to fetch: {}", url))?;
+
+    let start = std::time::Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        let done = fs::metadata(tmp).map(|m| m.len()).unwrap_or(0);
+        let elapsed = start.elapsed().as_secs_f64();
+        let eta = if done > 0 && elapsed > 0.5 {
+            let secs = (total.saturating_sub(done)) as f64 * elapsed / done as f64;
+            format!(", ETA {}", crate::output::format_duration(std::time::Duration::from_secs_f64(secs)))
+        } else {
+            String::new()
+        };
+        let line = format!(
+            "{} / {} ({}%{})",
+            crate::output::format_bytes(done),
+            crate::output::format_bytes(total),
+            done * 100 / total.max(1),
+            eta
+        );
+        pb.set_message(format!("{} {}", c!("›", dimmed), c!(line, dimmed)));
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    };
+
+    match status.code() {
+        Some(0) => Ok(()),
+        // Malformed URL
+        Some(3) => Err(Fatal(format!("Failed to download: {}", url)).into()),
+        _ => anyhow::bail!("Failed to download: {}", url),
+    }
+}
+
+/// Size of the resource at `url` from a HEAD request (after redirects)
+fn content_length(url: &str) -> Option<u64> {
+    let output = Command::new("curl").args(["-fsSLI", url]).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    // Each redirect hop prints its own headers; the last length is the file's
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|l| l.split_once(':'))
+        .filter(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .filter_map(|(_, value)| value.trim().parse().ok())
+        .next_back()
+        .filter(|&len| len > 0)
+}
+
+fn ensure_curl() -> Result<()> {
     if !command_exists("curl") {
         if let Some(pm) = SysPkgManager::detect() {
             pm.install("curl")?;
@@ -439,15 +1014,7 @@ pub fn fetch_url(url: &str, max_age: Option<std::time::Duration>) -> Result<Vec<
             anyhow::bail!("curl not found and no package manager available");
         }
     }
-    let output = Command::new("curl")
-        .args(["-fsSL", url])
-        .output()
-        .with_context(|| format!("Failed to fetch: {}", url))?;
-    if !output.status.success() {
-        anyhow::bail!("Failed to download: {}", url);
-    }
-    crate::cache::set(url, &output.stdout);
-    Ok(output.stdout)
+    Ok(())
 }
 
 /// Check if path is a tar.gz file
@@ -467,15 +1034,149 @@ pub fn extract_tar_gz(path: &Path) -> Result<PathBuf> {
         return Ok(cache_dir);
     }
 
-    let decoder = flate2::read::GzDecoder::new(&data[..]);
+    unpack_tar_gz(&data, &cache_dir).with_context(|| format!("Failed to extract: {}", path.display()))?;
+    Ok(cache_dir)
+}
+
+fn unpack_tar_gz(data: &[u8], dest: &Path) -> Result<()> {
+    let decoder = flate2::read::GzDecoder::new(data);
     let mut archive = tar::Archive::new(decoder);
-    fs::create_dir_all(&cache_dir)
-        .with_context(|| format!("Failed to create cache dir: {}", cache_dir.display()))?;
-    archive
-        .unpack(&cache_dir)
-        .with_context(|| format!("Failed to extract: {}", path.display()))?;
+    fs::create_dir_all(dest).with_context(|| format!("Failed to create cache dir: {}", dest.display()))?;
+    archive.unpack(dest)?;
+    Ok(())
+}
 
-    Ok(cache_dir)
+/// Check if a config path is a remote source: `git+<url>`, `ssh://`,
+/// `git@host:repo`, or an `http(s)://` URL
+pub fn is_remote_config(spec: &str) -> bool {
+    ["git+", "ssh://", "git@", "http://", "https://"].iter().any(|p| spec.starts_with(p))
+}
+
+/// Fetch a remote config into the user's cache dir and return the local
+/// path. Git sources are shallow-cloned (`#ref` picks a branch or tag) and
+/// updated on later runs; http(s) URLs are downloaded, and extracted when
+/// they point at a tar.gz. A source that can't be reached is an error.
+pub fn fetch_remote_config(spec: &str) -> Result<PathBuf> {
+    let parent = crate::cache::remote_config_dir();
+    private_dir(&parent)?;
+    let dir = parent.join(format!("{:x}", md5::compute(spec)));
+    match git_source(spec) {
+        Some((url, reference)) => fetch_git_config(url, reference, &dir)?,
+        None => fetch_http_config(spec, &dir)?,
+    }
+    Ok(dir)
+}
+
+/// Fail unless `dir` is a directory owned by us that nobody else can get into
+#[cfg(unix)]
+pub fn check_private(dir: &Path) -> Result<()> {
+    use std::os::unix::fs::MetadataExt;
+    let meta = fs::symlink_metadata(dir).with_context(|| format!("Failed to stat {}", dir.display()))?;
+    if !meta.is_dir() || meta.uid() != unsafe { libc::geteuid() } || meta.mode() & 0o077 != 0 {
+        anyhow::bail!("{} isn't a private directory owned by the current user", dir.display());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn check_private(_dir: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// Whether `path` itself (not a symlink's target) belongs to the current user
+#[cfg(unix)]
+pub fn owned_by_current_user(path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    fs::symlink_metadata(path).is_ok_and(|m| m.uid() == unsafe { libc::geteuid() })
+}
+
+#[cfg(not(unix))]
+pub fn owned_by_current_user(path: &Path) -> bool {
+    path.exists()
+}
+
+/// Create `dir` owner-only if it's missing, then make sure it's private
+fn private_dir(dir: &Path) -> Result<()> {
+    if let Some(parent) = dir.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    match builder.create(dir) {
+        Err(e) if e.kind() != std::io::ErrorKind::AlreadyExists => {
+            return Err(e).with_context(|| format!("Failed to create {}", dir.display()));
+        }
+        _ => {}
+    }
+    check_private(dir)
+}
+
+/// Git URL and optional ref of a remote config spec, None for plain downloads
+fn git_source(spec: &str) -> Option<(&str, Option<&str>)> {
+    let (url, reference) = match spec.split_once('#') {
+        Some((url, r)) => (url, Some(r)),
+        None => (spec, None),
+    };
+    if let Some(url) = url.strip_prefix("git+") {
+        return Some((url, reference));
+    }
+    let is_git = url.starts_with("ssh://") || url.starts_with("git@") || url.ends_with(".git");
+    is_git.then_some((url, reference))
+}
+
+fn fetch_git_config(url: &str, reference: Option<&str>, dir: &Path) -> Result<()> {
+    if !command_exists("git") {
+        if let Some(pm) = SysPkgManager::detect() {
+            pm.install("git")?;
+        } else {
+            anyhow::bail!("git not found and no package manager available");
+        }
+    }
+
+    let git = |args: &[&str]| -> Result<()> {
+        let output = Command::new("git")
+            .args(args)
+            .env("GIT_TERMINAL_PROMPT", "0")
+            .stdin(Stdio::null())
+            .output()
+            .context("Failed to run git")?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            anyhow::bail!("Failed to fetch config from {}: {}", url, stderr.trim());
+        }
+        Ok(())
+    };
+
+    let dir_str = dir.to_string_lossy();
+    if dir.join(".git").exists() {
+        git(&["-C", &dir_str, "fetch", "-q", "--depth", "1", "origin", reference.unwrap_or("HEAD")])?;
+        return git(&["-C", &dir_str, "reset", "-q", "--hard", "FETCH_HEAD"]);
+    }
+
+    let mut args = vec!["clone", "-q", "--depth", "1"];
+    if let Some(r) = reference {
+        args.extend(["--branch", r]);
+    }
+    args.extend([url, &dir_str]);
+    git(&args)
+}
+
+fn fetch_http_config(url: &str, dir: &Path) -> Result<()> {
+    // Always re-download
+    let data = fetch_url(url, Some(std::time::Duration::ZERO))?;
+
+    if dir.exists() {
+        fs::remove_dir_all(dir).with_context(|| format!("Failed to clear: {}", dir.display()))?;
+    }
+    let name = url.split(['?', '#']).next().unwrap_or(url);
+    if is_tar_gz(Path::new(name)) {
+        unpack_tar_gz(&data, dir).with_context(|| format!("Failed to extract: {}", url))?;
+    } else {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create cache dir: {}", dir.display()))?;
+        fs::write(dir.join("dek.toml"), &data)?;
+    }
+    Ok(())
 }
 
 /// Set DEK_LIB to data/functions.sh if it exists under the config directory.
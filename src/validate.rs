@@ -0,0 +1,417 @@
+use crate::config::{self, Config, FileConfig};
+use anyhow::{bail, Result};
+use owo_colors::OwoColorize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Package managers accepted as the prefix of an artifact dep ("apt.pkg:bin")
+const ARTIFACT_DEP_PMS: &[&str] = &["os", "apt", "pacman", "pac", "brew"];
+
+/// A config mistake, located as precisely as we can
+struct Problem {
+    file: PathBuf,
+    line: Option<usize>,
+    message: String,
+}
+
+/// One parsed config file, kept with its source for line lookups
+struct Source {
+    path: PathBuf,
+    content: String,
+    config: Config,
+}
+
+impl Source {
+    /// 1-based line of the first line containing `needle`
+    fn line_of(&self, needle: &str) -> Option<usize> {
+        if needle.is_empty() {
+            return None;
+        }
+        self.content.lines().position(|l| l.contains(needle)).map(|i| i + 1)
+    }
+
+    fn problem(&self, needle: &str, message: String) -> Problem {
+        Problem { file: self.path.clone(), line: self.line_of(needle), message }
+    }
+}
+
+/// Lint every config file and report all problems; errors if any were found
+pub fn run(config_path: Option<PathBuf>) -> Result<()> {
+    let path = crate::resolve_config(config_path)?;
+    let resolved = config::resolve_path(&path)?;
+
+    crate::output::print_header(&format!("Validating {}", path.display()));
+    println!();
+
+    let files = config::config_files(&resolved)?;
+    let base = if resolved.is_dir() { resolved.as_path() } else { Path::new("") };
+    let mut problems = Vec::new();
+    let mut sources = Vec::new();
+
+    for file in &files {
+        let content = fs::read_to_string(file)?;
+        match toml::from_str::<Config>(&content) {
+            Ok(config) => sources.push(Source { path: file.clone(), content, config }),
+            Err(e) => {
+                let line = e.span().map(|s| content[..s.start].lines().count().max(1));
+                problems.push(Problem { file: file.clone(), line, message: e.message().trim().replace('\n', ": ") });
+            }
+        }
+    }
+
+    let script_base = if resolved.is_dir() { resolved.as_path() } else { resolved.parent().unwrap_or(Path::new(".")) };
+    for source in &sources {
+        check_source(source, &mut problems);
+        check_scripts(source, script_base, &mut problems);
+    }
+    check_duplicates(&sources, base, &mut problems);
+    let meta_ok = check_meta(&resolved, &mut problems);
+    check_secrets(&resolved, &mut problems);
+
+    // The merged config only loads once every file parses
+    if sources.len() == files.len() && meta_ok {
+        match config::load_all(&resolved) {
+            Ok(merged) => {
+                if let Err(e) = crate::state::topo_sort(&merged.state) {
+                    problems.push(Problem { file: resolved.clone(), line: None, message: e.to_string() });
+                }
+                check_run_needs(&merged, &resolved, &mut problems);
+            }
+            // The per-file checks above already cover the file it names
+            Err(e) if e.downcast_ref::<config::InvalidConfig>().is_some_and(|c| files.iter().any(|f| same_file(f, &c.0))) => {}
+            Err(e) => problems.push(Problem { file: resolved.clone(), line: None, message: format!("{:#}", e) }),
+        }
+    }
+
+    problems.sort_by(|a, b| (&a.file, a.line).cmp(&(&b.file, b.line)));
+    for p in &problems {
+        let file = relative(&p.file, base);
+        let location = match p.line {
+            Some(line) => format!("{}:{}", file, line),
+            None => file,
+        };
+        println!("  {} {}  {}", c!("✗", red), c!(location, white), p.message);
+    }
+
+    if problems.is_empty() {
+        println!("{} {}, no problems", c!("✓", green), plural(files.len(), "file"));
+        return Ok(());
+    }
+
+    println!();
+    println!("{} {} in {}", c!("✗", red), plural(problems.len(), "problem"), plural(files.len(), "file"));
+    bail!("{} found", plural(problems.len(), "problem"));
+}
+
+fn check_source(source: &Source, problems: &mut Vec<Problem>) {
+    let config = &source.config;
+
+    if let Some(ref file) = config.file {
+        check_file_config(source, "file", file, problems);
+    }
+
+    for (name, run) in config.run.iter().flatten() {
+        for dep in &run.deps {
+            if let Err(e) = crate::parse_provider_spec(dep) {
+                problems.push(source.problem(dep, format!("run.{}: {}", name, e)));
+            }
+        }
+        if let Some(ref file) = run.file {
+            check_file_config(source, &format!("run.{}.file", name), file, problems);
+        }
+    }
+
+    for artifact in &config.artifact {
+        let label = artifact.name.as_deref().unwrap_or(&artifact.build);
+        if artifact.src.trim().is_empty() {
+            problems.push(source.problem(label, format!("artifact '{}': src is empty", label)));
+        }
+        if artifact.dest.trim().is_empty() {
+            problems.push(source.problem(label, format!("artifact '{}': dest is empty", label)));
+        }
+        for dep in &artifact.deps {
+            if let Some((pm, _)) = dep.split_once('.') {
+                if !ARTIFACT_DEP_PMS.contains(&pm) {
+                    problems.push(source.problem(dep, format!(
+                        "artifact '{}': unknown package manager '{}' in dep '{}'",
+                        label, pm, dep
+                    )));
+                }
+            }
+        }
+    }
+
+    for (url, sha) in config.meta.iter().flat_map(|m| &m.install_script_sha256) {
+        let hex = sha.strip_prefix("sha256:").unwrap_or(sha);
+        if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            problems.push(source.problem(sha, format!(
+                "meta.install_script_sha256: '{}' for {} isn't a sha256 hex digest",
+                sha, url
+            )));
+        }
+    }
+
+    for repo in &config.pacman_repo {
+        if repo.server.is_empty() && repo.include.is_none() {
+            problems.push(source.problem(&repo.name, format!("pacman_repo '{}': needs server or include", repo.name)));
+        }
+    }
+
+    for repo in &config.apt_repo {
+        if repo.components.is_empty() && !repo.suite.ends_with('/') {
+            problems.push(source.problem(&repo.name, format!(
+                "apt_repo '{}': components are required unless suite ends with '/'",
+                repo.name
+            )));
+        }
+    }
+
+    for cmd in &config.command {
+        if cmd.check.is_none() && cmd.creates.is_none() && cmd.removes.is_none() {
+            problems.push(source.problem(&cmd.name, format!("command '{}': needs check, creates or removes", cmd.name)));
+        }
+    }
+
+    for (label, field, value) in config::durations(config) {
+        if let Err(e) = crate::util::parse_duration(value) {
+            problems.push(source.problem(value, format!("{}: invalid {} '{}': {}", label, field, value, e)));
+        }
+    }
+
+    for assertion in &config.assert {
+        let modes = [&assertion.check, &assertion.foreach, &assertion.url];
+        if modes.iter().filter(|m| m.is_some()).count() > 1 {
+            let label = assertion.name.as_deref().or(assertion.url.as_deref()).unwrap_or_default();
+            problems.push(source.problem(label, format!(
+                "assert '{}': set only one of check, foreach and url",
+                label
+            )));
+        }
+    }
+
+    for state in &config.state {
+        for rule in &state.rewrite {
+            if let Err(e) = regex::Regex::new(&rule.pattern) {
+                problems.push(source.problem(&rule.pattern, format!(
+                    "state '{}': invalid rewrite regex: {}",
+                    state.name,
+                    regex_error(&e)
+                )));
+            }
+        }
+    }
+}
+
+/// `[script]` files have to exist and start with a shebang to be installed
+fn check_scripts(source: &Source, base: &Path, problems: &mut Vec<Problem>) {
+    for (name, path) in source.config.script.iter().flatten() {
+        match fs::read_to_string(base.join(path)) {
+            Ok(content) if !content.starts_with("#!") => {
+                problems.push(source.problem(path, format!("script '{}': {} has no shebang line", name, path)));
+            }
+            Ok(_) => {}
+            Err(_) => problems.push(source.problem(path, format!("script '{}': {} not found", name, path))),
+        }
+    }
+}
+
+fn check_file_config(source: &Source, prefix: &str, file: &FileConfig, problems: &mut Vec<Problem>) {
+    for entry in &file.line {
+        let delete = matches!(entry.mode, config::FileLineMode::Delete);
+        if delete && entry.original.is_none() && entry.original_regex.is_none() {
+            problems.push(source.problem(&entry.path, format!(
+                "{}.line for '{}': mode = \"delete\" needs original or original_regex",
+                prefix, entry.path
+            )));
+        }
+        if !delete && entry.line.is_empty() {
+            problems.push(source.problem(&entry.path, format!("{}.line for '{}': missing line", prefix, entry.path)));
+        }
+        let label = if entry.line.is_empty() { &entry.path } else { &entry.line };
+        if entry.original.is_some() && entry.original_regex.is_some() {
+            problems.push(source.problem(label, format!(
+                "{}.line '{}': set original or original_regex, not both",
+                prefix, label
+            )));
+        }
+        if let Some(ref pattern) = entry.original_regex {
+            if let Err(e) = regex::Regex::new(pattern) {
+                problems.push(source.problem(pattern, format!(
+                    "{}.line '{}': invalid original_regex: {}",
+                    prefix,
+                    label,
+                    regex_error(&e)
+                )));
+            }
+        }
+    }
+
+    for tmpl in &file.template {
+        if let Some(config::TemplateForeach::Expr(ref expr)) = tmpl.foreach {
+            if let Err(e) = minijinja::Environment::new().compile_expression(expr) {
+                problems.push(source.problem(expr, format!(
+                    "{}.template '{}': invalid foreach expression: {}",
+                    prefix, tmpl.dest, e
+                )));
+            }
+        }
+    }
+}
+
+/// Unknown `needs` targets and `needs` cycles across the merged run commands
+fn check_run_needs(merged: &Config, resolved: &Path, problems: &mut Vec<Problem>) {
+    let commands = merged.run.as_ref();
+    let mut names: Vec<&String> = commands.iter().flat_map(|c| c.keys()).collect();
+    names.sort();
+
+    let mut unknown = false;
+    for name in &names {
+        for need in &commands.unwrap()[*name].needs {
+            if !commands.unwrap().contains_key(need) {
+                unknown = true;
+                problems.push(Problem {
+                    file: resolved.to_path_buf(),
+                    line: None,
+                    message: format!("run.{}: needs unknown command '{}'", name, need),
+                });
+            }
+        }
+    }
+    if unknown {
+        return;
+    }
+
+    // A cycle is reached from each of its members; report it once
+    let mut in_cycle: Vec<String> = Vec::new();
+    for name in names {
+        if in_cycle.contains(name) {
+            continue;
+        }
+        let Err(e) = crate::run_needs_order(name, commands) else { continue };
+        let message = match e.downcast_ref::<crate::RunNeedsCycle>() {
+            Some(crate::RunNeedsCycle(chain)) => {
+                // Just the loop, not the path into it
+                let last = chain.last().expect("a cycle names at least one command");
+                let start = chain.iter().position(|n| n == last).unwrap_or(0);
+                if in_cycle.contains(last) {
+                    continue;
+                }
+                in_cycle.extend(chain[start..].iter().cloned());
+                format!("run.{}: needs cycle {}", last, chain[start..].join(" -> "))
+            }
+            None => e.to_string(),
+        };
+        problems.push(Problem { file: resolved.to_path_buf(), line: None, message });
+    }
+}
+
+/// meta.toml must parse, or nothing loads. False when it doesn't.
+fn check_meta(resolved: &Path, problems: &mut Vec<Problem>) -> bool {
+    let dir = if resolved.is_dir() { resolved } else { resolved.parent().unwrap_or(Path::new(".")) };
+    let meta_path = dir.join("meta.toml");
+    let Ok(content) = fs::read_to_string(&meta_path) else {
+        return true;
+    };
+    if let Err(e) = toml::from_str::<config::Meta>(&content) {
+        let line = e.span().map(|s| content[..s.start].lines().count().max(1));
+        problems.push(Problem { file: meta_path, line, message: e.message().trim().replace('\n', ": ") });
+        return false;
+    }
+    true
+}
+
+/// `[secrets]` in meta.toml: a known backend, and no secrets file that would
+/// ship with the config
+fn check_secrets(resolved: &Path, problems: &mut Vec<Problem>) {
+    // A meta.toml that doesn't parse is check_meta's to report
+    let Some(secrets) = config::load_meta(resolved).ok().flatten().and_then(|m| m.secrets) else {
+        return;
+    };
+    let dir = if resolved.is_dir() { resolved } else { resolved.parent().unwrap_or(Path::new(".")) };
+    let meta_path = dir.join("meta.toml");
+    let content = fs::read_to_string(&meta_path).unwrap_or_default();
+    let line_of = |needle: &str| content.lines().position(|l| l.contains(needle)).map(|i| i + 1);
+
+    if let Some(ref backend) = secrets.backend {
+        if !["pass", "1password", "op", "vault"].contains(&backend.as_str()) {
+            problems.push(Problem {
+                file: meta_path.clone(),
+                line: line_of("backend"),
+                message: format!("secrets: unknown backend '{}'. Use: pass, 1password, vault", backend),
+            });
+        }
+    }
+    if let Some(ref env_file) = secrets.env_file {
+        let path = dir.join(crate::util::expand_path(env_file));
+        let inside = fs::canonicalize(&path)
+            .ok()
+            .zip(fs::canonicalize(dir).ok())
+            .is_some_and(|(file, dir)| file.starts_with(dir));
+        if inside {
+            problems.push(Problem {
+                file: meta_path.clone(),
+                line: line_of("env_file"),
+                message: format!("secrets: env_file '{}' is inside the config dir and would ship with it", env_file),
+            });
+        }
+    }
+}
+
+/// Names that must be unique across files (later definitions silently win
+/// when merged)
+fn check_duplicates(sources: &[Source], base: &Path, problems: &mut Vec<Problem>) {
+    let mut seen: HashMap<(&str, String), &Path> = HashMap::new();
+
+    for source in sources {
+        let config = &source.config;
+        let mut names: Vec<(&str, String)> = Vec::new();
+        names.extend(config.state.iter().map(|s| ("state", s.name.clone())));
+        names.extend(config.command.iter().map(|c| ("command", c.name.clone())));
+        names.extend(config.run.iter().flatten().map(|(k, _)| ("run", k.clone())));
+        names.extend(config.aliases.iter().flatten().map(|(k, _)| ("alias", k.clone())));
+        names.extend(config.env.iter().flatten().map(|(k, _)| ("env", k.clone())));
+        names.extend(config.script.iter().flatten().map(|(k, _)| ("script", k.clone())));
+        names.extend(config.artifact.iter().map(|a| ("artifact dest", a.dest.clone())));
+        if let Some(ref file) = config.file {
+            names.extend(file.copy.iter().flatten().map(|(_, dst)| ("file.copy dest", dst.clone())));
+            names.extend(file.template.iter().map(|t| ("file.template dest", t.dest.clone())));
+        }
+
+        for (kind, name) in names {
+            if let Some(first) = seen.get(&(kind, name.clone())) {
+                let message = if *first == source.path.as_path() {
+                    format!("{} '{}' defined twice", kind, name)
+                } else {
+                    format!("{} '{}' already defined in {}", kind, name, relative(first, base))
+                };
+                problems.push(source.problem(&name, message));
+            } else {
+                seen.insert((kind, name), &source.path);
+            }
+        }
+    }
+}
+
+/// `path` relative to the config dir; the dir itself (cross-file problems) stays as is
+fn relative(path: &Path, base: &Path) -> String {
+    match path.strip_prefix(base) {
+        Ok(rel) if !rel.as_os_str().is_empty() => rel.display().to_string(),
+        _ => path.display().to_string(),
+    }
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    a == b || fs::canonicalize(a).ok().is_some_and(|a| fs::canonicalize(b).ok() == Some(a))
+}
+
+fn plural(n: usize, word: &str) -> String {
+    format!("{} {}{}", n, word, if n == 1 { "" } else { "s" })
+}
+
+/// Last line of a regex error ("error: unclosed group"), without the
+/// pattern excerpt above it
+fn regex_error(e: &regex::Error) -> String {
+    let msg = e.to_string();
+    msg.lines().last().unwrap_or(&msg).trim().to_string()
+}